@@ -0,0 +1,102 @@
+use crate::stats::Stats;
+use crate::world::World;
+use ayysee_compiler::simulator::Simulator;
+use ayysee_compiler::{CompileOptions, OptLevel};
+use std::path::Path;
+
+/// The optimization levels compared, with the flag selecting them.
+const LEVELS: &[(&str, OptLevel)] = &[
+    ("-O0", OptLevel::O0),
+    ("-O1", OptLevel::O1),
+    ("-O2", OptLevel::O2),
+    ("-Os", OptLevel::Os),
+];
+
+/// The measurements of a program compiled at one optimization level.
+struct Row {
+    flag: &'static str,
+    result: anyhow::Result<Measurement>,
+}
+
+struct Measurement {
+    stats: Stats,
+    /// Average and maximum number of instructions executed per tick.
+    average: f64,
+    max: usize,
+}
+
+/// Compiles the program at every optimization level and simulates it in `world`, returning
+/// a table comparing the results.
+pub(crate) fn bench(
+    path: &Path,
+    source: &str,
+    world: &World,
+    options: &CompileOptions,
+) -> anyhow::Result<String> {
+    // Parse errors are the same for all levels, so they are reported once.
+    crate::parse_source(path, source)?;
+    let rows = LEVELS
+        .iter()
+        .map(|&(flag, opt_level)| Row {
+            flag,
+            result: measure(
+                path,
+                source,
+                world,
+                &CompileOptions {
+                    opt_level,
+                    ..crate::without_line_limit(options)
+                },
+            ),
+        })
+        .collect::<Vec<_>>();
+    Ok(render(&rows, options))
+}
+
+fn measure(
+    path: &Path,
+    source: &str,
+    world: &World,
+    options: &CompileOptions,
+) -> anyhow::Result<Measurement> {
+    let program = crate::compile_program(path, source, options)?;
+    let stats = Stats::new(&program, &options.target);
+    let reports = world.run(&mut Simulator::new(program))?;
+    let total = reports.iter().map(|r| r.executed).sum::<usize>();
+    Ok(Measurement {
+        stats,
+        average: total as f64 / reports.len().max(1) as f64,
+        max: reports.iter().map(|r| r.executed).max().unwrap_or_default(),
+    })
+}
+
+fn render(rows: &[Row], options: &CompileOptions) -> String {
+    let mut out = format!(
+        "{:<6}{:>7}{:>11}{:>18}{:>10}\n",
+        "level", "lines", "registers", "instructions/tick", "max/tick"
+    );
+    for row in rows {
+        let line = match &row.result {
+            Ok(m) => {
+                let over = if m.stats.lines > options.target.max_lines {
+                    "  (over the limit)"
+                } else {
+                    ""
+                };
+                format!(
+                    "{:<6}{:>7}{:>11}{:>18.1}{:>10}{}",
+                    row.flag,
+                    m.stats.lines,
+                    m.stats.registers.len(),
+                    m.average,
+                    m.max,
+                    over
+                )
+            }
+            Err(e) => format!("{:<6}error: {:#}", row.flag, e),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
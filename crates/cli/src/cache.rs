@@ -0,0 +1,219 @@
+use anyhow::Context;
+use ayysee_compiler::CompileOptions;
+use ayysee_parser::ast::StatementKind;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The name of the build cache, in the output directory of the project.
+pub(crate) const CACHE_FILE: &str = ".galvanic-cache.json";
+
+/// The results of the last `cli build`, so that unchanged programs are not compiled again.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub(crate) struct Cache {
+    /// The entry of every program, by name.
+    programs: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Entry {
+    key: Key,
+    outcome: Outcome,
+}
+
+/// What a program was built from, as hashes kept apart so that a build can tell which of them
+/// changed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct Key {
+    compiler: String,
+    options: String,
+    source: String,
+    imports: String,
+}
+
+/// The result of compiling a program.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Outcome {
+    Compiled { mips: String },
+    Failed { diagnostics: String, exit_code: i32 },
+}
+
+/// Returns the key of a program in the cache, read from `path`, which changes whenever its
+/// source, the files it imports, the options or the version of the compiler do.
+pub(crate) fn key(path: &Path, source: &str, options: &CompileOptions) -> Key {
+    let hash = |write: &dyn Fn(&mut Fnv)| {
+        let mut hasher = Fnv::default();
+        write(&mut hasher);
+        format!("{:016x}", hasher.0)
+    };
+    Key {
+        compiler: hash(&|hasher| hasher.write_str(env!("CARGO_PKG_VERSION"))),
+        options: hash(&|hasher| hasher.write_str(&format!("{:?}", options))),
+        source: hash(&|hasher| hasher.write_str(source)),
+        imports: hash(&|hasher| hash_imports(path, source, hasher, &mut HashSet::new())),
+    }
+}
+
+/// Hashes the files imported by the source, read from `path`, and those they import in turn.
+/// A missing file is hashed too, so that the program is built again once it is added.
+fn hash_imports(path: &Path, source: &str, hasher: &mut Fnv, seen: &mut HashSet<PathBuf>) {
+    // A program that does not parse fails whatever it imports.
+    let Ok(program) = ayysee_parser::parse(source) else {
+        return;
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for statement in &program.statements {
+        let StatementKind::Import(name) = &statement.kind else {
+            continue;
+        };
+        let path = dir.join(name);
+        hasher.write_str(name);
+        match std::fs::read_to_string(&path) {
+            Ok(source) => {
+                hasher.write_str(&source);
+                if seen.insert(path.clone()) {
+                    hash_imports(&path, &source, hasher, seen);
+                }
+            }
+            Err(_) => hasher.write(&[0]),
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash, whose values, unlike those of the hasher of the standard library,
+/// are the same with every version of Rust.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Hashes the length of the text too, so that no two sequences of texts hash alike by
+    /// moving their ends.
+    fn write_str(&mut self, text: &str) {
+        self.write(&(text.len() as u64).to_le_bytes());
+        self.write(text.as_bytes());
+    }
+}
+
+impl Cache {
+    /// Loads the cache, which is empty when the file is missing or was written by an
+    /// incompatible version.
+    pub async fn load(path: &Path) -> Self {
+        match tokio::fs::read_to_string(path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        tokio::fs::write(path, serde_json::to_string_pretty(self)?)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Returns the outcome of the program, if it was built with the same key.
+    pub fn get(&self, name: &str, key: &Key) -> Option<&Outcome> {
+        self.programs
+            .get(name)
+            .filter(|entry| entry.key == *key)
+            .map(|entry| &entry.outcome)
+    }
+
+    /// Describes why the program, read from `path`, is not built with the key, naming the
+    /// first input that changed.
+    pub fn change(&self, name: &str, path: &Path, key: &Key) -> String {
+        let Some(Entry { key: last, .. }) = self.programs.get(name) else {
+            return format!("{} was not built yet", name);
+        };
+        let what = if last.compiler != key.compiler {
+            "the compiler".to_string()
+        } else if last.options != key.options {
+            format!("the options of {}", name)
+        } else if last.source != key.source {
+            path.display().to_string()
+        } else {
+            format!("the files {} imports", path.display())
+        };
+        format!("{} changed since the last build", what)
+    }
+
+    pub fn insert(&mut self, name: String, key: Key, outcome: Outcome) {
+        self.programs.insert(name, Entry { key, outcome });
+    }
+
+    /// Drops the programs no longer in the manifest.
+    pub fn retain(&mut self, names: &[&str]) {
+        self.programs
+            .retain(|name, _| names.contains(&name.as_str()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv() {
+        let mut hasher = Fnv::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_key_follows_imports() {
+        let dir = std::env::temp_dir().join(format!("galvanic-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let path = dir.join("main.ayy");
+        let source = "import \"lib/a.ayy\";\ndb.Setting = f(1);\n";
+        let options = CompileOptions::default();
+        let current = || key(&path, source, &options);
+
+        let missing = current();
+        std::fs::write(dir.join("lib/a.ayy"), "import \"b.ayy\";\n").unwrap();
+        let added = current();
+        assert_ne!(missing, added);
+        std::fs::write(dir.join("lib/b.ayy"), "fn f(x) { return x; }\n").unwrap();
+        let nested = current();
+        assert_ne!(added, nested);
+        assert_eq!(current(), nested);
+        std::fs::write(dir.join("lib/b.ayy"), "fn f(x) { return x + 1; }\n").unwrap();
+        assert_ne!(current(), nested);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_change_names_the_input() {
+        let path = Path::new("src/main.ayy");
+        let source = "db.Setting = 1;\n";
+        let options = CompileOptions::default();
+        let mut cache = Cache::default();
+        let built = key(path, source, &options);
+        assert_eq!(cache.change("main", path, &built), "main was not built yet");
+        cache.insert("main".into(), built, Outcome::Compiled { mips: "".into() });
+
+        let optimized = CompileOptions {
+            opt_level: ayysee_compiler::OptLevel::O0,
+            ..Default::default()
+        };
+        assert_eq!(
+            cache.change("main", path, &key(path, source, &optimized)),
+            "the options of main changed since the last build"
+        );
+        assert_eq!(
+            cache.change("main", path, &key(path, "db.Setting = 2;\n", &options)),
+            "src/main.ayy changed since the last build"
+        );
+    }
+}
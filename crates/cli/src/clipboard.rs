@@ -0,0 +1,44 @@
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Clipboard tools tried in order, with the arguments that make them read from stdin.
+const TOOLS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+    ("clip.exe", &[]),
+    ("clip", &[]),
+];
+
+/// Places `text` on the system clipboard using the first clipboard tool that is available.
+pub(crate) async fn copy(text: &str) -> anyhow::Result<()> {
+    for (tool, args) in TOOLS {
+        let mut child = match Command::new(tool)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(anyhow::anyhow!("failed to run {}: {}", tool, e)),
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes()).await?;
+        }
+        let status = child.wait().await?;
+        anyhow::ensure!(status.success(), "{} exited with {}", tool, status);
+        return Ok(());
+    }
+    anyhow::bail!(
+        "no clipboard tool found, install one of: {}",
+        TOOLS
+            .iter()
+            .map(|(tool, _)| *tool)
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
@@ -1,44 +1,514 @@
+use crate::template::Template;
+use ayysee_compiler::{GameData, Target};
+use ayysee_parser::format::FormatConfig;
 use clap::ValueEnum;
-use std::path::PathBuf;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub(crate) struct Args {
+    /// Print more log messages: `-v` for progress, `-vv` for compiler internals
+    #[clap(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Only print errors and the requested output, no progress messages
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
     #[clap(subcommand)]
     pub command: Commands,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+impl Args {
+    /// The most verbose level of log messages to print.
+    pub fn log_level(&self) -> tracing::Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => tracing::Level::ERROR,
+            (false, 0) => tracing::Level::WARN,
+            (false, 1) => tracing::Level::INFO,
+            (false, 2) => tracing::Level::DEBUG,
+            (false, _) => tracing::Level::TRACE,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 pub(crate) enum CompilationType {
     Ast,
+    /// The lossless syntax tree, with whitespace and comments
+    Cst,
+    #[default]
     Mips,
 }
 
-impl Default for CompilationType {
-    fn default() -> Self {
-        Self::Mips
-    }
-}
-
 impl std::fmt::Display for CompilationType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             CompilationType::Ast => write!(f, "ast"),
+            CompilationType::Cst => write!(f, "cst"),
             CompilationType::Mips => write!(f, "mips"),
         }
     }
 }
 
+/// How results are printed.
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum, Debug)]
+pub(crate) enum OutputFormat {
+    /// Human readable output
+    #[default]
+    Text,
+    /// A single JSON object with diagnostics, the program and metrics
+    Json,
+}
+
+/// Optimization level, mapped onto [`ayysee_compiler::OptLevel`].
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum, Deserialize, Debug)]
+pub(crate) enum OptLevel {
+    /// Straight translation, best for debugging
+    #[value(name = "0")]
+    #[serde(rename = "0")]
+    O0,
+    /// Single PHI coalescing, copy propagation, inlining and dead code pass
+    #[value(name = "1")]
+    #[serde(rename = "1")]
+    O1,
+    /// Run all passes until nothing changes
+    #[default]
+    #[value(name = "2")]
+    #[serde(rename = "2")]
+    O2,
+    /// Minimize the number of emitted lines
+    #[value(name = "s")]
+    #[serde(rename = "s")]
+    Os,
+}
+
+impl From<OptLevel> for ayysee_compiler::OptLevel {
+    fn from(level: OptLevel) -> Self {
+        match level {
+            OptLevel::O0 => ayysee_compiler::OptLevel::O0,
+            OptLevel::O1 => ayysee_compiler::OptLevel::O1,
+            OptLevel::O2 => ayysee_compiler::OptLevel::O2,
+            OptLevel::Os => ayysee_compiler::OptLevel::Os,
+        }
+    }
+}
+
+/// Flags shared by all commands that compile programs.
+#[derive(clap::Args, Default, Debug)]
+pub(crate) struct CompileFlags {
+    /// Optimization level: 0, 1, 2 (the default) or s (`-Os`)
+    #[clap(short = 'O', long, value_enum)]
+    pub opt_level: Option<OptLevel>,
+    /// The chip to compile for: ic10 (the default) or ic10-legacy. Overrides the target of
+    /// programs in the project manifest
+    #[clap(long, value_parser = parse_target)]
+    pub target: Option<Target>,
+    /// The target used when none is given on the command line or in the manifest, from the
+    /// configuration files.
+    #[clap(skip)]
+    pub default_target: Option<Target>,
+    /// A game data file listing the device pins and logic types, replacing the bundled one
+    #[clap(long)]
+    pub game_data: Option<PathBuf>,
+    /// The contents of the game data file given on the command line or in the
+    /// configuration files.
+    #[clap(skip)]
+    pub loaded_game_data: Option<GameData>,
+    /// Name the device pins and registers after the constants and variables holding them
+    /// with `alias` lines, as far as the line limit allows
+    #[clap(long)]
+    pub aliases: bool,
+    /// Define a constant, replacing the value of the program's `const` of the same name,
+    /// e.g. `-D TARGET_TEMPERATURE=300`. `true` and `false` stand for 1 and 0
+    #[clap(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = parse_definition)]
+    pub definitions: Vec<(String, f64)>,
+    /// Accept device fields that are not logic types of the game data, such as those of mods
+    #[clap(long)]
+    pub allow_unknown_logic_types: bool,
+}
+
+fn parse_definition(arg: &str) -> anyhow::Result<(String, f64)> {
+    let (name, value) = crate::template::parse_param(arg)?;
+    let value = match value.as_str() {
+        "true" => 1.0,
+        "false" => 0.0,
+        _ => value.parse().map_err(|_| {
+            anyhow::anyhow!("invalid value `{}` of `{}`, expected a number", value, name)
+        })?,
+    };
+    Ok((name, value))
+}
+
+fn parse_target(name: &str) -> Result<Target, String> {
+    Target::builtin(name).ok_or_else(|| {
+        format!(
+            "unknown target, expected one of: {}",
+            Target::BUILTIN.join(", ")
+        )
+    })
+}
+
+impl CompileFlags {
+    pub fn options(&self) -> ayysee_compiler::CompileOptions {
+        ayysee_compiler::CompileOptions {
+            opt_level: self.opt_level.unwrap_or_default().into(),
+            target: self
+                .target
+                .clone()
+                .or_else(|| self.default_target.clone())
+                .unwrap_or_default(),
+            game_data: self.loaded_game_data.clone().unwrap_or_default(),
+            aliases: self.aliases,
+            definitions: self.definitions.iter().cloned().collect(),
+            unknown_logic_types: self.allow_unknown_logic_types,
+            ..Default::default()
+        }
+    }
+}
+
+/// Placement of opening braces, mapped onto [`ayysee_parser::format::BraceStyle`].
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BraceStyle {
+    /// At the end of the line
+    SameLine,
+    /// On a line of its own
+    NextLine,
+}
+
+impl From<BraceStyle> for ayysee_parser::format::BraceStyle {
+    fn from(style: BraceStyle) -> Self {
+        match style {
+            BraceStyle::SameLine => ayysee_parser::format::BraceStyle::SameLine,
+            BraceStyle::NextLine => ayysee_parser::format::BraceStyle::NextLine,
+        }
+    }
+}
+
+/// Formatter settings, also read from `ayyseefmt.toml` and the `[format]` table of the
+/// configuration files.
+#[derive(clap::Args, Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FormatFlags {
+    /// Number of spaces blocks are indented by (default 4)
+    #[clap(long)]
+    pub indent_width: Option<usize>,
+    /// Split `if` conditions making a line longer than this before `&&` and `||` (default 100)
+    #[clap(long)]
+    pub max_line_length: Option<usize>,
+    /// Surround binary operators with spaces, as in `a + b` (default true)
+    #[clap(long)]
+    pub spaces_around_operators: Option<bool>,
+    /// End the last statement of a block with `;` (default true)
+    #[clap(long)]
+    pub trailing_semicolons: Option<bool>,
+    /// Where the opening brace of a body goes (default same-line)
+    #[clap(long, value_enum)]
+    pub brace_style: Option<BraceStyle>,
+}
+
+impl FormatFlags {
+    /// Fills in the settings not given yet from `other`.
+    pub fn or(&mut self, other: &FormatFlags) {
+        self.indent_width = self.indent_width.or(other.indent_width);
+        self.max_line_length = self.max_line_length.or(other.max_line_length);
+        self.spaces_around_operators = self
+            .spaces_around_operators
+            .or(other.spaces_around_operators);
+        self.trailing_semicolons = self.trailing_semicolons.or(other.trailing_semicolons);
+        self.brace_style = self.brace_style.or(other.brace_style);
+    }
+
+    pub fn config(&self) -> FormatConfig {
+        let default = FormatConfig::default();
+        FormatConfig {
+            indent_width: self.indent_width.unwrap_or(default.indent_width),
+            max_line_length: self.max_line_length.unwrap_or(default.max_line_length),
+            spaces_around_operators: self
+                .spaces_around_operators
+                .unwrap_or(default.spaces_around_operators),
+            trailing_semicolons: self
+                .trailing_semicolons
+                .unwrap_or(default.trailing_semicolons),
+            brace_style: self.brace_style.map_or(default.brace_style, Into::into),
+        }
+    }
+}
+
 #[derive(clap::Subcommand, Debug)]
 pub(crate) enum Commands {
     /// Invoke the ayysee compiler
     Compile {
-        /// The file to compile
+        /// The file to compile, or `-` to read the program from stdin
         file: PathBuf,
+        /// File to write the output to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
         /// Select what type of output to generate
-        #[clap(short, long, value_enum, default_value_t = CompilationType::default())]
+        #[clap(short, long, alias = "emit", value_enum, default_value_t = CompilationType::default())]
         output: CompilationType,
+        /// Copy the compiled program to the system clipboard
+        #[clap(long)]
+        copy: bool,
+        #[clap(long, value_enum, default_value_t = OutputFormat::default())]
+        format: OutputFormat,
+        /// Print the time spent in each compiler pass and the size of the program after it
+        #[clap(long)]
+        timings: bool,
+        #[clap(flatten)]
+        flags: CompileFlags,
     },
     /// Invoke the formatter
-    Format { files: Vec<PathBuf> },
+    #[clap(alias = "fmt")]
+    Format {
+        /// Files, directories (searched recursively for `.ayy` files) or glob patterns such
+        /// as `src/**/*.ayy`. `-` (or no files) formats stdin to stdout
+        files: Vec<PathBuf>,
+        #[clap(flatten)]
+        flags: FormatFlags,
+        /// The settings of the project manifest and the user configuration, which an
+        /// `ayyseefmt.toml` next to the formatted files overrides.
+        #[clap(skip)]
+        defaults: FormatFlags,
+    },
+    /// Compile a program and run it in the simulator. A fault stops the simulation and is
+    /// reported at the statement of the source it comes from
+    #[clap(alias = "run")]
+    Simulate {
+        /// The file to simulate, or `-` to read the program from stdin
+        file: PathBuf,
+        /// File to write the output to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+        /// TOML file describing the connected devices and their inputs over time
+        #[clap(short, long)]
+        world: Option<PathBuf>,
+        /// Print the device states after every tick as JSON
+        #[clap(long)]
+        json: bool,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Step through a program in a terminal UI showing registers and devices
+    Debug {
+        /// The file to debug
+        file: PathBuf,
+        /// TOML file describing the connected devices and their inputs over time
+        #[clap(short, long)]
+        world: Option<PathBuf>,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Print the statement of the source a line of the compiled program was generated for,
+    /// such as the LineNumber of an IC stopped by an error in game
+    Locate {
+        /// The file the program was compiled from
+        file: PathBuf,
+        /// The line of the compiled program, counting from 0 as the game does
+        line: usize,
+        /// File to write the output to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Check that a program compiles, without printing it
+    Check {
+        /// The file to check, or `-` to read the program from stdin
+        file: PathBuf,
+        #[clap(long, value_enum, default_value_t = OutputFormat::default())]
+        format: OutputFormat,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Report how much of the IC's line and register limits a program uses
+    Stats {
+        /// The file to analyze, or `-` to read the program from stdin
+        file: PathBuf,
+        /// File to write the output to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+        #[clap(long, value_enum, default_value_t = OutputFormat::default())]
+        format: OutputFormat,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Compare line count, register usage and instructions per tick at every optimization level
+    Bench {
+        /// The file to benchmark, or `-` to read the program from stdin
+        file: PathBuf,
+        /// File to write the output to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+        /// TOML file describing the world to simulate, see `simulate`
+        #[clap(short, long)]
+        world: Option<PathBuf>,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Run the `//! test` blocks embedded in programs
+    Test {
+        /// The files to test. When empty, all programs of the project manifest are tested
+        files: Vec<PathBuf>,
+        /// Path to the project manifest
+        #[clap(short, long, default_value = crate::project::MANIFEST_FILE)]
+        manifest: PathBuf,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Validate MIPS code copied from the game and print it with aliases and labels resolved
+    #[clap(alias = "disassemble")]
+    Disasm {
+        /// The MIPS file, or `-` to read it from stdin
+        file: PathBuf,
+        /// File to write the output to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+    },
+    /// Translate MIPS code copied from the game into ayysee, as far as possible
+    Decompile {
+        /// The MIPS file, or `-` to read it from stdin
+        file: PathBuf,
+        /// File to write the output to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+    },
+    /// Start an interactive session running statements against simulated devices
+    Repl {
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Run a Language Server Protocol server over stdin/stdout
+    Lsp,
+    /// Serve a JSON API to compile, format and simulate programs over HTTP
+    Serve {
+        /// Port to listen on
+        #[clap(long, default_value_t = 8080)]
+        port: u16,
+        /// Address to listen on. Use 0.0.0.0 to accept connections from other machines
+        #[clap(long, default_value = "127.0.0.1")]
+        host: String,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Create a new project with a manifest, an example program and a simulation scenario
+    Init {
+        /// Directory to create the project in
+        #[clap(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Create a program for a common build from a template
+    New {
+        /// The build to create a program for
+        #[clap(short, long, value_enum)]
+        template: Template,
+        /// File to write the program to, `<template>.ayy` by default
+        file: Option<PathBuf>,
+        /// Set a constant of the template, as `NAME=VALUE`. May be repeated
+        #[clap(long = "set", value_name = "NAME=VALUE", value_parser = crate::template::parse_param)]
+        params: Vec<(String, String)>,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Compile every program listed in the project manifest
+    Build {
+        /// Path to the project manifest
+        #[clap(short, long, default_value = crate::project::MANIFEST_FILE)]
+        manifest: PathBuf,
+        /// Compile nothing, and fail unless every program and output is up to date
+        #[clap(long)]
+        frozen: bool,
+        #[clap(flatten)]
+        flags: CompileFlags,
+    },
+    /// Work with the grammar of the language
+    Grammar {
+        #[clap(subcommand)]
+        command: GrammarCommand,
+    },
+    /// Manage the device pins, logic types and prefabs programs can use
+    GameData {
+        #[clap(subcommand)]
+        command: GameDataCommand,
+    },
+    /// Work with the devices wired to the ICs of a base
+    Devices {
+        #[clap(subcommand)]
+        command: DevicesCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum GameDataCommand {
+    /// Convert the game's Stationpedia export into a game data file for `--game-data`
+    Import {
+        /// The `Stationpedia.json` file, or `-` to read it from stdin
+        file: PathBuf,
+        /// File to write the game data to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum DevicesCommand {
+    /// Declare a constant for every device wired to an IC housing, named after the device
+    Import {
+        /// A device list exported from a save, in TOML or JSON, or `-` to read TOML from stdin
+        file: PathBuf,
+        /// The housing to declare the devices of, when the list has several
+        #[clap(long)]
+        housing: Option<String>,
+        /// File to write the declarations to, or `-` for stdout
+        #[clap(long, default_value = "-")]
+        out: PathBuf,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum GrammarCommand {
+    /// Write a tree-sitter grammar and highlight queries, for syntax highlighting in editors
+    Export {
+        /// Directory to write the grammar to. Run `tree-sitter generate` there to build it
+        #[clap(default_value = "tree-sitter-ayysee")]
+        out: PathBuf,
+    },
+}
+
+impl Commands {
+    /// Returns the compilation flags of the command, if it compiles programs.
+    pub fn compile_flags_mut(&mut self) -> Option<&mut CompileFlags> {
+        match self {
+            Commands::Compile { flags, .. }
+            | Commands::Simulate { flags, .. }
+            | Commands::Debug { flags, .. }
+            | Commands::Check { flags, .. }
+            | Commands::Locate { flags, .. }
+            | Commands::Stats { flags, .. }
+            | Commands::Bench { flags, .. }
+            | Commands::Test { flags, .. }
+            | Commands::Repl { flags }
+            | Commands::New { flags, .. }
+            | Commands::Serve { flags, .. }
+            | Commands::Build { flags, .. } => Some(flags),
+            _ => None,
+        }
+    }
+
+    /// Returns the formatter settings the configuration files fill in, if the command
+    /// formats programs.
+    pub fn format_flags_mut(&mut self) -> Option<&mut FormatFlags> {
+        match self {
+            Commands::Format { defaults, .. } => Some(defaults),
+            _ => None,
+        }
+    }
+
+    /// Returns the path of the project manifest the command works in.
+    pub fn manifest(&self) -> &Path {
+        match self {
+            Commands::Test { manifest, .. } | Commands::Build { manifest, .. } => manifest,
+            _ => Path::new(crate::project::MANIFEST_FILE),
+        }
+    }
 }
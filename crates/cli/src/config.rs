@@ -0,0 +1,175 @@
+use crate::commands::{Commands, FormatFlags, OptLevel};
+use crate::project::Project;
+use anyhow::Context;
+use ayysee_compiler::{GameData, Target};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// User-wide defaults, read from `~/.config/galvanic/config.toml`.
+///
+/// ```toml
+/// opt_level = "s"
+/// target = "ic10"
+/// game_data = "/path/to/game_data.toml"
+///
+/// [format]
+/// indent_width = 2
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    pub opt_level: Option<OptLevel>,
+    pub target: Option<String>,
+    /// A game data file replacing the bundled device pins and logic types.
+    pub game_data: Option<PathBuf>,
+    #[serde(default)]
+    pub format: FormatFlags,
+}
+
+impl Config {
+    /// Returns the path of the configuration file, following the XDG base directories.
+    pub fn path() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("galvanic").join("config.toml"))
+    }
+
+    /// Loads the configuration file, which is optional.
+    pub async fn load() -> anyhow::Result<Self> {
+        let path = match Self::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+        let content = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("invalid configuration {}: {}", path.display(), e))
+    }
+}
+
+/// Formatter settings of the programs in a directory and below, read like the `[format]`
+/// table of the configuration files.
+pub(crate) const FORMAT_FILE: &str = "ayyseefmt.toml";
+
+/// Loads the nearest [`FORMAT_FILE`] in `dir` or its parent directories, if any.
+pub(crate) async fn format_file(dir: &Path) -> anyhow::Result<FormatFlags> {
+    let dir =
+        std::path::absolute(dir).with_context(|| format!("failed to resolve {}", dir.display()))?;
+    let path = match dir
+        .ancestors()
+        .map(|d| d.join(FORMAT_FILE))
+        .find(|p| p.exists())
+    {
+        Some(path) => path,
+        None => return Ok(FormatFlags::default()),
+    };
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("invalid formatter settings {}: {}", path.display(), e))
+}
+
+/// Fills in the compilation and formatter flags not given on the command line, from the
+/// project manifest and then the user configuration.
+pub(crate) async fn apply_defaults(command: &mut Commands) -> anyhow::Result<()> {
+    if command.compile_flags_mut().is_none() && command.format_flags_mut().is_none() {
+        return Ok(());
+    }
+    let manifest = command.manifest().to_path_buf();
+    let config = Config::load().await?;
+    let project = if manifest.exists() {
+        Some(Project::load(&manifest).await?)
+    } else {
+        None
+    };
+    let manifest = project.as_ref().map(|p| &p.manifest);
+
+    if let Some(flags) = command.format_flags_mut() {
+        if let Some(manifest) = manifest {
+            flags.or(&manifest.format);
+        }
+        flags.or(&config.format);
+    }
+
+    let flags = match command.compile_flags_mut() {
+        Some(flags) => flags,
+        None => return Ok(()),
+    };
+    flags.opt_level = flags
+        .opt_level
+        .or(manifest.and_then(|m| m.opt_level))
+        .or(config.opt_level);
+    let target = manifest.and_then(|m| m.target.clone()).or(config.target);
+    if let Some(name) = target {
+        let target = match &project {
+            Some(project) => project.resolve_target(&name)?,
+            None => Target::builtin(&name)
+                .with_context(|| format!("unknown target `{}` in the configuration", name))?,
+        };
+        flags.default_target = Some(target);
+    }
+    let game_data = flags
+        .game_data
+        .clone()
+        .or_else(|| {
+            let project = project.as_ref()?;
+            Some(project.root.join(project.manifest.game_data.as_ref()?))
+        })
+        .or(config.game_data);
+    if let Some(path) = game_data {
+        let source = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let game_data = GameData::parse(&source)
+            .with_context(|| format!("invalid game data {}", path.display()))?;
+        flags.loaded_game_data = Some(game_data);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config: Config = toml::from_str(
+            r#"
+            opt_level = "s"
+            target = "ic10"
+            game_data = "/path/to/game_data.toml"
+
+            [format]
+            indent_width = 2
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(config.opt_level, Some(OptLevel::Os)));
+        assert_eq!(config.target.as_deref(), Some("ic10"));
+        assert_eq!(
+            config.game_data,
+            Some(PathBuf::from("/path/to/game_data.toml"))
+        );
+        assert_eq!(config.format.indent_width, Some(2));
+        assert_eq!(config.format.max_line_length, None);
+        assert!(toml::from_str::<Config>("opt_level = \"fast\"").is_err());
+        assert!(toml::from_str::<Config>("[format]\nindent = 2").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_format_file() {
+        let dir = std::env::temp_dir().join(format!("galvanic-format-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let flags = format_file(&dir.join("src")).await.unwrap();
+        assert_eq!(flags.indent_width, None);
+        std::fs::write(dir.join(FORMAT_FILE), "trailing_semicolons = false\n").unwrap();
+        let flags = format_file(&dir.join("src")).await.unwrap();
+        assert_eq!(flags.trailing_semicolons, Some(false));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
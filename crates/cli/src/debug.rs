@@ -0,0 +1,285 @@
+use crate::world::{self, World};
+use ayysee_compiler::simulator::{Simulator, TickResult};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+use stationeers_mips::types::Register;
+use stationeers_mips::Program;
+use std::io::Write;
+
+const HELP: &str = "s step  n next tick  c continue  b breakpoint  ↑↓ move  r restart  q quit";
+
+/// Number of ticks `continue` runs at most when no breakpoint is hit, as most programs
+/// loop forever.
+const CONTINUE_TICKS: usize = 1000;
+
+/// Runs the interactive debugger until the user quits.
+pub(crate) fn run(program: Program, world: World) -> anyhow::Result<()> {
+    let mut debugger = Debugger::new(program, world)?;
+    let _terminal = Terminal::enter()?;
+    let mut stdout = std::io::stdout();
+    loop {
+        debugger.draw(&mut stdout)?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Release && !debugger.handle(key)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Puts the terminal into raw mode on an alternate screen, restoring it when dropped or
+/// when the debugger panics.
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> anyhow::Result<Self> {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            Terminal::restore();
+            hook(info)
+        }));
+        terminal::enable_raw_mode()?;
+        execute!(
+            std::io::stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide
+        )?;
+        Ok(Terminal)
+    }
+
+    fn restore() {
+        let _ = execute!(
+            std::io::stdout(),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        Terminal::restore();
+    }
+}
+
+struct Debugger {
+    program: Program,
+    world: World,
+    simulator: Simulator,
+    /// The current tick, counting from 1; 0 before the first one started.
+    tick: usize,
+    /// Whether the current tick was interrupted by a step or breakpoint.
+    in_tick: bool,
+    /// The line selected for toggling breakpoints.
+    cursor: usize,
+    /// The first line shown in the listing.
+    scroll: usize,
+    status: String,
+}
+
+impl Debugger {
+    fn new(program: Program, world: World) -> anyhow::Result<Self> {
+        let mut simulator = Simulator::new(program.clone());
+        world.setup(&mut simulator)?;
+        Ok(Self {
+            program,
+            world,
+            simulator,
+            tick: 0,
+            in_tick: false,
+            cursor: 0,
+            scroll: 0,
+            status: "ready".to_string(),
+        })
+    }
+
+    /// Handles a key press, returning false when the debugger should exit.
+    fn handle(&mut self, key: KeyEvent) -> anyhow::Result<bool> {
+        let lines = self.program.instructions.len();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false)
+            }
+            KeyCode::Char('s') | KeyCode::Enter => {
+                self.start_tick()?;
+                match self.simulator.step() {
+                    Some(result) => self.end_tick(result),
+                    None => self.status = format!("tick {}: stepped", self.tick),
+                }
+            }
+            KeyCode::Char('n') => {
+                self.start_tick()?;
+                let result = self.simulator.tick();
+                self.end_tick(result);
+            }
+            KeyCode::Char('c') => {
+                for _ in 0..CONTINUE_TICKS {
+                    self.start_tick()?;
+                    let result = self.simulator.tick();
+                    let stop = matches!(
+                        result,
+                        TickResult::Breakpoint
+                            | TickResult::End
+                            | TickResult::Fault(_)
+                            | TickResult::Halted
+                    );
+                    self.end_tick(result);
+                    if stop {
+                        break;
+                    }
+                }
+            }
+            KeyCode::Char('b') => {
+                let set = self.simulator.toggle_breakpoint(self.cursor);
+                self.status = format!(
+                    "breakpoint {} line {}",
+                    if set { "set at" } else { "removed from" },
+                    self.cursor
+                );
+            }
+            KeyCode::Char('r') => {
+                let breakpoints = self.simulator.breakpoints().clone();
+                *self = Debugger::new(self.program.clone(), std::mem::take(&mut self.world))?;
+                for line in breakpoints {
+                    self.simulator.toggle_breakpoint(line);
+                }
+                self.status = "restarted".to_string();
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.cursor = (self.cursor + 1).min(lines.saturating_sub(1))
+            }
+            _ => (),
+        }
+        Ok(true)
+    }
+
+    /// Applies the world inputs when a new tick starts.
+    fn start_tick(&mut self) -> anyhow::Result<()> {
+        if !self.in_tick {
+            self.tick += 1;
+            self.world.apply_inputs(&mut self.simulator, self.tick)?;
+            self.in_tick = true;
+        }
+        Ok(())
+    }
+
+    fn end_tick(&mut self, result: TickResult) {
+        self.in_tick = result == TickResult::Breakpoint;
+        if self.in_tick || matches!(result, TickResult::Fault(_) | TickResult::Halted) {
+            self.cursor = self.simulator.line();
+        }
+        self.status = match result {
+            TickResult::Fault(fault) => format!("tick {}: fault on {}", self.tick, fault),
+            result => format!("tick {}: {:?}", self.tick, result),
+        };
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> anyhow::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let rows = height.saturating_sub(2);
+        let listing_width = (width * 3 / 5).max(30).min(width);
+
+        // Keep the cursor on screen.
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + rows {
+            self.scroll = self.cursor + 1 - rows;
+        }
+
+        queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        queue!(
+            out,
+            SetAttribute(Attribute::Reverse),
+            Print(fit(&format!(" galvanic debugger  {}", self.status), width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        let current = self.simulator.line();
+        let instructions = self.simulator.instructions();
+        for row in 0..rows {
+            let line = self.scroll + row;
+            let ins = match instructions.get(line) {
+                Some(ins) => ins,
+                None => break,
+            };
+            let breakpoint = if self.simulator.breakpoints().contains(&line) {
+                '●'
+            } else {
+                ' '
+            };
+            let marker = if line == current { '>' } else { ' ' };
+            let text = fit(
+                &format!("{}{} {:>3}  {}", breakpoint, marker, line, ins),
+                listing_width,
+            );
+            queue!(out, cursor::MoveTo(0, (row + 1) as u16))?;
+            if line == current {
+                queue!(out, SetForegroundColor(Color::Green))?;
+            }
+            if line == self.cursor {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(out, Print(text), SetAttribute(Attribute::Reset))?;
+            queue!(out, SetForegroundColor(Color::Reset))?;
+        }
+
+        let panel_width = width.saturating_sub(listing_width + 1);
+        for (row, text) in self.panel().iter().take(rows).enumerate() {
+            queue!(
+                out,
+                cursor::MoveTo((listing_width + 1) as u16, (row + 1) as u16),
+                Print(fit(text, panel_width))
+            )?;
+        }
+
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Dim),
+            Print(fit(HELP, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn registers(&self, a: Register, b: Register) -> String {
+        format!(
+            "  {:<4}{:<12}{:<4}{}",
+            a.to_string(),
+            self.simulator.register(a),
+            b.to_string(),
+            self.simulator.register(b)
+        )
+    }
+
+    /// The lines of the side panel: the registers, then every device with its variables.
+    fn panel(&self) -> Vec<String> {
+        let mut lines = vec!["Registers".to_string()];
+        for i in 0..8u8 {
+            let (a, b): (Register, Register) = (i.into(), (i + 8).into());
+            lines.push(self.registers(a, b));
+        }
+        lines.push(self.registers(Register::Sp, Register::Ra));
+        lines.push(String::new());
+        lines.push("Devices".to_string());
+        for (device, vars) in world::snapshot(&self.simulator) {
+            lines.push(format!("  {}", device));
+            for (variable, value) in vars {
+                lines.push(format!("    {} = {}", variable, value));
+            }
+        }
+        lines
+    }
+}
+
+/// Truncates or pads the text to exactly `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    format!("{:<width$.width$}", text, width = width)
+}
@@ -0,0 +1,511 @@
+use crate::disasm::Line;
+use stationeers_mips::instructions::{
+    Arithmetic, DeviceIo, FlowControl, Instruction, Logic, Misc, Stack, VariableSelection,
+};
+use stationeers_mips::types::{Device, JumpDest, Register, RegisterOrNumber};
+use std::collections::HashMap;
+
+/// A condition `lhs op rhs` read from a branch or select instruction.
+struct Cond {
+    lhs: String,
+    op: &'static str,
+    rhs: String,
+}
+
+impl Cond {
+    fn negate(self) -> Self {
+        let op = match self.op {
+            "==" => "!=",
+            "!=" => "==",
+            ">" => "<=",
+            ">=" => "<",
+            "<" => ">=",
+            "<=" => ">",
+            op => unreachable!("unknown comparison {}", op),
+        };
+        Self { op, ..self }
+    }
+}
+
+impl std::fmt::Display for Cond {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op, self.rhs)
+    }
+}
+
+/// The ayysee source lifted from MIPS code.
+pub(crate) struct Decompiled {
+    pub source: String,
+    /// The lines whose instruction has no equivalent and is only kept as a comment, so that
+    /// the source does not behave like the MIPS code.
+    pub dropped: Vec<usize>,
+}
+
+/// Lifts MIPS code into ayysee source on a best-effort basis.
+///
+/// Backward jumps become `loop`s and forward conditional branches become `if`/`else`.
+/// Registers and devices are named after their aliases. Instructions that cannot be
+/// expressed, such as branches of other shapes, are kept as comments and listed in
+/// [`Decompiled::dropped`].
+pub(crate) fn decompile(lines: &[Line]) -> Decompiled {
+    let mut decompiler = Decompiler {
+        lines,
+        names: HashMap::new(),
+        out: String::new(),
+        dropped: vec![],
+    };
+    for line in lines {
+        if let Some(Instruction::Misc(Misc::Alias { name, target })) = &line.instruction {
+            decompiler.names.insert(target.clone(), name.clone());
+        }
+    }
+    decompiler.declarations();
+    decompiler.block(0, lines.len(), 0);
+    Decompiled {
+        source: decompiler.out,
+        dropped: decompiler.dropped,
+    }
+}
+
+struct Decompiler<'a> {
+    lines: &'a [Line],
+    /// Names given to registers and devices by `alias`.
+    names: HashMap<String, String>,
+    out: String,
+    dropped: Vec<usize>,
+}
+
+impl Decompiler<'_> {
+    fn instruction(&self, idx: usize) -> Option<&Instruction> {
+        self.lines.get(idx).and_then(|l| l.instruction.as_ref())
+    }
+
+    fn name(&self, operand: impl ToString) -> String {
+        let operand = operand.to_string();
+        if let Ok(Device::Indirect(register)) = operand.parse() {
+            return format!("devices[{}]", self.name(register));
+        }
+        self.names.get(&operand).cloned().unwrap_or(operand)
+    }
+
+    fn emit(&mut self, indent: usize, line: impl AsRef<str>) {
+        self.out.push_str(&"    ".repeat(indent));
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    /// Emits the device aliases, the constants and a variable for every register the program
+    /// uses.
+    fn declarations(&mut self) {
+        let mut registers = vec![];
+        for line in self.lines {
+            match &line.instruction {
+                Some(Instruction::Misc(Misc::Alias { name, target }))
+                    if target.parse::<Device>().is_ok() =>
+                {
+                    self.emit(0, format!("alias {} = {};", name, target));
+                }
+                Some(Instruction::Misc(Misc::Alias { .. })) => (),
+                Some(Instruction::Misc(Misc::Define { name, value })) => {
+                    self.emit(0, format!("const {} = {};", name, value));
+                }
+                Some(ins) => {
+                    for operand in ins.to_string().split_whitespace().skip(1) {
+                        if let Ok(register) = operand.parse::<Register>() {
+                            let name = self.name(register);
+                            if !matches!(register, Register::Ra | Register::Sp)
+                                && !registers.contains(&name)
+                            {
+                                registers.push(name);
+                            }
+                        }
+                    }
+                }
+                None => (),
+            }
+        }
+        for register in registers {
+            self.emit(0, format!("let {} = 0;", register));
+        }
+    }
+
+    /// Returns the target line of an unconditional jump to a fixed line.
+    fn jump_target(&self, idx: usize) -> Option<usize> {
+        match self.instruction(idx)? {
+            Instruction::FlowControl(FlowControl::Jump {
+                a: JumpDest::Number(n),
+            }) => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    /// Returns the condition and target line of a conditional branch.
+    fn branch(&self, idx: usize) -> Option<(Cond, usize)> {
+        let cond = |lhs: &RegisterOrNumber, op, rhs: String| Cond {
+            lhs: self.name(lhs),
+            op,
+            rhs,
+        };
+        let zero = || "0".to_string();
+        let (cond, target) = match self.instruction(idx)? {
+            Instruction::FlowControl(flow) => match flow {
+                FlowControl::BranchEqual { a, b, c } => (cond(a, "==", self.name(b)), c),
+                FlowControl::BranchNotEqual { a, b, c } => (cond(a, "!=", self.name(b)), c),
+                FlowControl::BranchGreaterThan { a, b, c } => (cond(a, ">", self.name(b)), c),
+                FlowControl::BranchGreaterOrEqual { a, b, c } => (cond(a, ">=", self.name(b)), c),
+                FlowControl::BranchLessThan { a, b, c } => (cond(a, "<", self.name(b)), c),
+                FlowControl::BranchLessOrEqual { a, b, c } => (cond(a, "<=", self.name(b)), c),
+                FlowControl::BranchEqualZero { a, b } => (cond(a, "==", zero()), b),
+                FlowControl::BranchNotEqualZero { a, b } => (cond(a, "!=", zero()), b),
+                FlowControl::BranchGreaterThanZero { a, b } => (cond(a, ">", zero()), b),
+                FlowControl::BranchGreaterOrEqualZero { a, b } => (cond(a, ">=", zero()), b),
+                FlowControl::BranchLessThanZero { a, b } => (cond(a, "<", zero()), b),
+                FlowControl::BranchLessOrEqualZero { a, b } => (cond(a, "<=", zero()), b),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match target {
+            RegisterOrNumber::Number(n) => Some((cond, *n as usize)),
+            RegisterOrNumber::Register(_) => None,
+        }
+    }
+
+    /// Emits the lines in `start..end`.
+    fn block(&mut self, start: usize, end: usize, indent: usize) {
+        let mut idx = start;
+        while idx < end {
+            // The last jump back to this line closes a loop.
+            if let Some(last) = (idx..end).rev().find(|&j| self.jump_target(j) == Some(idx)) {
+                self.emit(indent, "loop {");
+                self.block(idx, last, indent + 1);
+                self.emit(indent, "}");
+                idx = last + 1;
+                continue;
+            }
+            if let Some((cond, target)) = self.branch(idx) {
+                if target > idx && target <= end {
+                    // The branch skips the body, so the body runs when it is not taken.
+                    let cond = cond.negate();
+                    let else_end = self
+                        .jump_target(target - 1)
+                        .filter(|&e| e > target && e <= end && target - 1 > idx);
+                    self.emit(indent, format!("if {} {{", cond));
+                    match else_end {
+                        Some(else_end) => {
+                            self.block(idx + 1, target - 1, indent + 1);
+                            self.emit(indent, "} else {");
+                            self.block(target, else_end, indent + 1);
+                            idx = else_end;
+                        }
+                        None => {
+                            self.block(idx + 1, target, indent + 1);
+                            idx = target;
+                        }
+                    }
+                    self.emit(indent, "}");
+                    continue;
+                }
+            }
+            if let Some(line) = self.statement(idx) {
+                self.emit(indent, line);
+            }
+            idx += 1;
+        }
+    }
+
+    /// Translates a single instruction, keeping it as a comment when it has no equivalent.
+    fn statement(&mut self, idx: usize) -> Option<String> {
+        let line = &self.lines[idx];
+        let ins = line.instruction.as_ref()?;
+        let assign = |register: &Register, value: String| {
+            Some(format!("{} = {};", self.name(register), value))
+        };
+        let binary = |register: &Register, a: &RegisterOrNumber, op: &str, b: &RegisterOrNumber| {
+            assign(
+                register,
+                format!("{} {} {}", self.name(a), op, self.name(b)),
+            )
+        };
+        let unary = |register: &Register, a: &RegisterOrNumber, op: &str| {
+            assign(register, format!("{} {} 0", self.name(a), op))
+        };
+        let call = |register: &Register, function: &str, args: &[&RegisterOrNumber]| {
+            let args = args.iter().map(|a| self.name(*a)).collect::<Vec<_>>();
+            assign(register, format!("{}({})", function, args.join(", ")))
+        };
+        let statement = match ins {
+            Instruction::Misc(misc) => match misc {
+                Misc::Alias { .. } | Misc::Define { .. } | Misc::Label { .. } => return None,
+                Misc::Comment { comment } => Some(format!("// {}", comment)),
+                Misc::Move { register, a } => assign(register, self.name(a)),
+                Misc::Yield => Some("yield;".to_string()),
+                _ => None,
+            },
+            Instruction::DeviceIo(DeviceIo::LoadDeviceVariable {
+                register,
+                device,
+                variable,
+            }) => assign(register, format!("{}.{}", self.name(device), variable)),
+            Instruction::DeviceIo(DeviceIo::StoreDeviceVariable {
+                device,
+                variable,
+                register,
+            }) => Some(format!(
+                "{}.{} = {};",
+                self.name(device),
+                variable,
+                self.name(register)
+            )),
+            Instruction::DeviceIo(DeviceIo::LoadSlot {
+                register,
+                device,
+                slot,
+                variable,
+            }) => assign(
+                register,
+                format!(
+                    "load_slot({}, {}, {})",
+                    self.name(device),
+                    self.name(slot),
+                    variable
+                ),
+            ),
+            Instruction::DeviceIo(DeviceIo::StoreSlot {
+                device,
+                slot,
+                variable,
+                register,
+            }) => Some(format!(
+                "store_slot({}, {}, {}, {});",
+                self.name(device),
+                self.name(slot),
+                variable,
+                self.name(register)
+            )),
+            Instruction::DeviceIo(DeviceIo::LoadReagent {
+                register,
+                device,
+                reagent_mode,
+                reagent,
+            }) => assign(
+                register,
+                format!(
+                    "load_reagent({}, {}, {})",
+                    self.name(device),
+                    reagent_mode,
+                    self.name(reagent)
+                ),
+            ),
+            Instruction::DeviceIo(DeviceIo::LoadBatch {
+                register,
+                type_hash,
+                variable,
+                batch_mode,
+            }) => assign(
+                register,
+                format!(
+                    "load_batch({}, {}, {})",
+                    self.name(type_hash),
+                    variable,
+                    batch_mode
+                ),
+            ),
+            Instruction::DeviceIo(DeviceIo::StoreBatch {
+                type_hash,
+                variable,
+                register,
+            }) => Some(format!(
+                "store_batch({}, {}, {});",
+                self.name(type_hash),
+                variable,
+                self.name(register)
+            )),
+            Instruction::DeviceIo(DeviceIo::LoadBatchNamed {
+                register,
+                type_hash,
+                name_hash,
+                variable,
+                batch_mode,
+            }) => assign(
+                register,
+                format!(
+                    "load_batch_named({}, {}, {}, {})",
+                    self.name(type_hash),
+                    self.name(name_hash),
+                    variable,
+                    batch_mode
+                ),
+            ),
+            Instruction::DeviceIo(DeviceIo::StoreBatchNamed {
+                type_hash,
+                name_hash,
+                variable,
+                register,
+            }) => Some(format!(
+                "store_batch_named({}, {}, {}, {});",
+                self.name(type_hash),
+                self.name(name_hash),
+                variable,
+                self.name(register)
+            )),
+            Instruction::Arithmetic(arithmetic) => match arithmetic {
+                Arithmetic::Add { register, a, b } => binary(register, a, "+", b),
+                Arithmetic::Subtract { register, a, b } => binary(register, a, "-", b),
+                Arithmetic::Multiply { register, a, b } => binary(register, a, "*", b),
+                Arithmetic::Divide { register, a, b } => binary(register, a, "/", b),
+                Arithmetic::Mod { register, a, b } => binary(register, a, "%", b),
+                Arithmetic::AbsoluteValue { register, a } => call(register, "abs", &[a]),
+                Arithmetic::Minimum { register, a, b } => call(register, "min", &[a, b]),
+                Arithmetic::Maximum { register, a, b } => call(register, "max", &[a, b]),
+                Arithmetic::SquareRoot { register, a } => call(register, "sqrt", &[a]),
+                Arithmetic::Floor { register, a } => call(register, "floor", &[a]),
+                Arithmetic::Ceiling { register, a } => call(register, "ceil", &[a]),
+                Arithmetic::Round { register, a } => call(register, "round", &[a]),
+                Arithmetic::Truncate { register, a } => call(register, "trunc", &[a]),
+                Arithmetic::Sine { register, a } => call(register, "sin", &[a]),
+                Arithmetic::Cosine { register, a } => call(register, "cos", &[a]),
+                Arithmetic::Tangent { register, a } => call(register, "tan", &[a]),
+                Arithmetic::ArcSine { register, a } => call(register, "asin", &[a]),
+                Arithmetic::ArcCosine { register, a } => call(register, "acos", &[a]),
+                Arithmetic::ArcTangent { register, a } => call(register, "atan", &[a]),
+                Arithmetic::ArcTangent2 { register, a, b } => call(register, "atan2", &[a, b]),
+                _ => None,
+            },
+            Instruction::Stack(stack) => match stack {
+                Stack::Push { a } => Some(format!("push({});", self.name(a))),
+                Stack::Pop { register } => assign(register, "pop()".to_string()),
+                Stack::Peek { register } => assign(register, "peek()".to_string()),
+                // Arrays are only known by their addresses on the stack.
+                Stack::Get { .. } | Stack::Put { .. } => None,
+            },
+            Instruction::Logic(logic) => match logic {
+                Logic::And { register, a, b } => binary(register, a, "&", b),
+                Logic::Or { register, a, b } => binary(register, a, "|", b),
+                Logic::Xor { register, a, b } => binary(register, a, "^", b),
+                Logic::Nor { register, a, b } => {
+                    assign(register, format!("~({} | {})", self.name(a), self.name(b)))
+                }
+                Logic::ShiftLeftLogical { register, a, b } => binary(register, a, "<<", b),
+                Logic::ShiftRightLogical { register, a, b } => binary(register, a, ">>", b),
+            },
+            Instruction::VariableSelection(select) => match select {
+                VariableSelection::SelectEqual { register, a, b } => binary(register, a, "==", b),
+                VariableSelection::SelectDeviceSet { register, d } => {
+                    assign(register, format!("is_set({})", self.name(d)))
+                }
+                VariableSelection::SelectDeviceNotSet { register, d } => {
+                    assign(register, format!("!is_set({})", self.name(d)))
+                }
+                VariableSelection::SelectNotEqual { register, a, b } => {
+                    binary(register, a, "!=", b)
+                }
+                VariableSelection::SelectGreaterThan { register, a, b } => {
+                    binary(register, a, ">", b)
+                }
+                VariableSelection::SelectGreaterOrEqual { register, a, b } => {
+                    binary(register, a, ">=", b)
+                }
+                VariableSelection::SelectLessThan { register, a, b } => binary(register, a, "<", b),
+                VariableSelection::SelectLessOrEqual { register, a, b } => {
+                    binary(register, a, "<=", b)
+                }
+                VariableSelection::SelectEqualZero { register, a } => unary(register, a, "=="),
+                VariableSelection::SelectNotEqualZero { register, a } => unary(register, a, "!="),
+                VariableSelection::SelectGreaterThanZero { register, a } => unary(register, a, ">"),
+                VariableSelection::SelectGreaterOrEqualZero { register, a } => {
+                    unary(register, a, ">=")
+                }
+                VariableSelection::SelectLessThanZero { register, a } => unary(register, a, "<"),
+                VariableSelection::SelectNaN { register, a } => {
+                    assign(register, format!("isnan({})", self.name(a)))
+                }
+                VariableSelection::SelectNotNaN { register, a } => {
+                    assign(register, format!("!isnan({})", self.name(a)))
+                }
+                VariableSelection::SelectLessOrEqualZero { register, a } => {
+                    unary(register, a, "<=")
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let statement = match statement {
+            Some(statement) => statement,
+            None => {
+                self.dropped.push(idx);
+                format!("// unsupported: {}", ins)
+            }
+        };
+        match &line.comment {
+            Some(comment) => Some(format!("{} // {}", statement, comment)),
+            None => Some(statement),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ayysee_compiler::simulator::{Simulator, TickResult};
+    use ayysee_compiler::CompileOptions;
+    use stationeers_mips::types::DeviceVariable;
+    use std::path::Path;
+
+    fn lines(mips: &str) -> Vec<Line> {
+        match crate::disasm::parse(Path::new("test.ic10"), mips) {
+            Ok(lines) => lines,
+            Err(errors) => panic!("{}", errors[0]),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mips = "\
+move r1 0
+l r0 d0 Temperature
+sgt r2 r0 300
+beqz r2 8
+sub r3 r0 300
+mul r3 r3 2
+add r1 r1 r3
+j 9
+add r1 r1 1
+s d1 Setting r1
+yield
+j 1
+";
+        let decompiled = decompile(&lines(mips));
+        assert!(decompiled.dropped.is_empty(), "{}", decompiled.source);
+        let options = CompileOptions::default();
+        let recompiled =
+            crate::compile_program(Path::new("test.ayy"), &decompiled.source, &options).unwrap();
+
+        // Both programs write the same settings, tick after tick.
+        let mut original = Simulator::new(mips.parse().unwrap());
+        let mut lifted = Simulator::new(recompiled);
+        for temperature in [250.0, 310.0, 400.0, 290.0] {
+            for simulator in [&mut original, &mut lifted] {
+                simulator.write(Device::D0, DeviceVariable::Temperature, temperature);
+                assert_eq!(simulator.tick(), TickResult::Yield);
+            }
+            let setting =
+                |simulator: &Simulator| simulator.read(Device::D1, DeviceVariable::Setting);
+            assert_eq!(
+                setting(&lifted),
+                setting(&original),
+                "{}",
+                decompiled.source
+            );
+        }
+        assert_eq!(original.read(Device::D1, DeviceVariable::Setting), 222.0);
+    }
+
+    #[test]
+    fn test_dropped_instructions() {
+        // A branch back to an earlier line is not a shape the decompiler knows.
+        let decompiled = decompile(&lines("l r0 d0 Setting\nbnez r0 0\ns d1 Setting r0\n"));
+        assert_eq!(decompiled.dropped, [1]);
+        assert!(decompiled.source.contains("// unsupported: bnez r0 0"));
+    }
+}
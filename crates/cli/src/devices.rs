@@ -0,0 +1,124 @@
+use anyhow::Context;
+use serde::Deserialize;
+use stationeers_mips::types::Device;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// A list of the IC housings of a base and the devices wired to their pins, as exported by
+/// save editors, in TOML or JSON:
+///
+/// ```toml
+/// [[housing]]
+/// name = "Greenhouse"
+/// pins = { d0 = "Greenhouse Sensor", d1 = "Wall Heater" }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DeviceList {
+    #[serde(rename = "housing")]
+    pub housings: Vec<Housing>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Housing {
+    pub name: String,
+    /// The name of the device on each pin.
+    #[serde(default)]
+    pub pins: BTreeMap<String, String>,
+}
+
+impl DeviceList {
+    /// Parses a device list, as JSON when `path` ends in `.json` and as TOML otherwise.
+    pub fn parse(path: &Path, content: &str) -> anyhow::Result<Self> {
+        let list: Self = if path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str(content)?
+        } else {
+            toml::from_str(content)?
+        };
+        for housing in &list.housings {
+            for pin in housing.pins.keys() {
+                pin.parse::<Device>().map_err(|_| {
+                    anyhow::anyhow!(
+                        "`{}` of {} is not a device pin, expected d0 to d5 or db",
+                        pin,
+                        housing.name
+                    )
+                })?;
+            }
+        }
+        Ok(list)
+    }
+
+    /// Returns the housing with the given name, which may be omitted when there is only one.
+    pub fn housing(&self, name: Option<&str>) -> anyhow::Result<&Housing> {
+        let names = || {
+            self.housings
+                .iter()
+                .map(|h| h.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match name {
+            Some(name) => self
+                .housings
+                .iter()
+                .find(|h| h.name == name)
+                .with_context(|| {
+                    format!("no housing named `{}`, expected one of: {}", name, names())
+                }),
+            None => match self.housings.as_slice() {
+                [housing] => Ok(housing),
+                [] => anyhow::bail!("the device list has no housings"),
+                _ => anyhow::bail!("pick a housing with --housing: {}", names()),
+            },
+        }
+    }
+}
+
+impl Housing {
+    /// Returns a program declaring a constant for every device on the pins of the housing,
+    /// named after the device.
+    pub fn declarations(&self, source: &str) -> String {
+        let mut out = format!(
+            "// The devices of {}, generated from {} by `cli devices import`.\n\
+             // Run it again instead of editing this file when the wiring changes.\n",
+            self.name, source
+        );
+        let mut taken = HashSet::new();
+        for (pin, device) in &self.pins {
+            let base = identifier(device);
+            let mut name = base.clone();
+            let mut n = 2;
+            while !taken.insert(name.clone()) {
+                name = format!("{}_{}", base, n);
+                n += 1;
+            }
+            out.push_str(&format!("\n// {}\nconst {} = {};\n", device, name, pin));
+        }
+        out
+    }
+}
+
+/// Turns the name of a device into an identifier: `Wall Heater (2)` becomes `wall_heater_2`.
+fn identifier(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c.to_ascii_lowercase());
+        } else if !out.is_empty() && !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    let out = out.trim_end_matches('_');
+    // Names must start with a letter, and not be a keyword, device pin or logic type.
+    let valid = out.starts_with(|c: char| c.is_ascii_alphabetic())
+        && out.parse::<Device>().is_err()
+        && ayysee_parser::parse(&format!("const {} = d0;", out))
+            .is_ok_and(|program| ayysee_compiler::resolve::check(&program).is_ok());
+    if valid {
+        out.to_string()
+    } else {
+        format!("device_{}", out)
+    }
+}
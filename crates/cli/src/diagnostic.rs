@@ -0,0 +1,166 @@
+use ayysee_parser::ParseError;
+use std::ops::Range;
+use std::path::Path;
+
+/// An error tied to a location in a source file, rendered with the offending source line.
+#[derive(Debug)]
+pub(crate) struct Diagnostic {
+    /// Name of the file shown in the diagnostic.
+    pub file: String,
+    /// The full source of the file.
+    pub source: String,
+    pub message: String,
+    /// Byte range in `source` the diagnostic points at.
+    pub span: Option<Range<usize>>,
+    /// Extra information printed below the source snippet.
+    pub note: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(path: &Path, source: &str, message: impl Into<String>) -> Self {
+        let file = if path == Path::new("-") {
+            "<stdin>".to_string()
+        } else {
+            path.display().to_string()
+        };
+        Self {
+            file,
+            source: source.to_string(),
+            message: message.into(),
+            span: None,
+            note: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Range<usize>) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn from_parse_error(path: &Path, source: &str, error: ParseError<'_>) -> Self {
+        let (message, span, expected) = match error {
+            ParseError::InvalidToken { location } => {
+                ("invalid token".to_string(), location..location + 1, vec![])
+            }
+            ParseError::UnrecognizedEOF { location, expected } => (
+                "unexpected end of file".to_string(),
+                location..location,
+                expected,
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => (
+                format!("unexpected token `{}`", token),
+                start..end,
+                expected,
+            ),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => (format!("extra token `{}`", token), start..end, vec![]),
+            ParseError::User { error } => {
+                (error.to_string(), error.span.start..error.span.end, vec![])
+            }
+        };
+        let mut diagnostic = Self::new(path, source, message).with_span(span);
+        let mut names: Vec<String> = vec![];
+        for name in expected.iter().map(|terminal| describe(terminal)) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        diagnostic.note = match names.split_last() {
+            None => None,
+            Some((last, [])) => Some(format!("expected {}", last)),
+            Some((last, rest)) => Some(format!("expected one of {} or {}", rest.join(", "), last)),
+        };
+        diagnostic
+    }
+}
+
+/// Describes a token the parser expected: punctuation and keywords as written, and the
+/// tokens matched by a pattern by what they are.
+fn describe(terminal: &str) -> String {
+    if !terminal.starts_with('r') {
+        format!("`{}`", terminal.trim_matches('"'))
+    } else if terminal.contains("a-zA-Z") {
+        "an identifier".to_string()
+    } else if terminal.contains("0-9") || terminal.contains("[01]") {
+        "a number".to_string()
+    } else {
+        "a string".to_string()
+    }
+}
+
+/// Returns the 1-based line and column of the byte `offset` in `source`.
+pub(crate) fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let col = before[line_start..].chars().count() + 1;
+    (line, col)
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        let span = match &self.span {
+            Some(span) => span,
+            None => return write!(f, " --> {}", self.file),
+        };
+        let (line, col) = line_col(&self.source, span.start);
+        writeln!(f, " --> {}:{}:{}", self.file, line, col)?;
+
+        let text = self.source.lines().nth(line - 1).unwrap_or_default();
+        let gutter = " ".repeat(line.to_string().len());
+        // Underline the span, but never past the end of the first line.
+        let width = self.source[span.start.min(self.source.len())..span.end.min(self.source.len())]
+            .lines()
+            .next()
+            .map(|s| s.chars().count())
+            .unwrap_or(0)
+            .max(1);
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line, text)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(col - 1),
+            "^".repeat(width)
+        )?;
+        if let Some(note) = &self.note {
+            write!(f, "\n{} = note: {}", gutter, note)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Several diagnostics reported at once, such as all the syntax errors of a file.
+#[derive(Debug)]
+pub(crate) struct Diagnostics(pub Vec<Diagnostic>);
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// Returns the diagnostics carried by the error, empty when it has no location.
+pub(crate) fn of(e: &anyhow::Error) -> Vec<&Diagnostic> {
+    if let Some(diagnostics) = e.downcast_ref::<Diagnostics>() {
+        diagnostics.0.iter().collect()
+    } else {
+        e.downcast_ref::<Diagnostic>().into_iter().collect()
+    }
+}
@@ -0,0 +1,144 @@
+use crate::diagnostic::Diagnostic;
+use stationeers_mips::instructions::{Instruction, Misc};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A line of MIPS code with its names resolved.
+pub(crate) struct Line {
+    /// The parsed instruction, `None` for empty lines.
+    pub instruction: Option<Instruction>,
+    /// The names replaced in this line, with what they resolved to.
+    pub resolved: Vec<(String, String)>,
+    /// The comment at the end of the line.
+    pub comment: Option<String>,
+}
+
+/// Parses MIPS code as written in the game, resolving `alias`, `define` and label names
+/// in operands. Returns one entry per source line, or a diagnostic for every invalid line.
+pub(crate) fn parse(path: &Path, source: &str) -> Result<Vec<Line>, Vec<Diagnostic>> {
+    let mut names = HashMap::<&str, String>::new();
+    for (idx, line) in source.lines().enumerate() {
+        let code = strip_comment(line).0;
+        let mut words = code.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("alias" | "define"), Some(name), Some(value)) => {
+                names.insert(name, value.to_string());
+            }
+            (Some(label), None, None) if label.ends_with(':') => {
+                names.insert(label.trim_end_matches(':'), idx.to_string());
+            }
+            _ => (),
+        }
+    }
+
+    let mut lines = vec![];
+    let mut errors = vec![];
+    let mut offset = 0;
+    for line in source.lines() {
+        let start = offset;
+        offset += line.len() + 1;
+        let (code, comment) = strip_comment(line);
+        let mut words = code.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => {
+                lines.push(Line {
+                    instruction: comment.map(|comment| {
+                        Misc::Comment {
+                            comment: comment.to_string(),
+                        }
+                        .into()
+                    }),
+                    resolved: vec![],
+                    comment: None,
+                });
+                continue;
+            }
+        };
+        // The name declared by an alias or define must stay as written.
+        let keep = if matches!(command, "alias" | "define") {
+            1
+        } else {
+            0
+        };
+        let mut resolved = vec![];
+        let mut operands = vec![command.to_string()];
+        for (i, word) in words.enumerate() {
+            match names.get(word) {
+                Some(value) if i >= keep => {
+                    resolved.push((word.to_string(), value.clone()));
+                    operands.push(value.clone());
+                }
+                _ => operands.push(word.to_string()),
+            }
+        }
+        match operands.join(" ").parse::<Instruction>() {
+            Ok(instruction) => lines.push(Line {
+                instruction: Some(instruction),
+                resolved,
+                comment: comment.map(str::to_string),
+            }),
+            Err(_) => errors.push(
+                Diagnostic::new(
+                    path,
+                    source,
+                    format!("invalid instruction `{}`", code.trim()),
+                )
+                .with_span(start..start + line.len()),
+            ),
+        }
+    }
+    if errors.is_empty() {
+        Ok(lines)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Splits a line into its code and the text of its `#` comment.
+fn strip_comment(line: &str) -> (&str, Option<&str>) {
+    match line.split_once('#') {
+        Some((code, comment)) => (code, Some(comment.trim())),
+        None => (line, None),
+    }
+}
+
+/// Renders the parsed lines with line numbers, annotating every resolved name.
+pub(crate) fn render(lines: &[Line]) -> String {
+    let text = lines
+        .iter()
+        .map(|line| {
+            line.instruction
+                .as_ref()
+                .map(Instruction::to_string)
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    let width = text.iter().map(String::len).max().unwrap_or(0);
+    let number_width = lines.len().saturating_sub(1).to_string().len();
+
+    let mut out = String::new();
+    for (idx, (line, text)) in lines.iter().zip(&text).enumerate() {
+        let mut notes = line
+            .resolved
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect::<Vec<_>>();
+        notes.extend(line.comment.clone());
+        let row = if notes.is_empty() {
+            format!("{:>nw$}  {}", idx, text, nw = number_width)
+        } else {
+            format!(
+                "{:>nw$}  {:<w$}  # {}",
+                idx,
+                text,
+                notes.join(", "),
+                nw = number_width,
+                w = width
+            )
+        };
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+    out
+}
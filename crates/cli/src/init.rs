@@ -0,0 +1,83 @@
+use crate::project::MANIFEST_FILE;
+use anyhow::Context;
+use std::path::Path;
+
+const MANIFEST: &str = r#"out_dir = "out"
+include = ["src/constants.ayy"]
+
+[[program]]
+name = "main"
+source = "src/main.ayy"
+scenarios = ["scenarios/main.toml"]
+"#;
+
+const CONSTANTS: &str = "\
+// Included in every program of the project, see `include` in galvanic.toml.
+const MIN_TEMPERATURE = 293.15;
+const MAX_TEMPERATURE = 303.15;
+";
+
+const PROGRAM: &str = "\
+// Keeps a room between MIN_TEMPERATURE and MAX_TEMPERATURE with a wall heater.
+const sensor = d0;
+const heater = d1;
+
+loop {
+    let temperature = load(sensor, Temperature);
+    if temperature < MIN_TEMPERATURE {
+        store(heater, On, 1);
+    }
+    if temperature > MAX_TEMPERATURE {
+        store(heater, On, 0);
+    }
+    yield;
+}
+";
+
+const SCENARIO: &str = "\
+# Run by `cli test`: the room starts warm enough and cools down on the second tick.
+ticks = 3
+
+[devices]
+d0 = { Temperature = 298.0 }
+d1 = { On = 0 }
+
+[[inputs]]
+tick = 2
+d0 = { Temperature = 280.0 }
+
+# Device states expected after the last tick.
+[expect]
+d1 = { On = 1 }
+";
+
+/// The files of a new project, relative to its root.
+const FILES: &[(&str, &str)] = &[
+    (MANIFEST_FILE, MANIFEST),
+    ("src/constants.ayy", CONSTANTS),
+    ("src/main.ayy", PROGRAM),
+    ("scenarios/main.toml", SCENARIO),
+];
+
+/// Creates a new project in `root` with a manifest, an example program, shared constants
+/// and a simulation scenario. Existing files are never overwritten.
+pub(crate) async fn init(root: &Path) -> anyhow::Result<()> {
+    for (name, _) in FILES {
+        let path = root.join(name);
+        anyhow::ensure!(
+            !path.exists(),
+            "{} already exists, refusing to overwrite it",
+            path.display()
+        );
+    }
+    for (name, content) in FILES {
+        let path = root.join(name);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, content)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use crate::project::Source;
+use crate::world::{self, DeviceState, World};
+use anyhow::Context;
+use ayysee_compiler::simulator::Simulator;
+use ayysee_compiler::CompileOptions;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the comment lines that hold test annotations.
+const ANNOTATION: &str = "//!";
+
+/// A test embedded in the program source.
+///
+/// A test starts with a `//! test <name>` line, followed by `//!` lines holding a TOML
+/// world description (see [`World`]) plus an `[expect]` table with the expected device
+/// states after the simulation:
+///
+/// ```text
+/// //! test heats_when_cold
+/// //! [devices]
+/// //! d0 = { Temperature = 280 }
+/// //! [expect]
+/// //! d1 = { On = 1 }
+/// ```
+#[derive(Debug)]
+pub(crate) struct InlineTest {
+    pub name: String,
+    pub world: World,
+    pub expect: BTreeMap<String, DeviceState>,
+}
+
+/// The tests of a single program.
+pub(crate) struct Suite {
+    pub path: PathBuf,
+    pub source: Source,
+    /// Tests loaded from scenario files, run in addition to the inline tests.
+    pub scenarios: Vec<InlineTest>,
+    pub options: CompileOptions,
+}
+
+/// Extracts all inline tests from the program source.
+pub(crate) fn extract(source: &str) -> anyhow::Result<Vec<InlineTest>> {
+    let mut blocks: Vec<(String, String)> = vec![];
+    let mut current: Option<(String, String)> = None;
+    for line in source.lines() {
+        let annotation = line.trim_start().strip_prefix(ANNOTATION);
+        match annotation {
+            Some(body) => {
+                let body = body.trim();
+                if let Some(name) = body.strip_prefix("test ") {
+                    blocks.extend(current.take());
+                    current = Some((name.trim().to_string(), String::new()));
+                } else if let Some((_, toml)) = &mut current {
+                    toml.push_str(body);
+                    toml.push('\n');
+                }
+            }
+            None => blocks.extend(current.take()),
+        }
+    }
+    blocks.extend(current);
+
+    blocks
+        .into_iter()
+        .map(|(name, toml)| {
+            parse_test(&name, &toml).map_err(|e| anyhow::anyhow!("invalid test `{}`: {}", name, e))
+        })
+        .collect()
+}
+
+fn parse_test(name: &str, toml: &str) -> anyhow::Result<InlineTest> {
+    let mut table: toml::Table = toml::from_str(toml)?;
+    let expect = match table.remove("expect") {
+        Some(expect) => expect.try_into()?,
+        None => BTreeMap::default(),
+    };
+    let world = table.try_into()?;
+    Ok(InlineTest {
+        name: name.to_string(),
+        world,
+        expect,
+    })
+}
+
+impl InlineTest {
+    /// Loads a scenario file, which has the same contents as an inline test. The test is
+    /// named after the file.
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        parse_test(&name, &content)
+            .map_err(|e| anyhow::anyhow!("invalid scenario {}: {}", path.display(), e))
+    }
+
+    /// Runs the test against the compiled program, returning the list of mismatches.
+    pub fn run(&self, program: stationeers_mips::Program) -> anyhow::Result<Vec<String>> {
+        let mut simulator = Simulator::new(program);
+        self.world.run(&mut simulator)?;
+        let actual = world::snapshot(&simulator);
+
+        let mut failures = vec![];
+        for (device, vars) in &self.expect {
+            for (variable, expected) in vars {
+                let value = actual
+                    .get(device)
+                    .and_then(|d| d.get(variable))
+                    .copied()
+                    .unwrap_or_default();
+                if (value - expected).abs() > f64::EPSILON {
+                    failures.push(format!(
+                        "{}.{}: expected {}, got {}",
+                        device, variable, expected, value
+                    ));
+                }
+            }
+        }
+        Ok(failures)
+    }
+}
@@ -0,0 +1,121 @@
+use crate::diagnostic::{self, Diagnostic};
+use crate::stats::Stats;
+use crate::world::TickReport;
+use serde::Serialize;
+use std::path::Path;
+
+/// The result of a command in `--format json` mode.
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct Report {
+    pub success: bool,
+    pub diagnostics: Vec<JsonDiagnostic>,
+    /// The syntax tree of the program, for `--output ast`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ast: Option<ayysee_parser::ast::Program>,
+    /// The concrete syntax tree of the program, for `--output cst`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cst: Option<ayysee_parser::cst::Node>,
+    /// The compiled MIPS program.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+    /// The formatted program, for the `/format` endpoint of `cli serve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    /// The simulated ticks, for the `/simulate` endpoint of `cli serve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ticks: Option<Vec<TickReport>>,
+}
+
+/// A diagnostic with its location resolved to 1-based lines and columns.
+#[derive(Serialize, Debug)]
+pub(crate) struct JsonDiagnostic {
+    pub severity: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    fn new(source: &str, offset: usize) -> Self {
+        let (line, column) = diagnostic::line_col(source, offset);
+        Self { line, column }
+    }
+}
+
+impl JsonDiagnostic {
+    /// Converts the error into one JSON diagnostic per diagnostic it carries.
+    pub fn all(e: &anyhow::Error) -> Vec<Self> {
+        let diagnostics = diagnostic::of(e);
+        if diagnostics.is_empty() {
+            return vec![Self {
+                severity: "error",
+                message: format!("{:#}", e),
+                file: None,
+                start: None,
+                end: None,
+                note: None,
+            }];
+        }
+        diagnostics.into_iter().map(Self::from).collect()
+    }
+}
+
+impl From<&Diagnostic> for JsonDiagnostic {
+    fn from(d: &Diagnostic) -> Self {
+        Self {
+            severity: "error",
+            message: d.message.clone(),
+            file: Some(d.file.clone()),
+            start: d.span.as_ref().map(|s| Position::new(&d.source, s.start)),
+            end: d.span.as_ref().map(|s| Position::new(&d.source, s.end)),
+            note: d.note.clone(),
+        }
+    }
+}
+
+/// Returned once a failure has already been printed (e.g. as JSON), so it is not reported
+/// twice. Holds the exit code of the original error.
+#[derive(Debug)]
+pub(crate) struct Reported(pub i32);
+
+impl std::fmt::Display for Reported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the error was already reported")
+    }
+}
+
+impl std::error::Error for Reported {}
+
+/// Writes the report, or a failed report holding the error, as JSON to `out`.
+pub(crate) fn emit(out: &Path, result: anyhow::Result<Report>) -> anyhow::Result<()> {
+    let (report, exit_code) = match result {
+        Ok(report) => (report, None),
+        Err(e) => {
+            let report = Report {
+                success: false,
+                diagnostics: JsonDiagnostic::all(&e),
+                ..Default::default()
+            };
+            (report, Some(crate::exit_code(&e)))
+        }
+    };
+    crate::write_output(out, &(serde_json::to_string_pretty(&report)? + "\n"))?;
+    match exit_code {
+        Some(code) => Err(Reported(code).into()),
+        None => Ok(()),
+    }
+}
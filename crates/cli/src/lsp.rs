@@ -0,0 +1,609 @@
+use crate::diagnostic;
+use ayysee_compiler::import::{self, ImportErrors};
+use ayysee_compiler::incremental::Session;
+use ayysee_compiler::resolve::SymbolTable;
+use ayysee_compiler::resolve::{self, ScopeId, SemanticError, SemanticErrors, SymbolKind};
+use ayysee_compiler::{CompileOptions, DebugInfo};
+use ayysee_parser::ast::{Program, Span};
+use ayysee_parser::format::FormatConfig;
+use ayysee_parser::incremental::{self, Edit};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Runs a Language Server Protocol server over stdin/stdout until the client sends `exit`.
+///
+/// Supports diagnostics on open/change, go-to-definition and hover for identifiers,
+/// whole-document formatting, semantic tokens, and inlay hints showing the register of each
+/// variable and the number of lines of the compiled program.
+pub(crate) async fn serve() -> anyhow::Result<()> {
+    // A panic in the compiler must not take down the whole server, it is reported as a
+    // diagnostic instead.
+    std::panic::set_hook(Box::new(|info| tracing::error!("{}", info)));
+
+    let mut stdin = BufReader::new(tokio::io::stdin());
+    let mut stdout = tokio::io::stdout();
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut stdin).await? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for response in server.handle(&message) {
+            write_message(&mut stdout, &response).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn read_message<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> anyhow::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let length = length.ok_or_else(|| anyhow::anyhow!("message without Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Value,
+) -> anyhow::Result<()> {
+    let body = serde_json::to_string(message)?;
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes())
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Server {
+    /// The open documents, keyed by their URI.
+    documents: HashMap<String, Document>,
+}
+
+struct Document {
+    text: String,
+    /// The syntax tree of the text, `None` while it has syntax errors.
+    program: Option<Program>,
+    /// Compiles the document again only when its code changed.
+    session: Session,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let program = ayysee_parser::parse(&text).ok();
+        Self {
+            text,
+            program,
+            session: Session::default(),
+        }
+    }
+
+    /// Compiles the document, which must parse, with the files it imports if it is a file.
+    fn compile(
+        &mut self,
+        uri: &str,
+        options: &CompileOptions,
+    ) -> Option<anyhow::Result<(stationeers_mips::Program, DebugInfo)>> {
+        let mut program = self.program.clone()?;
+        let compiled = match file_path(uri) {
+            Some(path) => import::expand(&mut program, &path, options).map_err(imported),
+            None => Ok(()),
+        }
+        .and_then(|()| self.session.compile(program, options));
+        Some(compiled.map_err(|e| crate::compile_error(Path::new(uri), &self.text, e)))
+    }
+
+    /// Applies a change sent by the client, reparsing only the statements it touches.
+    fn change(&mut self, change: &Value) {
+        let text = change["text"].as_str().unwrap_or_default();
+        let range = &change["range"];
+        let span = offset(&self.text, &range["start"]).zip(offset(&self.text, &range["end"]));
+        let Some((start, end)) = span else {
+            // Without a range, the change holds the whole text.
+            self.text = text.to_string();
+            self.program = ayysee_parser::parse(&self.text).ok();
+            return;
+        };
+        let edit = Edit {
+            span: Span::new(start, end),
+            text: text.to_string(),
+        };
+        self.text = edit.apply(&self.text);
+        self.program = match self.program.take() {
+            Some(program) => incremental::reparse(program, &edit, &self.text).ok(),
+            None => ayysee_parser::parse(&self.text).ok(),
+        };
+    }
+}
+
+/// Returns the path of a `file://` URI, percent-decoded.
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    // The host, if any, is the local machine.
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // Windows paths are written `file:///C:/...`.
+    let drive = path.as_bytes().get(1..3);
+    match drive {
+        Some([letter, b':']) if letter.is_ascii_alphabetic() => Some(PathBuf::from(&path[1..])),
+        _ => Some(PathBuf::from(path)),
+    }
+}
+
+/// Points the errors of an imported file at the `import` of the document, as editors only
+/// show the diagnostics of a document in it.
+fn imported(e: anyhow::Error) -> anyhow::Error {
+    let Some(errors) = e.downcast_ref::<ImportErrors>() else {
+        return e;
+    };
+    let errors = errors
+        .errors
+        .iter()
+        .map(|error| {
+            let (line, column) = diagnostic::line_col(&errors.source, error.span.start);
+            SemanticError {
+                message: format!(
+                    "{}:{}:{}: {}",
+                    errors.path.display(),
+                    line,
+                    column,
+                    error.message
+                ),
+                span: errors.import,
+            }
+        })
+        .collect();
+    SemanticErrors(errors).into()
+}
+
+impl Server {
+    /// Handles a single request or notification, returning the messages to send back.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 2,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentFormattingProvider": true,
+                    "semanticTokensProvider": {
+                        "legend": {
+                            "tokenTypes": TOKEN_TYPES,
+                            "tokenModifiers": TOKEN_MODIFIERS,
+                        },
+                        "full": true,
+                    },
+                    "inlayHintProvider": true,
+                },
+                "serverInfo": { "name": "galvanic" },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents
+                    .insert(uri.to_string(), Document::new(text.to_string()));
+                return vec![self.publish_diagnostics(uri)];
+            }
+            "textDocument/didChange" => {
+                if let (Some(document), Some(changes)) = (
+                    self.documents.get_mut(uri),
+                    params["contentChanges"].as_array(),
+                ) {
+                    for change in changes {
+                        document.change(change);
+                    }
+                }
+                return vec![self.publish_diagnostics(uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )];
+            }
+            "textDocument/definition" => self.definition(uri, &params["position"]),
+            "textDocument/hover" => self.hover(uri, &params["position"]),
+            "textDocument/formatting" => self.format(uri, &params["options"]),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(uri),
+            "textDocument/inlayHint" => self.inlay_hints(uri),
+            _ => {
+                // Unknown notifications are ignored, unknown requests get an error.
+                if message.get("id").is_none() {
+                    return vec![];
+                }
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": { "code": -32601, "message": "method not found" },
+                })];
+            }
+        };
+        match message.get("id") {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => vec![],
+        }
+    }
+
+    fn source(&self, uri: &str) -> &str {
+        self.documents
+            .get(uri)
+            .map(|document| document.text.as_str())
+            .unwrap_or_default()
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> Value {
+        let compiled = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let Some(document) = self.documents.get_mut(uri) else {
+                return Ok(());
+            };
+            match document.compile(uri, &CompileOptions::default()) {
+                Some(compiled) => compiled.map(|_| ()),
+                // Report the syntax errors.
+                None => crate::parse_source(Path::new(uri), &document.text).map(|_| ()),
+            }
+        }));
+        let source = self.source(uri);
+        let diagnostics = match compiled {
+            Ok(Ok(_)) => vec![],
+            Ok(Err(e)) => {
+                let diagnostics = diagnostic::of(&e);
+                if diagnostics.is_empty() {
+                    vec![lsp_diagnostic(format!("{:#}", e), range(source, 0, 0))]
+                } else {
+                    diagnostics
+                        .into_iter()
+                        .map(|d| {
+                            let span = d.span.clone().unwrap_or(0..0);
+                            let message = match &d.note {
+                                Some(note) => format!("{}\n{}", d.message, note),
+                                None => d.message.clone(),
+                            };
+                            lsp_diagnostic(message, range(source, span.start, span.end))
+                        })
+                        .collect()
+                }
+            }
+            Err(_) => vec![lsp_diagnostic(
+                "internal compiler error".to_string(),
+                range(source, 0, 0),
+            )],
+        };
+        notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    /// Resolves the names of a document, if it parses.
+    fn symbols(&self, uri: &str) -> Option<SymbolTable> {
+        let program = self.documents.get(uri)?.program.as_ref()?;
+        Some(resolve::resolve(program).0)
+    }
+
+    fn definition(&self, uri: &str, position: &Value) -> Value {
+        let source = self.source(uri);
+        let declaration = offset(source, position).and_then(|offset| {
+            let table = self.symbols(uri)?;
+            let (_, id) = table.symbol_at(offset)?;
+            table.symbol(id).declaration
+        });
+        match declaration {
+            Some(span) => json!({
+                "uri": uri,
+                "range": range(source, span.start, span.end),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn hover(&self, uri: &str, position: &Value) -> Value {
+        let source = self.source(uri);
+        let (table, (span, id)) = match offset(source, position).and_then(|offset| {
+            let table = self.symbols(uri)?;
+            let found = table.symbol_at(offset)?;
+            Some((table, found))
+        }) {
+            Some(found) => found,
+            None => return Value::Null,
+        };
+        let symbol = table.symbol(id);
+        let contents = match (&symbol.kind, symbol.declaration) {
+            (SymbolKind::External, _) => match table.device(id) {
+                Some(device) => format!("device `{}`", device),
+                None => format!("logic type `{}`", symbol.name),
+            },
+            (SymbolKind::Builtin { parameters }, _) => {
+                format!(
+                    "builtin function `{}` taking {} arguments",
+                    symbol.name, parameters
+                )
+            }
+            (_, Some(declaration)) => {
+                let line = line_at(source, declaration.start);
+                let mut contents = format!("```ayysee\n{}\n```", line);
+                if let Some(device) = table.device(id) {
+                    contents.push_str(&format!("\n\nresolves to device `{}`", device));
+                }
+                if let Some(doc) = &symbol.doc {
+                    contents.push_str(&format!("\n\n{}", doc));
+                }
+                contents
+            }
+            (_, None) => return Value::Null,
+        };
+        json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": range(source, span.start, span.end),
+        })
+    }
+
+    /// Classifies every name of the document, in the relative encoding of the protocol.
+    fn semantic_tokens(&self, uri: &str) -> Value {
+        let source = self.source(uri);
+        let Some(table) = self.symbols(uri) else {
+            return Value::Null;
+        };
+        let mut references = table.references.clone();
+        references.sort_by_key(|(span, _)| span.start);
+        references.dedup_by_key(|(span, _)| span.start);
+        let mut data = vec![];
+        let (mut last_line, mut last_col) = (0, 0);
+        for (span, id) in references {
+            let symbol = table.symbol(id);
+            let device = table.device(id).is_some();
+            let (token_type, mut modifiers) = match symbol.kind {
+                SymbolKind::Variable | SymbolKind::Array { .. } => (0, 0),
+                SymbolKind::Parameter => (1, 0),
+                SymbolKind::Constant if device => (3, READONLY),
+                SymbolKind::Constant => (0, READONLY),
+                SymbolKind::Function { .. } => (2, 0),
+                SymbolKind::Builtin { .. } => (2, DEFAULT_LIBRARY),
+                SymbolKind::External if device => (3, READONLY | DEFAULT_LIBRARY),
+                SymbolKind::External => (4, READONLY | DEFAULT_LIBRARY),
+            };
+            if symbol.declaration == Some(span) {
+                modifiers |= DECLARATION;
+            }
+            let (line, col) = line_character(source, span.start);
+            let delta_col = if line == last_line {
+                col - last_col
+            } else {
+                col
+            };
+            let length = utf16_len(&source[span.start..span.end]);
+            data.extend([line - last_line, delta_col, length]);
+            data.extend([token_type, modifiers as usize]);
+            (last_line, last_col) = (line, col);
+        }
+        json!({ "data": data })
+    }
+
+    /// Shows the registers each variable of the program ends up in, and the number of lines
+    /// of the compiled program at the end of the first line.
+    fn inlay_hints(&mut self, uri: &str) -> Value {
+        let Some(table) = self.symbols(uri) else {
+            return json!([]);
+        };
+        let options = CompileOptions::default();
+        let compiled = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            self.documents.get_mut(uri)?.compile(uri, &options)?.ok()
+        }));
+        let Ok(Some((mips, debug_info))) = compiled else {
+            return json!([]);
+        };
+        let source = self.source(uri);
+        let mut hints = vec![];
+        for symbol in &table.symbols {
+            // The variables of functions are inlined into their callers.
+            let Some(declaration) = symbol.declaration else {
+                continue;
+            };
+            if symbol.kind != SymbolKind::Variable || symbol.scope != ScopeId(0) {
+                continue;
+            }
+            let label = match debug_info.registers.get(&symbol.name) {
+                Some(registers) => registers
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                None => "inlined".to_string(),
+            };
+            hints.push(json!({
+                "position": position(source, declaration.end),
+                "label": format!(": {}", label),
+                "kind": 1,
+            }));
+        }
+        let first_line_end = source.find('\n').unwrap_or(source.len());
+        hints.push(json!({
+            "position": position(source, first_line_end),
+            "label": format!("{} of {} lines", mips.instructions.len(), options.target.max_lines),
+            "paddingLeft": true,
+        }));
+        json!(hints)
+    }
+
+    fn format(&self, uri: &str, options: &Value) -> Value {
+        let source = self.source(uri);
+        let mut config = FormatConfig::default();
+        if let Some(tab_size) = options["tabSize"].as_u64() {
+            config.indent_width = tab_size as usize;
+        }
+        let formatted = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let program = crate::parse_source(Path::new(uri), source)?;
+            ayysee_parser::format::format(program, source, &config)
+        }));
+        match formatted {
+            Ok(Ok(formatted)) => json!([{
+                "range": range(source, 0, source.len()),
+                "newText": formatted,
+            }]),
+            _ => Value::Null,
+        }
+    }
+}
+
+/// The semantic token types, indexed by the tokens sent to the client. Device pins (and
+/// constants bound to them) are namespaces, since their logic types are read through them.
+const TOKEN_TYPES: &[&str] = &["variable", "parameter", "function", "namespace", "property"];
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "readonly", "defaultLibrary"];
+
+const DECLARATION: u32 = 1;
+const READONLY: u32 = 1 << 1;
+const DEFAULT_LIBRARY: u32 = 1 << 2;
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// An LSP error diagnostic.
+fn lsp_diagnostic(message: String, range: Value) -> Value {
+    json!({
+        "range": range,
+        "severity": 1,
+        "source": "galvanic",
+        "message": message,
+    })
+}
+
+/// Converts a byte range of `source` into an LSP range.
+fn range(source: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position(source, start), "end": position(source, end) })
+}
+
+fn position(source: &str, offset: usize) -> Value {
+    let (line, character) = line_character(source, offset);
+    json!({ "line": line, "character": character })
+}
+
+/// Returns the line and character of a byte offset of `source`, from 0. Characters count
+/// UTF-16 code units, as LSP clients expect by default.
+fn line_character(source: &str, offset: usize) -> (usize, usize) {
+    let (line, _) = diagnostic::line_col(source, offset);
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    (line - 1, utf16_len(&source[line_start..offset]))
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Converts an LSP position into a byte offset of `source`.
+fn offset(source: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        source.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let text = source[line_start..].lines().next().unwrap_or_default();
+    let mut units = 0;
+    let column = text
+        .char_indices()
+        .find(|(_, c)| {
+            let past = units >= character;
+            units += c.len_utf16();
+            past
+        })
+        .map_or(text.len(), |(i, _)| i);
+    Some(line_start + column)
+}
+
+/// Returns the trimmed line containing the byte `offset`.
+fn line_at(source: &str, offset: usize) -> &str {
+    let start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let end = source[offset..]
+        .find('\n')
+        .map(|i| offset + i)
+        .unwrap_or(source.len());
+    source[start..end].trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_uris_are_percent_decoded() {
+        let path = |uri| file_path(uri).map(|path| path.to_string_lossy().into_owned());
+        assert_eq!(
+            path("file:///home/a%20b/main.ayy").as_deref(),
+            Some("/home/a b/main.ayy")
+        );
+        assert_eq!(
+            path("file:///tmp/%C3%A9t%C3%A9.ayy").as_deref(),
+            Some("/tmp/été.ayy")
+        );
+        assert_eq!(
+            path("file://localhost/tmp/x.ayy").as_deref(),
+            Some("/tmp/x.ayy")
+        );
+        assert_eq!(
+            path("file:///c%3A/src/x.ayy").as_deref(),
+            Some("c:/src/x.ayy")
+        );
+        assert_eq!(
+            path("file:///C:/src/x.ayy").as_deref(),
+            Some("C:/src/x.ayy")
+        );
+        assert_eq!(path("untitled:Untitled-1"), None);
+    }
+
+    #[test]
+    fn test_positions_count_utf16() {
+        // The emoji takes 4 bytes and 2 UTF-16 code units, `é` 2 bytes and 1 unit.
+        let source = "let a = 1;\n// 🔥é\nlet b = a; // 🔥\n";
+        let b = source.find("b =").unwrap();
+        assert_eq!(position(source, b), json!({ "line": 2, "character": 4 }));
+        let after = source.find("é").unwrap() + "é".len();
+        assert_eq!(
+            position(source, after),
+            json!({ "line": 1, "character": 6 })
+        );
+        for offset in [b, after, source.rfind("🔥").unwrap(), source.len()] {
+            assert_eq!(
+                super::offset(source, &position(source, offset)),
+                Some(offset)
+            );
+        }
+    }
+}
@@ -1,51 +1,648 @@
-use crate::commands::Commands;
-use ayysee_compiler::generate_program;
-use ayysee_parser::grammar::ProgramParser;
+use crate::commands::{Commands, DevicesCommand, GameDataCommand, GrammarCommand, OutputFormat};
+use anyhow::Context;
+use ayysee_compiler::import::ImportErrors;
+use ayysee_compiler::ir::generate_program_with_debug_info;
+use ayysee_compiler::resolve::SemanticErrors;
+use ayysee_compiler::simulator::Simulator;
+use ayysee_compiler::{CompileOptions, DebugInfo};
 use clap::Parser;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
 
+mod bench;
+mod cache;
+mod clipboard;
 mod commands;
+mod config;
+mod debug;
+mod decompile;
+mod devices;
+mod diagnostic;
+mod disasm;
+mod init;
+mod inline_test;
+mod json;
+mod lsp;
+mod project;
+mod repl;
+mod server;
+mod stats;
+mod template;
+mod world;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+/// Exit code for programs that fail to parse or compile, and other invalid input.
+const EXIT_COMPILE_ERROR: i32 = 1;
+/// Exit code for files that could not be read or written.
+const EXIT_IO_ERROR: i32 = 2;
+/// Exit code for bugs in galvanic itself.
+const EXIT_INTERNAL_ERROR: i32 = 3;
+
+/// Set by `--quiet` to suppress progress messages.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints a progress message to stderr, unless `--quiet` was given.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
 
+#[tokio::main]
+async fn main() {
     let args = commands::Args::parse();
-    match args.command {
-        Commands::Compile { file, output } => {
-            let file_contents = tokio::fs::read_to_string(file).await.unwrap();
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    // Logs go to stderr, so that compiled programs (and the language server protocol) can be
+    // piped from stdout.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(args.log_level())
+        .init();
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        eprintln!(
+            "error: internal compiler error, please report it with the program that caused it"
+        );
+        std::process::exit(EXIT_INTERNAL_ERROR);
+    }));
 
-            let parser = ProgramParser::new();
+    if let Err(e) = run(args).await {
+        if let Some(json::Reported(code)) = e.downcast_ref() {
+            std::process::exit(*code);
+        }
+        report(&e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+/// Prints the error to stderr, rendering diagnostics with their source.
+fn report(e: &anyhow::Error) {
+    eprintln!("{}", render(e));
+}
 
-            let parsed = parser.parse(&file_contents).unwrap();
+/// Returns the diagnostics of the error as [`report`] prints them.
+fn render(e: &anyhow::Error) -> String {
+    let diagnostics = diagnostic::of(e);
+    if diagnostics.is_empty() {
+        return format!("error: {:#}", e);
+    }
+    diagnostics
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Returns the process exit code for the error.
+fn exit_code(e: &anyhow::Error) -> i32 {
+    if e.chain().any(|cause| cause.is::<std::io::Error>()) {
+        EXIT_IO_ERROR
+    } else {
+        EXIT_COMPILE_ERROR
+    }
+}
+
+async fn run(mut args: commands::Args) -> anyhow::Result<()> {
+    config::apply_defaults(&mut args.command).await?;
+    match args.command {
+        Commands::Compile {
+            file,
+            out,
+            output,
+            copy,
+            format,
+            timings,
+            flags,
+        } => {
+            let mut options = flags.options();
+            if timings {
+                options.profile = Some(Default::default());
+            }
+            if format == OutputFormat::Json {
+                let result = json::emit(
+                    &out,
+                    async {
+                        let source = read_source(&file).await?;
+                        Ok(match output {
+                            commands::CompilationType::Ast => json::Report {
+                                success: true,
+                                ast: Some(parse_source(&file, &source)?),
+                                ..Default::default()
+                            },
+                            commands::CompilationType::Cst => json::Report {
+                                success: true,
+                                cst: Some(ayysee_parser::cst::build(
+                                    &parse_source(&file, &source)?,
+                                    &source,
+                                )),
+                                ..Default::default()
+                            },
+                            commands::CompilationType::Mips => json::Report {
+                                success: true,
+                                program: Some(compile_source(&file, &source, &options)?),
+                                ..Default::default()
+                            },
+                        })
+                    }
+                    .await,
+                );
+                print_timings(&options);
+                return result;
+            }
+            let file_contents = read_source(&file).await?;
 
             match output {
-                commands::CompilationType::Ast => println!("{:#?}", parsed),
+                commands::CompilationType::Ast => {
+                    let parsed = parse_source(&file, &file_contents)?;
+                    write_output(&out, &format!("{:#?}\n", parsed))?;
+                }
+                commands::CompilationType::Cst => {
+                    let parsed = parse_source(&file, &file_contents)?;
+                    let tree = ayysee_parser::cst::build(&parsed, &file_contents);
+                    write_output(&out, &tree.dump())?;
+                }
                 commands::CompilationType::Mips => {
-                    let compiled = generate_program(parsed)?;
-                    println!("{}", compiled);
+                    let compiled = compile_source(&file, &file_contents, &options)?;
+                    write_output(&out, &format!("{}\n", compiled))?;
+                    print_timings(&options);
+                    if copy {
+                        clipboard::copy(&compiled).await?;
+                        status!("Copied {} lines to the clipboard", compiled.lines().count());
+                    }
+                }
+            }
+        }
+        Commands::Simulate {
+            file,
+            out,
+            world,
+            json,
+            flags,
+        } => {
+            let source = read_source(&file).await?;
+            let world = match world {
+                Some(path) => world::World::load(&path).await?,
+                None => world::World::default(),
+            };
+            let (program, debug_info) =
+                compile_program_with_debug_info(&file, &source, &flags.options())?;
+            let mut simulator = Simulator::new(program);
+            let reports = world.run(&mut simulator)?;
+            let mut output = String::new();
+            if json {
+                output = serde_json::to_string_pretty(&reports)? + "\n";
+            } else {
+                for report in &reports {
+                    output.push_str(&format!("tick {}: {}\n", report.tick, report.result));
+                }
+                for (device, vars) in world::snapshot(&simulator) {
+                    output.push_str(&format!("{}\n", device));
+                    for (variable, value) in vars {
+                        output.push_str(&format!("  {} = {}\n", variable, value));
+                    }
+                }
+            }
+            write_output(&out, &output)?;
+            if let Some(fault) = simulator.fault() {
+                let message = format!("the program faulted on {}", fault);
+                let mut diagnostic = diagnostic::Diagnostic::new(&file, &source, message);
+                if let Some(span) = debug_info.statement(fault.line) {
+                    diagnostic = diagnostic.with_span(span.into());
                 }
+                return Err(diagnostic.into());
             }
         }
-        Commands::Format { files } => {
+        Commands::Locate {
+            file,
+            line,
+            out,
+            flags,
+        } => {
+            let source = read_source(&file).await?;
+            let (program, debug_info) =
+                compile_program_with_debug_info(&file, &source, &flags.options())?;
+            anyhow::ensure!(
+                line < program.instructions.len(),
+                "line {} is past the end of the program, which has {} lines",
+                line,
+                program.instructions.len()
+            );
+            let span = debug_info.statement(line).with_context(|| {
+                format!(
+                    "line {} (`{}`) has no statement",
+                    line, program.instructions[line]
+                )
+            })?;
+            let (row, col) = diagnostic::line_col(&source, span.start);
+            let statement = source[span.start..span.end]
+                .lines()
+                .next()
+                .unwrap_or_default();
+            let location = format!("{}:{}:{}: {}\n", file.display(), row, col, statement);
+            write_output(&out, &location)?;
+        }
+        Commands::Debug { file, world, flags } => {
+            let source = read_source(&file).await?;
+            let world = match world {
+                Some(path) => world::World::load(&path).await?,
+                None => world::World::default(),
+            };
+            let program = compile_program(&file, &source, &flags.options())?;
+            debug::run(program, world)?;
+        }
+        Commands::Check {
+            file,
+            format,
+            flags,
+        } => {
+            let result = async {
+                let source = read_source(&file).await?;
+                compile_source(&file, &source, &flags.options())
+            }
+            .await;
+            match format {
+                OutputFormat::Text => {
+                    result?;
+                    status!("{}: ok", file.display());
+                }
+                OutputFormat::Json => {
+                    return json::emit(
+                        Path::new("-"),
+                        result.map(|_| json::Report {
+                            success: true,
+                            ..Default::default()
+                        }),
+                    )
+                }
+            }
+        }
+        Commands::Stats {
+            file,
+            out,
+            format,
+            flags,
+        } => {
+            let options = flags.options();
+            let unlimited = without_line_limit(&options);
+            let result = async {
+                let source = read_source(&file).await?;
+                compile_program(&file, &source, &unlimited)
+            }
+            .await;
+            match format {
+                OutputFormat::Text => write_output(
+                    &out,
+                    &stats::Stats::new(&result?, &options.target).to_string(),
+                )?,
+                OutputFormat::Json => {
+                    return json::emit(
+                        &out,
+                        result.map(|program| json::Report {
+                            success: true,
+                            program: Some(program.to_string()),
+                            stats: Some(stats::Stats::new(&program, &options.target)),
+                            ..Default::default()
+                        }),
+                    )
+                }
+            }
+        }
+        Commands::Bench {
+            file,
+            out,
+            world,
+            flags,
+        } => {
+            let source = read_source(&file).await?;
+            let world = match world {
+                Some(path) => world::World::load(&path).await?,
+                None => world::World::default(),
+            };
+            write_output(
+                &out,
+                &bench::bench(&file, &source, &world, &flags.options())?,
+            )?;
+        }
+        Commands::Test {
+            files,
+            manifest,
+            flags,
+        } => {
+            let mut suites = vec![];
             if files.is_empty() {
-                let mut content: String = "".to_string();
-                tokio::io::stdin().read_to_string(&mut content).await?;
-                let parser = ProgramParser::new();
-                // TODO: need to figure out lifetimes to properly handle error
-                let parsed = parser.parse(&content).unwrap();
-                let formatted = ayysee_parser::format::format(parsed)?;
-                tokio::io::stdout()
-                    .write_all(&formatted.into_bytes())
-                    .await?;
+                let project = project::Project::load(&manifest).await?;
+                for program in &project.manifest.programs {
+                    let mut scenarios = vec![];
+                    for scenario in &program.scenarios {
+                        scenarios.push(
+                            inline_test::InlineTest::load(&project.root.join(scenario)).await?,
+                        );
+                    }
+                    suites.push(inline_test::Suite {
+                        path: project.source_path(program),
+                        source: project.read_source(program).await?,
+                        scenarios,
+                        options: project.options(program, &flags)?,
+                    });
+                }
             } else {
                 for file in files {
-                    let file_contents = tokio::fs::read_to_string(&file).await?;
-                    let parser = ProgramParser::new();
-                    // TODO: need to figure out lifetimes to properly handle error
-                    let parsed = parser.parse(&file_contents).unwrap();
-                    let formatted = ayysee_parser::format::format(parsed)?;
-                    tokio::fs::write(&file, formatted).await?;
+                    suites.push(inline_test::Suite {
+                        source: project::Source::new(&file, read_source(&file).await?),
+                        path: file,
+                        scenarios: vec![],
+                        options: flags.options(),
+                    });
+                }
+            }
+            run_tests(suites)?;
+        }
+        Commands::Disasm { file, out } => {
+            let source = read_source(&file).await?;
+            let lines = parse_mips(&file, &source)?;
+            write_output(&out, &disasm::render(&lines))?;
+            let max_lines = ayysee_compiler::Target::default().max_lines;
+            if lines.len() > max_lines {
+                eprintln!(
+                    "warning: {} lines, the IC only runs the first {}",
+                    lines.len(),
+                    max_lines
+                );
+            }
+        }
+        Commands::Decompile { file, out } => {
+            let source = read_source(&file).await?;
+            let lines = parse_mips(&file, &source)?;
+            let decompiled = decompile::decompile(&lines);
+            write_output(&out, &decompiled.source)?;
+            for &idx in &decompiled.dropped {
+                if let Some(instruction) = &lines[idx].instruction {
+                    eprintln!("warning: line {}: `{}` is not decompiled", idx, instruction);
+                }
+            }
+            anyhow::ensure!(
+                decompiled.dropped.is_empty(),
+                "{} instructions could not be decompiled and are only kept as comments",
+                decompiled.dropped.len()
+            );
+        }
+        Commands::Repl { flags } => repl::run(flags.options()).await?,
+        Commands::Lsp => lsp::serve().await?,
+        Commands::Serve { port, host, flags } => {
+            let listener = tokio::net::TcpListener::bind((host.as_str(), port))
+                .await
+                .with_context(|| format!("failed to listen on {}:{}", host, port))?;
+            status!("Listening on http://{}", listener.local_addr()?);
+            server::serve(listener, flags).await?;
+        }
+        Commands::Init { path } => {
+            init::init(&path).await?;
+            status!(
+                "Created a project in {}, run `cli build` and `cli test` there",
+                path.display()
+            );
+        }
+        Commands::New {
+            template,
+            file,
+            params,
+            flags,
+        } => {
+            let path = file.unwrap_or_else(|| template.default_path());
+            anyhow::ensure!(
+                !path.exists(),
+                "{} already exists, refusing to overwrite it",
+                path.display()
+            );
+            let source = template::instantiate(template, &params)?;
+            let program = compile_program(&path, &source, &flags.options())?;
+            let mut failures = vec![];
+            for case in inline_test::extract(&source)? {
+                for mismatch in case.run(program.clone())? {
+                    failures.push(format!("{}: {}", case.name, mismatch));
+                }
+            }
+            write_output(&path, &source)?;
+            anyhow::ensure!(
+                failures.is_empty(),
+                "created {}, but its tests fail with the values given, update them:\n{}",
+                path.display(),
+                failures.join("\n")
+            );
+            status!(
+                "Created {} from the {} template, adapt its constants and run `cli test {}`",
+                path.display(),
+                template.name(),
+                path.display()
+            );
+        }
+        Commands::Build {
+            manifest,
+            frozen,
+            flags,
+        } => {
+            let project = Arc::new(project::Project::load(&manifest).await?);
+            let out_dir = project.out_dir();
+            tokio::fs::create_dir_all(&out_dir).await?;
+            let cache_path = out_dir.join(cache::CACHE_FILE);
+            let cache = Arc::new(cache::Cache::load(&cache_path).await);
+            // Programs are independent, so they are all compiled at once and reported in
+            // the order they finish.
+            let mut tasks = tokio::task::JoinSet::new();
+            for idx in 0..project.manifest.programs.len() {
+                let project = project.clone();
+                let cache = cache.clone();
+                let options = project.options(&project.manifest.programs[idx], &flags)?;
+                let out = out_dir.join(format!("{}.ic10", project.manifest.programs[idx].name));
+                let target = options.target.name.clone();
+                tasks.spawn(async move {
+                    let program = &project.manifest.programs[idx];
+                    let result = async {
+                        let path = project.source_path(program);
+                        let source = project.read_source(program).await?;
+                        let key = cache::key(&path, &source.text, &options);
+                        if let Some(outcome) = cache.get(&program.name, &key) {
+                            if let cache::Outcome::Compiled { mips } = outcome {
+                                let written = tokio::fs::read_to_string(&out).await.ok();
+                                if written.as_ref() != Some(mips) {
+                                    anyhow::ensure!(!frozen, "{} is out of date", out.display());
+                                    tokio::fs::write(&out, mips).await.with_context(|| {
+                                        format!("failed to write {}", out.display())
+                                    })?;
+                                }
+                            }
+                            return Ok((key, outcome.clone(), true));
+                        }
+                        anyhow::ensure!(!frozen, "{}", cache.change(&program.name, &path, &key));
+                        let outcome = tokio::task::spawn_blocking(move || {
+                            match compile_source(&path, &source.text, &options) {
+                                Ok(mips) => cache::Outcome::Compiled { mips },
+                                Err(e) => {
+                                    let e = source.relocate(e);
+                                    cache::Outcome::Failed {
+                                        diagnostics: render(&e),
+                                        exit_code: exit_code(&e),
+                                    }
+                                }
+                            }
+                        })
+                        .await?;
+                        if let cache::Outcome::Compiled { mips } = &outcome {
+                            tokio::fs::write(&out, mips)
+                                .await
+                                .with_context(|| format!("failed to write {}", out.display()))?;
+                        }
+                        Ok((key, outcome, false))
+                    }
+                    .await;
+                    (program.name.clone(), target, result)
+                });
+            }
+            let total = tasks.len();
+            let mut built = vec![];
+            let mut failures = vec![];
+            while let Some(task) = tasks.join_next().await {
+                let (name, target, result) = task?;
+                let (key, outcome, cached) = match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        eprintln!("Failed to build {}", name);
+                        report(&e);
+                        failures.push(exit_code(&e));
+                        continue;
+                    }
+                };
+                match &outcome {
+                    cache::Outcome::Compiled { mips } => {
+                        let lines = mips.lines().count();
+                        let cached = if cached { ", cached" } else { "" };
+                        status!(
+                            "Compiled {} for {} ({} lines{})",
+                            name,
+                            target,
+                            lines,
+                            cached
+                        )
+                    }
+                    cache::Outcome::Failed {
+                        diagnostics,
+                        exit_code,
+                    } => {
+                        let cached = if cached { " (cached)" } else { "" };
+                        eprintln!("Failed to compile {}{}", name, cached);
+                        eprintln!("{}", diagnostics);
+                        failures.push(*exit_code);
+                    }
+                }
+                built.push((name, key, outcome));
+            }
+            if !frozen {
+                let mut cache = Arc::into_inner(cache).expect("all programs are built");
+                for (name, key, outcome) in built {
+                    cache.insert(name, key, outcome);
+                }
+                let names = project
+                    .manifest
+                    .programs
+                    .iter()
+                    .map(|p| p.name.as_str())
+                    .collect::<Vec<_>>();
+                cache.retain(&names);
+                cache.save(&cache_path).await?;
+            }
+            if let Some(code) = failures.iter().max() {
+                eprintln!("{} of {} programs failed to build", failures.len(), total);
+                return Err(json::Reported(*code).into());
+            }
+            status!("Built {} programs into {}", total, out_dir.display());
+        }
+        Commands::Devices {
+            command: DevicesCommand::Import { file, housing, out },
+        } => {
+            let content = read_source(&file).await?;
+            let list = devices::DeviceList::parse(&file, &content)
+                .with_context(|| format!("invalid device list {}", file.display()))?;
+            let housing = list.housing(housing.as_deref())?;
+            write_output(&out, &housing.declarations(&file.display().to_string()))?;
+            status!(
+                "Declared {} devices of {}",
+                housing.pins.len(),
+                housing.name
+            );
+        }
+        Commands::GameData {
+            command: GameDataCommand::Import { file, out },
+        } => {
+            let source = read_source(&file).await?;
+            let game_data = ayysee_compiler::GameData::from_stationpedia(&source)
+                .with_context(|| format!("failed to import {}", file.display()))?;
+            write_output(&out, &game_data.to_toml()?)?;
+            status!(
+                "Imported {} logic types and {} prefabs",
+                game_data.logic_types.len(),
+                game_data.prefabs.len()
+            );
+        }
+        Commands::Grammar {
+            command: GrammarCommand::Export { out },
+        } => {
+            for (name, content) in ayysee_parser::tree_sitter::FILES {
+                let path = out.join(name);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(&path, content)
+                    .await
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+            status!(
+                "Exported the tree-sitter grammar to {}, run `tree-sitter generate` there",
+                out.display()
+            );
+        }
+        Commands::Format {
+            files,
+            flags,
+            defaults,
+        } => {
+            // Settings given on the command line win over the `ayyseefmt.toml` of the
+            // files, which wins over the project manifest and the user configuration.
+            let config = |dir: PathBuf| {
+                let (flags, defaults) = (&flags, &defaults);
+                async move {
+                    let mut flags = flags.clone();
+                    flags.or(&config::format_file(&dir).await?);
+                    flags.or(defaults);
+                    anyhow::Ok(flags.config())
+                }
+            };
+            if files.is_empty() || files == [Path::new("-")] {
+                let config = config(PathBuf::from(".")).await?;
+                let content = read_source(Path::new("-")).await?;
+                let parsed = parse_source(Path::new("-"), &content)?;
+                let formatted = ayysee_parser::format::format(parsed, &content, &config)?;
+                write_output(Path::new("-"), &formatted)?;
+            } else {
+                for file in source_files(&files).await? {
+                    let dir = match file.parent() {
+                        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                        _ => PathBuf::from("."),
+                    };
+                    let config = config(dir).await?;
+                    let file_contents = tokio::fs::read_to_string(&file)
+                        .await
+                        .with_context(|| format!("failed to read {}", file.display()))?;
+                    let parsed = parse_source(&file, &file_contents)?;
+                    let formatted = ayysee_parser::format::format(parsed, &file_contents, &config)?;
+                    tokio::fs::write(&file, formatted)
+                        .await
+                        .with_context(|| format!("failed to write {}", file.display()))?;
                 }
             }
         }
@@ -53,3 +650,233 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Compiles each program and runs its inline tests and scenarios, reporting the results like
+/// `cargo test`.
+fn run_tests(suites: Vec<inline_test::Suite>) -> anyhow::Result<()> {
+    let mut tests = vec![];
+    for suite in suites {
+        let (path, source) = (&suite.path, &suite.source.text);
+        let mut cases = inline_test::extract(source)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        cases.extend(suite.scenarios);
+        if cases.is_empty() {
+            continue;
+        }
+        let program =
+            compile_program(path, source, &suite.options).map_err(|e| suite.source.relocate(e))?;
+        for case in cases {
+            tests.push((path.clone(), case, program.clone()));
+        }
+    }
+
+    println!("running {} tests", tests.len());
+    let total = tests.len();
+    let mut failures = vec![];
+    for (path, case, program) in tests {
+        let name = format!("{}::{}", path.display(), case.name);
+        match case.run(program) {
+            Ok(mismatches) if mismatches.is_empty() => println!("test {} ... ok", name),
+            Ok(mismatches) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, mismatches.join("\n")));
+            }
+            Err(e) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, e.to_string()));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, message) in &failures {
+            println!("\n---- {} ----\n{}", name, message);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        total - failures.len(),
+        failures.len()
+    );
+    anyhow::ensure!(failures.is_empty(), "{} tests failed", failures.len());
+    Ok(())
+}
+
+/// Parses ayysee source read from `path` into its AST.
+fn parse_source(path: &Path, source: &str) -> anyhow::Result<ayysee_parser::ast::Program> {
+    ayysee_parser::parse(source).map_err(|errors| {
+        let diagnostics = errors
+            .into_iter()
+            .map(|e| diagnostic::Diagnostic::from_parse_error(path, source, e))
+            .collect();
+        diagnostic::Diagnostics(diagnostics).into()
+    })
+}
+
+/// Parses and compiles ayysee source read from `path` into a MIPS program.
+fn compile_program(
+    path: &Path,
+    source: &str,
+    options: &CompileOptions,
+) -> anyhow::Result<stationeers_mips::Program> {
+    Ok(compile_program_with_debug_info(path, source, options)?.0)
+}
+
+/// Like [`compile_program`], also returning the statement each line was generated for.
+fn compile_program_with_debug_info(
+    path: &Path,
+    source: &str,
+    options: &CompileOptions,
+) -> anyhow::Result<(stationeers_mips::Program, DebugInfo)> {
+    let start = std::time::Instant::now();
+    let mut parsed = parse_source(path, source)?;
+    if let Some(profile) = &options.profile {
+        profile.record("parse", start, 0, 0);
+    }
+    // Imports are read relative to the file, so sources that are not read from one, such as
+    // the requests of the compile server, cannot import.
+    if path == Path::new("-") || path.is_file() {
+        ayysee_compiler::import::expand(&mut parsed, path, options).map_err(|e| {
+            if e.is::<ImportErrors>() || e.is::<SemanticErrors>() {
+                compile_error(path, source, e)
+            } else {
+                e
+            }
+        })?;
+    }
+    generate_program_with_debug_info(parsed, options).map_err(|e| compile_error(path, source, e))
+}
+
+/// Turns an error of the compiler into diagnostics pointing into the source.
+fn compile_error(path: &Path, source: &str, e: anyhow::Error) -> anyhow::Error {
+    if let Some(errors) = e.downcast_ref::<ImportErrors>() {
+        let diagnostics = errors
+            .errors
+            .iter()
+            .map(|error| {
+                diagnostic::Diagnostic::new(&errors.path, &errors.source, error.message.clone())
+                    .with_span(error.span.into())
+            })
+            .collect();
+        return diagnostic::Diagnostics(diagnostics).into();
+    }
+    match e.downcast_ref::<SemanticErrors>() {
+        Some(errors) => {
+            let diagnostics = errors
+                .0
+                .iter()
+                .map(|error| {
+                    diagnostic::Diagnostic::new(path, source, error.message.clone())
+                        .with_span(error.span.into())
+                })
+                .collect();
+            diagnostic::Diagnostics(diagnostics).into()
+        }
+        None => diagnostic::Diagnostic::new(path, source, e.to_string()).into(),
+    }
+}
+
+/// Prints the passes recorded while compiling, if profiling was enabled.
+fn print_timings(options: &CompileOptions) {
+    if let Some(profile) = &options.profile {
+        eprintln!("{}", profile);
+    }
+}
+
+/// Parses and compiles ayysee source read from `path` into MIPS.
+fn compile_source(path: &Path, source: &str, options: &CompileOptions) -> anyhow::Result<String> {
+    Ok(compile_program(path, source, options)?.to_string())
+}
+
+/// Returns the options with the line limit of the target lifted, so that programs over the
+/// limit can still be measured.
+fn without_line_limit(options: &CompileOptions) -> CompileOptions {
+    CompileOptions {
+        target: ayysee_compiler::Target {
+            max_lines: usize::MAX,
+            ..options.target.clone()
+        },
+        ..options.clone()
+    }
+}
+
+/// Parses MIPS code read from `path`, printing a diagnostic for every invalid line.
+fn parse_mips(path: &Path, source: &str) -> anyhow::Result<Vec<disasm::Line>> {
+    disasm::parse(path, source).map_err(|errors| {
+        for error in &errors {
+            eprintln!("{}\n", error);
+        }
+        anyhow::anyhow!("{} invalid lines", errors.len())
+    })
+}
+
+/// Expands the paths given on the command line into ayysee source files: directories are
+/// searched recursively for `.ayy` files and glob patterns are matched.
+async fn source_files(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if pattern.contains(['*', '?', '[']) {
+            let matches = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+            anyhow::ensure!(!matches.is_empty(), "no files match `{}`", pattern);
+            files.extend(matches);
+        } else if tokio::fs::metadata(path)
+            .await
+            .map(|m| m.is_dir())
+            .unwrap_or(false)
+        {
+            let mut dirs = vec![path.clone()];
+            let mut found = vec![];
+            while let Some(dir) = dirs.pop() {
+                let mut entries = tokio::fs::read_dir(&dir)
+                    .await
+                    .with_context(|| format!("failed to read {}", dir.display()))?;
+                while let Some(entry) = entries.next_entry().await? {
+                    let path = entry.path();
+                    if entry.file_type().await?.is_dir() {
+                        dirs.push(path);
+                    } else if path.extension() == Some("ayy".as_ref()) {
+                        found.push(path);
+                    }
+                }
+            }
+            found.sort();
+            files.extend(found);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Writes the output of a command to `path`, where `-` means stdout. A closed stdout (e.g.
+/// when piped into `head`) is not an error.
+fn write_output(path: &Path, content: &str) -> anyhow::Result<()> {
+    if path == Path::new("-") {
+        let mut stdout = std::io::stdout().lock();
+        match stdout
+            .write_all(content.as_bytes())
+            .and_then(|_| stdout.flush())
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+            result => Ok(result?),
+        }
+    } else {
+        std::fs::write(path, content).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// Reads the program source from `path`, where `-` means stdin.
+async fn read_source(path: &Path) -> anyhow::Result<String> {
+    if path == Path::new("-") {
+        let mut content = String::new();
+        tokio::io::stdin().read_to_string(&mut content).await?;
+        Ok(content)
+    } else {
+        tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))
+    }
+}
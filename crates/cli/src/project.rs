@@ -0,0 +1,365 @@
+use crate::commands::{CompileFlags, FormatFlags, OptLevel};
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use anyhow::Context;
+use ayysee_compiler::{CompileOptions, InstructionSet, Target};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The default name of the project manifest.
+pub(crate) const MANIFEST_FILE: &str = "galvanic.toml";
+
+/// A `galvanic.toml` project manifest, listing all programs that make up a base.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Manifest {
+    /// Directory (relative to the manifest) where compiled programs are written.
+    #[serde(default = "default_out_dir")]
+    pub out_dir: PathBuf,
+    /// Files (relative to the manifest) prepended to every program, such as shared constants.
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    #[serde(default, rename = "program")]
+    pub programs: Vec<ProgramEntry>,
+    /// Custom chip profiles, in addition to the built-in targets.
+    #[serde(default)]
+    pub targets: BTreeMap<String, TargetProfile>,
+    /// Default optimization level for commands run in the project.
+    #[serde(default)]
+    pub opt_level: Option<OptLevel>,
+    /// Default target for commands run in the project, and for programs without one.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// A game data file (relative to the manifest) replacing the bundled device pins and
+    /// logic types.
+    #[serde(default)]
+    pub game_data: Option<PathBuf>,
+    /// Formatter settings for the project's programs.
+    #[serde(default)]
+    pub format: FormatFlags,
+}
+
+/// A custom chip, e.g. from a mod. Unset limits are those of the IC10.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TargetProfile {
+    pub max_lines: Option<usize>,
+    pub registers: Option<usize>,
+    pub instruction_set: Option<InstructionSetName>,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum InstructionSetName {
+    Legacy,
+    Current,
+}
+
+impl From<InstructionSetName> for InstructionSet {
+    fn from(name: InstructionSetName) -> Self {
+        match name {
+            InstructionSetName::Legacy => InstructionSet::Legacy,
+            InstructionSetName::Current => InstructionSet::Current,
+        }
+    }
+}
+
+/// A single IC program in the project.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProgramEntry {
+    /// Name of the program, used for the output file name.
+    pub name: String,
+    /// Path to the ayysee source, relative to the manifest.
+    pub source: PathBuf,
+    /// The chip this program is meant to run on.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Flags made available to the program as constants set to `true`. The flags of the
+    /// other programs are set to `false`.
+    #[serde(default)]
+    pub cfg: Vec<String>,
+    /// Scenario files (relative to the manifest) run by `cli test` against this program.
+    #[serde(default)]
+    pub scenarios: Vec<PathBuf>,
+}
+
+fn default_out_dir() -> PathBuf {
+    PathBuf::from("out")
+}
+
+/// A loaded manifest together with the directory it lives in.
+pub(crate) struct Project {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Project {
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = read(path).await?;
+        let manifest: Manifest = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("invalid manifest {}: {}", path.display(), e))?;
+        let root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(Self { root, manifest })
+    }
+
+    pub fn out_dir(&self) -> PathBuf {
+        self.root.join(&self.manifest.out_dir)
+    }
+
+    pub fn source_path(&self, program: &ProgramEntry) -> PathBuf {
+        self.root.join(&program.source)
+    }
+
+    /// Returns the target with the given name, looking up custom profiles in the manifest
+    /// before the built-in targets.
+    pub fn resolve_target(&self, name: &str) -> anyhow::Result<Target> {
+        let profile = match self.manifest.targets.get(name) {
+            Some(profile) => profile,
+            None => {
+                return Target::builtin(name).with_context(|| format!("unknown target `{}`", name))
+            }
+        };
+        let ic10 = Target::ic10();
+        let target = Target {
+            name: name.to_string(),
+            max_lines: profile.max_lines.unwrap_or(ic10.max_lines),
+            registers: profile.registers.unwrap_or(ic10.registers),
+            instruction_set: profile
+                .instruction_set
+                .map(Into::into)
+                .unwrap_or(ic10.instruction_set),
+        };
+        anyhow::ensure!(
+            (1..=ic10.registers).contains(&target.registers),
+            "target `{}` must have between 1 and {} registers",
+            name,
+            ic10.registers
+        );
+        Ok(target)
+    }
+
+    /// Returns the options to compile the program with. The target given on the command
+    /// line takes precedence over the one of the program, which takes precedence over the
+    /// configured default, and so do the constants defined on the command line over the
+    /// `cfg` flags.
+    pub fn options(
+        &self,
+        program: &ProgramEntry,
+        flags: &CompileFlags,
+    ) -> anyhow::Result<CompileOptions> {
+        let mut options = flags.options();
+        if let (None, Some(name)) = (&flags.target, &program.target) {
+            options.target = self
+                .resolve_target(name)
+                .with_context(|| format!("invalid target of {}", program.name))?;
+        }
+        for flag in self.manifest.programs.iter().flat_map(|p| &p.cfg) {
+            let value = if program.cfg.contains(flag) { 1.0 } else { 0.0 };
+            options.definitions.entry(flag.clone()).or_insert(value);
+        }
+        Ok(options)
+    }
+
+    /// Reads the source of the program, with the included files prepended.
+    pub async fn read_source(&self, program: &ProgramEntry) -> anyhow::Result<Source> {
+        let mut files = vec![];
+        for include in &self.manifest.include {
+            let path = self.root.join(include);
+            let source = read(&path).await?;
+            files.push((path, source));
+        }
+        let path = self.source_path(program);
+        let source = read(&path).await?;
+        files.push((path, source));
+        Ok(Source::concat(files))
+    }
+}
+
+/// The source of a program, made of several files put one after the other.
+#[derive(Debug)]
+pub(crate) struct Source {
+    pub text: String,
+    /// The path and contents of each file, in the order they are in the text, each followed
+    /// by a newline but the last.
+    files: Vec<(PathBuf, String)>,
+}
+
+impl Source {
+    /// The source of a program read from a single file.
+    pub fn new(path: &Path, text: String) -> Self {
+        Self::concat(vec![(path.to_path_buf(), text)])
+    }
+
+    fn concat(files: Vec<(PathBuf, String)>) -> Self {
+        let text = files
+            .iter()
+            .map(|(_, source)| source.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self { text, files }
+    }
+
+    /// Points the diagnostics of the error, located in the text, into the file they are in,
+    /// so that they show its name and lines.
+    pub fn relocate(&self, mut e: anyhow::Error) -> anyhow::Error {
+        if let Some(diagnostics) = e.downcast_mut::<Diagnostics>() {
+            diagnostics
+                .0
+                .iter_mut()
+                .for_each(|d| self.relocate_diagnostic(d));
+        } else if let Some(diagnostic) = e.downcast_mut::<Diagnostic>() {
+            self.relocate_diagnostic(diagnostic);
+        }
+        e
+    }
+
+    fn relocate_diagnostic(&self, diagnostic: &mut Diagnostic) {
+        if diagnostic.source != self.text {
+            return;
+        }
+        // Diagnostics without a span are about the program, the last file.
+        let offset = diagnostic
+            .span
+            .as_ref()
+            .map_or(self.text.len(), |s| s.start);
+        let mut start = 0;
+        for (i, (path, source)) in self.files.iter().enumerate() {
+            let end = start + source.len();
+            if offset <= end || i == self.files.len() - 1 {
+                diagnostic.file = path.display().to_string();
+                diagnostic.source = source.clone();
+                diagnostic.span = diagnostic
+                    .span
+                    .take()
+                    .map(|span| span.start - start..span.end.min(end) - start);
+                return;
+            }
+            start = end + 1;
+        }
+    }
+}
+
+async fn read(path: &Path) -> anyhow::Result<String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+
+    fn project(manifest: &str) -> Project {
+        Project {
+            root: PathBuf::from("base"),
+            manifest: toml::from_str(manifest).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_manifest() {
+        let project = project(
+            r#"
+            include = ["src/constants.ayy"]
+            target = "ic10-legacy"
+
+            [[program]]
+            name = "furnace"
+            source = "src/furnace.ayy"
+            target = "big"
+            cfg = ["debug"]
+
+            [targets.big]
+            max_lines = 256
+            registers = 8
+
+            [targets.huge]
+            registers = 32
+            "#,
+        );
+        let manifest = &project.manifest;
+        assert_eq!(manifest.out_dir, PathBuf::from("out"));
+        assert_eq!(project.out_dir(), PathBuf::from("base/out"));
+        assert_eq!(manifest.include, [PathBuf::from("src/constants.ayy")]);
+        let program = &manifest.programs[0];
+        assert_eq!(program.cfg, ["debug"]);
+        assert_eq!(
+            project.source_path(program),
+            PathBuf::from("base/src/furnace.ayy")
+        );
+
+        let big = project.resolve_target("big").unwrap();
+        assert_eq!((big.max_lines, big.registers), (256, 8));
+        assert_eq!(big.instruction_set, Target::ic10().instruction_set);
+        assert!(project.resolve_target("huge").is_err());
+        assert!(project.resolve_target("small").is_err());
+
+        assert!(toml::from_str::<Manifest>("unknown = 1").is_err());
+        assert!(toml::from_str::<Manifest>("[[program]]\nname = \"a\"\n").is_err());
+    }
+
+    #[test]
+    fn test_cfg_flags() {
+        let project = project(
+            r#"
+            [[program]]
+            name = "a"
+            source = "a.ayy"
+            cfg = ["debug"]
+
+            [[program]]
+            name = "b"
+            source = "b.ayy"
+            cfg = ["metric"]
+            "#,
+        );
+        let programs = &project.manifest.programs;
+        let options = project
+            .options(&programs[0], &CompileFlags::default())
+            .unwrap();
+        let expected = BTreeMap::from([("debug".to_string(), 1.0), ("metric".to_string(), 0.0)]);
+        assert_eq!(options.definitions, expected);
+
+        let flags = CompileFlags {
+            definitions: vec![("metric".to_string(), 1.0)],
+            ..Default::default()
+        };
+        let options = project.options(&programs[0], &flags).unwrap();
+        assert_eq!(options.definitions["metric"], 1.0);
+    }
+
+    #[test]
+    fn test_relocate() {
+        let source = Source::concat(vec![
+            (PathBuf::from("constants.ayy"), "const A = 1;".to_string()),
+            (
+                PathBuf::from("main.ayy"),
+                "let x = A;\nlet y = B;".to_string(),
+            ),
+        ]);
+        assert_eq!(source.text, "const A = 1;\nlet x = A;\nlet y = B;");
+        let at = |span: Range<usize>| {
+            Diagnostic::new(Path::new("main.ayy"), &source.text, "error").with_span(span)
+        };
+
+        let e = source.relocate(anyhow::Error::new(Diagnostics(vec![at(6..7), at(32..33)])));
+        let diagnostics = &e.downcast_ref::<Diagnostics>().unwrap().0;
+        assert_eq!(diagnostics[0].file, "constants.ayy");
+        assert_eq!(diagnostics[0].source, "const A = 1;");
+        assert_eq!(diagnostics[0].span, Some(6..7));
+        assert_eq!(diagnostics[1].file, "main.ayy");
+        assert_eq!(diagnostics[1].source, "let x = A;\nlet y = B;");
+        assert_eq!(diagnostics[1].span, Some(19..20));
+
+        let e = source.relocate(anyhow::Error::new(Diagnostic::new(
+            Path::new("main.ayy"),
+            &source.text,
+            "error",
+        )));
+        let diagnostic = e.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.source, "let x = A;\nlet y = B;");
+        assert_eq!(diagnostic.span, None);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::world::{self, DeviceState, World};
+use ayysee_compiler::simulator::Simulator;
+use ayysee_compiler::CompileOptions;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+const HELP: &str = "\
+Enter ayysee statements to run them. Commands:
+  :set <device>.<LogicType> <value>   change a device input
+  :devices                            print all device variables
+  :mips                               print the program compiled so far
+  :reset                              forget all statements and devices
+  :help                               print this help
+  :quit                               exit the REPL";
+
+/// An interactive session. Every accepted statement is appended to the program, which is
+/// then compiled and run for a tick against the session's devices.
+#[derive(Default)]
+struct Session {
+    statements: Vec<String>,
+    world: World,
+    /// Device state after the last run, used to report what changed.
+    devices: BTreeMap<String, DeviceState>,
+    options: CompileOptions,
+}
+
+/// Runs the REPL on stdin/stdout until `:quit` or the end of input.
+pub(crate) async fn run(options: CompileOptions) -> anyhow::Result<()> {
+    // A statement the compiler or simulator chokes on must not end the session.
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("internal compiler error: {}", info)
+    }));
+    let mut session = Session {
+        options,
+        ..Default::default()
+    };
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    println!("galvanic REPL, type :help for help");
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "> " } else { ". " };
+        stdout.write_all(prompt.as_bytes()).await?;
+        stdout.flush().await?;
+        let line = match lines.next_line().await? {
+            Some(line) => line,
+            None => break,
+        };
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            if !session.command(line.trim())? {
+                break;
+            }
+            continue;
+        }
+        input.push_str(&line);
+        input.push('\n');
+        // Keep reading until all blocks are closed.
+        if input.matches('{').count() > input.matches('}').count() {
+            continue;
+        }
+        let entry = std::mem::take(&mut input);
+        if !entry.trim().is_empty() {
+            // The panic was already reported by the hook.
+            let _ = std::panic::catch_unwind(AssertUnwindSafe(|| session.eval(entry)));
+        }
+    }
+    Ok(())
+}
+
+impl Session {
+    /// Handles a `:command`, returning false when the REPL should exit.
+    fn command(&mut self, line: &str) -> anyhow::Result<bool> {
+        let mut parts = line.split_whitespace();
+        match parts.next().unwrap_or_default() {
+            ":quit" | ":q" => return Ok(false),
+            ":help" => println!("{}", HELP),
+            ":reset" => {
+                *self = Session {
+                    options: self.options.clone(),
+                    ..Default::default()
+                }
+            }
+            ":mips" => match self.compile(&self.statements) {
+                Ok(program) => print!("{}", program),
+                Err(e) => eprintln!("{}", e),
+            },
+            ":devices" => {
+                for (device, vars) in &self.devices {
+                    for (variable, value) in vars {
+                        println!("{}.{} = {}", device, variable, value);
+                    }
+                }
+            }
+            ":set" => {
+                let target = parts.next();
+                let value = parts.next().and_then(|v| v.parse::<f64>().ok());
+                match (target.and_then(|t| t.split_once('.')), value) {
+                    (Some((device, variable)), Some(value)) => {
+                        self.world
+                            .devices
+                            .entry(device.to_string())
+                            .or_default()
+                            .insert(variable.to_string(), value);
+                        self.devices
+                            .entry(device.to_string())
+                            .or_default()
+                            .insert(variable.to_string(), value);
+                    }
+                    _ => eprintln!("usage: :set <device>.<LogicType> <value>"),
+                }
+            }
+            other => eprintln!("unknown command `{}`, type :help for help", other),
+        }
+        Ok(true)
+    }
+
+    fn compile(&self, statements: &[String]) -> anyhow::Result<stationeers_mips::Program> {
+        let source = statements.concat();
+        crate::compile_program(Path::new("<repl>"), &source, &self.options)
+    }
+
+    /// Appends the statement to the program and runs it, printing the changed device
+    /// variables. Statements that fail to compile are dropped.
+    fn eval(&mut self, entry: String) {
+        let mut statements = self.statements.clone();
+        statements.push(entry);
+        let program = match self.compile(&statements) {
+            Ok(program) => program,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+        let mut simulator = Simulator::new(program);
+        if let Err(e) = self.world.run(&mut simulator) {
+            eprintln!("error: {:#}", e);
+            return;
+        }
+        self.statements = statements;
+        let devices = world::snapshot(&simulator);
+        for (device, vars) in &devices {
+            for (variable, value) in vars {
+                let before = self.devices.get(device).and_then(|d| d.get(variable));
+                if before != Some(value) {
+                    println!("{}.{} = {}", device, variable, value);
+                }
+            }
+        }
+        self.devices = devices;
+    }
+}
@@ -0,0 +1,280 @@
+use crate::commands::{CompileFlags, FormatFlags, OptLevel};
+use crate::json::{JsonDiagnostic, Report};
+use crate::world::World;
+use ayysee_compiler::simulator::Simulator;
+use ayysee_compiler::{CompileOptions, Target};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Requests with larger bodies are refused, programs are at most a few kilobytes.
+const MAX_BODY: usize = 1 << 20;
+/// Requests with a larger request line and headers are refused.
+const MAX_HEAD: usize = 16 << 10;
+/// The time a client has to send its request, and to receive the response, so that slow
+/// or idle clients do not hold connections open.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Simulations with more ticks are refused, so that a request cannot keep the server busy.
+const MAX_TICKS: usize = 10_000;
+
+/// The body of every request. Fields not used by an endpoint are ignored.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    source: String,
+    #[serde(default)]
+    opt_level: Option<OptLevel>,
+    /// The name of a built-in target.
+    #[serde(default)]
+    target: Option<String>,
+    /// The formatter settings of `/format`.
+    #[serde(default)]
+    format: FormatFlags,
+    /// The world `/simulate` runs the program in, as in `cli simulate --world`.
+    #[serde(default)]
+    world: Option<World>,
+}
+
+/// Serves the JSON API until the process is stopped: `POST /compile`, `/format` and
+/// `/simulate` take a [`Request`] and answer with the same report as `--format json`, with
+/// status 422 when the program has errors.
+///
+/// Each connection handles a single request. CORS is allowed from anywhere, so that the web
+/// playground can call the server.
+pub(crate) async fn serve(listener: TcpListener, flags: CompileFlags) -> anyhow::Result<()> {
+    // A panic in the compiler must not take down the whole server, the request gets an
+    // internal error instead.
+    std::panic::set_hook(Box::new(|info| tracing::error!("{}", info)));
+
+    let flags = Arc::new(flags);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let flags = flags.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &flags).await {
+                tracing::warn!("request from {} failed: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle(stream: TcpStream, flags: &CompileFlags) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let (status, body) = match tokio::time::timeout(TIMEOUT, read_request(&mut reader)).await {
+        Ok(Ok(Ok((method, path, body)))) => respond(&method, &path, &body, flags).await,
+        Ok(Ok(Err(response))) => response,
+        Ok(Err(e)) => return Err(e),
+        Err(_) => (
+            "408 Request Timeout",
+            error("the request was not sent in time"),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: POST, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Content-Type\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    tokio::time::timeout(TIMEOUT, async {
+        writer.write_all(response.as_bytes()).await?;
+        writer.shutdown().await
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("the response was not received in time"))??;
+    Ok(())
+}
+
+/// Reads the method, path and body of the request, or returns the response refusing it.
+async fn read_request(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> anyhow::Result<Result<(String, String, Vec<u8>), (&'static str, String)>> {
+    let too_large = || {
+        let message = error("the request line or headers are too large");
+        Ok(Err(("431 Request Header Fields Too Large", message)))
+    };
+    let mut head = (&mut *reader).take(MAX_HEAD as u64);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line).await?;
+    // Lines cut by the limit have no line feed.
+    if !request_line.ends_with('\n') && head.limit() == 0 {
+        return too_large();
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        head.read_line(&mut line).await?;
+        if !line.ends_with('\n') && head.limit() == 0 {
+            return too_large();
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Ok(Err((
+            "413 Payload Too Large",
+            error("the request is too large"),
+        )));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Ok((method, path, body)))
+}
+
+/// Returns the status and body of the response.
+async fn respond(
+    method: &str,
+    path: &str,
+    body: &[u8],
+    flags: &CompileFlags,
+) -> (&'static str, String) {
+    match (method, path) {
+        ("OPTIONS", _) => return ("204 No Content", String::new()),
+        ("POST", "/compile" | "/format" | "/simulate") => {}
+        ("POST", _) => return ("404 Not Found", error("unknown endpoint")),
+        _ => return ("405 Method Not Allowed", error("only POST is supported")),
+    }
+    let request: Request = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return ("400 Bad Request", error(&format!("invalid request: {}", e))),
+    };
+    let mut options = flags.options();
+    let path = path.to_string();
+    let report = tokio::task::spawn_blocking(move || {
+        let result = apply(&mut options, &request).and_then(|_| run(&path, &request, &options));
+        result.unwrap_or_else(|e| Report {
+            success: false,
+            diagnostics: JsonDiagnostic::all(&e),
+            ..Default::default()
+        })
+    })
+    .await;
+    match report {
+        Ok(report) => {
+            let status = if report.success {
+                "200 OK"
+            } else {
+                "422 Unprocessable Entity"
+            };
+            (status, serde_json::to_string(&report).unwrap_or_default())
+        }
+        // The compiler panicked.
+        Err(_) => (
+            "500 Internal Server Error",
+            error("internal compiler error"),
+        ),
+    }
+}
+
+/// Applies the options given in the request over those of the server.
+fn apply(options: &mut CompileOptions, request: &Request) -> anyhow::Result<()> {
+    if let Some(opt_level) = request.opt_level {
+        options.opt_level = opt_level.into();
+    }
+    if let Some(name) = &request.target {
+        options.target = Target::builtin(name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "unknown target `{}`, expected one of: {}",
+                name,
+                Target::BUILTIN.join(", ")
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn run(endpoint: &str, request: &Request, options: &CompileOptions) -> anyhow::Result<Report> {
+    let file = Path::new("<input>");
+    let source = &request.source;
+    let mut report = Report {
+        success: true,
+        ..Default::default()
+    };
+    match endpoint {
+        "/compile" => report.program = Some(crate::compile_source(file, source, options)?),
+        "/format" => {
+            let parsed = crate::parse_source(file, source)?;
+            let config = request.format.config();
+            report.formatted = Some(ayysee_parser::format::format(parsed, source, &config)?);
+        }
+        "/simulate" => {
+            let default = World::default();
+            let world = request.world.as_ref().unwrap_or(&default);
+            anyhow::ensure!(
+                world.ticks <= MAX_TICKS,
+                "at most {} ticks can be simulated",
+                MAX_TICKS
+            );
+            let program = crate::compile_program(file, source, options)?;
+            report.program = Some(program.to_string());
+            let mut simulator = Simulator::new(program);
+            report.ticks = Some(world.run(&mut simulator)?);
+        }
+        _ => unreachable!("unknown endpoint {}", endpoint),
+    }
+    Ok(report)
+}
+
+/// A failed report with a single diagnostic, for requests that could not be handled.
+fn error(message: &str) -> String {
+    let report = Report {
+        success: false,
+        diagnostics: JsonDiagnostic::all(&anyhow::anyhow!("{}", message)),
+        ..Default::default()
+    };
+    serde_json::to_string(&report).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(request: &[u8]) -> Result<(String, String, Vec<u8>), &'static str> {
+        let mut reader = request;
+        read_request(&mut reader)
+            .await
+            .unwrap()
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let request = b"POST /compile HTTP/1.1\r\nContent-Length: 4\r\n\r\n{}{}";
+        let expected = ("POST".to_string(), "/compile".to_string(), b"{}{}".to_vec());
+        assert_eq!(read(request).await, Ok(expected));
+
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD));
+        let status = "431 Request Header Fields Too Large";
+        assert_eq!(read(long_line.as_bytes()).await, Err(status));
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEAD));
+        assert_eq!(read(many_headers.as_bytes()).await, Err(status));
+
+        let large_body = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        let status = "413 Payload Too Large";
+        assert_eq!(read(large_body.as_bytes()).await, Err(status));
+    }
+}
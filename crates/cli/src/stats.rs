@@ -0,0 +1,86 @@
+use ayysee_compiler::Target;
+use serde::Serialize;
+use stationeers_mips::instructions::{Instruction, Program};
+use stationeers_mips::types::{Device, Register};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Resource usage of a compiled program, compared to the limits of the target chip.
+#[derive(Serialize, Debug)]
+pub(crate) struct Stats {
+    pub lines: usize,
+    pub max_lines: usize,
+    pub registers: BTreeSet<String>,
+    pub max_registers: usize,
+    pub devices: BTreeSet<String>,
+    /// Number of instructions in each instruction category.
+    pub categories: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    pub fn new(program: &Program, target: &Target) -> Self {
+        let mut registers = BTreeSet::new();
+        let mut devices = BTreeSet::new();
+        let mut categories = BTreeMap::<String, usize>::new();
+        for ins in &program.instructions {
+            *categories.entry(category(ins).to_string()).or_default() += 1;
+            // Operands are collected from the textual form, which covers every instruction
+            // without matching on each of them.
+            for operand in ins.to_string().split_whitespace().skip(1) {
+                if let Ok(register) = operand.parse::<Register>() {
+                    if !matches!(register, Register::Ra | Register::Sp) {
+                        registers.insert(register.to_string());
+                    }
+                } else if let Ok(device) = operand.parse::<Device>() {
+                    devices.insert(device.to_string());
+                }
+            }
+        }
+        Self {
+            lines: program.instructions.len(),
+            max_lines: target.max_lines,
+            registers,
+            max_registers: target.registers,
+            devices,
+            categories,
+        }
+    }
+}
+
+fn category(ins: &Instruction) -> &'static str {
+    match ins {
+        Instruction::DeviceIo(_) => "device io",
+        Instruction::FlowControl(_) => "flow control",
+        Instruction::VariableSelection(_) => "variable selection",
+        Instruction::Arithmetic(_) => "arithmetic",
+        Instruction::Logic(_) => "logic",
+        Instruction::Stack(_) => "stack",
+        Instruction::Misc(_) => "misc",
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let over = |used, max| if used > max { " (over the limit)" } else { "" };
+        writeln!(
+            f,
+            "lines:     {:>3} / {}{}",
+            self.lines,
+            self.max_lines,
+            over(self.lines, self.max_lines)
+        )?;
+        writeln!(
+            f,
+            "registers: {:>3} / {}{}",
+            self.registers.len(),
+            self.max_registers,
+            over(self.registers.len(), self.max_registers)
+        )?;
+        let devices = self.devices.iter().cloned().collect::<Vec<_>>();
+        writeln!(f, "devices:   {}", devices.join(", "))?;
+        writeln!(f, "instructions:")?;
+        for (category, count) in &self.categories {
+            writeln!(f, "  {:<20}{:>3}", category, count)?;
+        }
+        Ok(())
+    }
+}
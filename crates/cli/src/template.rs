@@ -0,0 +1,77 @@
+//! Ready-to-edit programs for common builds, created by `cli new`.
+
+use clap::ValueEnum;
+use std::path::PathBuf;
+
+/// A program to start from. Each has constants to adapt it to the build and inline tests.
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub(crate) enum Template {
+    /// An airlock emptied and filled by an active vent
+    Airlock,
+    /// Keeps a greenhouse between two temperatures with a heater and a cooler
+    Greenhouse,
+    /// Points a solar panel at the sun, following a daylight sensor
+    Solar,
+    /// Runs a filtration unit while the air holds too much pollutant
+    Filtration,
+}
+
+impl Template {
+    pub fn source(self) -> &'static str {
+        match self {
+            Template::Airlock => include_str!("../templates/airlock.ayy"),
+            Template::Greenhouse => include_str!("../templates/greenhouse.ayy"),
+            Template::Solar => include_str!("../templates/solar.ayy"),
+            Template::Filtration => include_str!("../templates/filtration.ayy"),
+        }
+    }
+
+    pub fn name(self) -> String {
+        self.to_possible_value().unwrap().get_name().to_string()
+    }
+
+    /// The file the program is written to when none is given.
+    pub fn default_path(self) -> PathBuf {
+        format!("{}.ayy", self.name()).into()
+    }
+}
+
+/// Parses a `NAME=VALUE` argument of `--set`.
+pub(crate) fn parse_param(arg: &str) -> anyhow::Result<(String, String)> {
+    match arg.split_once('=') {
+        Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+        None => anyhow::bail!("expected `NAME=VALUE`, e.g. `MAX_TEMPERATURE=300`"),
+    }
+}
+
+/// Returns the source of the template with the values of its constants replaced by
+/// `params`.
+pub(crate) fn instantiate(
+    template: Template,
+    params: &[(String, String)],
+) -> anyhow::Result<String> {
+    let mut source = template.source().to_string();
+    for (name, value) in params {
+        let declaration = format!("\nconst {} = ", name);
+        let Some(start) = source.find(&declaration) else {
+            anyhow::bail!(
+                "the {} template has no constant `{}`, it has {}",
+                template.name(),
+                name,
+                constants(template.source()).join(", ")
+            );
+        };
+        let start = start + declaration.len();
+        let end = start + source[start..].find(';').unwrap_or_default();
+        source.replace_range(start..end, value);
+    }
+    Ok(source)
+}
+
+/// Returns the names of the top-level constants of a template.
+fn constants(source: &str) -> Vec<&str> {
+    source
+        .lines()
+        .filter_map(|line| line.strip_prefix("const ")?.split(' ').next())
+        .collect()
+}
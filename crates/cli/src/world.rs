@@ -0,0 +1,186 @@
+use anyhow::Context;
+use ayysee_compiler::simulator::{Simulator, TickResult};
+use serde::{Deserialize, Serialize};
+use stationeers_mips::types::{Device, DeviceVariable};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Logic variable values of a single device, keyed by the logic type name.
+pub(crate) type DeviceState = BTreeMap<String, f64>;
+
+/// The world a program is simulated in, loaded from a TOML file.
+///
+/// ```toml
+/// ticks = 10
+///
+/// [devices]
+/// d0 = { Temperature = 293.15 }
+/// db = {}
+///
+/// [[inputs]]
+/// tick = 3
+/// d0 = { Temperature = 310.0 }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct World {
+    /// Number of ticks to simulate.
+    #[serde(default = "default_ticks")]
+    pub ticks: usize,
+    /// Devices connected to the IC, with the initial values of their variables.
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceState>,
+    /// Device variables changed before the given tick is executed.
+    #[serde(default)]
+    pub inputs: Vec<Input>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Input {
+    /// The tick (counting from 1) before which the values are applied.
+    pub tick: usize,
+    #[serde(flatten)]
+    pub devices: BTreeMap<String, DeviceState>,
+}
+
+fn default_ticks() -> usize {
+    1
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self {
+            ticks: default_ticks(),
+            devices: Default::default(),
+            inputs: Default::default(),
+        }
+    }
+}
+
+/// The outcome of a single simulated tick.
+#[derive(Serialize, Debug)]
+pub(crate) struct TickReport {
+    pub tick: usize,
+    pub result: String,
+    /// Number of instructions executed during the tick.
+    pub executed: usize,
+    pub devices: BTreeMap<String, DeviceState>,
+}
+
+impl World {
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_toml(&content)
+            .map_err(|e| anyhow::anyhow!("invalid world file {}: {}", path.display(), e))
+    }
+
+    pub fn from_toml(content: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Runs the simulation and returns the device states after every tick.
+    ///
+    /// The simulation stops early once the program runs past its last instruction, faults or
+    /// halts.
+    pub fn run(&self, simulator: &mut Simulator) -> anyhow::Result<Vec<TickReport>> {
+        self.setup(simulator)?;
+        let mut reports = vec![];
+        for tick in 1..=self.ticks {
+            self.apply_inputs(simulator, tick)?;
+            let executed = simulator.executed();
+            let result = simulator.tick();
+            let stop = matches!(
+                result,
+                TickResult::End | TickResult::Fault(_) | TickResult::Halted
+            );
+            let result = match result {
+                TickResult::Fault(fault) => format!("Fault on {}", fault),
+                result => format!("{:?}", result),
+            };
+            reports.push(TickReport {
+                tick,
+                result,
+                executed: simulator.executed() - executed,
+                devices: snapshot(simulator),
+            });
+            if stop {
+                break;
+            }
+        }
+        Ok(reports)
+    }
+
+    /// Connects the devices with their initial values.
+    pub fn setup(&self, simulator: &mut Simulator) -> anyhow::Result<()> {
+        apply(simulator, &self.devices)
+    }
+
+    /// Applies the inputs changed before the given tick.
+    pub fn apply_inputs(&self, simulator: &mut Simulator, tick: usize) -> anyhow::Result<()> {
+        for input in self.inputs.iter().filter(|i| i.tick == tick) {
+            apply(simulator, &input.devices)?;
+        }
+        Ok(())
+    }
+}
+
+fn apply(simulator: &mut Simulator, devices: &BTreeMap<String, DeviceState>) -> anyhow::Result<()> {
+    for (device, vars) in devices {
+        let device: Device = device
+            .parse()
+            .map_err(|_| anyhow::anyhow!("unknown device `{}`", device))?;
+        for (variable, value) in vars {
+            let variable: DeviceVariable = variable
+                .parse()
+                .map_err(|_| anyhow::anyhow!("unknown logic type `{}`", variable))?;
+            simulator.write(device, variable, *value);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the current variables of every device, keyed by their names.
+pub(crate) fn snapshot(simulator: &Simulator) -> BTreeMap<String, DeviceState> {
+    simulator
+        .devices()
+        .map(|(device, vars)| {
+            let vars = vars.iter().map(|(v, x)| (v.to_string(), *x)).collect();
+            (device.to_string(), vars)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let world = World::from_toml(
+            r#"
+            ticks = 10
+
+            [devices]
+            d0 = { Temperature = 293.15 }
+            db = {}
+
+            [[inputs]]
+            tick = 3
+            d0 = { Temperature = 310.0 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(world.ticks, 10);
+        assert_eq!(world.devices["d0"]["Temperature"], 293.15);
+        assert!(world.devices["db"].is_empty());
+        assert_eq!(world.inputs[0].tick, 3);
+        assert_eq!(world.inputs[0].devices["d0"]["Temperature"], 310.0);
+
+        let world = World::from_toml("").unwrap();
+        assert_eq!(world.ticks, 1);
+        assert!(world.devices.is_empty());
+        assert!(World::from_toml("tick = 1").is_err());
+    }
+}
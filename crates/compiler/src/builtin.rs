@@ -0,0 +1,1132 @@
+//! Functions provided by the compiler rather than defined in programs.
+//!
+//! `load`, `store`, their batch variants and the math functions are builtins like any other: downstream crates can add functions for
+//! game mods by implementing [`Builtin`] and registering them in
+//! [`CompileOptions::builtins`](crate::CompileOptions::builtins). A builtin lowers to MIPS
+//! instructions, which the simulator runs like the rest of the program.
+
+use stationeers_mips::instructions::{Arithmetic, DeviceIo, Instruction, Stack, VariableSelection};
+use stationeers_mips::types::{
+    BatchMode, Device, DeviceVariable, ReagentMode, Register, RegisterOrNumber, Slot, TypeHash,
+};
+use std::sync::Arc;
+
+/// An argument of a builtin call, as seen when lowering it.
+#[derive(Clone, Debug)]
+pub enum Argument<'a> {
+    /// A number, or the register holding a value computed at run time.
+    Value(RegisterOrNumber),
+    /// A device pin or logic type.
+    External(&'a str),
+}
+
+impl Argument<'_> {
+    pub fn value(&self) -> anyhow::Result<RegisterOrNumber> {
+        match self {
+            Argument::Value(value) => Ok(value.clone()),
+            Argument::External(name) => anyhow::bail!("`{}` is not a value", name),
+        }
+    }
+
+    /// Returns the device pin the argument names, or the one whose number it holds, as with
+    /// `devices[i]`.
+    pub fn device(&self) -> anyhow::Result<Device> {
+        match self {
+            Argument::External(name) => name
+                .parse()
+                .map_err(|_| anyhow::anyhow!("`{}` is not a device pin of the chip", name)),
+            Argument::Value(RegisterOrNumber::Register(register)) => {
+                Ok(Device::Indirect(*register))
+            }
+            Argument::Value(RegisterOrNumber::Number(x)) => Device::PINS
+                .get(*x as usize)
+                .filter(|_| *x >= 0.0 && x.fract() == 0.0)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("there is no device pin {}", x)),
+        }
+    }
+
+    /// Returns the logic type the argument names, which may be one the game data knows of
+    /// but this version of the MIPS crate does not.
+    pub fn logic_type(&self) -> anyhow::Result<DeviceVariable> {
+        match self {
+            Argument::External(name) => Ok(name
+                .parse()
+                .unwrap_or_else(|_| DeviceVariable::Other(name.to_string()))),
+            Argument::Value(_) => anyhow::bail!("expected a logic type"),
+        }
+    }
+
+    /// Returns the slot index the argument holds, as a number or in a register.
+    pub fn slot(&self) -> anyhow::Result<Slot> {
+        match self.value()? {
+            RegisterOrNumber::Number(x) if x < 0.0 || x.fract() != 0.0 => {
+                anyhow::bail!("`{}` is not a slot index", x)
+            }
+            index => Ok(index.into()),
+        }
+    }
+
+    /// Returns the batch mode the argument names, `Average` to `Maximum` or their number.
+    pub fn batch_mode(&self) -> anyhow::Result<BatchMode> {
+        self.mode("batch mode")
+    }
+
+    /// Returns the reagent mode the argument names, `Contents`, `Required`, `Recipe` or their
+    /// number.
+    pub fn reagent_mode(&self) -> anyhow::Result<ReagentMode> {
+        self.mode("reagent mode")
+    }
+
+    /// Parses a mode operand, which the instruction needs to be a constant.
+    fn mode<T: std::str::FromStr>(&self, kind: &str) -> anyhow::Result<T> {
+        let mode = match self {
+            Argument::External(name) => name.to_string(),
+            Argument::Value(RegisterOrNumber::Number(x)) => x.to_string(),
+            Argument::Value(RegisterOrNumber::Register(_)) => {
+                anyhow::bail!("the {} must be known at compile time", kind)
+            }
+        };
+        mode.parse()
+            .map_err(|_| anyhow::anyhow!("`{}` is not a {}", mode, kind))
+    }
+
+    /// Returns the prefab or name hash the argument holds, as a number or in a register.
+    pub fn hash(&self) -> anyhow::Result<TypeHash> {
+        Ok(self.value()?.to_string().parse()?)
+    }
+}
+
+/// What an argument of a builtin is, which calls are checked against before lowering.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    /// A number, known at compile time or computed at run time.
+    Number,
+    /// A device pin, or its number as with `devices[i]`.
+    Device,
+    /// A logic type or slot logic type.
+    LogicType,
+    /// A batch or reagent mode, or its number.
+    Mode,
+}
+
+impl Type {
+    /// Returns the type of a device pin, logic type or mode name.
+    pub fn of_external(name: &str) -> Self {
+        if name.parse::<Device>().is_ok() {
+            Type::Device
+        } else if crate::game_data::is_mode(name) {
+            Type::Mode
+        } else {
+            Type::LogicType
+        }
+    }
+
+    /// Whether an argument of type `found` can be passed where this type is expected.
+    pub fn accepts(self, found: Type) -> bool {
+        match self {
+            Type::Number | Type::LogicType => found == self,
+            Type::Device | Type::Mode => found == self || found == Type::Number,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Type::Number => "number",
+            Type::Device => "device pin",
+            Type::LogicType => "logic type",
+            Type::Mode => "mode",
+        })
+    }
+}
+
+/// A function provided by the compiler.
+pub trait Builtin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The number of arguments the function takes.
+    fn arity(&self) -> usize;
+
+    /// The types of the arguments, numbers unless the function reads or writes devices.
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Number; self.arity()]
+    }
+
+    /// Whether calls do something besides computing their result, such as writing to a
+    /// device, so that they are kept when the result is unused.
+    fn has_effects(&self) -> bool {
+        false
+    }
+
+    /// Returns the instructions of a call, which leave its result in `result`.
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>>;
+
+    /// Returns the instruction jumping to `target` when the call returns 0, for functions
+    /// whose result can be tested without computing it, like comparisons are with `Os`.
+    fn branch_if_false(
+        &self,
+        _args: &[Argument],
+        _target: RegisterOrNumber,
+    ) -> Option<Instruction> {
+        None
+    }
+}
+
+/// The builtins programs can call.
+#[derive(Clone)]
+pub struct Builtins(Vec<Arc<dyn Builtin>>);
+
+impl Default for Builtins {
+    /// `load`, `store`, their slot and batch variants, `load_reagent`, `is_set`, the stack
+    /// functions `push`, `pop` and `peek`, `isnan`, `isfinite`, `clamp`, `rand` and the math
+    /// functions of [`Math::ALL`].
+    fn default() -> Self {
+        let mut builtins: Vec<Arc<dyn Builtin>> = vec![
+            Arc::new(Load),
+            Arc::new(Store),
+            Arc::new(LoadSlot),
+            Arc::new(StoreSlot),
+            Arc::new(LoadReagent),
+            Arc::new(IsSet),
+            Arc::new(LoadBatch),
+            Arc::new(StoreBatch),
+            Arc::new(LoadBatchNamed),
+            Arc::new(StoreBatchNamed),
+            Arc::new(Push),
+            Arc::new(Pop),
+            Arc::new(Peek),
+            Arc::new(IsNan),
+            Arc::new(IsFinite),
+            Arc::new(Clamp),
+            Arc::new(Rand),
+        ];
+        for math in Math::ALL {
+            builtins.push(Arc::new(math));
+        }
+        Self(builtins)
+    }
+}
+
+impl std::fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.iter().map(|b| b.name()))
+            .finish()
+    }
+}
+
+impl Builtins {
+    /// Adds a builtin, replacing the one with the same name if any.
+    pub fn register(&mut self, builtin: impl Builtin + 'static) {
+        self.0.retain(|b| b.name() != builtin.name());
+        self.0.push(Arc::new(builtin));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Builtin> {
+        self.iter().find(|b| b.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Builtin> {
+        self.0.iter().map(|b| b.as_ref())
+    }
+}
+
+/// `load(device, logic_type)` reads a variable of a device.
+struct Load;
+
+impl Builtin for Load {
+    fn name(&self) -> &str {
+        "load"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Device, Type::LogicType]
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::LoadDeviceVariable {
+            register: result,
+            device: args[0].device()?,
+            variable: args[1].logic_type()?,
+        }
+        .into()])
+    }
+}
+
+/// `store(device, logic_type, value)` writes a variable of a device.
+struct Store;
+
+impl Builtin for Store {
+    fn name(&self) -> &str {
+        "store"
+    }
+
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Device, Type::LogicType, Type::Number]
+    }
+
+    fn has_effects(&self) -> bool {
+        true
+    }
+
+    fn lower(&self, args: &[Argument], _: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::StoreDeviceVariable {
+            device: args[0].device()?,
+            variable: args[1].logic_type()?,
+            register: args[2].value()?,
+        }
+        .into()])
+    }
+}
+
+/// `load_slot(device, slot, slot_logic_type)` reads a variable of the item in a slot of a
+/// device.
+struct LoadSlot;
+
+impl Builtin for LoadSlot {
+    fn name(&self) -> &str {
+        "load_slot"
+    }
+
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Device, Type::Number, Type::LogicType]
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::LoadSlot {
+            register: result,
+            device: args[0].device()?,
+            slot: args[1].slot()?,
+            variable: args[2].logic_type()?,
+        }
+        .into()])
+    }
+}
+
+/// `store_slot(device, slot, slot_logic_type, value)` writes a variable of the item in a slot
+/// of a device.
+struct StoreSlot;
+
+impl Builtin for StoreSlot {
+    fn name(&self) -> &str {
+        "store_slot"
+    }
+
+    fn arity(&self) -> usize {
+        4
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Device, Type::Number, Type::LogicType, Type::Number]
+    }
+
+    fn has_effects(&self) -> bool {
+        true
+    }
+
+    fn lower(&self, args: &[Argument], _: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::StoreSlot {
+            device: args[0].device()?,
+            slot: args[1].slot()?,
+            variable: args[2].logic_type()?,
+            register: args[3].value()?,
+        }
+        .into()])
+    }
+}
+
+/// `load_reagent(device, reagent_mode, reagent_hash)` reads the amount of a reagent in a
+/// device, or the amount its recipe needs.
+struct LoadReagent;
+
+impl Builtin for LoadReagent {
+    fn name(&self) -> &str {
+        "load_reagent"
+    }
+
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Device, Type::Mode, Type::Number]
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::LoadReagent {
+            register: result,
+            device: args[0].device()?,
+            reagent_mode: args[1].reagent_mode()?,
+            reagent: args[2].value()?.into(),
+        }
+        .into()])
+    }
+}
+
+/// `is_set(device)` is whether a device is plugged into the pin.
+struct IsSet;
+
+impl Builtin for IsSet {
+    fn name(&self) -> &str {
+        "is_set"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Device]
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![VariableSelection::SelectDeviceSet {
+            register: result,
+            d: args[0].device()?,
+        }
+        .into()])
+    }
+
+    fn branch_if_false(&self, args: &[Argument], target: RegisterOrNumber) -> Option<Instruction> {
+        Some(
+            DeviceIo::BranchDeviceNotSet {
+                device: args[0].device().ok()?,
+                line: target,
+            }
+            .into(),
+        )
+    }
+}
+
+/// `isnan(x)` is whether `x` is NaN, which devices read in the game can be.
+struct IsNan;
+
+impl Builtin for IsNan {
+    fn name(&self) -> &str {
+        "isnan"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![VariableSelection::SelectNaN {
+            register: result,
+            a: args[0].value()?,
+        }
+        .into()])
+    }
+}
+
+/// `isfinite(x)` is whether `x` is neither NaN nor infinite, tested as `x - x == 0`.
+struct IsFinite;
+
+impl Builtin for IsFinite {
+    fn name(&self) -> &str {
+        "isfinite"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        let x = args[0].value()?;
+        Ok(vec![
+            Arithmetic::Subtract {
+                register: result,
+                a: x.clone(),
+                b: x,
+            }
+            .into(),
+            VariableSelection::SelectEqualZero {
+                register: result,
+                a: result.into(),
+            }
+            .into(),
+        ])
+    }
+}
+
+/// `clamp(x, low, high)` limits `x` to the range from `low` to `high`, with a `max` and a
+/// `min` computing in the register of the result.
+struct Clamp;
+
+impl Builtin for Clamp {
+    fn name(&self) -> &str {
+        "clamp"
+    }
+
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        let (x, low, high) = (args[0].value()?, args[1].value()?, args[2].value()?);
+        let is_result = |bound: &RegisterOrNumber| matches!(bound, RegisterOrNumber::Register(r) if *r == result);
+        // The result may share its register with a bound the call is the last use of, which
+        // must be read by the first instruction.
+        if is_result(&high) {
+            if is_result(&low) {
+                // Both bounds are the same value, already in the result.
+                return Ok(vec![]);
+            }
+            return Ok(vec![
+                Arithmetic::Minimum {
+                    register: result,
+                    a: x,
+                    b: high,
+                }
+                .into(),
+                Arithmetic::Maximum {
+                    register: result,
+                    a: result.into(),
+                    b: low,
+                }
+                .into(),
+            ]);
+        }
+        Ok(vec![
+            Arithmetic::Maximum {
+                register: result,
+                a: x,
+                b: low,
+            }
+            .into(),
+            Arithmetic::Minimum {
+                register: result,
+                a: result.into(),
+                b: high,
+            }
+            .into(),
+        ])
+    }
+}
+
+/// `rand()` returns a random number from 0 up to 1, excluded.
+struct Rand;
+
+impl Builtin for Rand {
+    fn name(&self) -> &str {
+        "rand"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    /// Each call returns a new number, so calls are never merged or dropped.
+    fn has_effects(&self) -> bool {
+        true
+    }
+
+    fn lower(&self, _: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![Arithmetic::Random { register: result }.into()])
+    }
+}
+
+/// `push(value)` puts a value on top of the stack of the chip, which holds 512. Functions
+/// called with `jal` keep their return address on the stack, so they must pop what they
+/// push before returning.
+struct Push;
+
+impl Builtin for Push {
+    fn name(&self) -> &str {
+        "push"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn has_effects(&self) -> bool {
+        true
+    }
+
+    fn lower(&self, args: &[Argument], _: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![Stack::Push {
+            a: args[0].value()?,
+        }
+        .into()])
+    }
+}
+
+/// `pop()` takes the value on top of the stack.
+struct Pop;
+
+impl Builtin for Pop {
+    fn name(&self) -> &str {
+        "pop"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn has_effects(&self) -> bool {
+        true
+    }
+
+    fn lower(&self, _: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![Stack::Pop { register: result }.into()])
+    }
+}
+
+/// `peek()` reads the value on top of the stack, leaving it there.
+struct Peek;
+
+impl Builtin for Peek {
+    fn name(&self) -> &str {
+        "peek"
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn lower(&self, _: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![Stack::Peek { register: result }.into()])
+    }
+}
+
+/// `load_batch(type_hash, logic_type, batch_mode)` reads a variable of all devices of a
+/// prefab on the network, combined by the batch mode.
+struct LoadBatch;
+
+impl Builtin for LoadBatch {
+    fn name(&self) -> &str {
+        "load_batch"
+    }
+
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Number, Type::LogicType, Type::Mode]
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::LoadBatch {
+            register: result,
+            type_hash: args[0].hash()?,
+            variable: args[1].logic_type()?,
+            batch_mode: args[2].batch_mode()?,
+        }
+        .into()])
+    }
+}
+
+/// `store_batch(type_hash, logic_type, value)` writes a variable of all devices of a prefab
+/// on the network.
+struct StoreBatch;
+
+impl Builtin for StoreBatch {
+    fn name(&self) -> &str {
+        "store_batch"
+    }
+
+    fn arity(&self) -> usize {
+        3
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Number, Type::LogicType, Type::Number]
+    }
+
+    fn has_effects(&self) -> bool {
+        true
+    }
+
+    fn lower(&self, args: &[Argument], _: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::StoreBatch {
+            type_hash: args[0].hash()?,
+            variable: args[1].logic_type()?,
+            register: args[2].value()?,
+        }
+        .into()])
+    }
+}
+
+/// `load_batch_named(type_hash, name_hash, logic_type, batch_mode)` reads a variable of the
+/// devices of a prefab on the network which were given a name with a labeller.
+struct LoadBatchNamed;
+
+impl Builtin for LoadBatchNamed {
+    fn name(&self) -> &str {
+        "load_batch_named"
+    }
+
+    fn arity(&self) -> usize {
+        4
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Number, Type::Number, Type::LogicType, Type::Mode]
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::LoadBatchNamed {
+            register: result,
+            type_hash: args[0].hash()?,
+            name_hash: args[1].hash()?,
+            variable: args[2].logic_type()?,
+            batch_mode: args[3].batch_mode()?,
+        }
+        .into()])
+    }
+}
+
+/// `store_batch_named(type_hash, name_hash, logic_type, value)` writes a variable of the
+/// devices of a prefab on the network which were given a name with a labeller.
+struct StoreBatchNamed;
+
+impl Builtin for StoreBatchNamed {
+    fn name(&self) -> &str {
+        "store_batch_named"
+    }
+
+    fn arity(&self) -> usize {
+        4
+    }
+
+    fn parameters(&self) -> Vec<Type> {
+        vec![Type::Number, Type::Number, Type::LogicType, Type::Number]
+    }
+
+    fn has_effects(&self) -> bool {
+        true
+    }
+
+    fn lower(&self, args: &[Argument], _: Register) -> anyhow::Result<Vec<Instruction>> {
+        Ok(vec![DeviceIo::StoreBatchNamed {
+            type_hash: args[0].hash()?,
+            name_hash: args[1].hash()?,
+            variable: args[2].logic_type()?,
+            register: args[3].value()?,
+        }
+        .into()])
+    }
+}
+
+/// A math function computed by a single instruction, such as `sqrt(x)` or `max(a, b)`.
+#[derive(Clone, Copy)]
+struct Math {
+    name: &'static str,
+    instruction: MathInstruction,
+}
+
+#[derive(Clone, Copy)]
+enum MathInstruction {
+    Unary(fn(Register, RegisterOrNumber) -> Arithmetic),
+    Binary(fn(Register, RegisterOrNumber, RegisterOrNumber) -> Arithmetic),
+}
+
+impl Math {
+    const ALL: [Math; 15] = [
+        Math::unary("abs", |register, a| Arithmetic::AbsoluteValue {
+            register,
+            a,
+        }),
+        Math::binary("min", |register, a, b| Arithmetic::Minimum {
+            register,
+            a,
+            b,
+        }),
+        Math::binary("max", |register, a, b| Arithmetic::Maximum {
+            register,
+            a,
+            b,
+        }),
+        Math::unary("sqrt", |register, a| Arithmetic::SquareRoot { register, a }),
+        Math::unary("floor", |register, a| Arithmetic::Floor { register, a }),
+        Math::unary("ceil", |register, a| Arithmetic::Ceiling { register, a }),
+        Math::unary("round", |register, a| Arithmetic::Round { register, a }),
+        Math::unary("trunc", |register, a| Arithmetic::Truncate { register, a }),
+        Math::unary("sin", |register, a| Arithmetic::Sine { register, a }),
+        Math::unary("cos", |register, a| Arithmetic::Cosine { register, a }),
+        Math::unary("tan", |register, a| Arithmetic::Tangent { register, a }),
+        Math::unary("asin", |register, a| Arithmetic::ArcSine { register, a }),
+        Math::unary("acos", |register, a| Arithmetic::ArcCosine { register, a }),
+        Math::unary("atan", |register, a| Arithmetic::ArcTangent { register, a }),
+        Math::binary("atan2", |register, a, b| Arithmetic::ArcTangent2 {
+            register,
+            a,
+            b,
+        }),
+    ];
+
+    const fn unary(name: &'static str, f: fn(Register, RegisterOrNumber) -> Arithmetic) -> Self {
+        Self {
+            name,
+            instruction: MathInstruction::Unary(f),
+        }
+    }
+
+    const fn binary(
+        name: &'static str,
+        f: fn(Register, RegisterOrNumber, RegisterOrNumber) -> Arithmetic,
+    ) -> Self {
+        Self {
+            name,
+            instruction: MathInstruction::Binary(f),
+        }
+    }
+}
+
+impl Builtin for Math {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        match self.instruction {
+            MathInstruction::Unary(_) => 1,
+            MathInstruction::Binary(_) => 2,
+        }
+    }
+
+    fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+        let instruction = match self.instruction {
+            MathInstruction::Unary(f) => f(result, args[0].value()?),
+            MathInstruction::Binary(f) => f(result, args[0].value()?, args[1].value()?),
+        };
+        Ok(vec![instruction.into()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, TickResult};
+    use crate::{CompileOptions, OptLevel};
+
+    struct Larger;
+
+    impl Builtin for Larger {
+        fn name(&self) -> &str {
+            "larger"
+        }
+
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn lower(&self, args: &[Argument], result: Register) -> anyhow::Result<Vec<Instruction>> {
+            Ok(vec![Arithmetic::Maximum {
+                register: result,
+                a: args[0].value()?,
+                b: args[1].value()?,
+            }
+            .into()])
+        }
+    }
+
+    #[test]
+    fn test_custom_builtin() {
+        let source = "db.Setting = larger(d0.Temperature, 300);";
+        let program = ayysee_parser::parse(source).unwrap();
+        assert!(crate::resolve::check(&program).is_err());
+
+        let mut options = CompileOptions::default();
+        options.builtins.register(Larger);
+        let mips = crate::ir::generate_program_with(program, &options).unwrap();
+        let mut simulator = Simulator::new(mips);
+        simulator.write(Device::D0, DeviceVariable::Temperature, 310.0);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 310.0);
+
+        let program = ayysee_parser::parse("larger(1);").unwrap();
+        let errors = crate::resolve::check_with(&program, &options).unwrap_err();
+        assert_eq!(
+            errors.to_string(),
+            "function `larger` takes 2 arguments but 1 was given"
+        );
+    }
+
+    #[test]
+    fn test_math() {
+        let source = r"
+            let t = d0.Setting;
+            d1.Setting = abs(t) + sqrt(16);
+            d2.Setting = min(t, -3) * max(t, 2);
+            d3.Setting = floor(t) * 1000 + ceil(t) * 100 + round(t) * 10 + trunc(t);
+            db.Setting = round(2.5) + round(-0.5);
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        simulator.write(Device::D0, DeviceVariable::Setting, -2.7);
+        simulator.tick();
+        let read = |device| simulator.read(device, DeviceVariable::Setting);
+        assert_eq!(read(Device::D1), 6.7);
+        assert_eq!(read(Device::D2), -3.0 * 2.0);
+        assert_eq!(read(Device::D3), -3000.0 - 200.0 - 30.0 - 2.0);
+        // Halfway values round to even, like in the game.
+        assert_eq!(read(Device::Db), 2.0);
+    }
+
+    #[test]
+    fn test_slots() {
+        // Counts the occupied slots of a vending machine, and locks the first one.
+        let source = r"
+            let count = 0;
+            let slot = 0;
+            loop {
+                if slot >= 3 {
+                    break;
+                }
+                count = count + load_slot(d0, slot, Occupied);
+                slot = slot + 1;
+            }
+            db.Setting = count;
+            store_slot(d0, 0, Lock, 1);
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        simulator.write_slot(Device::D0, 0, DeviceVariable::Occupied, 1.0);
+        simulator.write_slot(Device::D0, 2, DeviceVariable::Occupied, 1.0);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 2.0);
+        assert_eq!(
+            simulator.read_slot(Device::D0, 0, DeviceVariable::Lock),
+            1.0
+        );
+        assert_eq!(
+            simulator.read_slot(Device::D0, 1, DeviceVariable::Lock),
+            0.0
+        );
+
+        let program = ayysee_parser::parse("db.Setting = load_slot(d0, -1, Occupied);").unwrap();
+        assert!(crate::ir::generate_program(program).is_err());
+    }
+
+    #[test]
+    fn test_reagents() {
+        // Stops the furnace once it holds enough iron for the recipe.
+        let source = r"
+            const IRON = -654790771;
+            let missing = load_reagent(d0, Required, IRON) - load_reagent(d0, Contents, IRON);
+            db.On = missing > 0;
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        simulator.write_reagent(Device::D0, ReagentMode::Required, -654790771.0, 10.0);
+        simulator.write_reagent(Device::D0, ReagentMode::Contents, -654790771.0, 4.0);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::On), 1.0);
+
+        let program = ayysee_parser::parse("db.On = load_reagent(d0, Average, 1);").unwrap();
+        assert!(crate::ir::generate_program(program).is_err());
+    }
+
+    #[test]
+    fn test_is_set() {
+        let source = r"
+            loop {
+                if is_set(d0) {
+                    db.Setting = d0.Temperature;
+                } else {
+                    db.Setting = -1;
+                }
+                d1.On = is_set(d2);
+                yield;
+            }
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O2, OptLevel::Os] {
+            let options = CompileOptions {
+                opt_level,
+                ..Default::default()
+            };
+            let program = ayysee_parser::parse(source).unwrap();
+            let mips = crate::ir::generate_program_with(program, &options).unwrap();
+            if opt_level == OptLevel::Os {
+                assert!(mips.to_string().contains("bdns d0"), "{}", mips);
+            }
+            let mut simulator = Simulator::new(mips);
+            simulator.write(Device::D0, DeviceVariable::Temperature, 300.0);
+            simulator.set_connected(Device::D2, false);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 300.0);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::On), 0.0);
+            simulator.set_connected(Device::D0, false);
+            simulator.set_connected(Device::D2, true);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), -1.0);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::On), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_nan() {
+        // Falls back to a safe setting while the sensor reads garbage.
+        let source = r"
+            let t = d0.Temperature;
+            db.On = isnan(t);
+            db.Mode = isfinite(t);
+            db.Setting = max(t, 0) + 1;
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        assert!(mips.to_string().contains("snan"), "{}", mips);
+        for (t, nan, finite) in [
+            (300.0, 0.0, 1.0),
+            (f64::INFINITY, 0.0, 0.0),
+            (f64::NAN, 1.0, 0.0),
+        ] {
+            let mut simulator = Simulator::new(mips.clone());
+            simulator.write(Device::D0, DeviceVariable::Temperature, t);
+            simulator.tick();
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::On), nan, "{}", t);
+            assert_eq!(
+                simulator.read(Device::Db, DeviceVariable::Mode),
+                finite,
+                "{}",
+                t
+            );
+            // NaN goes through arithmetic like in the game, `max` included.
+            let setting = simulator.read(Device::Db, DeviceVariable::Setting);
+            assert_eq!(setting.is_nan(), t.is_nan(), "{}", t);
+        }
+    }
+
+    #[test]
+    fn test_clamp() {
+        // The upper bound is last used by the call, so the result can take its register.
+        let source = r"
+            let high = d1.Setting;
+            db.Setting = clamp(d0.Temperature, 0, high);
+            db.On = clamp(d0.Temperature, d1.Setting, d1.Setting);
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O2, OptLevel::Os] {
+            let options = CompileOptions {
+                opt_level,
+                ..Default::default()
+            };
+            let program = ayysee_parser::parse(source).unwrap();
+            let mips = crate::ir::generate_program_with(program, &options).unwrap();
+            let text = mips.to_string();
+            // A `max` and a `min` per call, without branches.
+            assert_eq!(text.matches("max").count(), 2, "{}", text);
+            assert!(!text.lines().any(|line| line.starts_with('b')), "{}", text);
+            for (t, setting) in [(-5.0, 0.0), (5.0, 5.0), (50.0, 10.0)] {
+                let mut simulator = Simulator::new(mips.clone());
+                simulator.write(Device::D0, DeviceVariable::Temperature, t);
+                simulator.write(Device::D1, DeviceVariable::Setting, 10.0);
+                simulator.tick();
+                let read = |variable| simulator.read(Device::Db, variable);
+                assert_eq!(read(DeviceVariable::Setting), setting, "{}\n{}", t, text);
+                assert_eq!(read(DeviceVariable::On), 10.0, "{}\n{}", t, text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rand() {
+        let source = r"
+            rand();
+            d0.Setting = rand();
+            d1.Setting = rand();
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        assert_eq!(mips.to_string().matches("rand").count(), 3, "{}", mips);
+        let run = |seed| {
+            let mut simulator = Simulator::with_seed(mips.clone(), seed);
+            simulator.tick();
+            let read = |device| simulator.read(device, DeviceVariable::Setting);
+            (read(Device::D0), read(Device::D1))
+        };
+        let (a, b) = run(42);
+        assert!((0.0..1.0).contains(&a) && (0.0..1.0).contains(&b));
+        assert_ne!(a, b);
+        assert_eq!(run(42), (a, b));
+        assert_ne!(run(43), (a, b));
+    }
+
+    #[test]
+    fn test_batch() {
+        // Turns the heaters on while the average temperature of the sensors is low.
+        let source = r"
+            const SENSOR = -1252983604;
+            const HEATER = 24258244;
+            let t = load_batch(SENSOR, Temperature, Average);
+            store_batch(HEATER, On, t < 290);
+            db.Setting = load_batch(SENSOR, Temperature, Maximum);
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        let sensors = [280.0, 290.0].map(|t| {
+            let sensor = simulator.connect(-1252983604.0);
+            simulator.write_network(sensor, DeviceVariable::Temperature, t);
+            sensor
+        });
+        let heater = simulator.connect(24258244.0);
+        simulator.tick();
+        assert_eq!(simulator.read_network(heater, DeviceVariable::On), 1.0);
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 290.0);
+        assert_eq!(simulator.read_network(sensors[0], DeviceVariable::On), 0.0);
+
+        let program = ayysee_parser::parse("db.Setting = load_batch(1, On, Bogus);").unwrap();
+        assert!(crate::ir::generate_program(program).is_err());
+    }
+
+    #[test]
+    fn test_batch_named() {
+        let source = r"
+            const PUMP = -321403609;
+            const INLET = 101;
+            const OUTLET = 102;
+            let p = load_batch_named(PUMP, INLET, Pressure, Maximum);
+            store_batch_named(PUMP, OUTLET, On, p > 1000);
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        let inlet = simulator.connect_named(-321403609.0, 101.0);
+        let outlet = simulator.connect_named(-321403609.0, 102.0);
+        simulator.write_network(inlet, DeviceVariable::Pressure, 1500.0);
+        simulator.tick();
+        assert_eq!(simulator.read_network(outlet, DeviceVariable::On), 1.0);
+        assert_eq!(simulator.read_network(inlet, DeviceVariable::On), 0.0);
+    }
+
+    #[test]
+    fn test_trigonometry() {
+        // Points a solar panel at the sun, from its direction relative to the panel.
+        let source = r"
+            const PI = 3.141592653589793;
+            let x = d0.Setting;
+            let y = d1.Setting;
+            d2.Setting = atan2(y, x) * 180 / PI;
+            d3.Setting = asin(sin(y)) + acos(cos(x)) + atan(tan(0.5));
+        ";
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        simulator.write(Device::D0, DeviceVariable::Setting, 1.0);
+        simulator.write(Device::D1, DeviceVariable::Setting, 1.0);
+        simulator.tick();
+        let read = |device| simulator.read(device, DeviceVariable::Setting);
+        assert!((read(Device::D2) - 45.0).abs() < 1e-9);
+        assert!((read(Device::D3) - 2.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,25 @@
+//! What the generated program is made of, for tools that show compiler internals next to
+//! the source, such as the language server.
+
+use ayysee_parser::ast::Span;
+use stationeers_mips::types::Register;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    /// The registers holding each variable of the program (functions excluded), in the
+    /// order the values are assigned. Variables whose values were all inlined are missing.
+    pub registers: BTreeMap<String, Vec<Register>>,
+    /// The statement of the source each line of the program was generated for. Lines
+    /// without a statement, such as `alias` lines, have none.
+    pub lines: Vec<Option<Span>>,
+}
+
+impl DebugInfo {
+    /// Returns the statement the line of the program was generated for, such as the line a
+    /// [`Fault`](crate::simulator::Fault) happened on or the `LineNumber` of an IC stopped
+    /// by an error in game.
+    pub fn statement(&self, line: usize) -> Option<Span> {
+        self.lines.get(line).copied().flatten()
+    }
+}
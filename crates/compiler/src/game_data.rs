@@ -0,0 +1,196 @@
+//! The names of the game programs can refer to, loaded from data files so that a game
+//! update adding logic types does not need a new compiler.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+
+/// How batch instructions combine the values of several devices. They are part of the
+/// instruction set rather than of the game data.
+const BATCH_MODES: [&str; 4] = ["Average", "Sum", "Minimum", "Maximum"];
+/// Which amounts of reagents `lr` reads.
+const REAGENT_MODES: [&str; 3] = ["Contents", "Required", "Recipe"];
+
+/// Whether `name` is a batch or reagent mode.
+pub(crate) fn is_mode(name: &str) -> bool {
+    BATCH_MODES.contains(&name) || REAGENT_MODES.contains(&name)
+}
+
+/// The device pins, logic types and prefabs known to the compiler.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GameData {
+    pub devices: Vec<String>,
+    pub logic_types: Vec<String>,
+    /// The logic types of the items in device slots.
+    #[serde(default)]
+    pub slot_logic_types: Vec<String>,
+    /// The hash of every prefab, by prefab name, as used by batch instructions.
+    #[serde(default)]
+    pub prefabs: BTreeMap<String, i64>,
+}
+
+impl Default for GameData {
+    /// The tables bundled with the compiler.
+    fn default() -> Self {
+        static BUNDLED: OnceLock<GameData> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| {
+                GameData::parse(include_str!("../data/game_data.toml"))
+                    .expect("the bundled game data is valid")
+            })
+            .clone()
+    }
+}
+
+impl GameData {
+    /// Parses a game data file, in the TOML format of `data/game_data.toml`.
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    /// Returns the names available to every program as constants.
+    pub fn externals(&self) -> impl Iterator<Item = &str> {
+        self.devices
+            .iter()
+            .chain(&self.logic_types)
+            .chain(&self.slot_logic_types)
+            .map(String::as_str)
+            .chain(BATCH_MODES)
+            .chain(REAGENT_MODES)
+    }
+
+    pub fn is_external(&self, name: &str) -> bool {
+        self.externals().any(|external| external == name)
+    }
+
+    /// Imports the logic types, slot logic types and prefab hashes of the `Stationpedia.json`
+    /// exported from the game. The game does not list the device pins, so the bundled ones
+    /// are kept.
+    pub fn from_stationpedia(source: &str) -> anyhow::Result<Self> {
+        let stationpedia: Stationpedia = serde_json::from_str(source)?;
+        let mut logic_types = BTreeSet::new();
+        let mut slot_logic_types = BTreeSet::new();
+        let mut prefabs = BTreeMap::new();
+        for page in stationpedia.pages {
+            logic_types.extend(page.logic.iter().map(|logic| strip_tags(&logic.name)));
+            slot_logic_types.extend(page.slot_logic.iter().map(|logic| strip_tags(&logic.name)));
+            if let (Some(name), Some(hash)) = (page.prefab_name, page.prefab_hash) {
+                prefabs.insert(name, hash);
+            }
+        }
+        anyhow::ensure!(
+            !logic_types.is_empty(),
+            "the Stationpedia export has no logic types"
+        );
+        Ok(Self {
+            devices: Self::default().devices,
+            logic_types: logic_types.into_iter().collect(),
+            slot_logic_types: slot_logic_types.into_iter().collect(),
+            prefabs,
+        })
+    }
+
+    /// Writes the game data in the format read by [`GameData::parse`].
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+/// The parts of the game's Stationpedia export the compiler uses.
+#[derive(Deserialize)]
+struct Stationpedia {
+    pages: Vec<Page>,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    #[serde(rename = "PrefabName")]
+    prefab_name: Option<String>,
+    #[serde(rename = "PrefabHash")]
+    prefab_hash: Option<i64>,
+    #[serde(rename = "LogicInsert", default)]
+    logic: Vec<Logic>,
+    #[serde(rename = "LogicSlotInsert", default)]
+    slot_logic: Vec<Logic>,
+}
+
+#[derive(Deserialize)]
+struct Logic {
+    #[serde(rename = "LogicName")]
+    name: String,
+}
+
+/// Removes the rich text tags (`<color=orange>`, `<link=...>`) the Stationpedia wraps
+/// names in.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_game_data() {
+        let bundled = GameData::default();
+        assert!(bundled.is_external("d0"));
+        assert!(bundled.is_external("Temperature"));
+        assert!(!bundled.is_external("Bogus"));
+        assert!(bundled.is_external("Maximum"));
+        assert!(bundled.is_external("Occupied"));
+        assert!(bundled.is_external("Recipe"));
+
+        let custom = GameData::parse("devices = [\"d0\"]\nlogic_types = [\"Bogus\"]\n").unwrap();
+        assert!(custom.is_external("Bogus"));
+        assert!(!custom.is_external("Temperature"));
+        assert!(GameData::parse("devices = []\n").is_err());
+    }
+
+    #[test]
+    fn test_stationpedia() {
+        let source = r#"{
+            "pages": [
+                {
+                    "Key": "ThingStructureGasSensor",
+                    "PrefabName": "StructureGasSensor",
+                    "PrefabHash": -1252983604,
+                    "LogicInsert": [
+                        {"LogicName": "Temperature", "LogicAccessTypes": "Read"},
+                        {"LogicName": "<link=LogicTypePressure><color=orange>Pressure</color></link>", "LogicAccessTypes": "Read"}
+                    ]
+                },
+                {
+                    "Key": "ThingStructureVendingMachine",
+                    "PrefabName": "StructureVendingMachine",
+                    "PrefabHash": -443130773,
+                    "LogicInsert": [{"LogicName": "On", "LogicAccessTypes": "Read Write"}],
+                    "LogicSlotInsert": [{"LogicName": "Occupied", "LogicAccessTypes": "0, 1"}]
+                },
+                {"Key": "LogicTypeOn", "Title": "On"}
+            ],
+            "reagents": {}
+        }"#;
+        let imported = GameData::from_stationpedia(source).unwrap();
+        assert_eq!(imported.devices, GameData::default().devices);
+        assert_eq!(imported.logic_types, ["On", "Pressure", "Temperature"]);
+        assert_eq!(imported.slot_logic_types, ["Occupied"]);
+        assert_eq!(imported.prefabs["StructureGasSensor"], -1252983604);
+        assert_eq!(
+            GameData::parse(&imported.to_toml().unwrap()).unwrap(),
+            imported
+        );
+
+        assert!(GameData::from_stationpedia(r#"{"pages": []}"#).is_err());
+    }
+}
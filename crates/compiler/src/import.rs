@@ -0,0 +1,431 @@
+//! `import "path";` statements, which bring the functions and constants of other files into
+//! a program.
+//!
+//! The parser keeps the statements, and [`expand`] replaces those of the top level with the
+//! functions and constants of the files they name, before the program is compiled. A file
+//! imported several times, directly or through other files, is only added once, and the
+//! files it imports come before it. The imported names are not prefixed, so a name defined
+//! in two of the files is an error.
+//!
+//! The source of the program is the only one debug info and diagnostics know of, so the
+//! imported statements point at the `import` of the program they come through. Errors in an
+//! imported file are found by checking it on its own, and reported as [`ImportErrors`].
+
+use crate::resolve::{SemanticError, SemanticErrors};
+use crate::CompileOptions;
+use anyhow::Context;
+use ayysee_parser::ast::StatementKind;
+use ayysee_parser::ast::{Block, Expr, ExprKind, Identifier, Program, Span, Statement};
+use ayysee_parser::visit::{walk_block_mut, walk_expr, walk_expr_mut, walk_statement_mut};
+use ayysee_parser::visit::{Visitor, VisitorMut};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The errors of a file imported by the program.
+#[derive(Debug)]
+pub struct ImportErrors {
+    /// The file, as named from the directory of the program.
+    pub path: PathBuf,
+    /// The source of the file, which the spans of the errors point into.
+    pub source: String,
+    /// The `import` statement of the program the file is imported through.
+    pub import: Span,
+    pub errors: Vec<SemanticError>,
+}
+
+impl std::fmt::Display for ImportErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", self.path.display(), error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ImportErrors {}
+
+/// Replaces the `import` statements of the top level of the program, read from `path`,
+/// with the statements of the files they import, named relative to the importing file.
+///
+/// Imported files can only hold functions, constants and imports of their own.
+pub fn expand(program: &mut Program, path: &Path, options: &CompileOptions) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut importer = Importer {
+        options,
+        chain: vec![(
+            std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            path.to_path_buf(),
+        )],
+        modules: HashMap::new(),
+        defined: HashMap::new(),
+        import: Span::default(),
+    };
+    let mut imports = vec![];
+    for statement in &program.statements {
+        if let StatementKind::Import(name) = &statement.kind {
+            importer.import = statement.span;
+            imports.push((importer.load(dir, name)?, statement.span));
+        }
+    }
+    if imports.is_empty() {
+        return Ok(());
+    }
+
+    let errors = program
+        .statements
+        .iter()
+        .filter_map(|statement| {
+            let identifier = definition(statement)?;
+            let other = importer.defined.get::<str>(identifier.as_ref())?;
+            Some(SemanticError {
+                message: already_defined(identifier, other),
+                span: identifier.span,
+            })
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(SemanticErrors(errors).into());
+    }
+
+    let mut statements = vec![];
+    let mut added = vec![];
+    for (path, import) in imports {
+        let mut order = vec![];
+        importer.closure(&[path], &mut order);
+        for path in order {
+            if added.contains(&path) {
+                continue;
+            }
+            for mut statement in importer.modules[&path].statements.iter().cloned() {
+                Relocate(import).visit_statement_mut(&mut statement);
+                statements.push(statement);
+            }
+            added.push(path);
+        }
+    }
+    statements.extend(
+        program
+            .statements
+            .drain(..)
+            .filter(|statement| !matches!(statement.kind, StatementKind::Import(_))),
+    );
+    program.statements = statements;
+    Ok(())
+}
+
+/// A file imported by the program.
+struct Module {
+    /// The functions and constants of the file.
+    statements: Vec<Statement>,
+    /// The files it imports, by their canonical path.
+    imports: Vec<PathBuf>,
+}
+
+struct Importer<'a> {
+    options: &'a CompileOptions,
+    /// The canonical and given paths of the files being imported, each importing the next,
+    /// starting with the program.
+    chain: Vec<(PathBuf, PathBuf)>,
+    /// The files imported so far, by their canonical path.
+    modules: HashMap<PathBuf, Module>,
+    /// The file each imported function and constant is defined in.
+    defined: HashMap<String, PathBuf>,
+    /// The `import` statement of the program the files are being imported through.
+    import: Span,
+}
+
+impl Importer<'_> {
+    /// Imports the file `name` of the directory `dir`, returning its canonical path.
+    fn load(&mut self, dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+        let path = dir.join(name);
+        let canonical = std::fs::canonicalize(&path)
+            .with_context(|| format!("failed to import {}", path.display()))?;
+        if let Some(start) = self.chain.iter().position(|(p, _)| *p == canonical) {
+            let cycle = self.chain[start..]
+                .iter()
+                .map(|(_, path)| path)
+                .chain([&path])
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            anyhow::bail!("files import each other: {}", cycle.join(" imports "));
+        }
+        if self.modules.contains_key(&canonical) {
+            return Ok(canonical);
+        }
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to import {}", path.display()))?;
+        let import = self.import;
+        let errors = |errors| ImportErrors {
+            path: path.clone(),
+            source: source.clone(),
+            import,
+            errors,
+        };
+        let parsed = match ayysee_parser::parse(&source) {
+            Ok(parsed) => parsed,
+            Err(e) => return Err(errors(e.into_iter().map(syntax_error).collect()).into()),
+        };
+
+        self.chain.push((canonical.clone(), path.clone()));
+        let mut module = Module {
+            statements: vec![],
+            imports: vec![],
+        };
+        let mut problems = vec![];
+        for statement in parsed.statements {
+            if let StatementKind::Import(name) = &statement.kind {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                module.imports.push(self.load(dir, name)?);
+                continue;
+            }
+            let Some(identifier) = definition(&statement) else {
+                problems.push(SemanticError {
+                    message: "an imported file can only hold functions, constants and imports"
+                        .to_string(),
+                    span: statement.span,
+                });
+                continue;
+            };
+            match self.defined.get::<str>(identifier.as_ref()) {
+                Some(other) if *other != path => problems.push(SemanticError {
+                    message: already_defined(identifier, other),
+                    span: identifier.span,
+                }),
+                _ => {
+                    self.defined.insert(identifier.to_string(), path.clone());
+                }
+            }
+            // The arrays of the program are told apart by where they are declared, which is
+            // the `import` for all those of imported files.
+            let mut arrays = Arrays(vec![]);
+            arrays.visit_statement(&statement);
+            problems.extend(arrays.0.into_iter().map(|span| SemanticError {
+                message: "functions of an imported file cannot declare arrays".to_string(),
+                span,
+            }));
+            module.statements.push(statement);
+        }
+        self.chain.pop();
+
+        if problems.is_empty() {
+            // The file is checked with the files it imports, as if it were the program.
+            let mut order = vec![];
+            self.closure(&module.imports, &mut order);
+            let statements = order
+                .iter()
+                .flat_map(|path| self.modules[path].statements.iter().cloned())
+                .chain(module.statements.iter().cloned())
+                .collect();
+            if let Err(SemanticErrors(e)) =
+                crate::resolve::check_with(&Program::new(statements), self.options)
+            {
+                problems = e;
+            }
+        }
+        if !problems.is_empty() {
+            return Err(errors(problems).into());
+        }
+        self.modules.insert(canonical.clone(), module);
+        Ok(canonical)
+    }
+
+    /// Adds the files of `imports` to `order` with the files they import, directly or not,
+    /// each after those it imports and only once.
+    fn closure(&self, imports: &[PathBuf], order: &mut Vec<PathBuf>) {
+        for path in imports {
+            if !order.contains(path) {
+                self.closure(&self.modules[path].imports, order);
+                order.push(path.clone());
+            }
+        }
+    }
+}
+
+/// Returns the name of the function or constant the statement defines.
+fn definition(statement: &Statement) -> Option<&Identifier> {
+    match &statement.kind {
+        StatementKind::Function { identifier, .. } | StatementKind::Constant(identifier, _) => {
+            Some(identifier)
+        }
+        _ => None,
+    }
+}
+
+fn already_defined(identifier: &Identifier, path: &Path) -> String {
+    format!("`{}` is already defined in {}", identifier, path.display())
+}
+
+fn syntax_error(error: ayysee_parser::ParseError<'_>) -> SemanticError {
+    let (message, start, end) = match error {
+        ayysee_parser::ParseError::InvalidToken { location } => {
+            ("invalid token".to_string(), location, location + 1)
+        }
+        ayysee_parser::ParseError::UnrecognizedEOF { location, .. } => {
+            ("unexpected end of file".to_string(), location, location)
+        }
+        ayysee_parser::ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            ..
+        } => (format!("unexpected token `{}`", token), start, end),
+        ayysee_parser::ParseError::ExtraToken {
+            token: (start, token, end),
+        } => (format!("extra token `{}`", token), start, end),
+        ayysee_parser::ParseError::User { error } => {
+            (error.to_string(), error.span.start, error.span.end)
+        }
+    };
+    SemanticError {
+        message,
+        span: Span::new(start, end),
+    }
+}
+
+/// Moves every node to the span of an `import`.
+struct Relocate(Span);
+
+impl VisitorMut for Relocate {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        statement.span = self.0;
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        block.span = self.0;
+        walk_block_mut(self, block)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        expr.span = self.0;
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+        identifier.span = self.0;
+    }
+}
+
+/// Collects the spans of the array declarations.
+struct Arrays(Vec<Span>);
+
+impl Visitor for Arrays {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::Array(..) = expr.kind {
+            self.0.push(expr.span);
+        }
+        walk_expr(self, expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::Simulator;
+    use stationeers_mips::types::{Device, DeviceVariable};
+
+    /// Writes the files in a new directory, returning it.
+    fn write(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("galvanic-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, source) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn expand_file(path: &Path) -> anyhow::Result<Program> {
+        let source = std::fs::read_to_string(path).unwrap();
+        let mut program = ayysee_parser::parse(&source).unwrap();
+        expand(&mut program, path, &CompileOptions::default())?;
+        Ok(program)
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = write(
+            "imports",
+            &[
+                (
+                    "main.ayy",
+                    "import \"lib/pid.ayy\";\nimport \"lib/math.ayy\";\ndb.Setting = step(d0.Temperature, TARGET);\n",
+                ),
+                (
+                    "lib/pid.ayy",
+                    "import \"math.ayy\";\nconst TARGET = 300;\nfn step(x, target) { return double(target - x); }\n",
+                ),
+                ("lib/math.ayy", "fn double(x) { return x * 2; }\n"),
+            ],
+        );
+        let program = expand_file(&dir.join("main.ayy")).unwrap();
+        assert_eq!(
+            program.to_string(),
+            "fn double(x) {\n    return x * 2;\n}\n\nconst TARGET = 300;\n\nfn step(x, target) {\n    return double(target - x);\n}\n\ndb.Setting = step(d0.Temperature, TARGET);\n"
+        );
+        let import = Span::new(0, "import \"lib/pid.ayy\";".len());
+        assert!(program.statements[..3].iter().all(|s| s.span == import));
+
+        let mut simulator = Simulator::new(crate::ir::generate_program(program).unwrap());
+        simulator.write(Device::D0, DeviceVariable::Temperature, 290.0);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 20.0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = write(
+            "import-errors",
+            &[
+                ("cycle.ayy", "import \"a.ayy\";\n"),
+                ("a.ayy", "import \"b.ayy\";\nconst A = 1;\n"),
+                ("b.ayy", "import \"a.ayy\";\n"),
+                ("twice.ayy", "import \"c.ayy\";\nconst C = 2;\n"),
+                ("c.ayy", "const C = 1;\nd0.On = 1;\nfn f() { return x; }\n"),
+            ],
+        );
+        let error = expand_file(&dir.join("cycle.ayy")).unwrap_err();
+        let (a, b) = (dir.join("a.ayy"), dir.join("b.ayy"));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "files import each other: {} imports {} imports {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+
+        let error = expand_file(&dir.join("twice.ayy")).unwrap_err();
+        let errors = error.downcast_ref::<ImportErrors>().unwrap();
+        assert_eq!(errors.path, dir.join("c.ayy"));
+        assert_eq!(
+            errors
+                .errors
+                .iter()
+                .map(|e| (e.message.as_str(), &errors.source[e.span.start..e.span.end]))
+                .collect::<Vec<_>>(),
+            vec![(
+                "an imported file can only hold functions, constants and imports",
+                "d0.On = 1;"
+            )]
+        );
+
+        std::fs::write(dir.join("c.ayy"), "const C = 1;\nfn f() { return x; }\n").unwrap();
+        let error = expand_file(&dir.join("twice.ayy")).unwrap_err();
+        let errors = error.downcast_ref::<ImportErrors>().unwrap();
+        assert_eq!(errors.errors[0].message, "cannot find variable `x`");
+
+        std::fs::write(dir.join("c.ayy"), "const C = 1;\n").unwrap();
+        let error = expand_file(&dir.join("twice.ayy")).unwrap_err();
+        let errors = error.downcast_ref::<SemanticErrors>().unwrap();
+        assert_eq!(
+            errors.0[0].message,
+            format!("`C` is already defined in {}", dir.join("c.ayy").display())
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+//! Compiling a program again after an edit, for the language server and other tools that
+//! compile on every change.
+//!
+//! Functions are inlined where they are called, so the code of a program is made of its
+//! top-level statements and the functions they call. A [`Session`] remembers the last
+//! compilation and reuses it as long as none of these changed, only moved: edits to
+//! comments, formatting, or functions the program does not call, such as those of an
+//! included module used by other programs. Otherwise the program is compiled again, with
+//! its variables kept in the registers they had where possible, so that the output changes
+//! no more than the edit requires.
+//!
+//! The unit of reuse is the whole program rather than each function: the functions called
+//! once are inlined into their caller, and registers are allocated across the whole
+//! program, so a function changing can change the code of the others.
+
+use crate::{CompileOptions, DebugInfo};
+use ayysee_parser::ast::{
+    Block, Expr, ExprKind, Identifier, Program, Span, Statement, StatementKind,
+};
+use ayysee_parser::visit::{walk_block_mut, walk_expr, walk_expr_mut, walk_statement};
+use ayysee_parser::visit::{walk_statement_mut, Visitor, VisitorMut};
+use stationeers_mips as mips;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+#[derive(Default)]
+pub struct Session {
+    previous: Option<Previous>,
+    reused: bool,
+}
+
+struct Previous {
+    key: u64,
+    /// The spans of the statements of the code, in the order [`code`] lists them.
+    spans: Vec<Span>,
+    program: mips::Program,
+    debug_info: DebugInfo,
+}
+
+impl Session {
+    /// Compiles the program, reusing the last compilation of the session when the code did
+    /// not change.
+    pub fn compile(
+        &mut self,
+        program: Program,
+        options: &CompileOptions,
+    ) -> anyhow::Result<(mips::Program, DebugInfo)> {
+        let code = code(&program);
+        let key = key(&code, options);
+        let spans = spans(&code);
+        self.reused = false;
+        if let Some(previous) = self.previous.as_mut().filter(|p| p.key == key) {
+            // Functions the program does not call can still have errors.
+            crate::resolve::check_with(&program, options)?;
+            // The statements are the same, in the same order, so only their spans moved.
+            let moved = previous
+                .spans
+                .iter()
+                .copied()
+                .zip(spans.iter().copied())
+                .collect::<HashMap<_, _>>();
+            for span in previous.debug_info.lines.iter_mut().flatten() {
+                *span = moved.get(span).copied().unwrap_or(*span);
+            }
+            previous.spans = spans;
+            self.reused = true;
+            return Ok((previous.program.clone(), previous.debug_info.clone()));
+        }
+
+        let hints = self
+            .previous
+            .as_ref()
+            .map(|p| p.debug_info.registers.clone())
+            .unwrap_or_default();
+        let (mips, debug_info) = crate::ir::generate_program_with_hints(program, options, &hints)?;
+        self.previous = Some(Previous {
+            key,
+            spans,
+            program: mips.clone(),
+            debug_info: debug_info.clone(),
+        });
+        Ok((mips, debug_info))
+    }
+
+    /// Returns whether the last call to [`Session::compile`] reused the compilation before.
+    pub fn reused(&self) -> bool {
+        self.reused
+    }
+}
+
+/// Returns the statements the program is compiled from: the top-level statements, then the
+/// functions they call, directly or not, by name.
+fn code(program: &Program) -> Vec<&Statement> {
+    let mut functions = Functions::default();
+    for statement in &program.statements {
+        functions.visit_statement(statement);
+    }
+    let mut calls = Calls::default();
+    let mut code = vec![];
+    for statement in &program.statements {
+        if !matches!(statement.kind, StatementKind::Function { .. }) {
+            calls.visit_statement(statement);
+            code.push(statement);
+        }
+    }
+    let mut called = BTreeMap::new();
+    while let Some(name) = calls.0.pop() {
+        if called.contains_key(&name) {
+            continue;
+        }
+        // Builtins and the prelude are part of the compiler.
+        let Some(function) = functions.0.get(&name) else {
+            continue;
+        };
+        calls.visit_statement(function);
+        called.insert(name, *function);
+    }
+    code.extend(called.into_values());
+    code
+}
+
+/// Identifies the code and the options it is compiled with, ignoring where the statements
+/// are and their comments, and the options that do not change the output.
+fn key(code: &[&Statement], options: &CompileOptions) -> u64 {
+    let CompileOptions {
+        opt_level,
+        target,
+        game_data,
+        builtins,
+        aliases,
+        definitions,
+        profile: _,
+        unknown_logic_types,
+    } = options;
+    let mut hasher = DefaultHasher::new();
+    format!(
+        "{:?}",
+        (
+            opt_level,
+            target,
+            game_data,
+            builtins,
+            aliases,
+            definitions,
+            unknown_logic_types
+        )
+    )
+    .hash(&mut hasher);
+    for statement in code {
+        let mut statement = (*statement).clone();
+        Normalize.visit_statement_mut(&mut statement);
+        format!("{:?}", statement).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn spans(code: &[&Statement]) -> Vec<Span> {
+    let mut spans = Spans::default();
+    for statement in code {
+        spans.visit_statement(statement);
+    }
+    spans.0
+}
+
+/// Collects the functions defined anywhere in the program, by name.
+#[derive(Default)]
+struct Functions<'a>(HashMap<String, &'a Statement>);
+
+impl<'a> Functions<'a> {
+    fn visit_statement(&mut self, statement: &'a Statement) {
+        if let StatementKind::Function { identifier, .. } = &statement.kind {
+            self.0.insert(identifier.to_string(), statement);
+        }
+        for block in crate::resolve::blocks(statement) {
+            for statement in block.statements() {
+                self.visit_statement(statement);
+            }
+        }
+    }
+}
+
+/// Collects the names of the functions called.
+#[derive(Default)]
+struct Calls(Vec<String>);
+
+impl Visitor for Calls {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let StatementKind::FunctionCall { identifier, .. } = &statement.kind {
+            self.0.push(identifier.to_string());
+        }
+        walk_statement(self, statement)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::FunctionCall(identifier, _) = &expr.kind {
+            self.0.push(identifier.to_string());
+        }
+        walk_expr(self, expr)
+    }
+}
+
+#[derive(Default)]
+struct Spans(Vec<Span>);
+
+impl Visitor for Spans {
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.0.push(statement.span);
+        walk_statement(self, statement)
+    }
+}
+
+/// Clears the spans and comments of a statement.
+struct Normalize;
+
+impl VisitorMut for Normalize {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        statement.span = Span::default();
+        statement.trivia = Default::default();
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        block.span = Span::default();
+        walk_block_mut(self, block)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        expr.span = Span::default();
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+        identifier.span = Span::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(session: &mut Session, source: &str) -> (String, DebugInfo) {
+        let program = ayysee_parser::parse(source).unwrap();
+        let (mips, debug_info) = session
+            .compile(program, &CompileOptions::default())
+            .unwrap();
+        (mips.to_string(), debug_info)
+    }
+
+    #[test]
+    fn test_reuses_unchanged_code() {
+        let source = r"
+            fn twice(x) {
+                return x * 2;
+            }
+            fn unused(x) {
+                return x;
+            }
+            let t = d0.Temperature;
+            db.Setting = twice(t);
+        ";
+        let mut session = Session::default();
+        let (mips, _) = compile(&mut session, source);
+        assert!(!session.reused());
+
+        // Comments, and functions the program does not call.
+        let edited = format!(
+            "// Heating\n{}",
+            source.replace("return x;", "return x + 1;")
+        );
+        let (reused, debug_info) = compile(&mut session, &edited);
+        assert!(session.reused());
+        assert_eq!(reused, mips);
+        // The statements moved with the comment.
+        let (_, fresh) = compile(&mut Session::default(), &edited);
+        assert_eq!(debug_info.lines, fresh.lines);
+
+        // Profiling does not change the output.
+        let options = CompileOptions {
+            profile: Some(Default::default()),
+            ..Default::default()
+        };
+        let program = ayysee_parser::parse(&edited).unwrap();
+        session.compile(program, &options).unwrap();
+        assert!(session.reused());
+
+        compile(&mut session, &edited.replace("x * 2", "x * 3"));
+        assert!(!session.reused());
+
+        // Errors in functions the program does not call are still reported.
+        let program = ayysee_parser::parse(&edited.replace("return x + 1;", "return y;")).unwrap();
+        assert!(session
+            .compile(program, &CompileOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_keeps_registers() {
+        let mut session = Session::default();
+        let (_, before) = compile(
+            &mut session,
+            "let a = d0.Temperature; let b = d1.Temperature; db.Setting = a + b;",
+        );
+        let edited = "let a = d0.Temperature; let z = d3.Temperature; let b = d1.Temperature;
+            db.Setting = a + b + z;";
+        let (_, after) = compile(&mut session, edited);
+        assert!(!session.reused());
+        assert_eq!(after.registers["a"], before.registers["a"]);
+        assert_eq!(after.registers["b"], before.registers["b"]);
+        // Compiled on its own, `a` moves to another register.
+        let (_, fresh) = compile(&mut Session::default(), edited);
+        assert_ne!(fresh.registers["a"], before.registers["a"]);
+    }
+}
@@ -1,30 +1,63 @@
 use super::types::{BlockId, VarId, VarOrConst, VarValue};
+use crate::builtin::Argument;
 use crate::ir;
 use crate::ir::register_allocation::RegisterAllocation;
+use crate::ir::OptLevel;
+use crate::{CompileOptions, DebugInfo, InstructionSet};
 use ayysee_parser::ast;
-use mips::types::{Register, RegisterOrNumber};
+use mips::types::{Device, Register, RegisterOrNumber};
 use stationeers_mips as mips;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
 
 struct State<'a> {
     mips_program: mips::instructions::Program,
     ir_program: &'a ir::Program,
-    registers: RegisterAllocation,
+    registers: &'a RegisterAllocation,
     block_start: HashMap<BlockId, usize>,
     // The location of jumps that want to jump to the end
     jump_to_end: Vec<usize>,
+    /// The line each called function starts at.
+    function_start: HashMap<String, usize>,
+    /// The location of the `jal`s to each function, set once all functions are generated.
+    calls: Vec<(usize, String)>,
+    /// Whether the function being generated saved `ra` on the stack, as it calls others.
+    saves_ra: bool,
+    options: &'a CompileOptions,
+    /// The statement each line was generated for, up to the lines of the current one.
+    lines: Vec<Option<ast::Span>>,
+    span: Option<ast::Span>,
 }
 
 impl<'a> State<'a> {
-    pub fn new(ir_program: &'a ir::Program) -> anyhow::Result<Self> {
-        let registers = RegisterAllocation::allocate(&ir_program)?;
-        Ok(Self {
-            mips_program: Default::default(),
+    /// Starts the program with the `prefix` lines.
+    pub fn new(
+        ir_program: &'a ir::Program,
+        registers: &'a RegisterAllocation,
+        options: &'a CompileOptions,
+        prefix: Vec<mips::instructions::Instruction>,
+    ) -> Self {
+        Self {
+            mips_program: mips::instructions::Program {
+                instructions: prefix,
+            },
             ir_program,
             registers,
             block_start: Default::default(),
             jump_to_end: Default::default(),
-        })
+            function_start: Default::default(),
+            calls: Default::default(),
+            saves_ra: false,
+            options,
+            lines: vec![],
+            span: None,
+        }
+    }
+
+    /// Attributes the lines generated since the last call to the current statement.
+    fn mark(&mut self) {
+        let len = self.mips_program.instructions.len();
+        self.lines.resize(len, self.span);
     }
 
     fn var_to_register(&self, v: &VarOrConst) -> RegisterOrNumber {
@@ -55,15 +88,35 @@ impl<'a> State<'a> {
         self.block_start
             .insert(block_id, self.mips_program.instructions.len());
         let block = &self.ir_program.blocks[block_id.0];
-        for ins in &block.instructions {
+        for (idx, ins) in block.instructions.iter().enumerate() {
+            let var = match ins {
+                ir::Instruction::Assignment { id, .. } => Some(id),
+                ir::Instruction::Branch {
+                    cond: VarOrConst::Var(id),
+                    ..
+                } => Some(id),
+                _ => None,
+            };
+            if let Some(span) = var.and_then(|id| self.ir_program.spans.get(id)) {
+                self.mark();
+                self.span = Some(*span);
+            }
             match ins {
+                ir::Instruction::Assignment { .. }
+                    if self
+                        .fused_comparison(&block.instructions, idx + 1)
+                        .is_some() =>
+                {
+                    // Generated as part of the branch that follows.
+                }
                 ir::Instruction::Assignment { id, value } => self.generate_assignment(id, value)?,
                 ir::Instruction::Branch {
                     cond,
                     true_block,
                     false_block,
                 } => {
-                    self.generate_branch(cond, true_block, false_block)?;
+                    let comparison = self.fused_comparison(&block.instructions, idx);
+                    self.generate_branch(cond, comparison, true_block, false_block)?;
                     return Ok(());
                 }
                 ir::Instruction::Yield => {
@@ -71,13 +124,41 @@ impl<'a> State<'a> {
                         .instructions
                         .push(mips::instructions::Instruction::new_yield());
                 }
-                ir::Instruction::Return(_) => {
+                ir::Instruction::Halt => {
+                    self.mips_program
+                        .instructions
+                        .push(mips::instructions::Misc::Halt.into());
+                    return Ok(());
+                }
+                ir::Instruction::Return(None) => {
+                    self.jump_to_end.push(self.mips_program.instructions.len());
+                    self.mips_program
+                        .instructions
+                        .push(mips::instructions::FlowControl::Jump { a: (-1.0).into() }.into());
+                    return Ok(());
+                }
+                ir::Instruction::Return(Some(value)) => {
+                    if self.saves_ra {
+                        self.mips_program.instructions.push(
+                            mips::instructions::Stack::Pop {
+                                register: Register::Ra,
+                            }
+                            .into(),
+                        );
+                    }
+                    self.mips_program.instructions.push(
+                        mips::instructions::Stack::Push {
+                            a: self.var_to_register(value),
+                        }
+                        .into(),
+                    );
                     self.mips_program.instructions.push(
                         mips::instructions::FlowControl::Jump {
                             a: Register::Ra.into(),
                         }
                         .into(),
                     );
+                    return Ok(());
                 }
             }
         }
@@ -97,13 +178,15 @@ impl<'a> State<'a> {
     fn generate_assignment(&mut self, id: &VarId, value: &VarValue) -> anyhow::Result<()> {
         let register = self.registers.get(*id).unwrap();
         match value {
-            VarValue::Single(simple) => self.mips_program.instructions.push(
-                mips::instructions::Misc::Move {
-                    register,
-                    a: self.var_to_register(simple),
+            VarValue::Single(simple) => {
+                let a = self.var_to_register(simple);
+                // The copies put in the register of their source need no line.
+                if !matches!(a, RegisterOrNumber::Register(r) if r == register) {
+                    self.mips_program
+                        .instructions
+                        .push(mips::instructions::Misc::Move { register, a }.into());
                 }
-                .into(),
-            ),
+            }
             VarValue::BinaryOp { lhs, op, rhs } => {
                 let a = self.var_to_register(lhs);
                 let b = self.var_to_register(rhs);
@@ -120,12 +203,28 @@ impl<'a> State<'a> {
                     ast::BinaryOpcode::Div => {
                         mips::instructions::Arithmetic::Divide { register, a, b }.into()
                     }
-                    ast::BinaryOpcode::Conj => {
+                    ast::BinaryOpcode::Mod => {
+                        mips::instructions::Arithmetic::Mod { register, a, b }.into()
+                    }
+                    ast::BinaryOpcode::Pow => {
+                        self.mips_program.instructions.extend(power(register, a, b));
+                        return Ok(());
+                    }
+                    ast::BinaryOpcode::BitAnd | ast::BinaryOpcode::Conj => {
                         mips::instructions::Logic::And { register, a, b }.into()
                     }
-                    ast::BinaryOpcode::Disj => {
+                    ast::BinaryOpcode::BitOr | ast::BinaryOpcode::Disj => {
                         mips::instructions::Logic::Or { register, a, b }.into()
                     }
+                    ast::BinaryOpcode::BitXor => {
+                        mips::instructions::Logic::Xor { register, a, b }.into()
+                    }
+                    ast::BinaryOpcode::ShiftLeft => {
+                        mips::instructions::Logic::ShiftLeftLogical { register, a, b }.into()
+                    }
+                    ast::BinaryOpcode::ShiftRight => {
+                        mips::instructions::Logic::ShiftRightLogical { register, a, b }.into()
+                    }
                     ast::BinaryOpcode::Equals => {
                         mips::instructions::VariableSelection::SelectEqual { register, a, b }.into()
                     }
@@ -156,89 +255,383 @@ impl<'a> State<'a> {
                 };
                 self.mips_program.instructions.push(instruction);
             }
+            VarValue::UnaryOp { op, operand } => {
+                let a = self.var_to_register(operand);
+                let instruction = match op {
+                    ast::UnaryOpcode::Not => {
+                        mips::instructions::VariableSelection::SelectEqualZero { register, a }
+                            .into()
+                    }
+                    ast::UnaryOpcode::Neg => mips::instructions::Arithmetic::Subtract {
+                        register,
+                        a: RegisterOrNumber::Number(0.0),
+                        b: a,
+                    }
+                    .into(),
+                    ast::UnaryOpcode::BitNot => mips::instructions::Logic::Nor {
+                        register,
+                        a,
+                        b: RegisterOrNumber::Number(0.0),
+                    }
+                    .into(),
+                };
+                self.mips_program.instructions.push(instruction);
+            }
             VarValue::Call { name, args } => {
-                if name == "store" {
-                    self.mips_program.instructions.push(
-                        mips::instructions::DeviceIo::StoreDeviceVariable {
-                            device: args[0].external().unwrap().parse().unwrap(),
-                            variable: args[1].external().unwrap().parse().unwrap(),
-                            register: self.var_to_register(&args[2]),
-                        }
-                        .into(),
-                    );
-                } else if name == "load" {
-                    self.mips_program.instructions.push(
-                        mips::instructions::DeviceIo::LoadDeviceVariable {
-                            register,
-                            device: args[0].external().unwrap().parse().unwrap(),
-                            variable: args[1].external().unwrap().parse().unwrap(),
-                        }
-                        .into(),
-                    )
+                if let Some(builtin) = self.options.builtins.get(name) {
+                    let instructions = builtin.lower(&self.builtin_args(args), register)?;
+                    self.mips_program.instructions.extend(instructions);
                 } else {
-                    let f = match self.ir_program.functions.get(name) {
-                        None => anyhow::bail!("function {} not found", name),
-                        Some(x) => x,
-                    };
-                    // This has to be fixed later.
-                    self.mips_program.instructions.push(
-                        mips::instructions::FlowControl::Jump {
-                            a: (f.block_id.0 as f64).into(),
-                        }
-                        .into(),
+                    anyhow::ensure!(
+                        self.ir_program.functions.contains_key(name),
+                        "function {} not found",
+                        name
                     );
-                    // self.generate_block(f.block_id)?;
+                    for arg in args {
+                        self.mips_program.instructions.push(
+                            mips::instructions::Stack::Push {
+                                a: self.var_to_register(arg),
+                            }
+                            .into(),
+                        );
+                    }
+                    // The function starts at a line only known once it is generated.
+                    self.calls
+                        .push((self.mips_program.instructions.len(), name.clone()));
+                    self.mips_program
+                        .instructions
+                        .push(mips::instructions::FlowControl::JumpAndLink { a: -1 }.into());
+                    self.mips_program
+                        .instructions
+                        .push(mips::instructions::Stack::Pop { register }.into());
                 }
             }
+            VarValue::Get { address } => self.mips_program.instructions.push(
+                mips::instructions::Stack::Get {
+                    register,
+                    device: Device::Db,
+                    address: self.var_to_register(address),
+                }
+                .into(),
+            ),
+            VarValue::Put { address, value } => self.mips_program.instructions.push(
+                mips::instructions::Stack::Put {
+                    device: Device::Db,
+                    address: self.var_to_register(address),
+                    value: self.var_to_register(value),
+                }
+                .into(),
+            ),
             VarValue::Phi(_) => (),
             VarValue::Param => (),
         }
         Ok(())
     }
 
+    /// Generates a function called with `jal`, after the code of the program.
+    fn generate_function(&mut self, name: &str, function: &ir::Function) -> anyhow::Result<()> {
+        self.mark();
+        self.span = None;
+        self.function_start
+            .insert(name.to_string(), self.mips_program.instructions.len());
+        // The last argument was pushed last.
+        for param in function.params.iter().rev() {
+            let register = self.registers.get(*param).unwrap();
+            self.mips_program
+                .instructions
+                .push(mips::instructions::Stack::Pop { register }.into());
+        }
+        let blocks = self.ir_program.function_blocks(function.block_id);
+        self.saves_ra = !self.ir_program.callees(&blocks).is_empty();
+        if self.saves_ra {
+            self.mips_program.instructions.push(
+                mips::instructions::Stack::Push {
+                    a: Register::Ra.into(),
+                }
+                .into(),
+            );
+        }
+        self.generate_block(function.block_id)
+    }
+
+    fn builtin_args<'b>(&self, args: &'b [VarOrConst]) -> Vec<Argument<'b>> {
+        args.iter()
+            .map(|arg| match arg {
+                VarOrConst::External(name) => Argument::External(name),
+                _ => Argument::Value(self.var_to_register(arg)),
+            })
+            .collect()
+    }
+
+    /// With `Os`, returns the comparison or builtin call computing the condition of the
+    /// branch at `idx` when it can be folded into the branch instruction itself.
+    fn fused_comparison<'b>(
+        &self,
+        instructions: &'b [ir::Instruction],
+        idx: usize,
+    ) -> Option<&'b VarValue> {
+        if self.options.opt_level != OptLevel::Os
+            || self.options.target.instruction_set == InstructionSet::Legacy
+            || idx == 0
+        {
+            return None;
+        }
+        let cond = match instructions.get(idx)? {
+            ir::Instruction::Branch {
+                cond: VarOrConst::Var(cond),
+                ..
+            } => cond,
+            _ => return None,
+        };
+        match &instructions[idx - 1] {
+            ir::Instruction::Assignment {
+                id,
+                value: value @ VarValue::BinaryOp { op, .. },
+            } if id == cond && self.ir_program.uses(*id).len() == 1 && op.is_comparison() => {
+                Some(value)
+            }
+            ir::Instruction::Assignment {
+                id,
+                value: value @ VarValue::Call { name, args },
+            } if id == cond
+                && self.ir_program.uses(*id).len() == 1
+                && self.options.builtins.get(name).is_some_and(|builtin| {
+                    builtin
+                        .branch_if_false(&self.builtin_args(args), RegisterOrNumber::Number(0.0))
+                        .is_some()
+                }) =>
+            {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the instruction jumping to `target` when the branch condition is false.
+    fn branch_if_false(
+        &self,
+        cond_var: &VarOrConst,
+        comparison: Option<&VarValue>,
+        target: RegisterOrNumber,
+    ) -> mips::instructions::Instruction {
+        use mips::instructions::FlowControl;
+        let (lhs, op, rhs) = match comparison {
+            Some(VarValue::BinaryOp { lhs, op, rhs }) => (lhs, op, rhs),
+            Some(VarValue::Call { name, args }) => {
+                return self
+                    .options
+                    .builtins
+                    .get(name)
+                    .and_then(|builtin| builtin.branch_if_false(&self.builtin_args(args), target))
+                    .expect("only calls of builtins with a branch are fused");
+            }
+            _ => {
+                return FlowControl::BranchEqualZero {
+                    a: self.var_to_register(cond_var),
+                    b: target,
+                }
+                .into()
+            }
+        };
+        let a = self.var_to_register(lhs);
+        let b = self.var_to_register(rhs);
+        let c = target;
+        match op {
+            ast::BinaryOpcode::Equals => FlowControl::BranchNotEqual { a, b, c },
+            ast::BinaryOpcode::NotEquals => FlowControl::BranchEqual { a, b, c },
+            ast::BinaryOpcode::Greater => FlowControl::BranchLessOrEqual { a, b, c },
+            ast::BinaryOpcode::GreaterEquals => FlowControl::BranchLessThan { a, b, c },
+            ast::BinaryOpcode::Lower => FlowControl::BranchGreaterOrEqual { a, b, c },
+            ast::BinaryOpcode::LowerEquals => FlowControl::BranchGreaterThan { a, b, c },
+            _ => unreachable!("{:?} is not a comparison", op),
+        }
+        .into()
+    }
+
     fn generate_branch(
         &mut self,
         cond_var: &VarOrConst,
+        comparison: Option<&VarValue>,
         true_block_id: &BlockId,
         false_block_id: &BlockId,
     ) -> anyhow::Result<()> {
         // record the index of current instruction, so that we can edit it later
         let jeqz_idx = self.mips_program.instructions.len();
-        self.mips_program.instructions.push(
-            mips::instructions::FlowControl::BranchEqualZero {
-                a: self.var_to_register(cond_var),
-                b: (-1.0).into(),
-            }
-            .into(),
-        );
+        let branch = self.branch_if_false(cond_var, comparison, (-1.0).into());
+        self.mips_program.instructions.push(branch);
 
         self.generate_block(*true_block_id)?;
-        self.generate_block(*false_block_id)?;
+        // The true block ends with a jump, so a false block generated before, such as the
+        // body of `loop { ... } until cond;`, is only reached by the branch.
+        if !self.block_start.contains_key(false_block_id) {
+            self.generate_block(*false_block_id)?;
+        }
 
         // Fix branch jump
         let idx = self.block_start[false_block_id];
         self.mips_program.instructions[jeqz_idx] =
-            mips::instructions::FlowControl::BranchEqualZero {
-                a: self.var_to_register(cond_var),
-                b: RegisterOrNumber::Number(idx as f64),
-            }
-            .into();
+            self.branch_if_false(cond_var, comparison, RegisterOrNumber::Number(idx as f64));
         Ok(())
     }
 }
 
 // The Program is expected to be in SSA form (each variable assigned once)
+//
+// The variables are put in the registers `hints` gives their names where possible.
 pub fn generate_mips_from_ir(
-    ir_program: ir::Program,
-) -> anyhow::Result<mips::instructions::Program> {
-    let mut state = State::new(&ir_program)?;
+    mut ir_program: ir::Program,
+    options: &CompileOptions,
+    hints: &BTreeMap<String, Vec<Register>>,
+) -> anyhow::Result<(mips::instructions::Program, DebugInfo)> {
+    let start = Instant::now();
+    let mut var_hints = HashMap::default();
+    for (id, name) in &ir_program.names {
+        if let Some(register) = hints.get(name).and_then(|r| r.first()) {
+            var_hints.entry(*id).or_insert(*register);
+        }
+    }
+    let registers =
+        RegisterAllocation::allocate(&mut ir_program, options.target.registers, &var_hints)?;
+    let size = ir_program.instruction_count();
+    options.record("allocate registers", start, size, size);
+
+    let mut debug_info = DebugInfo::default();
+    for (id, name) in &ir_program.names {
+        if let Some(register) = registers.get(*id) {
+            let registers = debug_info.registers.entry(name.clone()).or_default();
+            if !registers.contains(&register) {
+                registers.push(register);
+            }
+        }
+    }
+
+    let (mut program, mut lines) = generate(&ir_program, &registers, options, vec![])?;
+    // Jumps are to absolute lines, so the program is generated again after the aliases
+    // rather than having them inserted.
+    let mut aliases = aliases(&ir_program, &debug_info, options.aliases);
+    let room = options
+        .target
+        .max_lines
+        .saturating_sub(program.instructions.len());
+    aliases.truncate(room);
+    if !aliases.is_empty() {
+        (program, lines) = generate(&ir_program, &registers, options, aliases)?;
+    }
+    debug_info.lines = lines;
+    Ok((program, debug_info))
+}
+
+fn generate(
+    ir_program: &ir::Program,
+    registers: &RegisterAllocation,
+    options: &CompileOptions,
+    prefix: Vec<mips::instructions::Instruction>,
+) -> anyhow::Result<(mips::instructions::Program, Vec<Option<ast::Span>>)> {
+    let mut state = State::new(ir_program, registers, options, prefix);
+    let start = Instant::now();
     state.generate_block(BlockId(0))?;
+    let mut functions = ir_program.functions.iter().collect::<Vec<_>>();
+    functions.sort_by_key(|(_, function)| function.block_id.0);
+    for (name, function) in functions {
+        state.generate_function(name, function)?;
+    }
+    state.mark();
+    // Running past the last line ends the program as well, so a final jump to the end is
+    // not needed.
+    let last = state.mips_program.instructions.len().checked_sub(1);
+    if options.opt_level == OptLevel::Os
+        && last.is_some()
+        && state.jump_to_end.last() == last.as_ref()
+    {
+        state.jump_to_end.pop();
+        state.mips_program.instructions.pop();
+        state.lines.pop();
+    }
     for i in state.jump_to_end {
         state.mips_program.instructions[i] = mips::instructions::FlowControl::Jump {
             a: (state.mips_program.instructions.len() as f64).into(),
         }
         .into();
     }
+    for (i, name) in state.calls {
+        state.mips_program.instructions[i] = mips::instructions::FlowControl::JumpAndLink {
+            a: state.function_start[&name] as i32,
+        }
+        .into();
+    }
+    options.record(
+        "generate mips",
+        start,
+        ir_program.instruction_count(),
+        state.mips_program.instructions.len(),
+    );
+    Ok((state.mips_program, state.lines))
+}
+
+/// Returns `alias` lines for the `alias` statements of the program. With `all`, they are
+/// followed by lines naming the device pins after the constants bound to them, then the
+/// registers holding a single variable of the program after that variable.
+fn aliases(
+    ir_program: &ir::Program,
+    debug_info: &DebugInfo,
+    all: bool,
+) -> Vec<mips::instructions::Instruction> {
+    let mut aliases = vec![];
+    for (name, device) in &ir_program.aliases {
+        aliases.push((name.clone(), device.to_string()));
+    }
+    if all {
+        for (name, device) in &ir_program.devices {
+            aliases.push((name.clone(), device.to_string()));
+        }
+        let mut holders = HashMap::<Register, Vec<&String>>::default();
+        for (name, registers) in &debug_info.registers {
+            for register in registers {
+                holders.entry(*register).or_default().push(name);
+            }
+        }
+        let mut registers = holders.into_iter().collect::<Vec<_>>();
+        registers.sort_by_key(|(register, _)| *register as u8);
+        for (register, names) in registers {
+            if let [name] = names.as_slice() {
+                aliases.push((name.to_string(), register.to_string()));
+            }
+        }
+    }
+    aliases
+        .into_iter()
+        // Names of registers and pins would be ambiguous in the game.
+        .filter(|(name, _)| name.parse::<Register>().is_err() && name.parse::<Device>().is_err())
+        .map(|(name, target)| mips::instructions::Misc::Alias { name, target }.into())
+        .collect()
+}
 
-    Ok(state.mips_program)
+/// Returns the instructions computing `a ** b` into `register`, which the register allocation
+/// keeps apart from the operands. The game has no such instruction, so this computes
+/// `exp(b * log(a))`, the IR multiplying instead for whole exponents.
+fn power(
+    register: Register,
+    a: RegisterOrNumber,
+    b: RegisterOrNumber,
+) -> Vec<mips::instructions::Instruction> {
+    use mips::instructions::{Arithmetic, Misc};
+    if let (RegisterOrNumber::Number(a), RegisterOrNumber::Number(b)) = (&a, &b) {
+        // The operands of a `**` can become constants once copies are propagated.
+        let a = a.powf(*b).into();
+        return vec![Misc::Move { register, a }.into()];
+    }
+    vec![
+        Arithmetic::Logarithm { register, a }.into(),
+        Arithmetic::Multiply {
+            register,
+            a: register.into(),
+            b,
+        }
+        .into(),
+        Arithmetic::Exponent {
+            register,
+            a: register.into(),
+        }
+        .into(),
+    ]
 }
@@ -4,10 +4,14 @@ mod register_allocation;
 pub mod types;
 
 use crate::ir::codegen::generate_mips_from_ir;
+use crate::{CompileOptions, DebugInfo};
 use anyhow::Context;
-use ayysee_parser::ast::{self, Expr};
+use ayysee_parser::ast::{self, BinaryOpcode, Expr, ExprKind, UnaryOpcode};
+use ayysee_parser::visit::{walk_expr, walk_statement, Visitor};
+pub use optimize::OptLevel;
 use stationeers_mips as mips;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
 pub use types::*;
 
 struct State {
@@ -17,6 +21,48 @@ struct State {
     program: Program,
     sealed_blocks: HashSet<BlockId>,
     unresolved_phis: HashMap<BlockId, Vec<(String, VarId, usize)>>,
+    /// The parameters and body of every function, inlined where they are called.
+    functions: HashMap<String, (Vec<ast::Identifier>, ast::Block)>,
+    /// Prepended to the names of variables, so that those of an inlined function do not
+    /// clash with the caller's.
+    prefix: String,
+    /// The variables defined by the `let`s of each block around the statement, innermost
+    /// last, with their name in the IR.
+    scopes: Vec<HashMap<String, String>>,
+    /// The number of variables renamed because they shadow another.
+    shadowed: usize,
+    /// The functions being inlined or generated, innermost last.
+    inlining: Vec<String>,
+    /// The number of calls inlined so far.
+    inlined: usize,
+    /// The functions of the prelude, whose statements are not in the source of the program.
+    prelude: HashSet<String>,
+    /// The statement being generated.
+    span: Option<ast::Span>,
+    /// The label of each loop around the statement, innermost last, with the blocks
+    /// `continue` and `break` move to: the start of the next iteration and the block after
+    /// the loop.
+    loops: Vec<(Option<String>, BlockId, BlockId)>,
+    /// The blocks after a `break`, `continue` or `return`, which are never reached.
+    unreachable: HashSet<BlockId>,
+    /// The block `return` moves to, in a function with several `return`s.
+    exit: Option<BlockId>,
+    /// The functions compiled once and called rather than inlined.
+    called: HashSet<String>,
+    /// The called functions whose body is still to be generated.
+    pending: Vec<String>,
+    /// The calls to the functions called rather than inlined, by the variable they assign.
+    call_spans: HashMap<VarId, ast::Span>,
+    /// The lengths of the arrays, by the start of their name in their `let`.
+    lengths: HashMap<usize, usize>,
+    /// The first address and the length of the arrays, by name.
+    arrays: HashMap<String, (usize, usize)>,
+    /// The number of addresses at the top of the stack taken by arrays.
+    stack_used: usize,
+    /// The constants given outside the program, replacing its `const`s of the same name.
+    definitions: BTreeMap<String, f64>,
+    /// Whether device fields can be logic types the game data does not list.
+    unknown_logic_types: bool,
 }
 
 impl Default for State {
@@ -28,6 +74,25 @@ impl Default for State {
             program: Default::default(),
             sealed_blocks: Default::default(),
             unresolved_phis: Default::default(),
+            functions: Default::default(),
+            prefix: Default::default(),
+            scopes: Default::default(),
+            shadowed: 0,
+            inlining: Default::default(),
+            inlined: 0,
+            prelude: Default::default(),
+            span: None,
+            loops: Default::default(),
+            unreachable: Default::default(),
+            exit: None,
+            called: Default::default(),
+            pending: Default::default(),
+            call_spans: Default::default(),
+            lengths: Default::default(),
+            arrays: Default::default(),
+            stack_used: 0,
+            definitions: Default::default(),
+            unknown_logic_types: false,
         }
     }
 }
@@ -53,13 +118,17 @@ impl State {
         if let Some(phis) = phis {
             for (name, id, idx) in phis {
                 let mut all: Vec<VarId> = vec![];
-                let prevs = self.program.blocks[block.0].prev.clone();
-                tracing::debug!("Sealing {:?}, prev: {:?}", block, prevs);
-                for prev in &prevs {
-                    let i = self.read_variable(*prev, &name).into();
-                    if i != id {
-                        all.push(i);
-                    }
+                tracing::debug!(
+                    "Sealing {:?}, prev: {:?}",
+                    block,
+                    self.program.blocks[block.0].prev
+                );
+                // Reading variables adds instructions but never changes the edges, so the
+                // predecessors are indexed rather than cloned. The operands are in the order
+                // of the predecessors they flow from, including the PHI itself.
+                for i in 0..self.program.blocks[block.0].prev.len() {
+                    let prev = self.program.blocks[block.0].prev[i];
+                    all.push(self.read_variable(prev, &name));
                 }
                 let value = VarValue::Phi(all);
                 self.program.blocks[block.0].instructions[idx] =
@@ -67,29 +136,32 @@ impl State {
             }
         }
 
-        let next = self.program.blocks[block.0].next.clone();
-        for n in next {
-            self.seal_block(n);
+        for i in 0..self.program.blocks[block.0].next.len() {
+            let next = self.program.blocks[block.0].next[i];
+            self.seal_block(next);
         }
     }
 
     fn connect_blocks(&mut self, from: BlockId, to: BlockId) {
+        // Unreachable blocks would only add values that never flow into `to`.
+        if self.unreachable.contains(&from) {
+            return;
+        }
         self.program.blocks[from.0].next.push(to);
         self.program.blocks[to.0].prev.push(from);
     }
 
     fn assign(&mut self, block: BlockId, name: &str, v: VarId) {
+        // The variables the compiler adds have a `#`, which names in the source cannot.
+        if self.prefix.is_empty() && !name.contains('#') {
+            self.program.names.push((v, name.to_string()));
+        }
         self.defs
             .entry(name.to_string())
             .or_default()
             .insert(block, v);
     }
 
-    fn assign_external(&mut self, block: BlockId, name: &str) {
-        let id = self.add_variable(block, VarValue::Single(VarOrConst::External(name.into())));
-        self.assign(block, name, id)
-    }
-
     fn next_var(&mut self) -> VarId {
         let x = self.next_var;
         self.next_var = VarId(self.next_var.0 + 1);
@@ -98,32 +170,50 @@ impl State {
 
     fn add_variable(&mut self, block: BlockId, value: VarValue) -> VarId {
         let id = self.next_var();
-        self.program.blocks[block.0]
-            .instructions
-            .push(Instruction::Assignment { id, value });
+        if let Some(span) = self.span {
+            self.program.spans.insert(id, span);
+        }
+        self.push_instruction(block, Instruction::Assignment { id, value });
         id
     }
 
-    // TODO: return error from this method
+    /// Adds an instruction at the end of the block, before the branch ending it if any: a
+    /// variable first read through a block whose branch is already generated, as from the
+    /// block after a `break`, adds a PHI to it. Returns the index of the instruction.
+    fn push_instruction(&mut self, block: BlockId, instruction: Instruction) -> usize {
+        let instructions = &mut self.program.blocks[block.0].instructions;
+        let idx = match instructions.last() {
+            Some(Instruction::Branch { .. }) => instructions.len() - 1,
+            _ => instructions.len(),
+        };
+        instructions.insert(idx, instruction);
+        idx
+    }
+
     fn read_variable(&mut self, block: BlockId, name: &str) -> VarId {
         if let Some(x) = self
             .defs
             .get(name)
-            .context(format!("{}", name))
-            .unwrap()
+            .expect("variables are resolved before generating IR")
             .get(&block)
         {
             return *x;
         }
         if !self.sealed_blocks.contains(&block) {
             tracing::debug!("Block {:?} is not sealed", block);
-            let id = self.add_variable(block, VarValue::Phi(vec![]));
+            let id = self.next_var();
+            let idx = self.push_instruction(
+                block,
+                Instruction::Assignment {
+                    id,
+                    value: VarValue::Phi(vec![]),
+                },
+            );
             self.assign(block, name, id);
-            self.unresolved_phis.entry(block).or_default().push((
-                name.to_string(),
-                id,
-                self.program.blocks[block.0].instructions.len() - 1,
-            ));
+            self.unresolved_phis
+                .entry(block)
+                .or_default()
+                .push((name.to_string(), id, idx));
             return id;
         }
 
@@ -134,129 +224,183 @@ impl State {
         self.assign(block, name, id);
         let mut all: Vec<VarId> = vec![];
 
-        let prevs = self.program.blocks[block.0].prev.clone();
-        for prev in &prevs {
-            all.push(self.read_variable(*prev, name).into());
+        for i in 0..self.program.blocks[block.0].prev.len() {
+            let prev = self.program.blocks[block.0].prev[i];
+            all.push(self.read_variable(prev, name));
         }
         tracing::debug!(
             "reading block:{:?} name:{}: prevs{:?} all:{:?}",
             block,
             name,
-            prevs,
+            self.program.blocks[block.0].prev,
             all
         );
 
         let value = if all.len() == 1 {
-            all[0].clone().into()
+            all[0].into()
         } else {
             VarValue::Phi(all)
         };
-        self.program.blocks[block.0]
-            .instructions
-            .push(Instruction::Assignment { id, value });
+        self.push_instruction(block, Instruction::Assignment { id, value });
 
         id
     }
 
-    fn init(&mut self) {
-        let externals = vec![
-            "db",
-            "d0",
-            "d1",
-            "d2",
-            "d3",
-            "d4",
-            "d5",
-            "Activate",
-            "AirRelease",
-            "Charge",
-            "ClearMemory",
-            "Color",
-            "CompletionRatio",
-            "ElevatorLevel",
-            "ElevatorSpeed",
-            "Error",
-            "ExportCount",
-            "Filtration",
-            "Harvest",
-            "Horizontal",
-            "HorizontalRatio",
-            "Idle",
-            "ImportCount",
-            "Lock",
-            "Maximum",
-            "Mode",
-            "On",
-            "Open",
-            "Output",
-            "Plant",
-            "PositionX",
-            "PositionY",
-            "Power",
-            "PowerActual",
-            "PowerPotential",
-            "PowerRequired",
-            "Pressure",
-            "PressureExternal",
-            "PressureInternal",
-            "PressureSetting",
-            "Quantity",
-            "Ratio",
-            "RatioCarbonDioxide",
-            "RatioNitrogen",
-            "RatioOxygen",
-            "RatioPollutant",
-            "RatioVolatiles",
-            "RatioWater",
-            "Reagents",
-            "RecipeHash",
-            "RequestHash",
-            "RequiredPower",
-            "Setting",
-            "SolarAngle",
-            "Temperature",
-            "TemperatureSettings",
-            "TotalMoles",
-            "VelocityMagnitude",
-            "VelocityRelativeX",
-            "VelocityRelativeY",
-            "VelocityRelativeZ",
-            "Vertical",
-            "VerticalRatio",
-            "Volume",
-        ];
-
-        for external in externals {
+    fn init(
+        &mut self,
+        options: &CompileOptions,
+        program: &ast::Program,
+        symbols: &crate::resolve::SymbolTable,
+    ) {
+        for symbol in &symbols.symbols {
+            if let (crate::resolve::SymbolKind::Array { length }, Some(span)) =
+                (&symbol.kind, symbol.declaration)
+            {
+                self.lengths.insert(span.start, *length);
+            }
+        }
+        for external in options.game_data.externals() {
+            self.consts.insert(
+                external.to_string(),
+                VarOrConst::External(external.to_string()),
+            );
+        }
+        for (name, x) in &options.definitions {
             self.consts
-                .insert(external.into(), VarOrConst::External(external.into()));
+                .insert(name.clone(), VarOrConst::Const((*x).into()));
+        }
+        self.definitions = options.definitions.clone();
+        self.unknown_logic_types = options.unknown_logic_types;
+        let prelude = crate::prelude::functions()
+            .iter()
+            .filter(|f| !matches!(&f.kind, ast::StatementKind::Function { identifier, .. } if options.builtins.get(identifier.as_ref()).is_some()));
+        for statement in prelude {
+            self.collect_functions(statement);
+        }
+        self.prelude = self.functions.keys().cloned().collect();
+        for statement in &program.statements {
+            self.collect_functions(statement);
+        }
+        self.called = called_functions(program, &self.functions, &self.prelude);
+    }
+
+    fn collect_functions(&mut self, statement: &ast::Statement) {
+        if let ast::StatementKind::Function {
+            identifier,
+            parameters,
+            body,
+        } = &statement.kind
+        {
+            self.functions
+                .insert(identifier.to_string(), (parameters.clone(), body.clone()));
+            self.prelude.remove::<str>(identifier.as_ref());
+        }
+        for block in crate::resolve::blocks(statement) {
+            for statement in block.statements() {
+                self.collect_functions(statement);
+            }
+        }
+    }
+
+    /// Returns the name under which a variable of the current function is stored.
+    fn var(&self, name: &str) -> String {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+            .unwrap_or_else(|| format!("{}{}", self.prefix, name))
+    }
+
+    /// Returns the name in the IR of the variable a `let` defines. A `let` in an inner block
+    /// shadowing a variable gets a name of its own, so that the variable is left as is once
+    /// the block ends.
+    fn define(&mut self, name: &str) -> String {
+        if let Some(var) = self.scopes.last().and_then(|scope| scope.get(name)) {
+            return var.clone();
+        }
+        let mut var = format!("{}{}", self.prefix, name);
+        let shadows = self.defs.contains_key(&var)
+            || self.scopes.iter().any(|scope| scope.contains_key(name));
+        if self.scopes.len() > 1 && shadows {
+            self.shadowed += 1;
+            var = format!("{}#{}", var, self.shadowed);
         }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), var.clone());
+        }
+        var
     }
 }
 
 pub fn generate_program(program: ayysee_parser::ast::Program) -> anyhow::Result<mips::Program> {
-    let mut ir = generate_ir(program)?;
+    generate_program_with(program, &CompileOptions::default())
+}
+
+pub fn generate_program_with(
+    program: ayysee_parser::ast::Program,
+    options: &CompileOptions,
+) -> anyhow::Result<mips::Program> {
+    Ok(generate_program_with_debug_info(program, options)?.0)
+}
+
+/// Like [`generate_program_with`], also returning where the variables of the program ended
+/// up.
+pub fn generate_program_with_debug_info(
+    program: ayysee_parser::ast::Program,
+    options: &CompileOptions,
+) -> anyhow::Result<(mips::Program, DebugInfo)> {
+    generate_program_with_hints(program, options, &Default::default())
+}
+
+/// Like [`generate_program_with_debug_info`], putting the variables in the registers
+/// `hints` gives their names where possible.
+pub(crate) fn generate_program_with_hints(
+    program: ayysee_parser::ast::Program,
+    options: &CompileOptions,
+    hints: &BTreeMap<String, Vec<mips::types::Register>>,
+) -> anyhow::Result<(mips::Program, DebugInfo)> {
+    let mut ir = generate_ir_with(program, options)?;
     tracing::info!("IR Program before optimize:\n{:?}", ir);
-    optimize::optimize(&mut ir);
+    optimize::optimize(&mut ir, options);
     tracing::info!("IR Program:\n{:?}", ir);
-    Ok(generate_mips_from_ir(ir)?)
+    let (program, debug_info) = generate_mips_from_ir(ir, options, hints)?;
+    let lines = program.instructions.len();
+    let start = Instant::now();
+    options.target.verify(&program)?;
+    options.record("verify", start, lines, lines);
+    Ok((program, debug_info))
 }
 
 pub fn generate_ir(program: ayysee_parser::ast::Program) -> anyhow::Result<Program> {
+    generate_ir_with(program, &CompileOptions::default())
+}
+
+pub fn generate_ir_with(
+    program: ayysee_parser::ast::Program,
+    options: &CompileOptions,
+) -> anyhow::Result<Program> {
+    let start = Instant::now();
+    let (symbols, errors) = crate::resolve::resolve_with(&program, options);
+    if !errors.is_empty() {
+        return Err(crate::resolve::SemanticErrors(errors).into());
+    }
+    options.record("resolve", start, 0, 0);
+    let start = Instant::now();
     let mut state = State::default();
     let block = state.new_block(true);
-    state.init();
-
-    // This is simple program
-    // TODO: also handle programs with explicit main methods
-    state.program.functions.insert(
-        "main".into(),
-        Function {
-            block_id: BlockId(0),
-            params: Default::default(),
-            ret: None,
-        },
-    );
-    process_stmts(&mut state, block, &program.statements)?;
+    state.init(options, &program, &symbols);
+
+    let mut block = process_stmts(&mut state, block, &program.statements)?;
+    // A program can put its code in a `main` function, run after the top-level statements.
+    if state.functions.contains_key("main") && !state.prelude.contains("main") {
+        process_call(&mut state, &mut block, "main", None, &[])?;
+    }
+    while let Some(name) = state.pending.pop() {
+        process_function(&mut state, &name)?;
+    }
+    check_recursion(&state.program, &state.call_spans)?;
+    state.program.reindex();
+    options.record("generate ir", start, 0, state.program.instruction_count());
 
     Ok(state.program)
 }
@@ -266,48 +410,77 @@ fn process_stmts(
     mut block: BlockId,
     statements: &[ast::Statement],
 ) -> anyhow::Result<BlockId> {
+    // The variables defined in a block are only visible in it.
+    state.scopes.push(HashMap::default());
     for stmt in statements {
         tracing::debug!("{:?}", stmt);
-        match stmt {
-            ast::Statement::FunctionCall {
+        let outer = state.span;
+        if !state.inlining.iter().any(|f| state.prelude.contains(f)) {
+            state.span = Some(stmt.span);
+        }
+        match &stmt.kind {
+            ast::StatementKind::FunctionCall {
                 identifier,
                 arguments,
             } => {
-                let args: Vec<VarOrConst> = arguments
-                    .iter()
-                    .map(|a| process_expr(state, block, a))
-                    .collect();
-                state.add_variable(
-                    block,
-                    VarValue::Call {
-                        name: identifier.to_string(),
-                        args,
-                    },
-                );
+                let span = Some(identifier.span);
+                process_call(state, &mut block, identifier.as_ref(), span, arguments)?;
             }
-            ast::Statement::Definition {
+            ast::StatementKind::Definition {
                 identifier,
                 expression,
             } => {
-                let v = process_expr(state, block, &expression);
-                let id = match v {
-                    VarOrConst::Const(_) => state.add_variable(block, VarValue::Single(v)),
-                    VarOrConst::Var(id) => id,
-                    VarOrConst::External(_) => state.add_variable(block, VarValue::Single(v)),
-                };
-                state.assign(block, identifier.as_ref(), id);
+                if let ExprKind::Array(value, _) = &expression.kind {
+                    process_array(state, &mut block, identifier, value)?;
+                } else {
+                    let v = process_expr(state, &mut block, expression)?;
+                    let id = match v {
+                        VarOrConst::Const(_) => state.add_variable(block, VarValue::Single(v)),
+                        VarOrConst::Var(id) => id,
+                        VarOrConst::External(_) => state.add_variable(block, VarValue::Single(v)),
+                    };
+                    let name = state.define(identifier.as_ref());
+                    state.arrays.remove(&name);
+                    state.assign(block, &name, id);
+                }
+            }
+            ast::StatementKind::TupleDefinition {
+                identifiers,
+                expressions,
+            } => {
+                anyhow::ensure!(
+                    identifiers.len() == expressions.len(),
+                    "{} variables are defined from {} values",
+                    identifiers.len(),
+                    expressions.len()
+                );
+                let mut ids = vec![];
+                for expression in expressions {
+                    let v = process_expr(state, &mut block, expression)?;
+                    ids.push(match v {
+                        VarOrConst::Var(id) => id,
+                        _ => state.add_variable(block, VarValue::Single(v)),
+                    });
+                }
+                for (identifier, id) in identifiers.iter().zip(ids) {
+                    let name = state.define(identifier.as_ref());
+                    state.arrays.remove(&name);
+                    state.assign(block, &name, id);
+                }
             }
-            ast::Statement::Assignment { lhs, rhs } => {
-                let v = process_expr(state, block, &rhs);
+            ast::StatementKind::Assignment { lhs, rhs } => {
+                let v = process_expr(state, &mut block, rhs)?;
                 let id = match v {
                     VarOrConst::Var(id) => id,
                     _ => state.add_variable(block, v.into()),
                 };
-                match *(*lhs) {
-                    ast::Expr::Identifier(ref ident) => state.assign(block, ident.as_ref(), id),
-                    ast::Expr::FieldExpr(ref d, ref logic) => {
-                        let arg0 = process_expr(state, block, &Expr::Identifier(d.clone()));
-                        let arg1 = process_expr(state, block, &Expr::Identifier(logic.clone()));
+                match lhs.kind {
+                    ExprKind::Identifier(ref ident) => {
+                        state.assign(block, &state.var(ident.as_ref()), id)
+                    }
+                    ExprKind::FieldExpr(ref d, ref logic) => {
+                        let arg0 = process_expr(state, &mut block, d)?;
+                        let arg1 = process_logic_type(state, block, logic)?;
                         state.add_variable(
                             block,
                             VarValue::Call {
@@ -316,41 +489,81 @@ fn process_stmts(
                             },
                         );
                     }
+                    ExprKind::Index(ref array, ref index) => {
+                        let address = process_address(state, &mut block, array, index)?;
+                        let value = id.into();
+                        state.add_variable(block, VarValue::Put { address, value });
+                    }
                     _ => anyhow::bail!(
                         "unsupported assignment, left side is expected to be identifier"
                     ),
                 }
             }
-            ast::Statement::Constant(identifier, expression) => {
-                let v = process_expr(state, block, &expression);
+            ast::StatementKind::Constant(identifier, expression) => {
+                let v = match state.definitions.get::<str>(identifier.as_ref()) {
+                    Some(x) => VarOrConst::Const((*x).into()),
+                    None => process_expr(state, &mut block, expression)?,
+                };
+                if let (true, Some(Ok(device))) = (
+                    state.prefix.is_empty(),
+                    v.external().map(|name| name.parse::<mips::types::Device>()),
+                ) {
+                    state.program.devices.push((identifier.to_string(), device));
+                }
                 state.consts.insert(identifier.to_string(), v);
             }
-            ast::Statement::IfStatement(if_stmt) => match if_stmt {
+            ast::StatementKind::Alias { identifier, alias } => {
+                let v = process_identifier(state, block, identifier)?;
+                let device = v
+                    .external()
+                    .and_then(|name| name.parse::<mips::types::Device>().ok())
+                    .with_context(|| format!("`{}` is not a device pin", identifier))?;
+                if state.prefix.is_empty() {
+                    state.program.aliases.push((alias.to_string(), device));
+                }
+                state.consts.insert(alias.to_string(), v);
+            }
+            ast::StatementKind::Block(body) => {
+                block = process_stmts(state, block, body.statements())?;
+            }
+            ast::StatementKind::IfStatement(if_stmt) => match if_stmt {
                 ast::IfStatement::If { condition, body } => {
-                    process_cond(
-                        state,
-                        &mut block,
-                        condition,
-                        body,
-                        &ast::Block::Statements(vec![]),
-                    )?;
+                    let branches = [(condition.as_ref(), body)];
+                    process_cond(state, &mut block, &branches, &ast::Block::default())?;
                 }
                 ast::IfStatement::IfElse {
                     condition,
                     body,
                     else_body,
                 } => {
-                    process_cond(state, &mut block, condition, body, else_body)?;
+                    let branches = [(condition.as_ref(), body)];
+                    process_cond(state, &mut block, &branches, else_body)?;
+                }
+                ast::IfStatement::ElseIf {
+                    branches,
+                    else_body,
+                } => {
+                    let branches = branches
+                        .iter()
+                        .map(|(condition, body)| (condition.as_ref(), body))
+                        .collect::<Vec<_>>();
+                    let else_body = else_body.clone().unwrap_or_default();
+                    process_cond(state, &mut block, &branches, &else_body)?;
                 }
             },
-            ast::Statement::Loop { body } => {
+            ast::StatementKind::Loop { label, body } => {
                 // Prepare the next block, so that break statements can move to it
                 let block_next = state.new_block(false);
                 let block_body = state.new_block(false);
 
                 state.connect_blocks(block, block_body);
 
-                let body_end = process_stmts(state, block_body, body.statements())?;
+                state
+                    .loops
+                    .push((label_name(label), block_body, block_next));
+                let body_end = process_stmts(state, block_body, body.statements());
+                state.loops.pop();
+                let body_end = body_end?;
 
                 state.connect_blocks(body_end, block_body);
                 if state.sealed_blocks.contains(&block) {
@@ -359,217 +572,1141 @@ fn process_stmts(
 
                 block = block_next;
             }
-            ast::Statement::Yield {} => {
+            ast::StatementKind::Match {
+                value,
+                arms,
+                default_arm,
+            } => process_match(state, &mut block, value, arms, default_arm.as_ref())?,
+            ast::StatementKind::LoopUntil {
+                label,
+                body,
+                condition,
+            } => {
+                // `continue` moves to the test of the condition, which is only known after
+                // the body.
+                let block_next = state.new_block(false);
+                let block_body = state.new_block(false);
+                let mut block_cond = state.new_block(false);
+
+                state.connect_blocks(block, block_body);
+
+                state
+                    .loops
+                    .push((label_name(label), block_cond, block_next));
+                let body_end = process_stmts(state, block_body, body.statements());
+                state.loops.pop();
+                state.connect_blocks(body_end?, block_cond);
+
+                // The body can leave every iteration with `break`.
+                if state.program.blocks[block_cond.0].prev.is_empty() {
+                    state.unreachable.insert(block_cond);
+                }
+                let cond = process_expr(state, &mut block_cond, condition)?;
+                state.program.blocks[block_cond.0]
+                    .instructions
+                    .push(Instruction::Branch {
+                        cond,
+                        true_block: block_next,
+                        false_block: block_body,
+                    });
+                state.connect_blocks(block_cond, block_next);
+                state.connect_blocks(block_cond, block_body);
+                if state.sealed_blocks.contains(&block) {
+                    state.seal_block(block_body);
+                }
+
+                block = block_next;
+            }
+            ast::StatementKind::For {
+                label,
+                variable,
+                start,
+                end,
+                body,
+            } => process_for(state, &mut block, label, variable, start, end, body)?,
+            ast::StatementKind::Yield => {
                 state.program.blocks[block.0]
                     .instructions
                     .push(Instruction::Yield);
             }
-            ast::Statement::Function {
-                identifier,
-                parameters,
-                body,
-            } => {
-                let fn_block_id = state.new_block(true);
-                state.defs.clear();
-                let mut params = vec![];
-                for p in parameters {
-                    let id = state.add_variable(fn_block_id, VarValue::Param);
-                    params.push(id);
-                    state.assign(fn_block_id, &p.to_string(), id);
-                }
-                process_stmts(state, fn_block_id, body.statements())?;
-                state.defs.clear();
-                state.program.functions.insert(
-                    identifier.to_string(),
-                    Function {
-                        block_id: fn_block_id,
-                        params,
-                        ret: None,
-                    },
-                );
+            ast::StatementKind::Halt => {
+                state.program.blocks[block.0]
+                    .instructions
+                    .push(Instruction::Halt);
+                block = state.new_block(true);
+                state.unreachable.insert(block);
+            }
+            ast::StatementKind::Break(label) | ast::StatementKind::Continue(label) => {
+                let name = label_name(label);
+                let (_, body, next) = state
+                    .loops
+                    .iter()
+                    .rev()
+                    .find(|(loop_label, _, _)| name.is_none() || *loop_label == name)
+                    .cloned()
+                    .with_context(|| match label {
+                        Some(label) => format!("`{}` is not the label of a loop", label),
+                        None => "`break` and `continue` can only be used inside a loop".to_string(),
+                    })?;
+                let target = match stmt.kind {
+                    ast::StatementKind::Break(_) => next,
+                    _ => body,
+                };
+                state.connect_blocks(block, target);
+                // The statements after it are never executed, but still generated so that
+                // they are checked.
+                block = state.new_block(true);
+                state.unreachable.insert(block);
             }
-            ast::Statement::Return(expr) => {
-                let var = process_expr(state, block, &expr);
-                let var_id = state.add_variable(block, var.into());
+            // Functions are generated where they are called.
+            ast::StatementKind::Function { .. } => (),
+            // At the top level, `return;` jumps past the end of the program.
+            ast::StatementKind::Return(expr) if state.exit.is_none() => {
+                anyhow::ensure!(
+                    expr.is_none(),
+                    "`return` outside a function ends the program and cannot return a value"
+                );
                 state.program.blocks[block.0]
                     .instructions
-                    .push(Instruction::Return(var_id));
+                    .push(Instruction::Return(None));
+                block = state.new_block(true);
+                state.unreachable.insert(block);
+            }
+            ast::StatementKind::Return(expr) => {
+                let exit = state.exit.expect("`return` is inside a function");
+                let value = match expr {
+                    Some(expr) => process_expr(state, &mut block, expr)?,
+                    None => VarOrConst::Const(0.0.into()),
+                };
+                let id = match value {
+                    VarOrConst::Var(id) => id,
+                    VarOrConst::External(name) => anyhow::bail!(
+                        "`{}` can only be returned by the last statement of a function",
+                        name
+                    ),
+                    v => state.add_variable(block, v.into()),
+                };
+                state.assign(block, &state.var("return"), id);
+                state.connect_blocks(block, exit);
+                block = state.new_block(true);
+                state.unreachable.insert(block);
             }
+            ast::StatementKind::Import(path) => anyhow::bail!(
+                "cannot import `{}` into a program that was not read from a file",
+                path
+            ),
             _ => {
                 anyhow::bail!("unimplemented statement {:?}", stmt);
             }
         }
+        state.span = outer;
     }
+    state.scopes.pop();
     Ok(block)
 }
 
+/// Generates the branches of an `if` and its `else if`s as a chain: each condition is
+/// tested in turn in the false branch of the one before, and every body joins the block
+/// after the statement.
 fn process_cond(
     state: &mut State,
     block_id: &mut BlockId,
-    cond_expr: &Expr,
-    true_block: &ast::Block,
-    false_block: &ast::Block,
+    branches: &[(&Expr, &ast::Block)],
+    else_block: &ast::Block,
 ) -> anyhow::Result<()> {
-    let sealed = state.sealed_blocks.contains(&block_id);
-    let cond_var = process_expr(state, *block_id, cond_expr);
+    let mut ends = vec![];
+    let mut sealed = state.sealed_blocks.contains(block_id);
+    for (cond_expr, true_block) in branches {
+        let cond_var = process_expr(state, block_id, cond_expr)?;
+        sealed = state.sealed_blocks.contains(block_id);
 
-    let true_block_id_start = state.new_block(sealed);
-    state.connect_blocks(*block_id, true_block_id_start);
-    let true_block_id_end = process_stmts(state, true_block_id_start, true_block.statements())?;
+        let true_block_id_start = state.new_block(sealed);
+        state.connect_blocks(*block_id, true_block_id_start);
+        ends.push(process_stmts(
+            state,
+            true_block_id_start,
+            true_block.statements(),
+        )?);
 
-    let false_block_id_start = state.new_block(sealed);
-    state.connect_blocks(*block_id, false_block_id_start);
-    let false_block_id_end = process_stmts(state, false_block_id_start, false_block.statements())?;
+        let false_block_id_start = state.new_block(sealed);
+        state.connect_blocks(*block_id, false_block_id_start);
+        state.program.blocks[block_id.0]
+            .instructions
+            .push(Instruction::Branch {
+                cond: cond_var,
+                true_block: true_block_id_start,
+                false_block: false_block_id_start,
+            });
+        *block_id = false_block_id_start;
+    }
+    ends.push(process_stmts(state, *block_id, else_block.statements())?);
 
-    state.program.blocks[block_id.0]
-        .instructions
-        .push(Instruction::Branch {
-            cond: cond_var,
-            true_block: true_block_id_start,
-            false_block: false_block_id_start,
-        });
     *block_id = state.new_block(sealed);
-    state.connect_blocks(true_block_id_end, *block_id);
-    state.connect_blocks(false_block_id_end, *block_id);
+    for end in ends {
+        state.connect_blocks(end, *block_id);
+    }
     Ok(())
 }
 
-fn process_expr(state: &mut State, block: BlockId, expr: &ayysee_parser::ast::Expr) -> VarOrConst {
-    match expr {
-        Expr::Constant(v) => VarOrConst::Const(Into::<f64>::into(v).into()),
-        Expr::Identifier(ident) => {
-            if let Some(x) = state.consts.get(&ident.to_string()) {
-                x.clone()
-            } else {
-                VarOrConst::Var(state.read_variable(block, ident.as_ref()))
+/// Generates a `match` as a chain of tests of the value against the pattern of each arm,
+/// which the code generator turns into a branch per arm.
+fn process_match(
+    state: &mut State,
+    block_id: &mut BlockId,
+    value: &Expr,
+    arms: &[(Box<Expr>, ast::Block)],
+    default_arm: Option<&ast::Block>,
+) -> anyhow::Result<()> {
+    let value = process_expr(state, block_id, value)?;
+    let sealed = state.sealed_blocks.contains(block_id);
+    let mut ends = vec![];
+    for (pattern, body) in arms {
+        let pattern = match process_expr(state, block_id, pattern)? {
+            pattern @ VarOrConst::Const(_) => pattern,
+            _ => {
+                anyhow::bail!("the pattern of a `match` arm must be a number known at compile time")
             }
-        }
-        Expr::BinaryOp(lhs_expr, op, rhs_expr) => {
-            let lhs = process_expr(state, block, lhs_expr);
-            let rhs = process_expr(state, block, rhs_expr);
-            VarOrConst::Var(state.add_variable(block, VarValue::BinaryOp { lhs, op: *op, rhs }))
-        }
-        Expr::UnaryOp(_, _) => todo!(),
-        Expr::FunctionCall(ident, args) => {
-            let args = args.iter().map(|a| process_expr(state, block, a)).collect();
-            VarOrConst::Var(state.add_variable(
-                block,
-                VarValue::Call {
-                    name: ident.to_string(),
-                    args,
+        };
+        let cond = state.add_variable(
+            *block_id,
+            VarValue::BinaryOp {
+                lhs: value.clone(),
+                op: BinaryOpcode::Equals,
+                rhs: pattern,
+            },
+        );
+
+        let arm_block_id = state.new_block(sealed);
+        state.connect_blocks(*block_id, arm_block_id);
+        ends.push(process_stmts(state, arm_block_id, body.statements())?);
+
+        let rest_block_id = state.new_block(sealed);
+        state.connect_blocks(*block_id, rest_block_id);
+        state.program.blocks[block_id.0]
+            .instructions
+            .push(Instruction::Branch {
+                cond: cond.into(),
+                true_block: arm_block_id,
+                false_block: rest_block_id,
+            });
+        *block_id = rest_block_id;
+    }
+    let default_arm = default_arm.map_or(&[][..], |body| body.statements());
+    ends.push(process_stmts(state, *block_id, default_arm)?);
+
+    *block_id = state.new_block(sealed);
+    for end in ends {
+        state.connect_blocks(end, *block_id);
+    }
+    Ok(())
+}
+
+/// Generates a call, inlining the functions of the program and the prelude. `span` is the
+/// name of the function in the call, if it is in the source.
+fn process_call(
+    state: &mut State,
+    block: &mut BlockId,
+    name: &str,
+    span: Option<ast::Span>,
+    args: &[Box<Expr>],
+) -> anyhow::Result<VarOrConst> {
+    // The calls in the prelude are not in the source of the program.
+    let span = span.filter(|_| !state.inlining.iter().any(|f| state.prelude.contains(f)));
+    let mut values = vec![];
+    for arg in args {
+        values.push(process_expr(state, block, arg)?);
+    }
+    let Some((params, body)) = state.functions.get(name).cloned() else {
+        return Ok(VarOrConst::Var(state.add_variable(
+            *block,
+            VarValue::Call {
+                name: name.to_string(),
+                args: values,
+            },
+        )));
+    };
+    if state.inlining.iter().any(|f| f == name) {
+        let message = format!("function `{}` calls itself, which is not supported", name);
+        return Err(located(message, span));
+    }
+    // Devices and logic types are only known at compile time, so functions taking them
+    // are always inlined.
+    if state.called.contains(name) && values.iter().all(|v| v.external().is_none()) {
+        if !state.program.functions.contains_key(name) {
+            let block_id = state.new_block(true);
+            state.program.functions.insert(
+                name.to_string(),
+                Function {
+                    block_id,
+                    params: vec![],
                 },
-            ))
+            );
+            state.pending.push(name.to_string());
+        }
+        let id = state.add_variable(
+            *block,
+            VarValue::Call {
+                name: name.to_string(),
+                args: values,
+            },
+        );
+        if let Some(span) = span {
+            state.call_spans.insert(id, span);
         }
-        Expr::FieldExpr(d, logic) => {
-            let arg0 = process_expr(state, block, &Expr::Identifier(d.clone()));
-            let arg1 = process_expr(state, block, &Expr::Identifier(logic.clone()));
+        return Ok(VarOrConst::Var(id));
+    }
 
-            VarOrConst::Var(state.add_variable(
-                block,
-                VarValue::Call {
-                    name: "load".to_string(),
-                    args: vec![arg0, arg1],
-                },
-            ))
+    // Functions do not see the variables around them.
+    state.inlined += 1;
+    let prefix = format!("{}#{}.", name, state.inlined);
+    let outer = std::mem::replace(&mut state.prefix, prefix);
+    // Nor the loops they are called in.
+    let outer_loops = std::mem::take(&mut state.loops);
+    let outer_scopes = std::mem::take(&mut state.scopes);
+    state.inlining.push(name.to_string());
+    for (param, value) in params.iter().zip(values) {
+        let param = state.var(param.as_ref());
+        match value {
+            VarOrConst::Var(id) => state.assign(*block, &param, id),
+            VarOrConst::Const(_) => {
+                let id = state.add_variable(*block, VarValue::Single(value));
+                state.assign(*block, &param, id);
+            }
+            // Devices and logic types stay known at compile time.
+            VarOrConst::External(_) => {
+                state.consts.insert(param, value);
+            }
         }
     }
+    let result = process_body(state, block, &body)?;
+    state.inlining.pop();
+    state.loops = outer_loops;
+    state.scopes = outer_scopes;
+    state.prefix = outer;
+    Ok(result)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::simulator::{Simulator, TickResult};
-    use ayysee_parser::grammar::ProgramParser;
-    use stationeers_mips::types::{Device, DeviceVariable};
-    use test_log::test;
-
-    fn compile(ayysee: &str) -> mips::Program {
-        let parser = ProgramParser::new();
-        let ayysee_program = parser.parse(ayysee).unwrap();
-        tracing::debug!("ayysee_program:\n{:?}", ayysee_program);
-        let mips = generate_program(ayysee_program).unwrap();
-        tracing::debug!("MIPS:\n{}", mips);
-        mips
+/// Generates the body of a function from `block`, returning its result. The statements after
+/// it continue from the block left in `block`.
+fn process_body(
+    state: &mut State,
+    block: &mut BlockId,
+    body: &ast::Block,
+) -> anyhow::Result<VarOrConst> {
+    let (statements, ret) = match body.statements().split_last() {
+        Some((last, rest)) => match &last.kind {
+            ast::StatementKind::Return(expr) => (rest, expr.as_deref()),
+            _ => (body.statements(), None),
+        },
+        None => (body.statements(), None),
+    };
+    // Most functions only return at the end, their result is simply the last expression.
+    if !statements.iter().any(returns) {
+        *block = process_stmts(state, *block, statements)?;
+        return match ret {
+            Some(expr) => process_expr(state, block, expr),
+            None => Ok(VarOrConst::Const(0.0.into())),
+        };
     }
 
-    #[test]
-    #[ignore]
-    fn test_empty_program() {
-        let mips = compile("");
-        let mut simulator = Simulator::new(mips);
-        assert_eq!(simulator.tick(), TickResult::End);
+    // Otherwise every `return` assigns the result and moves to the exit block.
+    let start = *block;
+    let exit = state.new_block(false);
+    let outer = state.exit.replace(exit);
+    let end = process_stmts(state, *block, body.statements());
+    state.exit = outer;
+    let end = end?;
+    // Running past the last statement returns 0.
+    if !state.unreachable.contains(&end) {
+        let zero = state.add_variable(end, VarValue::Single(VarOrConst::Const(0.0.into())));
+        state.assign(end, &state.var("return"), zero);
+        state.connect_blocks(end, exit);
+    }
+    if state.sealed_blocks.contains(&start) {
+        state.seal_block(exit);
     }
+    *block = exit;
+    if state.program.blocks[exit.0].prev.is_empty() {
+        // The function loops forever.
+        state.unreachable.insert(exit);
+        return Ok(VarOrConst::Const(0.0.into()));
+    }
+    Ok(VarOrConst::Var(
+        state.read_variable(exit, &state.var("return")),
+    ))
+}
 
-    #[test]
-    fn test_simple_store() {
-        let mips = compile(
-            r"
-                // comments are supported
-                store(d0, Setting, 1);
-            ",
-        );
-        let mut simulator = Simulator::new(mips);
-        assert_eq!(simulator.tick(), TickResult::End);
-        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 1.0);
+/// Returns whether the statement contains a `return`, outside of the functions it defines.
+fn returns(statement: &ast::Statement) -> bool {
+    match &statement.kind {
+        ast::StatementKind::Return(_) => true,
+        ast::StatementKind::Function { .. } => false,
+        _ => crate::resolve::blocks(statement)
+            .into_iter()
+            .any(|block| block.statements().iter().any(returns)),
     }
+}
 
-    #[test]
-    fn test_new_store_syntax() {
-        let mips = compile(
-            r"
-                d0.Setting = 1;
-            ",
-        );
-        let mut simulator = Simulator::new(mips);
-        assert_eq!(simulator.tick(), TickResult::End);
-        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 1.0);
+/// Generates the body of a function called with `jal`, from the block created at its first
+/// call.
+fn process_function(state: &mut State, name: &str) -> anyhow::Result<()> {
+    let (params, body) = state.functions[name].clone();
+    let mut block = state.program.functions[name].block_id;
+    state.prefix = format!("{}.", name);
+    state.inlining = vec![name.to_string()];
+    let mut ids = vec![];
+    for param in &params {
+        let id = state.add_variable(block, VarValue::Param);
+        state.assign(block, &state.var(param.as_ref()), id);
+        ids.push(id);
     }
+    state.program.functions.get_mut(name).unwrap().params = ids;
+    let result = process_body(state, &mut block, &body)?;
+    anyhow::ensure!(
+        result.external().is_none(),
+        "function `{}` returns `{}`, which is only known at compile time, but is called from several places",
+        name,
+        result.external().unwrap_or(&String::new())
+    );
+    state.program.blocks[block.0]
+        .instructions
+        .push(Instruction::Return(Some(result)));
+    state.prefix.clear();
+    state.inlining.clear();
+    Ok(())
+}
 
-    #[test]
-    fn test_simple_variable() {
-        let mips = compile(
-            r"
-                let x = 1;
-                let y = x + 2;
-                store(d0, Setting, y);
-            ",
-        );
-        let mut simulator = Simulator::new(mips);
-        assert_eq!(simulator.tick(), TickResult::End);
-        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 3.0);
+/// Checks that the functions called with `jal` do not call themselves, through other
+/// functions: they keep their variables in the same registers at every call. The error
+/// points at the call closing the cycle.
+fn check_recursion(
+    program: &Program,
+    call_spans: &HashMap<VarId, ast::Span>,
+) -> anyhow::Result<()> {
+    fn visit<'a>(
+        program: &'a Program,
+        call_spans: &HashMap<VarId, ast::Span>,
+        name: &'a str,
+        stack: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+    ) -> anyhow::Result<()> {
+        if !done.insert(name) {
+            return Ok(());
+        }
+        stack.push(name);
+        for block in program.function_blocks(program.functions[name].block_id) {
+            for ins in &program.blocks[block.0].instructions {
+                let Instruction::Assignment {
+                    id,
+                    value: VarValue::Call { name: callee, .. },
+                } = ins
+                else {
+                    continue;
+                };
+                if !program.functions.contains_key(callee) {
+                    continue;
+                }
+                if stack.contains(&callee.as_str()) {
+                    let message =
+                        format!("function `{}` calls itself, which is not supported", callee);
+                    return Err(located(message, call_spans.get(id).copied()));
+                }
+                visit(program, call_spans, callee, stack, done)?;
+            }
+        }
+        stack.pop();
+        Ok(())
     }
 
-    #[test]
-    fn test_simple_load() {
-        let mips = compile(
-            r"
-                let x = load(d0, Setting);
-                let y = x + 2;
-                store(d0, Setting, y);
-                ",
-        );
-        let mut simulator = Simulator::new(mips);
-        simulator.write(Device::D0, DeviceVariable::Setting, 2.0);
-        assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
-        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 4.0);
+    let mut names = program.functions.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut done = HashSet::new();
+    for name in names {
+        visit(program, call_spans, name, &mut vec![], &mut done)?;
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_new_load() {
-        let mips = compile(
-            r"
-                store(d0, Setting, d0.Setting + 2);
-                ",
-        );
-        let mut simulator = Simulator::new(mips);
-        simulator.write(Device::D0, DeviceVariable::Setting, 2.0);
-        assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
-        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 4.0);
+/// Returns the functions of the program to compile once and call, rather than inline where
+/// they are called: those called from several places, when that takes fewer lines.
+fn called_functions(
+    program: &ast::Program,
+    functions: &HashMap<String, (Vec<ast::Identifier>, ast::Block)>,
+    prelude: &HashSet<String>,
+) -> HashSet<String> {
+    let mut calls = Cost::default();
+    for statement in &program.statements {
+        calls.visit_statement(statement);
+    }
+    let mut called = HashSet::new();
+    for (name, (params, body)) in functions {
+        let sites = calls.calls.get(name).copied().unwrap_or_default();
+        if prelude.contains(name) || sites < 2 {
+            continue;
+        }
+        let mut cost = Cost::default();
+        cost.visit_block(body);
+        // Each call pushes the arguments, jumps and pops the result. The function pops the
+        // arguments, saves `ra` if it calls others, pushes the result and jumps back.
+        let lines = cost.lines + params.len() + 4 + sites * (params.len() + 2);
+        if lines < sites * cost.lines {
+            called.insert(name.clone());
+        }
     }
+    called
+}
 
-    #[test]
-    fn test_simple_conditional() {
-        let mips = compile(
+/// Estimates the number of lines statements compile to, and counts the calls of each
+/// function.
+#[derive(Default)]
+struct Cost {
+    lines: usize,
+    calls: HashMap<String, usize>,
+}
+
+impl Visitor for Cost {
+    fn visit_statement(&mut self, statement: &ast::Statement) {
+        self.lines += match &statement.kind {
+            ast::StatementKind::IfStatement(ast::IfStatement::IfElse { .. }) => 2,
+            // A branch for each condition, and a jump past the rest after each body but the
+            // last.
+            ast::StatementKind::IfStatement(ast::IfStatement::ElseIf {
+                branches,
+                else_body,
+            }) => 2 * branches.len() - usize::from(else_body.is_none()),
+            // The comparison and branch before each iteration, then the increment and the
+            // jump back.
+            ast::StatementKind::For { .. } => 4,
+            // The test of the condition and the branch back.
+            ast::StatementKind::LoopUntil { .. } => 2,
+            // A branch for each arm, and a jump to the end after each body.
+            ast::StatementKind::Match { arms, .. } => 2 * arms.len(),
+            ast::StatementKind::IfStatement(_)
+            | ast::StatementKind::Loop { .. }
+            | ast::StatementKind::Yield
+            | ast::StatementKind::Break(_)
+            | ast::StatementKind::Continue(_)
+            | ast::StatementKind::Halt => 1,
+            ast::StatementKind::FunctionCall { identifier, .. } => {
+                *self.calls.entry(identifier.to_string()).or_default() += 1;
+                1
+            }
+            _ => 0,
+        };
+        walk_statement(self, statement)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.lines += match &expr.kind {
+            ExprKind::BinaryOp(..) | ExprKind::UnaryOp(..) | ExprKind::FieldExpr(..) => 1,
+            // The address, then `get` or `put`.
+            ExprKind::Index(..) => 2,
+            ExprKind::FunctionCall(identifier, _) => {
+                *self.calls.entry(identifier.to_string()).or_default() += 1;
+                1
+            }
+            _ => 0,
+        };
+        walk_expr(self, expr)
+    }
+}
+
+/// Returns an error pointing at `span` like those of the resolver, or without a location
+/// when the source of the error is unknown.
+fn located(message: String, span: Option<ast::Span>) -> anyhow::Error {
+    match span {
+        Some(span) => {
+            crate::resolve::SemanticErrors(vec![crate::resolve::SemanticError { message, span }])
+                .into()
+        }
+        None => anyhow::anyhow!(message),
+    }
+}
+
+/// Returns the value of a name, reporting the variables without a definition like the
+/// resolver does rather than failing to read them.
+fn process_identifier(
+    state: &mut State,
+    block: BlockId,
+    ident: &ast::Identifier,
+) -> anyhow::Result<VarOrConst> {
+    let name = state.var(ident.as_ref());
+    // The variables of inlined functions hide the constants of the program.
+    let local = !state.prefix.is_empty() && state.defs.contains_key(&name);
+    if let Some(x) = state.consts.get(&name) {
+        Ok(x.clone())
+    } else if let Some(x) = state.consts.get::<str>(ident.as_ref()).filter(|_| !local) {
+        Ok(x.clone())
+    } else if state.defs.contains_key(&name) {
+        Ok(VarOrConst::Var(state.read_variable(block, &name)))
+    } else {
+        let message = format!("cannot find variable `{}`", ident);
+        Err(located(message, Some(ident.span)))
+    }
+}
+
+/// Processes the logic type of a device field, which may be one the game data does not
+/// list when [`CompileOptions::unknown_logic_types`] is set.
+fn process_logic_type(
+    state: &mut State,
+    block: BlockId,
+    ident: &ast::Identifier,
+) -> anyhow::Result<VarOrConst> {
+    match process_identifier(state, block, ident) {
+        Err(_) if state.unknown_logic_types => Ok(VarOrConst::External(ident.to_string())),
+        result => result,
+    }
+}
+
+fn process_expr(
+    state: &mut State,
+    block: &mut BlockId,
+    expr: &ayysee_parser::ast::Expr,
+) -> anyhow::Result<VarOrConst> {
+    Ok(match &expr.kind {
+        ExprKind::Constant(v) => VarOrConst::Const(Into::<f64>::into(v).into()),
+        ExprKind::Hash(name) => VarOrConst::Const(f64::from(mips::hash::hash(name)).into()),
+        ExprKind::Identifier(ident) => process_identifier(state, *block, ident)?,
+        ExprKind::BinaryOp(lhs_expr, op @ (BinaryOpcode::Conj | BinaryOpcode::Disj), rhs_expr) => {
+            // `and` and `or` are bitwise, so their operands must be 0 or 1.
+            let lhs = process_condition(state, block, lhs_expr)?;
+            let rhs = process_condition(state, block, rhs_expr)?;
+            VarOrConst::Var(state.add_variable(*block, VarValue::BinaryOp { lhs, op: *op, rhs }))
+        }
+        ExprKind::BinaryOp(lhs_expr, BinaryOpcode::Pow, rhs_expr) => {
+            let lhs = process_expr(state, block, lhs_expr)?;
+            let rhs = process_expr(state, block, rhs_expr)?;
+            process_power(state, block, lhs, rhs)
+        }
+        ExprKind::BinaryOp(lhs_expr, op, rhs_expr) => {
+            let lhs = process_expr(state, block, lhs_expr)?;
+            let rhs = process_expr(state, block, rhs_expr)?;
+            VarOrConst::Var(state.add_variable(*block, VarValue::BinaryOp { lhs, op: *op, rhs }))
+        }
+        ExprKind::UnaryOp(op, operand_expr) => {
+            let operand = process_expr(state, block, operand_expr)?;
+            match VarValue::unary(*op, operand) {
+                VarValue::Single(value) => value,
+                value => VarOrConst::Var(state.add_variable(*block, value)),
+            }
+        }
+        ExprKind::FunctionCall(ident, args) => {
+            process_call(state, block, ident.as_ref(), Some(ident.span), args)?
+        }
+        ExprKind::DeviceIndex(index) => match process_expr(state, block, index)? {
+            // A pin known at compile time is named directly rather than through a register.
+            VarOrConst::Const(x) => match mips::types::Device::PINS.get(x.0 as usize) {
+                Some(device) if x.0 >= 0.0 && x.0.fract() == 0.0 => {
+                    VarOrConst::External(device.to_string())
+                }
+                _ => anyhow::bail!("there is no device pin {}", x.0),
+            },
+            index => index,
+        },
+        ExprKind::Index(array, index) => {
+            let address = process_address(state, block, array, index)?;
+            VarOrConst::Var(state.add_variable(*block, VarValue::Get { address }))
+        }
+        ExprKind::Array(..) => {
+            anyhow::bail!("arrays can only be declared with `let`, as in `let buf = [0; 8];`")
+        }
+        ExprKind::FieldExpr(d, logic) => {
+            let arg0 = process_expr(state, block, d)?;
+            let arg1 = process_logic_type(state, *block, logic)?;
+
+            VarOrConst::Var(state.add_variable(
+                *block,
+                VarValue::Call {
+                    name: "load".to_string(),
+                    args: vec![arg0, arg1],
+                },
+            ))
+        }
+    })
+}
+
+/// Generates `lhs ** rhs`, which the game has no instruction for. Constants are folded, and
+/// whole exponents multiply by squaring, unrolled when the exponent is a constant and in a
+/// loop otherwise, so that `2 ** n` is exact. Other exponents compute `exp(rhs * log(lhs))`:
+/// as with `powf`, a negative `lhs` then gives NaN, the power not being a real number.
+fn process_power(
+    state: &mut State,
+    block: &mut BlockId,
+    lhs: VarOrConst,
+    rhs: VarOrConst,
+) -> VarOrConst {
+    fn binary(
+        state: &mut State,
+        block: BlockId,
+        lhs: impl Into<VarOrConst>,
+        op: BinaryOpcode,
+        rhs: impl Into<VarOrConst>,
+    ) -> VarId {
+        let (lhs, rhs) = (lhs.into(), rhs.into());
+        state.add_variable(block, VarValue::BinaryOp { lhs, op, rhs })
+    }
+    let number = |x: f64| VarOrConst::Const(x.into());
+    match (&lhs, &rhs) {
+        (VarOrConst::Const(a), VarOrConst::Const(b)) => return number(a.0.powf(b.0)),
+        // Larger exponents overflow or underflow whatever the method.
+        (_, VarOrConst::Const(b)) if b.0.fract() == 0.0 && b.0.abs() <= u32::MAX as f64 => {
+            let mut n = b.0.abs() as u32;
+            let mut base = lhs;
+            let mut result = None;
+            loop {
+                if n & 1 == 1 {
+                    result = Some(match result {
+                        Some(result) => {
+                            binary(state, *block, result, BinaryOpcode::Mul, base.clone())
+                        }
+                        None => variable(state, *block, base.clone()),
+                    });
+                }
+                n >>= 1;
+                if n == 0 {
+                    break;
+                }
+                base = binary(state, *block, base.clone(), BinaryOpcode::Mul, base).into();
+            }
+            return match result {
+                None => number(1.0),
+                Some(result) if b.0 < 0.0 => {
+                    binary(state, *block, number(1.0), BinaryOpcode::Div, result).into()
+                }
+                Some(result) => result.into(),
+            };
+        }
+        (_, VarOrConst::Const(_)) => {
+            let op = BinaryOpcode::Pow;
+            return binary(state, *block, lhs, op, rhs).into();
+        }
+        _ => (),
+    }
+
+    // `rhs` is whole when its remainder by 1 is 0.
+    let remainder = binary(state, *block, rhs.clone(), BinaryOpcode::Mod, number(1.0));
+    let whole = binary(state, *block, remainder, BinaryOpcode::Equals, number(0.0));
+    let block_whole = state.new_block(false);
+    let block_fraction = state.new_block(false);
+    let block_inverse = state.new_block(false);
+    let block_cond = state.new_block(false);
+    let block_body = state.new_block(false);
+    let block_multiply = state.new_block(false);
+    let block_step = state.new_block(false);
+    let block_next = state.new_block(false);
+    let branch = |state: &mut State, block: BlockId, cond: VarId, true_block, false_block| {
+        state.program.blocks[block.0]
+            .instructions
+            .push(Instruction::Branch {
+                cond: cond.into(),
+                true_block,
+                false_block,
+            });
+        state.connect_blocks(block, true_block);
+        state.connect_blocks(block, false_block);
+    };
+    branch(state, *block, whole, block_whole, block_fraction);
+
+    let id = whole.0;
+    let [result, base, exponent] =
+        ["result", "base", "exponent"].map(|name| state.var(&format!("#pow{}{}", id, name)));
+    let power = binary(
+        state,
+        block_fraction,
+        lhs.clone(),
+        BinaryOpcode::Pow,
+        rhs.clone(),
+    );
+    state.assign(block_fraction, &result, power);
+    state.connect_blocks(block_fraction, block_next);
+
+    // A negative exponent raises the inverse of `lhs` instead.
+    let one = state.add_variable(block_whole, VarValue::Single(number(1.0)));
+    state.assign(block_whole, &result, one);
+    let first = variable(state, block_whole, lhs.clone());
+    state.assign(block_whole, &base, first);
+    let first = variable(state, block_whole, rhs.clone());
+    state.assign(block_whole, &exponent, first);
+    let negative = binary(
+        state,
+        block_whole,
+        rhs.clone(),
+        BinaryOpcode::Lower,
+        number(0.0),
+    );
+    branch(state, block_whole, negative, block_inverse, block_cond);
+
+    let inverse = binary(state, block_inverse, number(1.0), BinaryOpcode::Div, lhs);
+    state.assign(block_inverse, &base, inverse);
+    let opposite = state.add_variable(block_inverse, VarValue::unary(UnaryOpcode::Neg, rhs));
+    state.assign(block_inverse, &exponent, opposite);
+    state.connect_blocks(block_inverse, block_cond);
+
+    let current = state.read_variable(block_cond, &result);
+    let square = state.read_variable(block_cond, &base);
+    let n = state.read_variable(block_cond, &exponent);
+    let more = binary(state, block_cond, n, BinaryOpcode::Greater, number(0.0));
+    branch(state, block_cond, more, block_body, block_next);
+
+    let bit = binary(state, block_body, n, BinaryOpcode::Mod, number(2.0));
+    branch(state, block_body, bit, block_multiply, block_step);
+
+    let product = binary(state, block_multiply, current, BinaryOpcode::Mul, square);
+    state.assign(block_multiply, &result, product);
+    state.connect_blocks(block_multiply, block_step);
+
+    let next_square = binary(state, block_step, square, BinaryOpcode::Mul, square);
+    state.assign(block_step, &base, next_square);
+    let even = binary(state, block_step, n, BinaryOpcode::Sub, bit);
+    let half = binary(state, block_step, even, BinaryOpcode::Div, number(2.0));
+    state.assign(block_step, &exponent, half);
+    state.connect_blocks(block_step, block_cond);
+
+    if state.sealed_blocks.contains(block) {
+        state.seal_block(block_whole);
+        state.seal_block(block_fraction);
+    }
+    *block = block_next;
+    VarOrConst::Var(state.read_variable(block_next, &result))
+}
+
+/// Returns the variable holding a value, adding a copy of a constant.
+fn variable(state: &mut State, block: BlockId, value: VarOrConst) -> VarId {
+    match value {
+        VarOrConst::Var(id) => id,
+        value => state.add_variable(block, VarValue::Single(value)),
+    }
+}
+
+/// Generates `let name = [value; length];`. Arrays take the addresses at the top of the
+/// stack, below those of the arrays before them, leaving the bottom to `push` and to the
+/// calls of functions. Every element is set to the value, in a loop for longer arrays.
+fn process_array(
+    state: &mut State,
+    block: &mut BlockId,
+    identifier: &ast::Identifier,
+    value: &Expr,
+) -> anyhow::Result<()> {
+    let length = state.lengths[&identifier.span.start];
+    let stack_size = crate::simulator::STACK_SIZE;
+    state.stack_used += length;
+    if state.stack_used > stack_size {
+        let message = format!(
+            "the arrays take more than the {} values of the stack",
+            stack_size
+        );
+        return Err(located(message, Some(identifier.span)));
+    }
+    let base = stack_size - state.stack_used;
+    let value = process_expr(state, block, value)?;
+    let name = state.define(identifier.as_ref());
+    state.arrays.insert(name.clone(), (base, length));
+
+    let address = |x: usize| VarOrConst::Const((x as f64).into());
+    // Short arrays take fewer lines with a `put` for each element than with a loop.
+    if length <= 4 {
+        for i in base..base + length {
+            let value = value.clone();
+            state.add_variable(
+                *block,
+                VarValue::Put {
+                    address: address(i),
+                    value,
+                },
+            );
+        }
+        return Ok(());
+    }
+    let counter = format!("{}#address", name);
+    let start = state.add_variable(*block, VarValue::Single(address(base)));
+    state.assign(*block, &counter, start);
+    let body = state.new_block(false);
+    let next = state.new_block(false);
+    state.connect_blocks(*block, body);
+    let current = state.read_variable(body, &counter);
+    state.add_variable(
+        body,
+        VarValue::Put {
+            address: current.into(),
+            value,
+        },
+    );
+    let following = state.add_variable(
+        body,
+        VarValue::BinaryOp {
+            lhs: current.into(),
+            op: BinaryOpcode::Add,
+            rhs: address(1),
+        },
+    );
+    state.assign(body, &counter, following);
+    let cond = state.add_variable(
+        body,
+        VarValue::BinaryOp {
+            lhs: following.into(),
+            op: BinaryOpcode::Lower,
+            rhs: address(base + length),
+        },
+    );
+    state.program.blocks[body.0]
+        .instructions
+        .push(Instruction::Branch {
+            cond: cond.into(),
+            true_block: body,
+            false_block: next,
+        });
+    state.connect_blocks(body, body);
+    state.connect_blocks(body, next);
+    if state.sealed_blocks.contains(block) {
+        state.seal_block(body);
+    }
+    *block = next;
+    Ok(())
+}
+
+/// Generates `for variable in start..end { body }`. A counter the compiler adds is compared
+/// to `end` before each iteration, and incremented after the body, where `continue` moves
+/// to. The variable is a copy of the counter, so assigning to it does not change the
+/// iterations.
+fn process_for(
+    state: &mut State,
+    block: &mut BlockId,
+    label: &Option<ast::Identifier>,
+    variable: &ast::Identifier,
+    start: &Expr,
+    end: &Expr,
+    body: &ast::Block,
+) -> anyhow::Result<()> {
+    let start = process_expr(state, block, start)?;
+    let end = process_expr(state, block, end)?;
+    let first = state.add_variable(*block, VarValue::Single(start));
+    let counter = state.var(&format!("#for{}", first.0));
+    state.assign(*block, &counter, first);
+
+    let block_cond = state.new_block(false);
+    let block_body = state.new_block(false);
+    let block_step = state.new_block(false);
+    let block_next = state.new_block(false);
+    state.connect_blocks(*block, block_cond);
+
+    let current = state.read_variable(block_cond, &counter);
+    let cond = state.add_variable(
+        block_cond,
+        VarValue::BinaryOp {
+            lhs: current.into(),
+            op: BinaryOpcode::Lower,
+            rhs: end,
+        },
+    );
+    state.program.blocks[block_cond.0]
+        .instructions
+        .push(Instruction::Branch {
+            cond: cond.into(),
+            true_block: block_body,
+            false_block: block_next,
+        });
+    state.connect_blocks(block_cond, block_body);
+    state.connect_blocks(block_cond, block_next);
+
+    let copy = state.add_variable(block_body, current.into());
+    let variable = state.define(variable.as_ref());
+    state.assign(block_body, &variable, copy);
+    state
+        .loops
+        .push((label_name(label), block_step, block_next));
+    let body_end = process_stmts(state, block_body, body.statements());
+    state.loops.pop();
+    state.connect_blocks(body_end?, block_step);
+
+    // The body can leave every iteration with `break`.
+    if state.program.blocks[block_step.0].prev.is_empty() {
+        state.unreachable.insert(block_step);
+    }
+    let following = state.add_variable(
+        block_step,
+        VarValue::BinaryOp {
+            lhs: current.into(),
+            op: BinaryOpcode::Add,
+            rhs: VarOrConst::Const(1.0.into()),
+        },
+    );
+    state.assign(block_step, &counter, following);
+    state.connect_blocks(block_step, block_cond);
+    if state.sealed_blocks.contains(block) {
+        state.seal_block(block_cond);
+    }
+    *block = block_next;
+    Ok(())
+}
+
+fn label_name(label: &Option<ast::Identifier>) -> Option<String> {
+    label.as_ref().map(ToString::to_string)
+}
+
+/// Returns the address on the stack of an element of an array, checking its index when it
+/// is known at compile time.
+fn process_address(
+    state: &mut State,
+    block: &mut BlockId,
+    array: &ast::Identifier,
+    index: &Expr,
+) -> anyhow::Result<VarOrConst> {
+    let (base, length) = *state
+        .arrays
+        .get(&state.var(array.as_ref()))
+        .with_context(|| format!("`{}` is not an array", array))?;
+    Ok(match process_expr(state, block, index)? {
+        VarOrConst::Const(x) => {
+            anyhow::ensure!(
+                x.0 >= 0.0 && x.0 < length as f64 && x.0.fract() == 0.0,
+                "index {} is out of bounds for array `{}` of length {}",
+                x.0,
+                array,
+                length
+            );
+            VarOrConst::Const((base as f64 + x.0).into())
+        }
+        index => VarOrConst::Var(state.add_variable(
+            *block,
+            VarValue::BinaryOp {
+                lhs: index,
+                op: BinaryOpcode::Add,
+                rhs: VarOrConst::Const((base as f64).into()),
+            },
+        )),
+    })
+}
+
+/// Generates an expression as 1 when it is nonzero and 0 otherwise, comparing it to 0
+/// unless it is already a comparison or logical operation.
+fn process_condition(
+    state: &mut State,
+    block: &mut BlockId,
+    expr: &ayysee_parser::ast::Expr,
+) -> anyhow::Result<VarOrConst> {
+    let boolean = match &expr.kind {
+        ExprKind::BinaryOp(_, op, _) => {
+            op.is_comparison() || matches!(op, BinaryOpcode::Conj | BinaryOpcode::Disj)
+        }
+        ExprKind::UnaryOp(op, _) => matches!(op, UnaryOpcode::Not),
+        _ => false,
+    };
+    Ok(match process_expr(state, block, expr)? {
+        VarOrConst::Const(x) => VarOrConst::Const(f64::from(x.0 != 0.0).into()),
+        value if boolean => value,
+        lhs => VarOrConst::Var(state.add_variable(
+            *block,
+            VarValue::BinaryOp {
+                lhs,
+                op: BinaryOpcode::NotEquals,
+                rhs: VarOrConst::Const(0.0.into()),
+            },
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, TickResult};
+    use crate::{GameData, Target};
+    use stationeers_mips::types::{Device, DeviceVariable};
+    use test_log::test;
+
+    fn compile(ayysee: &str) -> mips::Program {
+        compile_with(ayysee, OptLevel::default())
+    }
+
+    fn compile_with(ayysee: &str, opt_level: OptLevel) -> mips::Program {
+        let ayysee_program = ayysee_parser::parse(ayysee).unwrap();
+        tracing::debug!("ayysee_program:\n{:?}", ayysee_program);
+        let options = CompileOptions {
+            opt_level,
+            ..Default::default()
+        };
+        let mips = generate_program_with(ayysee_program, &options).unwrap();
+        tracing::debug!("MIPS:\n{}", mips);
+        mips
+    }
+
+    #[test]
+    #[ignore]
+    fn test_empty_program() {
+        let mips = compile("");
+        let mut simulator = Simulator::new(mips);
+        assert_eq!(simulator.tick(), TickResult::End);
+    }
+
+    #[test]
+    fn test_simple_store() {
+        let mips = compile(
+            r"
+                // comments are supported
+                store(d0, Setting, 1);
+            ",
+        );
+        let mut simulator = Simulator::new(mips);
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 1.0);
+    }
+
+    #[test]
+    fn test_new_store_syntax() {
+        let mips = compile(
+            r"
+                d0.Setting = 1;
+            ",
+        );
+        let mut simulator = Simulator::new(mips);
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 1.0);
+    }
+
+    #[test]
+    fn test_simple_variable() {
+        let mips = compile(
+            r"
+                let x = 1;
+                let y = x + 2;
+                store(d0, Setting, y);
+            ",
+        );
+        let mut simulator = Simulator::new(mips);
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 3.0);
+    }
+
+    #[test]
+    fn test_simple_load() {
+        let mips = compile(
+            r"
+                let x = load(d0, Setting);
+                let y = x + 2;
+                store(d0, Setting, y);
+                ",
+        );
+        let mut simulator = Simulator::new(mips);
+        simulator.write(Device::D0, DeviceVariable::Setting, 2.0);
+        assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 4.0);
+    }
+
+    #[test]
+    fn test_new_load() {
+        let mips = compile(
+            r"
+                store(d0, Setting, d0.Setting + 2);
+                ",
+        );
+        let mut simulator = Simulator::new(mips);
+        simulator.write(Device::D0, DeviceVariable::Setting, 2.0);
+        assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 4.0);
+    }
+
+    #[test]
+    fn test_simple_conditional() {
+        let mips = compile(
             r"
                 if load(d0, Setting) > 5 {
                     store(d0, Setting, 1);
@@ -599,6 +1736,7 @@ mod tests {
                 let x = 1;
                 if d0.Setting > 5 {
                     let x = 2;
+                    d2.Setting = x;
                 }
                 d1.Setting = x;
             ",
@@ -608,12 +1746,15 @@ mod tests {
             simulator.write(Device::D0, DeviceVariable::Setting, 3.0);
             assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
             assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 1.0);
+            assert_eq!(simulator.read(Device::D2, DeviceVariable::Setting), 0.0);
         }
         {
+            // The `let` in the body shadows `x` rather than assigning it.
             let mut simulator = Simulator::new(mips);
             simulator.write(Device::D0, DeviceVariable::Setting, 8.0);
             assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
-            assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 2.0);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 1.0);
+            assert_eq!(simulator.read(Device::D2, DeviceVariable::Setting), 2.0);
         }
     }
 
@@ -644,6 +1785,256 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_undefined_variable() {
+        // The resolver rejects such programs first, but the IR reports them the same way
+        // rather than panicking should the two ever disagree.
+        let mut state = State::default();
+        let block = state.new_block(true);
+        let ident = ast::Identifier::new("x", ast::Span::new(12, 13));
+        let error = process_identifier(&mut state, block, &ident).unwrap_err();
+        let errors = error
+            .downcast_ref::<crate::resolve::SemanticErrors>()
+            .unwrap();
+        assert_eq!(
+            errors.0,
+            vec![crate::resolve::SemanticError {
+                message: "cannot find variable `x`".to_string(),
+                span: ast::Span::new(12, 13),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_block_scopes() {
+        let source = r"
+            fn f(p) {
+                if p > 0 {
+                    let p = p * 10;
+                    d2.Setting = p;
+                }
+                return p;
+            }
+            let x = 1;
+            let total = 0;
+            for i in 0..3 {
+                let x = x + i;
+                {
+                    let x = x * 100;
+                    total = total + x;
+                }
+                total = total + x;
+                x = 7;
+            }
+            let i = 5;
+            {
+                for i in 0..2 {}
+            }
+            db.Setting = total;
+            db.On = x;
+            db.Mode = i;
+            d1.Setting = f(d0.Setting);
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Setting, 4.0);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            // The inner `x`s are 1, 2 and 3, and 100 times those; the outer one stays 1.
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 606.0);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::On), 1.0);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Mode), 5.0);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 4.0);
+            assert_eq!(simulator.read(Device::D2, DeviceVariable::Setting), 40.0);
+        }
+    }
+
+    #[test]
+    fn test_else_if() {
+        let chain = r"
+            let t = d0.Temperature;
+            let mode = 0;
+            if t < 280 {
+                mode = 1;
+            } else if t < 300 {
+                mode = 2;
+            } else if t < 320 {
+                mode = 3;
+            } else {
+                mode = 4;
+            }
+            db.Setting = mode;
+        ";
+        let nested = r"
+            let t = d0.Temperature;
+            let mode = 0;
+            if t < 280 {
+                mode = 1;
+            } else {
+                if t < 300 {
+                    mode = 2;
+                } else {
+                    if t < 320 {
+                        mode = 3;
+                    } else {
+                        mode = 4;
+                    }
+                }
+            }
+            db.Setting = mode;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(chain, opt_level);
+            for (t, mode) in [(270.0, 1.0), (290.0, 2.0), (310.0, 3.0), (330.0, 4.0)] {
+                let mut simulator = Simulator::new(mips.clone());
+                simulator.write(Device::D0, DeviceVariable::Temperature, t);
+                assert_eq!(simulator.tick(), TickResult::End);
+                assert_eq!(
+                    simulator.read(Device::Db, DeviceVariable::Setting),
+                    mode,
+                    "{:?} at {}",
+                    opt_level,
+                    t
+                );
+            }
+            // Every branch joins the same block, instead of one block per level.
+            let lines = mips.to_string().lines().count();
+            let nested_lines = compile_with(nested, opt_level).to_string().lines().count();
+            assert!(lines <= nested_lines, "{:?}", opt_level);
+        }
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        let source = r"
+            let t = d0.Temperature;
+            d1.Setting = -t;
+            d2.Setting = !t;
+            d3.Setting = -(t - 300) * !!(t > 10);
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(source, opt_level);
+            for (t, neg, not, last) in [(0.0, 0.0, 1.0, 0.0), (273.5, -273.5, 0.0, 26.5)] {
+                let mut simulator = Simulator::new(mips.clone());
+                simulator.write(Device::D0, DeviceVariable::Temperature, t);
+                assert_eq!(simulator.tick(), TickResult::End);
+                assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), neg);
+                assert_eq!(simulator.read(Device::D2, DeviceVariable::Setting), not);
+                assert_eq!(simulator.read(Device::D3, DeviceVariable::Setting), last);
+            }
+        }
+    }
+
+    #[test]
+    fn test_folds_unary_operators_on_constants() {
+        let mips = compile_with(
+            r"
+                const LIMIT = 300;
+                d0.Setting = -LIMIT;
+                d1.Setting = !-(2.5);
+                d2.Setting = -(-(4));
+            ",
+            OptLevel::O0,
+        );
+        let text = mips.to_string();
+        assert!(!text.contains("sub") && !text.contains("seqz"), "{}", text);
+        let mut simulator = Simulator::new(mips);
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), -300.0);
+        assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 0.0);
+        assert_eq!(simulator.read(Device::D2, DeviceVariable::Setting), 4.0);
+    }
+
+    #[test]
+    fn test_modulo_and_power() {
+        let source = r"
+            let t = d0.Setting;
+            let n = d1.Setting;
+            d2.Setting = t % 60;
+            d3.Setting = t ** 2 + 2 ** n;
+            d4.Setting = t ** n ** 2;
+            db.Setting = -t ** 3;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(source, opt_level);
+            let mut simulator = Simulator::new(mips.clone());
+            simulator.write(Device::D0, DeviceVariable::Setting, 125.0);
+            simulator.write(Device::D1, DeviceVariable::Setting, 0.5);
+            assert_eq!(simulator.tick(), TickResult::End);
+            let read = |device| simulator.read(device, DeviceVariable::Setting);
+            assert_eq!(read(Device::D2), 5.0, "{:?}", opt_level);
+            assert!(
+                (read(Device::D3) - 15626.414).abs() < 1e-3,
+                "{:?}",
+                opt_level
+            );
+            // 125 ** 0.25
+            assert!((read(Device::D4) - 3.3437).abs() < 1e-3, "{:?}", opt_level);
+            assert_eq!(read(Device::Db), -1953125.0, "{:?}", opt_level);
+
+            // Unlike `%` in most languages, the result takes the sign of the divisor.
+            let mut simulator = Simulator::new(mips);
+            simulator.write(Device::D0, DeviceVariable::Setting, -5.0);
+            assert_eq!(simulator.tick(), TickResult::End);
+            assert_eq!(simulator.read(Device::D2, DeviceVariable::Setting), 55.0);
+        }
+    }
+
+    #[test]
+    fn test_power_of_whole_exponents() {
+        let mips = compile("d0.Setting = 2 ** (3 ** 2);");
+        let text = mips.to_string();
+        assert!(text.starts_with("s d0 Setting 512\n"), "{}", text);
+
+        // The exponents are only known at run time, so `2 ** n` needs a loop rather than
+        // `exp(n * log(2))`, which is 511.99999999999994 for `n = 9`.
+        let source = r"
+            let n = d0.Setting;
+            d1.Setting = 2 ** n;
+            d2.Setting = (-3) ** n;
+            d3.Setting = 2 ** -n;
+            d4.Setting = (-2) ** (n / 2);
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Setting, 9.0);
+            // The loops take more lines than the game runs in a tick.
+            while simulator.tick() == TickResult::LimitHit {}
+            let read = |device| simulator.read(device, DeviceVariable::Setting);
+            assert_eq!(read(Device::D1), 512.0, "{:?}", opt_level);
+            assert_eq!(read(Device::D2), -19683.0, "{:?}", opt_level);
+            assert_eq!(read(Device::D3), 1.0 / 512.0, "{:?}", opt_level);
+            // A negative number has no real power of 4.5.
+            assert!(read(Device::D4).is_nan(), "{:?}", opt_level);
+        }
+    }
+
+    #[test]
+    fn test_halt() {
+        let source = r"
+            loop {
+                let p = d0.Pressure;
+                if p > 1000 {
+                    db.On = 0;
+                    halt;
+                    db.On = 1;
+                }
+                db.Setting = p;
+                yield;
+            }
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::Db, DeviceVariable::On, 1.0);
+            simulator.write(Device::D0, DeviceVariable::Pressure, 500.0);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            simulator.write(Device::D0, DeviceVariable::Pressure, 2000.0);
+            assert_eq!(simulator.tick(), TickResult::Halted, "{:?}", opt_level);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::On), 0.0);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 500.0);
+            assert_eq!(simulator.tick(), TickResult::Halted);
+        }
+    }
+
     #[test]
     fn test_loop() {
         let mips = compile(
@@ -663,6 +2054,44 @@ mod tests {
         assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 2.0);
     }
 
+    #[test]
+    fn test_break_and_continue() {
+        let source = r"
+            let i = 0;
+            let total = 0;
+            loop {
+                yield;
+                i = i + 1;
+                if i > 6 {
+                    break;
+                }
+                if i == 3 {
+                    continue;
+                }
+                let j = 0;
+                loop {
+                    j = j + 1;
+                    if j > 2 {
+                        break;
+                    }
+                    total = total + i;
+                }
+            }
+            db.Setting = total;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            while simulator.tick() == TickResult::Yield {}
+            // Twice each of 1, 2, 4, 5 and 6.
+            assert_eq!(
+                simulator.read(Device::Db, DeviceVariable::Setting),
+                36.0,
+                "{:?}",
+                opt_level
+            );
+        }
+    }
+
     #[test]
     fn test_web_example() {
         let mips = compile(
@@ -704,6 +2133,719 @@ loop {
         // This is just a sanity check that we can process all those operations
     }
 
+    #[test]
+    fn test_opt_levels_agree() {
+        let source = r"
+            let x = 0;
+            if load(d0, Setting) > 5 {
+                x = 1;
+            } else {
+                x = 2;
+            }
+            store(d0, Setting, x);
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            for (input, expected) in [(2.0, 2.0), (8.0, 1.0)] {
+                let mut simulator = Simulator::new(compile_with(source, opt_level));
+                simulator.write(Device::D0, DeviceVariable::Setting, input);
+                assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+                assert_eq!(
+                    simulator.read(Device::D0, DeviceVariable::Setting),
+                    expected,
+                    "{:?}",
+                    opt_level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_keeps_live_variables_apart() {
+        let sources = [
+            // Both PHIs of the loop take `a` when it repeats.
+            r"
+                let c = 2;
+                let a = d0.Temperature + 1;
+                loop {
+                    if 1 { break; }
+                    c = a;
+                }
+                db.Setting = c;
+            ",
+            // `a` is still read when the loop repeats, after its last read in the body.
+            r"
+                let a = d0.Temperature;
+                let n = 0;
+                loop {
+                    let b = a + 1;
+                    n = n + b;
+                    if n > 20 { break; }
+                }
+                db.Setting = n + 1 - 24 + 1;
+            ",
+        ];
+        for source in sources {
+            for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+                let mut simulator = Simulator::new(compile_with(source, opt_level));
+                simulator.write(Device::D0, DeviceVariable::Temperature, 3.0);
+                assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+                assert_eq!(
+                    simulator.read(Device::Db, DeviceVariable::Setting),
+                    2.0,
+                    "{:?}",
+                    opt_level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_os_fuses_comparison_into_branch() {
+        let source = r"
+            loop {
+                if d0.Temperature > 300 {
+                    d1.On = 1;
+                } else {
+                    d1.On = 0;
+                }
+                yield;
+            }
+        ";
+        let o2 = compile_with(source, OptLevel::O2);
+        let os = compile_with(source, OptLevel::Os);
+        assert!(os.instructions.len() < o2.instructions.len());
+        for (temperature, on) in [(280.0, 0.0), (310.0, 1.0)] {
+            let mut simulator = Simulator::new(os.clone());
+            simulator.write(Device::D0, DeviceVariable::Temperature, temperature);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::On), on);
+        }
+    }
+
+    #[test]
+    fn test_os_executes_fewer_instructions() {
+        let source = r"
+            loop {
+                if d0.Temperature > 300 {
+                    d1.On = 1;
+                }
+                yield;
+            }
+        ";
+        let executed = |opt_level| {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Temperature, 310.0);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            simulator.executed()
+        };
+        assert!(executed(OptLevel::Os) < executed(OptLevel::O2));
+    }
+
+    #[test]
+    fn test_legacy_target_does_not_fuse_branches() {
+        let source = r"
+            loop {
+                if d0.Temperature > 300 {
+                    d1.On = 1;
+                }
+                yield;
+            }
+        ";
+        let options = CompileOptions {
+            opt_level: OptLevel::Os,
+            target: Target::builtin("ic10-legacy").unwrap(),
+            ..Default::default()
+        };
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = generate_program_with(program, &options).unwrap();
+        assert!(mips.to_string().contains("beqz"));
+    }
+
+    #[test]
+    fn test_hash() {
+        let source = r#"
+            const SENSOR = hash("StructureGasSensor");
+            db.Setting = load_batch(SENSOR, Temperature, Maximum);
+            d0.Setting = hash("StructureVendingMachine");
+        "#;
+        let mips = compile_with(source, OptLevel::O2);
+        assert!(mips
+            .to_string()
+            .contains("lb r0 -1252983604 Temperature Maximum"));
+        let mut simulator = Simulator::new(mips);
+        let sensor = simulator.connect(-1252983604.0);
+        simulator.write_network(sensor, DeviceVariable::Temperature, 290.0);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 290.0);
+        assert_eq!(
+            simulator.read(Device::D0, DeviceVariable::Setting),
+            -443130773.0
+        );
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let source = r"
+            const MASK = ~0xF0 & 0xFF;
+            let mode = d0.Mode;
+            d1.Setting = mode & MASK | (mode >> 4 ^ 1) << 8;
+            d1.Mode = ~mode;
+            d1.On = mode && 2;
+            d1.Open = mode & 2 || 0;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(source, opt_level);
+            assert!(mips.to_string().contains("and r"), "{}", mips);
+            let mut simulator = Simulator::new(mips);
+            simulator.write(Device::D0, DeviceVariable::Mode, 0x35 as f64);
+            simulator.tick();
+            let read = |variable| simulator.read(Device::D1, variable);
+            assert_eq!(
+                read(DeviceVariable::Setting),
+                0x205 as f64,
+                "{:?}",
+                opt_level
+            );
+            assert_eq!(read(DeviceVariable::Mode), -0x36 as f64);
+            assert_eq!(read(DeviceVariable::On), 1.0, "{:?}", opt_level);
+            assert_eq!(read(DeviceVariable::Open), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_game_data() {
+        let options = CompileOptions {
+            game_data: GameData::parse("devices = [\"d0\"]\nlogic_types = [\"NewThing\"]").unwrap(),
+            ..Default::default()
+        };
+        let program = ayysee_parser::parse("d0.NewThing = d0.NewThing + 1;").unwrap();
+        let mips = generate_program_with(program, &options).unwrap();
+        assert!(mips.to_string().contains("l r0 d0 NewThing"));
+
+        let program = ayysee_parser::parse("d0.Setting = 1;").unwrap();
+        assert!(generate_program_with(program, &options).is_err());
+    }
+
+    #[test]
+    fn test_definitions() {
+        let source = r"
+            const TARGET = 20;
+            const LIMIT = TARGET * 2;
+            d0.Setting = LIMIT + OFFSET;
+        ";
+        let options = CompileOptions {
+            definitions: [("TARGET".to_string(), 300.0), ("OFFSET".to_string(), 1.0)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = generate_program_with(program, &options).unwrap();
+        let mut simulator = Simulator::new(mips);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 601.0);
+
+        let program = ayysee_parser::parse(source).unwrap();
+        assert!(generate_program_with(program, &CompileOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_unknown_logic_types() {
+        let source = "d0.ModPower = d1.ModPower + 1;";
+        let options = CompileOptions {
+            unknown_logic_types: true,
+            ..Default::default()
+        };
+        let program = ayysee_parser::parse(source).unwrap();
+        let mips = generate_program_with(program, &options).unwrap();
+        assert_eq!(
+            mips.to_string(),
+            "l r0 d1 ModPower\nadd r0 r0 1\ns d0 ModPower r0\nj 4\n"
+        );
+    }
+
+    #[test]
+    fn test_target_limits() {
+        let source = r"
+            let a = d0.Temperature;
+            let b = d1.Temperature;
+            d2.Setting = a + b;
+        ";
+        let compile = |target| {
+            let options = CompileOptions {
+                target,
+                ..Default::default()
+            };
+            generate_program_with(ayysee_parser::parse(source).unwrap(), &options)
+        };
+        assert!(compile(Target::ic10()).is_ok());
+        let short = Target {
+            max_lines: 2,
+            ..Target::ic10()
+        };
+        assert!(compile(short).is_err());
+        let single_register = Target {
+            registers: 1,
+            ..Target::ic10()
+        };
+        assert!(compile(single_register).is_err());
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let program = ayysee_parser::parse(
+            "let x = load(d0, Temperature); let y = x; let z = 3; store(d1, Setting, x + y + z);",
+        )
+        .unwrap();
+        let (_, debug_info) =
+            generate_program_with_debug_info(program, &CompileOptions::default()).unwrap();
+        assert_eq!(debug_info.registers["x"].len(), 1);
+        assert_eq!(debug_info.registers["x"], debug_info.registers["y"]);
+        // Inlined into the addition.
+        assert!(!debug_info.registers.contains_key("z"));
+    }
+
+    #[test]
+    fn test_aliases() {
+        let source = r"
+            const sensor = d0;
+            let temp = sensor.Temperature;
+            let other = d1.Temperature;
+            db.Setting = temp + other;
+        ";
+        let compile = |max_lines| {
+            let options = CompileOptions {
+                aliases: true,
+                target: Target {
+                    max_lines,
+                    ..Target::ic10()
+                },
+                ..Default::default()
+            };
+            generate_program_with(ayysee_parser::parse(source).unwrap(), &options).unwrap()
+        };
+        let mips = compile(128);
+        let text = mips.to_string();
+        assert!(text.starts_with("alias sensor d0\n"), "{}", text);
+        assert!(text.contains("alias temp r"), "{}", text);
+        assert!(text.contains("alias other r"), "{}", text);
+
+        let mut simulator = Simulator::new(mips.clone());
+        simulator.write(Device::D0, DeviceVariable::Temperature, 1.0);
+        simulator.write(Device::D1, DeviceVariable::Temperature, 2.0);
+        assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 3.0);
+
+        // Only the first aliases fit in a line budget that is nearly used up.
+        let lines = mips.instructions.len();
+        let short = compile(lines - 1);
+        assert_eq!(short.instructions.len(), lines - 1);
+        assert_eq!(short.to_string().matches("alias").count(), 2);
+        assert!(short.to_string().starts_with("alias sensor d0\n"));
+    }
+
+    #[test]
+    fn test_arrays() {
+        // A moving average of the last 8 readings, next to a short array and the stack.
+        let source = r"
+            let readings = [0; 8];
+            let bounds = [d0.Temperature; 2];
+            let next = 0;
+            push(5);
+            yield;
+            loop {
+                readings[next] = d0.Temperature;
+                next = (next + 1) % 8;
+                let sum = 0;
+                let i = 0;
+                loop {
+                    if i >= 8 {
+                        break;
+                    }
+                    sum = sum + readings[i];
+                    i = i + 1;
+                }
+                bounds[1] = max(bounds[1], d0.Temperature);
+                db.Setting = sum / 8;
+                db.Mode = bounds[0] + bounds[1];
+                yield;
+            }
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(source, opt_level);
+            let text = mips.to_string();
+            assert!(text.contains("put db 502 r"), "{}", text);
+            assert!(text.contains("get r"), "{}", text);
+            let mut simulator = Simulator::new(mips);
+            simulator.write(Device::D0, DeviceVariable::Temperature, 80.0);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            for temperature in [80.0, 16.0, 24.0] {
+                simulator.write(Device::D0, DeviceVariable::Temperature, temperature);
+                assert_eq!(simulator.tick(), TickResult::Yield);
+            }
+            assert_eq!(
+                simulator.read(Device::Db, DeviceVariable::Setting),
+                15.0,
+                "{:?}",
+                opt_level
+            );
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Mode), 160.0);
+            assert_eq!(simulator.stack(), [5.0]);
+        }
+
+        let program = ayysee_parser::parse("let a = [0; 300];\nlet b = [0; 300];").unwrap();
+        let Err(error) = generate_program_with(program, &CompileOptions::default()) else {
+            panic!("600 values are stored on the stack");
+        };
+        assert_eq!(
+            error
+                .downcast_ref::<crate::resolve::SemanticErrors>()
+                .unwrap()
+                .0,
+            [crate::resolve::SemanticError {
+                message: "the arrays take more than the 512 values of the stack".to_string(),
+                span: ast::Span::new(22, 23),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_for() {
+        let source = r"
+            let total = 0;
+            for i in 0..6 {
+                if i == 4 {
+                    break;
+                }
+                if !is_set(devices[i]) {
+                    continue;
+                }
+                devices[i].On = 1;
+                total = total + i;
+            }
+            for k in 3..1 {
+                total = 100;
+            }
+            db.Setting = total;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.set_connected(Device::D1, false);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 5.0);
+            assert_eq!(simulator.read(Device::D3, DeviceVariable::On), 1.0);
+            assert_eq!(simulator.read(Device::D4, DeviceVariable::On), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_loop_until() {
+        let source = r"
+            let tries = 0;
+            loop {
+                tries = tries + 1;
+                d1.On = 1;
+                yield;
+                if d0.Mode == 2 {
+                    continue;
+                }
+                if d0.Mode == 3 {
+                    break;
+                }
+            } until d0.Pressure > 100;
+            d1.On = 0;
+            db.Setting = tries;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            simulator.write(Device::D0, DeviceVariable::Pressure, 50.0);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::On), 1.0);
+            simulator.write(Device::D0, DeviceVariable::Mode, 2.0);
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            simulator.write(Device::D0, DeviceVariable::Pressure, 150.0);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::On), 0.0);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 3.0);
+
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            assert_eq!(simulator.tick(), TickResult::Yield);
+            simulator.write(Device::D0, DeviceVariable::Mode, 3.0);
+            assert_eq!(simulator.tick(), TickResult::End);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_labeled_loops() {
+        let source = r"
+            let found = -1;
+            let skipped = 0;
+            rows: for row in 0..2 {
+                let column = 0;
+                loop {
+                    if d0.Setting == row * 10 + column {
+                        found = row * 10 + column;
+                        break rows;
+                    }
+                    column = column + 1;
+                    if column == 2 {
+                        skipped = skipped + 1;
+                        continue rows;
+                    }
+                }
+            }
+            db.Setting = found;
+            db.Mode = skipped;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Setting, 11.0);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 11.0);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Mode), 1.0);
+
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Setting, 5.0);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), -1.0);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Mode), 2.0);
+        }
+    }
+
+    #[test]
+    fn test_top_level_return() {
+        let source = r"
+            fn main() {
+                d1.Setting = 2;
+            }
+            if !is_set(d0) {
+                return;
+            }
+            for i in 0..3 {
+                if d0.Setting == i {
+                    db.Setting = i;
+                    return;
+                }
+            }
+            db.Setting = -1;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.set_connected(Device::D0, false);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 0.0);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 0.0);
+
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Setting, 1.0);
+            assert_eq!(simulator.tick(), TickResult::End);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 1.0);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 0.0);
+
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Setting, 5.0);
+            assert_eq!(simulator.tick(), TickResult::End);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), -1.0);
+            assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 2.0);
+        }
+    }
+
+    #[test]
+    fn test_tuple_definition() {
+        let source = r"
+            let (p, t) = (d0.Pressure, d0.Temperature);
+            let (a, b) = (1, p);
+            let (a, b) = (b, a + t);
+            let (a, b) = (b, a + t);
+            db.Setting = a;
+            db.On = b;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Pressure, 100.0);
+            simulator.write(Device::D0, DeviceVariable::Temperature, 10.0);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            // (1, 100), then (100, 11), then (11, 110).
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 11.0);
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::On), 110.0);
+        }
+    }
+
+    #[test]
+    fn test_match() {
+        let source = r"
+            const HEATING = 2;
+            let mode = d0.Mode;
+            let power = 0;
+            match mode {
+                0 => {}
+                1 => {
+                    power = 10;
+                }
+                HEATING => {
+                    power = 20;
+                    d1.On = 1;
+                }
+                -1 => { halt; }
+                _ => {
+                    power = 5;
+                }
+            }
+            db.Setting = power;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(source, opt_level);
+            for (mode, power) in [(0.0, 0.0), (1.0, 10.0), (2.0, 20.0), (7.0, 5.0)] {
+                let mut simulator = Simulator::new(mips.clone());
+                simulator.write(Device::D0, DeviceVariable::Mode, mode);
+                assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+                assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), power);
+                assert_eq!(
+                    simulator.read(Device::D1, DeviceVariable::On),
+                    f64::from(mode == 2.0)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_stack() {
+        let source = r"
+            push(d0.Setting);
+            push(d1.Setting);
+            db.Setting = peek();
+            let top = pop();
+            db.Mode = pop() - top;
+            push(7);
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mut simulator = Simulator::new(compile_with(source, opt_level));
+            simulator.write(Device::D0, DeviceVariable::Setting, 5.0);
+            simulator.write(Device::D1, DeviceVariable::Setting, 2.0);
+            simulator.tick();
+            assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 2.0);
+            assert_eq!(
+                simulator.read(Device::Db, DeviceVariable::Mode),
+                3.0,
+                "{:?}",
+                opt_level
+            );
+            assert_eq!(simulator.stack(), [7.0]);
+        }
+    }
+
+    #[test]
+    fn test_device_index() {
+        let source = r"
+            let total = 0;
+            let i = 0;
+            loop {
+                if i > 5 {
+                    break;
+                }
+                if is_set(devices[i]) {
+                    total = total + devices[i].Temperature;
+                    devices[i].On = 1;
+                }
+                i = i + 1;
+            }
+            db.Setting = total + devices[2].Pressure;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(source, opt_level);
+            let text = mips.to_string();
+            assert!(text.contains(" dr"), "{}", text);
+            assert!(
+                text.contains("l r") && text.contains(" d2 Pressure"),
+                "{}",
+                text
+            );
+            let mut simulator = Simulator::new(mips);
+            for (i, device) in Device::PINS.into_iter().enumerate() {
+                simulator.write(device, DeviceVariable::Temperature, i as f64);
+            }
+            simulator.write(Device::D2, DeviceVariable::Pressure, 100.0);
+            simulator.set_connected(Device::D4, false);
+            simulator.tick();
+            assert_eq!(
+                simulator.read(Device::Db, DeviceVariable::Setting),
+                111.0,
+                "{:?}",
+                opt_level
+            );
+            assert_eq!(simulator.read(Device::D5, DeviceVariable::On), 1.0);
+            assert_eq!(simulator.read(Device::D4, DeviceVariable::On), 0.0);
+        }
+
+        let program = ayysee_parser::parse("db.Setting = devices[6].On;").unwrap();
+        let Err(error) = generate_program_with(program, &CompileOptions::default()) else {
+            panic!("pin 6 is compiled");
+        };
+        assert_eq!(error.to_string(), "there is no device pin 6");
+    }
+
+    #[test]
+    fn test_alias_statement() {
+        let source = r"
+            alias sensor = d0;
+            const heater = d1;
+            if sensor.Temperature < 290 {
+                heater.On = 1;
+            }
+        ";
+        let mips = compile(source);
+        let text = mips.to_string();
+        assert!(text.starts_with("alias sensor d0\n"), "{}", text);
+        assert_eq!(text.matches("alias").count(), 1, "{}", text);
+
+        let mut simulator = Simulator::new(mips);
+        simulator.write(Device::D0, DeviceVariable::Temperature, 280.0);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::D1, DeviceVariable::On), 1.0);
+
+        let program = ayysee_parser::parse("let x = 1;\nalias sensor = x;").unwrap();
+        let Err(error) = generate_program_with(program, &CompileOptions::default()) else {
+            panic!("a variable is aliased");
+        };
+        assert_eq!(error.to_string(), "`x` is not a device pin");
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = r"
+            fn twice(x) {
+                let y = x * 2;
+                return y;
+            }
+            let t = d0.Temperature;
+            db.Setting = twice(t);
+        ";
+        let options = CompileOptions {
+            aliases: true,
+            ..Default::default()
+        };
+        let (mips, debug_info) =
+            generate_program_with_debug_info(ayysee_parser::parse(source).unwrap(), &options)
+                .unwrap();
+        assert_eq!(debug_info.lines.len(), mips.instructions.len());
+        let statement = |prefix: &str| {
+            let line = mips
+                .instructions
+                .iter()
+                .position(|ins| ins.to_string().starts_with(prefix))
+                .unwrap();
+            debug_info
+                .statement(line)
+                .map(|span| &source[span.start..span.end])
+        };
+        assert_eq!(statement("alias"), None);
+        assert_eq!(statement("l "), Some("let t = d0.Temperature;"));
+        assert_eq!(statement("mul "), Some("let y = x * 2;"));
+        assert_eq!(statement("s "), Some("db.Setting = twice(t);"));
+    }
+
     // TODO: check if inline optimization works well here
     #[test]
     fn test_supports_functions() {
@@ -722,4 +2864,168 @@ loop {
         assert_eq!(simulator.tick(), crate::simulator::TickResult::End);
         assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 3.0);
     }
+
+    #[test]
+    fn test_calls_functions() {
+        // Too long to inline three times.
+        let source = r"
+            fn level(t) {
+                if t < 280 {
+                    return 0;
+                }
+                let scaled = (t - 280) * 2 / 10 + 1;
+                if scaled > 10 {
+                    return 10;
+                }
+                if scaled > 5 {
+                    scaled = scaled + d5.Setting * 2 - d5.Setting / 3;
+                }
+                return scaled;
+            }
+            let offset = d3.Setting;
+            d0.Setting = level(d0.Temperature);
+            d1.Setting = level(d1.Temperature);
+            d2.Setting = level(d2.Temperature) + offset;
+        ";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let mips = compile_with(source, opt_level);
+            assert_eq!(
+                mips.to_string().matches("jal").count(),
+                3,
+                "{:?}",
+                opt_level
+            );
+            let mut simulator = Simulator::new(mips);
+            simulator.write(Device::D0, DeviceVariable::Temperature, 270.0);
+            simulator.write(Device::D1, DeviceVariable::Temperature, 300.0);
+            simulator.write(Device::D2, DeviceVariable::Temperature, 500.0);
+            simulator.write(Device::D3, DeviceVariable::Setting, 7.0);
+            assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+            let setting = |d| simulator.read(d, DeviceVariable::Setting);
+            assert_eq!(
+                [
+                    setting(Device::D0),
+                    setting(Device::D1),
+                    setting(Device::D2)
+                ],
+                [0.0, 5.0, 17.0],
+                "{:?}",
+                opt_level
+            );
+        }
+    }
+
+    #[test]
+    fn test_keeps_registers_across_calls() {
+        let source = r"
+            fn weigh(x) {
+                let a = x * 2 + 1;
+                let b = a * a - x / 2;
+                if b > 100 {
+                    b = b - 100 + d5.Setting;
+                }
+                return b + 1;
+            }
+            fn combine(x, y) {
+                let p = weigh(x);
+                let r = p * 3 + weigh(y) + x * y - d5.Setting;
+                if r > 1000 {
+                    r = 1000 + d5.Setting;
+                }
+                return r;
+            }
+            let a = d3.Setting;
+            let b = d4.Setting;
+            d0.Setting = combine(1, 2);
+            d1.Setting = combine(a, b) + a * b + weigh(b);
+            d2.Setting = combine(2, 1);
+        ";
+        let mips = compile(source);
+        // `combine` calls `weigh`, so it saves `ra`.
+        assert!(mips.to_string().contains("push ra"));
+        let mut simulator = Simulator::new(mips);
+        simulator.write(Device::D3, DeviceVariable::Setting, 2.0);
+        simulator.write(Device::D4, DeviceVariable::Setting, 3.0);
+        while simulator.tick() == TickResult::LimitHit {}
+        assert_eq!(simulator.read(Device::D0, DeviceVariable::Setting), 55.5);
+        assert_eq!(simulator.read(Device::D1, DeviceVariable::Setting), 184.0);
+        assert_eq!(simulator.read(Device::D2, DeviceVariable::Setting), 86.5);
+    }
+
+    #[test]
+    fn test_early_return() {
+        let mips = compile(
+            r"
+                fn first_above(limit) {
+                    let i = 0;
+                    loop {
+                        i = i + 1;
+                        if i * i > limit {
+                            return i;
+                        }
+                    }
+                }
+                db.Setting = first_above(20);
+            ",
+        );
+        let mut simulator = Simulator::new(mips);
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 5.0);
+    }
+
+    #[test]
+    fn test_recursion() {
+        fn located(source: &str) -> (String, usize, &str) {
+            let Err(error) = generate_program(ayysee_parser::parse(source).unwrap()) else {
+                panic!("recursion is not supported");
+            };
+            let errors = error.downcast::<crate::resolve::SemanticErrors>().unwrap();
+            let span = errors.0[0].span;
+            let line = source[..span.start].lines().count();
+            (
+                errors.0[0].message.clone(),
+                line,
+                &source[span.start..span.end],
+            )
+        }
+
+        // Called with `jal` from both places.
+        let source = r"
+            fn ping(x) {
+                if x > 0 {
+                    return pong(x - 1);
+                }
+                return 0;
+            }
+            fn pong(x) {
+                return ping(x);
+            }
+            db.Setting = ping(3);
+            db.Mode = ping(2);
+        ";
+        assert_eq!(
+            located(source),
+            (
+                "function `ping` calls itself, which is not supported".to_string(),
+                9,
+                "ping"
+            )
+        );
+
+        // Inlined, as it is called once.
+        let source = r"
+            fn count(x) {
+                count(x - 1);
+            }
+            count(3);
+        ";
+        assert_eq!(
+            located(source),
+            (
+                "function `count` calls itself, which is not supported".to_string(),
+                3,
+                "count"
+            )
+        );
+    }
 }
@@ -1,26 +1,80 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
 
 use crate::ir::types::{Instruction, Program, VarId, VarOrConst, VarValue};
+use crate::CompileOptions;
 
-use super::types::BlockId;
+/// How hard the compiler tries to optimize the generated program.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum OptLevel {
+    /// Straight translation of the source, easiest to follow when debugging.
+    O0,
+    /// A single PHI coalescing, copy propagation, inlining and dead variable pass.
+    O1,
+    /// Runs the IR passes until nothing changes anymore.
+    #[default]
+    O2,
+    /// Like `O2`, but also picks instruction sequences with the fewest lines.
+    Os,
+}
+
+/// Parses the level as given to `-O`: `0`, `1`, `2` or `s`.
+impl std::str::FromStr for OptLevel {
+    type Err = anyhow::Error;
 
-pub fn optimize(program: &mut Program) {
-    inline(program);
-    remove_unused_variables(program);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            "s" => Ok(OptLevel::Os),
+            _ => anyhow::bail!("unknown optimization level `{}`", s),
+        }
+    }
+}
+
+pub fn optimize(program: &mut Program, options: &CompileOptions) {
+    match options.opt_level {
+        OptLevel::O0 => (),
+        OptLevel::O1 => {
+            coalesce_phis(program, options);
+            propagate_copies(program, options);
+            inline(program, options);
+            remove_unused_variables(program, options);
+        }
+        OptLevel::O2 | OptLevel::Os => loop {
+            let coalesced = coalesce_phis(program, options);
+            let propagated = propagate_copies(program, options);
+            inline(program, options);
+            if !remove_unused_variables(program, options) && !coalesced && !propagated {
+                break;
+            }
+        },
+    }
 }
 
 // Returns true if any variables were removed.
-fn remove_unused_variables(program: &mut Program) -> bool {
-    let mut pos = HashMap::<VarId, (BlockId, usize)>::default();
+fn remove_unused_variables(program: &mut Program, options: &CompileOptions) -> bool {
+    let start = Instant::now();
+    let size = program.instruction_count();
     let mut stack: Vec<VarId> = Vec::default();
     let mut used = HashSet::<VarId>::default();
-    for (block_id, block) in program.blocks.iter().enumerate() {
-        for (ins_id, ins) in block.instructions.iter().enumerate() {
+    for block in &program.blocks {
+        for ins in &block.instructions {
             match ins {
                 Instruction::Assignment { id, value } => {
-                    pos.insert(*id, (BlockId(block_id), ins_id));
+                    // Called functions pop their arguments and can have effects.
+                    if matches!(value, VarValue::Param) {
+                        used.insert(*id);
+                    }
+                    if let VarValue::Put { .. } = value {
+                        used.insert(*id);
+                        stack.push(*id);
+                    }
                     if let VarValue::Call { name, args } = value {
-                        if name == "store" {
+                        if options.builtins.get(name).is_some_and(|b| b.has_effects())
+                            || program.functions.contains_key(name)
+                        {
                             used.insert(*id);
                             stack.push(*id);
                             for arg in args {
@@ -42,19 +96,19 @@ fn remove_unused_variables(program: &mut Program) -> bool {
                         stack.push(*id);
                     }
                 }
-                Instruction::Yield => (),
-                Instruction::Return(var_id) => {
-                    used.insert(*var_id);
-                    stack.push(*var_id);
+                Instruction::Yield | Instruction::Halt => (),
+                Instruction::Return(value) => {
+                    if let Some(VarOrConst::Var(id)) = value {
+                        used.insert(*id);
+                        stack.push(*id);
+                    }
                 }
             }
         }
     }
-    while !stack.is_empty() {
-        let id = stack.pop().unwrap();
+    while let Some(id) = stack.pop() {
         used.insert(id);
-        let p = pos.get(&id).unwrap();
-        let ins = &program.blocks[p.0 .0].instructions[p.1];
+        let value = program.value(id).expect("the variable is defined");
         let mut maybe_add = |v: &VarOrConst| {
             if let VarOrConst::Var(x) = v {
                 if !used.contains(x) {
@@ -63,28 +117,32 @@ fn remove_unused_variables(program: &mut Program) -> bool {
                 }
             }
         };
-        if let Instruction::Assignment { id: _, value } = ins {
-            match value {
-                VarValue::Single(x) => maybe_add(x),
-                VarValue::BinaryOp { lhs, op: _, rhs } => {
-                    maybe_add(lhs);
-                    maybe_add(rhs);
-                }
-                VarValue::Call { name: _, args } => {
-                    for a in args {
-                        maybe_add(a);
-                    }
+        match value {
+            VarValue::Single(x) => maybe_add(x),
+            VarValue::BinaryOp { lhs, op: _, rhs } => {
+                maybe_add(lhs);
+                maybe_add(rhs);
+            }
+            VarValue::UnaryOp { op: _, operand } => maybe_add(operand),
+            VarValue::Call { name: _, args } => {
+                for a in args {
+                    maybe_add(a);
                 }
-                VarValue::Phi(phi) => {
-                    for x in phi {
-                        if !used.contains(x) {
-                            used.insert(*x);
-                            stack.push(*x);
-                        }
+            }
+            VarValue::Get { address } => maybe_add(address),
+            VarValue::Put { address, value } => {
+                maybe_add(address);
+                maybe_add(value);
+            }
+            VarValue::Phi(phi) => {
+                for x in phi {
+                    if !used.contains(x) {
+                        used.insert(*x);
+                        stack.push(*x);
                     }
                 }
-                VarValue::Param => (),
             }
+            VarValue::Param => (),
         }
     }
     let mut removed_any = false;
@@ -98,9 +156,153 @@ fn remove_unused_variables(program: &mut Program) -> bool {
             removed_any = true;
         }
     }
+    if removed_any {
+        program.reindex();
+    }
+    options.record("remove unused", start, size, program.instruction_count());
     removed_any
 }
 
+/// Replaces the PHIs that only ever merge copies of a single value by that value, such as
+/// the webs of PHIs a variable left unchanged by a loop goes through. [`inline`] then
+/// propagates the value into their uses, which saves the `move`s between the registers of
+/// the PHIs. Returns true if any PHI was replaced.
+fn coalesce_phis(program: &mut Program, options: &CompileOptions) -> bool {
+    let start = Instant::now();
+    let size = program.instruction_count();
+    let mut phis = BTreeMap::new();
+    for block in &program.blocks {
+        for ins in &block.instructions {
+            if let Instruction::Assignment {
+                id,
+                value: VarValue::Phi(operands),
+            } = ins
+            {
+                let operands = operands
+                    .iter()
+                    .map(|operand| copied(program, *operand))
+                    .collect::<Vec<_>>();
+                phis.insert(*id, operands);
+            }
+        }
+    }
+    let mut replaced = vec![];
+    for &id in phis.keys() {
+        // The values flowing into the PHI, through the PHIs it merges.
+        let mut sources = HashSet::new();
+        let mut seen = HashSet::from([id]);
+        let mut stack = vec![id];
+        while let Some(phi) = stack.pop() {
+            for operand in &phis[&phi] {
+                match operand {
+                    VarOrConst::Var(x) if phis.contains_key(x) => {
+                        if seen.insert(*x) {
+                            stack.push(*x);
+                        }
+                    }
+                    _ => {
+                        sources.insert(operand);
+                    }
+                }
+            }
+            if sources.len() > 1 {
+                break;
+            }
+        }
+        if let (1, Some(source)) = (sources.len(), sources.into_iter().next()) {
+            replaced.push((id, source.clone()));
+        }
+    }
+    for (id, value) in &replaced {
+        program.set_value(*id, VarValue::Single(value.clone()));
+    }
+    options.record("coalesce phis", start, size, size);
+    !replaced.is_empty()
+}
+
+/// Replaces the reads of variables that are copies of another value by that value, in
+/// every instruction: the operands of PHIs, branches and returns as well as those of the
+/// other assignments. The copies are then unused, and the PHIs can share the register of
+/// the copied value, saving their `move`s. Returns true if any read was replaced.
+fn propagate_copies(program: &mut Program, options: &CompileOptions) -> bool {
+    let start = Instant::now();
+    let size = program.instruction_count();
+    let mut copies = HashMap::new();
+    for block in &program.blocks {
+        for ins in &block.instructions {
+            if let Instruction::Assignment {
+                id,
+                value: VarValue::Single(VarOrConst::Var(_)),
+            } = ins
+            {
+                let value = copied(program, *id);
+                if value != VarOrConst::Var(*id) {
+                    copies.insert(*id, value);
+                }
+            }
+        }
+    }
+    let propagate = |operand: &mut VarOrConst| match operand {
+        VarOrConst::Var(id) => match copies.get(id) {
+            Some(value) => {
+                *operand = value.clone();
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    };
+    let mut propagated = false;
+    for block in &mut program.blocks {
+        for ins in &mut block.instructions {
+            let operands = match ins {
+                Instruction::Assignment { value, .. } => match value {
+                    VarValue::Single(x) => vec![x],
+                    // PHIs only merge variables.
+                    VarValue::Phi(operands) => {
+                        for operand in operands {
+                            if let Some(VarOrConst::Var(x)) = copies.get(operand) {
+                                *operand = *x;
+                                propagated = true;
+                            }
+                        }
+                        vec![]
+                    }
+                    VarValue::BinaryOp { lhs, rhs, .. } => vec![lhs, rhs],
+                    VarValue::UnaryOp { operand, .. } => vec![operand],
+                    VarValue::Call { args, .. } => args.iter_mut().collect(),
+                    VarValue::Get { address } => vec![address],
+                    VarValue::Put { address, value } => vec![address, value],
+                    VarValue::Param => vec![],
+                },
+                Instruction::Branch { cond, .. } => vec![cond],
+                Instruction::Return(value) => value.iter_mut().collect(),
+                Instruction::Yield | Instruction::Halt => vec![],
+            };
+            for operand in operands {
+                propagated |= propagate(operand);
+            }
+        }
+    }
+    program.reindex();
+    options.record("propagate copies", start, size, program.instruction_count());
+    propagated
+}
+
+/// Returns the value the variable is a copy of, following chains of copies.
+fn copied(program: &Program, mut id: VarId) -> VarOrConst {
+    // Copies only form cycles in unreachable loops.
+    let mut seen = HashSet::new();
+    while let Some(VarValue::Single(value)) = program.value(id) {
+        match value {
+            VarOrConst::Var(x) if seen.insert(id) => id = *x,
+            VarOrConst::Var(_) => break,
+            value => return value.clone(),
+        }
+    }
+    VarOrConst::Var(id)
+}
+
 struct InlineState<'a> {
     program: &'a mut Program,
     inlined: HashSet<VarId>,
@@ -131,63 +333,53 @@ impl<'a> InlineState<'a> {
                 let rhs = self.inline_simple(&rhs);
                 self.set_var(id, VarValue::BinaryOp { lhs, op, rhs });
             }
+            VarValue::UnaryOp { op, operand } => {
+                let operand = self.inline_simple(&operand);
+                self.set_var(id, VarValue::unary(op, operand));
+            }
             VarValue::Call { name, args } => {
                 let args: Vec<VarOrConst> = args.iter().map(|a| self.inline_simple(a)).collect();
                 self.set_var(id, VarValue::Call { name, args });
             }
-            VarValue::Param => (),
-        }
-    }
-
-    fn find_var(&self, var_id: VarId) -> (BlockId, usize) {
-        // TODO: optimize this, we should record the location of everything
-        for (block_id, block) in self.program.blocks.iter().enumerate() {
-            for (idx, ins) in block.instructions.iter().enumerate() {
-                match ins {
-                    Instruction::Assignment { id, value: _ } => {
-                        if var_id == *id {
-                            return (BlockId(block_id), idx);
-                        }
-                    }
-                    _ => (),
-                }
+            VarValue::Get { address } => {
+                let address = self.inline_simple(&address);
+                self.set_var(id, VarValue::Get { address });
             }
+            VarValue::Put { address, value } => {
+                let address = self.inline_simple(&address);
+                let value = self.inline_simple(&value);
+                self.set_var(id, VarValue::Put { address, value });
+            }
+            VarValue::Param => (),
         }
-        unreachable!("could not find {:?}", var_id)
     }
 
     fn get_value(&self, id: VarId) -> VarValue {
-        let (block_id, idx) = self.find_var(id);
-        if let Instruction::Assignment { id: _, value } =
-            &self.program.blocks[block_id.0].instructions[idx]
-        {
-            return value.clone();
+        match self.program.value(id) {
+            Some(value) => value.clone(),
+            None => unreachable!("could not find {:?}", id),
         }
-        unreachable!()
     }
 
     fn inline_simple(&mut self, v: &VarOrConst) -> VarOrConst {
-        match v {
-            VarOrConst::Var(id) => {
-                self.inline_variable(*id);
-                let next_value = self.get_value(*id);
-                if let VarValue::Single(s) = next_value {
-                    return s.clone();
-                }
+        if let VarOrConst::Var(id) = v {
+            self.inline_variable(*id);
+            if let Some(VarValue::Single(s)) = self.program.value(*id) {
+                return s.clone();
             }
-            _ => (),
         }
         v.clone()
     }
 
     fn set_var(&mut self, id: VarId, value: VarValue) {
-        let (block_id, idx) = self.find_var(id);
-        self.program.blocks[block_id.0].instructions[idx] = Instruction::Assignment { id, value };
+        self.program.set_value(id, value);
     }
 }
 
 // Inlines the variables where possible
-fn inline(program: &mut Program) {
+fn inline(program: &mut Program, options: &CompileOptions) {
+    let start = Instant::now();
+    let size = program.instruction_count();
     let mut vars = HashSet::<VarId>::default();
     for b in &program.blocks {
         for ins in &b.instructions {
@@ -203,48 +395,45 @@ fn inline(program: &mut Program) {
     for id in vars {
         state.inline_variable(id);
     }
-    return;
+    options.record("inline", start, size, size);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::ir::types::Block;
-    use ayysee_parser::grammar::ProgramParser;
+    use crate::simulator::{Simulator, TickResult};
+    use stationeers_mips::types::{Device, DeviceVariable};
     use test_log::test;
 
     #[test]
     fn test_remove_unused_variables() {
-        let mut program = Program {
-            blocks: vec![Block {
-                instructions: vec![Instruction::Assignment {
-                    id: VarId(0),
-                    value: VarValue::Single(VarOrConst::Const((1.0).into())),
-                }],
-                next: vec![],
-                prev: vec![],
+        let mut program = Program::default();
+        program.blocks = vec![Block {
+            instructions: vec![Instruction::Assignment {
+                id: VarId(0),
+                value: VarValue::Single(VarOrConst::Const((1.0).into())),
             }],
-            functions: Default::default(),
-        };
-        optimize(&mut program);
+            ..Default::default()
+        }];
+        program.reindex();
+        optimize(&mut program, &CompileOptions::default());
         assert_eq!(program.blocks[0].instructions.len(), 0);
     }
 
     #[test]
     fn test_inlines_variables() {
-        let parser = ProgramParser::new();
-        let parsed = parser
-            .parse(
-                r"
+        let parsed = ayysee_parser::parse(
+            r"
                 let x = 1;
                 let y = x;
                 let z = y;
                 store(d0, Setting, z);
                 ",
-            )
-            .unwrap();
+        )
+        .unwrap();
         let mut program = crate::ir::generate_ir(parsed).unwrap();
-        optimize(&mut program);
+        optimize(&mut program, &CompileOptions::default());
         assert_eq!(
             program.blocks[0].instructions.len(),
             1,
@@ -252,4 +441,187 @@ mod tests {
             program
         );
     }
+
+    #[test]
+    fn test_keeps_index_up_to_date() {
+        let parsed = ayysee_parser::parse(
+            r"
+                let x = load(d0, Temperature);
+                let y = x;
+                if y > 300 {
+                    store(d1, On, y + 1);
+                } else {
+                    let z = 2;
+                    store(d1, On, z);
+                }
+                ",
+        )
+        .unwrap();
+        let mut program = crate::ir::generate_ir(parsed).unwrap();
+        optimize(&mut program, &CompileOptions::default());
+        let snapshot = |program: &Program| {
+            let mut vars = Vec::new();
+            for block in &program.blocks {
+                for ins in &block.instructions {
+                    if let Instruction::Assignment { id, .. } = ins {
+                        let mut uses = program.uses(*id).to_vec();
+                        uses.sort_by_key(|(block, idx)| (block.0, *idx));
+                        vars.push((*id, program.definition(*id), uses));
+                    }
+                }
+            }
+            vars
+        };
+        let kept = snapshot(&program);
+        program.reindex();
+        assert_eq!(kept, snapshot(&program));
+    }
+
+    #[test]
+    fn test_coalesces_phi_webs() {
+        // `x` goes through the PHIs of the loop and of both `if`s without changing.
+        let parsed = ayysee_parser::parse(
+            r"
+                let x = load(d0, Setting);
+                loop {
+                    if d1.On {
+                        yield;
+                    }
+                    if d2.On {
+                        yield;
+                    }
+                    d1.Setting = x;
+                }
+                ",
+        )
+        .unwrap();
+        let mut program = crate::ir::generate_ir(parsed).unwrap();
+        let phis = |program: &Program| {
+            program
+                .blocks
+                .iter()
+                .flat_map(|block| &block.instructions)
+                .filter(|ins| {
+                    matches!(
+                        ins,
+                        Instruction::Assignment {
+                            value: VarValue::Phi(_),
+                            ..
+                        }
+                    )
+                })
+                .count()
+        };
+        assert!(phis(&program) > 0);
+        optimize(&mut program, &CompileOptions::default());
+        assert_eq!(phis(&program), 0, "instructions: {:?}", program);
+    }
+
+    #[test]
+    fn test_propagates_copies() {
+        // The copies of `t` and `a` are merged by the PHIs of the `if`s with other values.
+        let sources = [
+            r"
+                let y = 0;
+                loop {
+                    let t = d1.Setting;
+                    if d2.On {
+                        y = t;
+                    } else {
+                        y = t + 1;
+                    }
+                    db.Setting = y;
+                    yield;
+                }
+            ",
+            r"
+                fn pick(a) {
+                    let b = a;
+                    if d2.On {
+                        b = a * 2;
+                    }
+                    return b;
+                }
+                db.Setting = pick(d0.Setting);
+                db.Mode = pick(d1.Setting);
+            ",
+        ];
+        for source in sources {
+            let moves = |opt_level| {
+                let options = CompileOptions {
+                    opt_level,
+                    ..Default::default()
+                };
+                let parsed = ayysee_parser::parse(source).unwrap();
+                let mips = crate::ir::generate_program_with(parsed, &options).unwrap();
+                mips.to_string()
+                    .lines()
+                    .filter(|l| l.starts_with("move "))
+                    .count()
+            };
+            assert!(moves(OptLevel::O0) > 0, "{}", source);
+            assert_eq!(moves(OptLevel::O1), 0, "{}", source);
+            assert_eq!(moves(OptLevel::O2), 0, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_coalesced_phis_keep_values() {
+        // The `a` of the inner loops shadows the outer one, which goes through the PHIs of
+        // both loops without changing.
+        let sources = [
+            (
+                r"
+                    let a = d0.Setting;
+                    for i in 0..1 {
+                        let a = 3;
+                        for j in 0..2 {
+                            a = a + 1;
+                        }
+                    }
+                    db.Setting = a;
+                ",
+                3.0,
+            ),
+            (
+                r"
+                    let a = d0.Setting;
+                    let b = 0;
+                    for i in 0..2 {
+                        let a = b;
+                        for j in 0..a + 1 {
+                            a = a + 1;
+                            b = b + a;
+                        }
+                    }
+                    db.Setting = a * 100 + b;
+                ",
+                306.0,
+            ),
+        ];
+        for (source, expected) in sources {
+            for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+                let options = CompileOptions {
+                    opt_level,
+                    ..Default::default()
+                };
+                let parsed = ayysee_parser::parse(source).unwrap();
+                let mips = crate::ir::generate_program_with(parsed, &options).unwrap();
+                // The copies in the register of their source take no line.
+                let self_moves = mips.to_string().lines().any(|line| {
+                    matches!(line.split(' ').collect::<Vec<_>>()[..], ["move", a, b] if a == b)
+                });
+                assert!(!self_moves, "{:?}: {}", opt_level, mips);
+                let mut simulator = Simulator::new(mips);
+                simulator.write(Device::D0, DeviceVariable::Setting, 3.0);
+                assert_eq!(simulator.tick(), TickResult::End, "{:?}", opt_level);
+                assert_eq!(
+                    simulator.read(Device::Db, DeviceVariable::Setting),
+                    expected,
+                    "{:?}",
+                    opt_level
+                );
+            }
+        }
+    }
 }
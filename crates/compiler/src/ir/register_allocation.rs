@@ -1,6 +1,7 @@
 use super::{BlockId, VarId};
 use crate::ir;
 use anyhow::Context;
+use ayysee_parser::ast::BinaryOpcode;
 use stationeers_mips::types::Register;
 use std::collections::{HashMap, HashSet};
 
@@ -9,38 +10,43 @@ pub struct RegisterAllocation {
 }
 
 impl RegisterAllocation {
-    pub fn allocate(ir_program: &ir::Program) -> anyhow::Result<Self> {
-        // TODO:
-        let mut next = 0;
-        // let mut vars = HashMap::default();
+    /// Assigns each variable one of the first `registers` registers, preferring the register
+    /// `hints` gives it when that one is free.
+    ///
+    /// A PHI shares its register with its operands, so the program is first changed to copy
+    /// the operands that would otherwise share a register with a live variable.
+    pub fn allocate(
+        ir_program: &mut ir::Program,
+        registers: usize,
+        hints: &HashMap<VarId, Register>,
+    ) -> anyhow::Result<Self> {
+        let (webs, interference) = merge_phis(ir_program)?;
 
+        // Assign vars to nodes, the PHI webs first.
+        let mut next = 0;
         let mut var_to_node: HashMap<VarId, i32> = HashMap::default();
-        // Assign vars to nodes
-
-        // First, assign registers for PHI variables
-        for block in &ir_program.blocks {
-            for ins in &block.instructions {
-                if let ir::Instruction::Assignment { id, value } = ins {
-                    if let ir::VarValue::Phi(phi) = value {
-                        var_to_node.insert(*id, next);
-                        for var_id in phi {
-                            var_to_node.insert(*var_id, next);
-                        }
-                        next += 1;
-                    }
-                }
-            }
-        }
-        // The assign all remaining variables.
-        for block in &ir_program.blocks {
-            for ins in &block.instructions {
-                if let ir::Instruction::Assignment { id, value: _ } = ins {
-                    if var_to_node.contains_key(id) {
-                        continue;
-                    }
-                    var_to_node.insert(*id, next);
+        let mut root_to_node: HashMap<VarId, i32> = HashMap::default();
+        let (phis, others): (Vec<_>, Vec<_>) = ir_program
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|ins| match ins {
+                ir::Instruction::Assignment { id, value } => Some((*id, value)),
+                _ => None,
+            })
+            .partition(|(_, value)| matches!(value, ir::VarValue::Phi(_)));
+        for (id, value) in phis.into_iter().chain(others) {
+            let operands = match value {
+                ir::VarValue::Phi(phi) => phi.as_slice(),
+                _ => &[],
+            };
+            for var_id in std::iter::once(&id).chain(operands) {
+                let root = webs.get(var_id).copied().unwrap_or(*var_id);
+                let node = *root_to_node.entry(root).or_insert_with(|| {
                     next += 1;
-                }
+                    next - 1
+                });
+                var_to_node.insert(*var_id, node);
             }
         }
 
@@ -56,13 +62,30 @@ impl RegisterAllocation {
         let mut vars: Vec<VarId> = var_to_node.keys().copied().collect();
         vars.sort();
         for var_id in &vars {
-            add_edges(&mut graph, ir_program, *var_id, &var_to_node);
+            let node = var_to_node[var_id];
+            graph.edges.entry(node).or_default();
+            for other in interference.get(var_id).into_iter().flatten() {
+                graph.add_edge(node, var_to_node[other]);
+            }
         }
         tracing::debug!("Graph: {:?}", graph);
 
+        let mut node_hints = Hints {
+            colors: HashMap::default(),
+            edges: graph.edges.clone(),
+        };
+        for var_id in &vars {
+            if let Some(register) = hints.get(var_id) {
+                node_hints
+                    .colors
+                    .entry(var_to_node[var_id])
+                    .or_insert(*register as i32);
+            }
+        }
+
         let mut colors = HashMap::default();
         anyhow::ensure!(
-            color_graph(&mut graph, &mut colors),
+            color_graph(&mut graph, &mut colors, &node_hints, registers as i32),
             "The program is too complex, failed to perform register allocation"
         );
         tracing::debug!("Colors: {:?}", colors);
@@ -74,7 +97,7 @@ impl RegisterAllocation {
                 .context(format!("var_to_node[{:?}] missing", var_id))
                 .unwrap();
             let color = colors
-                .get(&node)
+                .get(node)
                 .context(format!(
                     "color missing for var: {:?} node: {:?}",
                     var_id, node
@@ -111,7 +134,7 @@ impl Graph {
     fn remove_node(&mut self, node: i32) -> HashSet<i32> {
         let edges = self.edges.remove(&node).unwrap();
         for e in &edges {
-            if let Some(x) = self.edges.get_mut(&e) {
+            if let Some(x) = self.edges.get_mut(e) {
                 x.remove(&node);
             }
         }
@@ -119,18 +142,29 @@ impl Graph {
     }
 }
 
+/// The colors preferred for some nodes, with the edges of the whole graph, so that the
+/// other nodes keep away from the colors their neighbours prefer.
+struct Hints {
+    colors: HashMap<i32, i32>,
+    edges: HashMap<i32, HashSet<i32>>,
+}
+
 // node->color
-fn color_graph(g: &mut Graph, colors: &mut HashMap<i32, i32>) -> bool {
+fn color_graph(
+    g: &mut Graph,
+    colors: &mut HashMap<i32, i32>,
+    hints: &Hints,
+    max_colors: i32,
+) -> bool {
     if g.edges.is_empty() {
         return true;
     }
-    let mut nodes: Vec<i32> = g.edges.keys().into_iter().copied().collect();
+    let mut nodes: Vec<i32> = g.edges.keys().copied().collect();
     nodes.sort();
     // unwrap ok, guaranteed to have a key
     let node = nodes
         .into_iter()
-        .filter(|n| g.edges.get(n).unwrap().len() < 16)
-        .next();
+        .find(|n| (g.edges.get(n).unwrap().len() as i32) < max_colors);
     let node = match node {
         None => {
             tracing::debug!("Graph too complex to color:\n{:?}", g);
@@ -140,7 +174,7 @@ fn color_graph(g: &mut Graph, colors: &mut HashMap<i32, i32>) -> bool {
     };
     let edges = g.remove_node(node);
     tracing::trace!("start coloring: {node}, edges: {:?}", edges);
-    if !color_graph(g, colors) {
+    if !color_graph(g, colors, hints, max_colors) {
         return false;
     }
     tracing::trace!("end coloring: {node}, edges: {:?}", edges);
@@ -154,7 +188,26 @@ fn color_graph(g: &mut Graph, colors: &mut HashMap<i32, i32>) -> bool {
         })
         .copied()
         .collect();
-    for color in 0..16 {
+    let free = |c: &i32| *c < max_colors && !used_colors.contains(c);
+    // The neighbours colored after this node still get the colors they prefer.
+    let reserved: HashSet<i32> = hints.edges[&node]
+        .iter()
+        .filter(|n| !colors.contains_key(n))
+        .filter_map(|n| hints.colors.get(n))
+        .copied()
+        .collect();
+    let preferred = hints
+        .colors
+        .get(&node)
+        .copied()
+        .filter(free)
+        .or_else(|| (0..max_colors).find(|c| free(c) && !reserved.contains(c)));
+    if let Some(color) = preferred {
+        colors.insert(node, color);
+        tracing::trace!("colored: {node}, color {color}");
+        return true;
+    }
+    for color in 0..max_colors {
         if !used_colors.contains(&color) {
             colors.insert(node, color);
             tracing::trace!("colored: {node}, color {color}");
@@ -164,89 +217,298 @@ fn color_graph(g: &mut Graph, colors: &mut HashMap<i32, i32>) -> bool {
     unreachable!()
 }
 
-fn find_var(program: &ir::Program, var_id: VarId) -> (BlockId, usize) {
-    for (block_idx, block) in program.blocks.iter().enumerate() {
-        for (ins_idx, ins) in block.instructions.iter().enumerate() {
-            if let ir::Instruction::Assignment { id, .. } = ins {
-                if id == &var_id {
-                    return (BlockId(block_idx), ins_idx);
+/// Returns the variables assigned by each called function, including in the functions it
+/// calls. The variables live across a call must be in other registers.
+fn clobbered(program: &ir::Program) -> HashMap<&str, HashSet<VarId>> {
+    fn visit<'a>(
+        program: &'a ir::Program,
+        name: &'a str,
+        clobbered: &mut HashMap<&'a str, HashSet<VarId>>,
+    ) {
+        if clobbered.contains_key(name) {
+            return;
+        }
+        let blocks = program.function_blocks(program.functions[name].block_id);
+        let mut vars = HashSet::default();
+        for block in &blocks {
+            for ins in &program.blocks[block.0].instructions {
+                if let ir::Instruction::Assignment { id, .. } = ins {
+                    vars.insert(*id);
                 }
             }
         }
+        // Recursion is rejected when generating the IR.
+        for callee in program.callees(&blocks) {
+            visit(program, callee, clobbered);
+            vars.extend(clobbered[callee].iter().copied());
+        }
+        clobbered.insert(name, vars);
+    }
+
+    let mut clobbered = HashMap::default();
+    for name in program.functions.keys() {
+        visit(program, name, &mut clobbered);
     }
-    panic!("Could not find assignment for var {:?}", var_id)
+    clobbered
 }
 
-fn add_edges_rec(
-    graph: &mut Graph,
-    program: &ir::Program,
-    pos: (BlockId, usize),
-    var_id: VarId,
-    visited: &mut HashSet<BlockId>,
-    var_to_node: &HashMap<VarId, i32>,
-) -> bool {
-    tracing::trace!("add_edges_rec({:?}), pos={:?}", var_id, pos);
-    let block = &program.blocks[pos.0 .0];
-    let mut used = false;
-    if pos.1 >= block.instructions.len() {
-        for n in &block.next {
-            if !visited.contains(&n) {
-                visited.insert(*n);
-                used |= add_edges_rec(graph, program, (*n, 0), var_id, visited, var_to_node);
+/// The variables each variable is live at the same time as.
+type Interference = HashMap<VarId, HashSet<VarId>>;
+
+/// Merges every PHI with its operands into a web of variables sharing a register, since no
+/// instruction is generated for a PHI. An operand live at the same time as a variable of the
+/// web is copied at the end of the block it flows from instead, and when that is not enough,
+/// all the operands are, and the PHI is copied at the start of its block.
+///
+/// Returns the variable naming the web of each merged variable, with the interference the
+/// webs were checked against.
+fn merge_phis(program: &mut ir::Program) -> anyhow::Result<(HashMap<VarId, VarId>, Interference)> {
+    // A PHI takes its value when entering the block, wherever it was added.
+    for block in &mut program.blocks {
+        let (mut phis, others): (Vec<_>, Vec<_>) = block.instructions.drain(..).partition(is_phi);
+        phis.extend(others);
+        block.instructions = phis;
+    }
+    program.reindex();
+
+    let mut next = program
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|ins| match ins {
+            ir::Instruction::Assignment { id, .. } => Some(id.0 + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    // The operands and PHIs already copied.
+    let mut copies = HashSet::new();
+    let mut isolated = HashSet::new();
+    loop {
+        let interference = interference(program);
+        let interferes = |a: &[VarId], b: &[VarId]| {
+            a.iter().any(|x| {
+                interference
+                    .get(x)
+                    .is_some_and(|edges| b.iter().any(|y| edges.contains(y)))
+            })
+        };
+
+        let mut webs: HashMap<VarId, VarId> = HashMap::default();
+        let mut members: HashMap<VarId, Vec<VarId>> = HashMap::default();
+        let mut conflicts = vec![];
+        for (block_idx, block) in program.blocks.iter().enumerate() {
+            for ins in &block.instructions {
+                let ir::Instruction::Assignment {
+                    id,
+                    value: ir::VarValue::Phi(phi),
+                } = ins
+                else {
+                    continue;
+                };
+                for (i, var_id) in phi.iter().enumerate() {
+                    let root = webs.get(id).copied().unwrap_or(*id);
+                    let other = webs.get(var_id).copied().unwrap_or(*var_id);
+                    if root == other {
+                        continue;
+                    }
+                    let web = members.remove(&root).unwrap_or_else(|| vec![root]);
+                    let other_web = members.remove(&other).unwrap_or_else(|| vec![other]);
+                    if interferes(&web, &other_web) {
+                        conflicts.push((BlockId(block_idx), *id, i));
+                        members.insert(root, web);
+                        members.insert(other, other_web);
+                        continue;
+                    }
+                    for var_id in &other_web {
+                        webs.insert(*var_id, root);
+                    }
+                    webs.insert(root, root);
+                    members.insert(root, web.into_iter().chain(other_web).collect());
+                }
             }
         }
-        return used;
+        if conflicts.is_empty() {
+            return Ok((webs, interference));
+        }
+
+        // A PHI is changed once per round, its other conflicts being found again in the next.
+        let mut changed = HashSet::new();
+        for (block_id, id, i) in conflicts {
+            if !changed.insert(id) {
+                continue;
+            }
+            let instructions = &mut program.blocks[block_id.0].instructions;
+            let phi = instructions
+                .iter()
+                .position(
+                    |ins| matches!(ins, ir::Instruction::Assignment { id: x, .. } if *x == id),
+                )
+                .context("the PHI is in its block")?;
+            let ir::Instruction::Assignment {
+                value: ir::VarValue::Phi(operands),
+                ..
+            } = &instructions[phi]
+            else {
+                unreachable!("conflicts are found on PHIs");
+            };
+            // The conflicting operand is copied first, and when that is not enough, all the
+            // operands and the PHI itself.
+            let copied: Vec<usize> = if copies.contains(&operands[i]) {
+                (0..operands.len())
+                    .filter(|j| !copies.contains(&operands[*j]))
+                    .collect()
+            } else {
+                vec![i]
+            };
+            let isolate = copies.contains(&operands[i]) && isolated.insert(id);
+            anyhow::ensure!(
+                !copied.is_empty() || isolate,
+                "failed to allocate a register to {:?}",
+                id
+            );
+            if isolate {
+                let copy = VarId(next);
+                next += 1;
+                let ir::Instruction::Assignment { id: phi_id, .. } = &mut instructions[phi] else {
+                    unreachable!("conflicts are found on PHIs");
+                };
+                *phi_id = copy;
+                isolated.insert(copy);
+                let idx = instructions.iter().take_while(|ins| is_phi(ins)).count();
+                let value = ir::VarValue::Single(copy.into());
+                instructions.insert(idx, ir::Instruction::Assignment { id, value });
+            }
+            for i in copied {
+                let copy = VarId(next);
+                next += 1;
+                copies.insert(copy);
+                let ir::Instruction::Assignment {
+                    value: ir::VarValue::Phi(operands),
+                    ..
+                } = &mut program.blocks[block_id.0].instructions[phi]
+                else {
+                    unreachable!("conflicts are found on PHIs");
+                };
+                let value = ir::VarValue::Single(operands[i].into());
+                operands[i] = copy;
+                let prev = program.blocks[block_id.0].prev[i];
+                let instructions = &mut program.blocks[prev.0].instructions;
+                let idx = match instructions.last() {
+                    Some(ir::Instruction::Branch { .. }) => instructions.len() - 1,
+                    _ => instructions.len(),
+                };
+                instructions.insert(idx, ir::Instruction::Assignment { id: copy, value });
+            }
+        }
+        program.reindex();
     }
-    let used_later = add_edges_rec(
-        graph,
-        program,
-        (pos.0, pos.1 + 1),
-        var_id,
-        visited,
-        var_to_node,
-    );
-    used |= used_later;
-
-    let ins = &block.instructions[pos.1];
-    let used_vars = match ins {
-        ir::Instruction::Assignment { id, value } => {
-            let mut v = value.used_vars();
-            v.insert(*id);
-            v
-        }
-        ir::Instruction::Branch {
-            cond,
-            true_block: _,
-            false_block: _,
-        } => cond.used_vars(),
-        ir::Instruction::Yield => HashSet::default(),
-        ir::Instruction::Return(var_id) => [*var_id].into(),
-    };
-    used |= used_vars.contains(&var_id);
+}
 
-    if used_later {
-        let node = var_to_node.get(&var_id).unwrap();
-        for used_var_id in used_vars {
-            let used_node = var_to_node.get(&used_var_id).unwrap();
-            graph.add_edge(*node, *used_node);
+fn is_phi(ins: &ir::Instruction) -> bool {
+    matches!(
+        ins,
+        ir::Instruction::Assignment {
+            value: ir::VarValue::Phi(_),
+            ..
+        }
+    )
+}
+
+/// Returns the variables each variable is live at the same time as, which cannot share its
+/// register: those live after it is assigned, and those assigned by the functions called
+/// while it is live.
+fn interference(program: &ir::Program) -> Interference {
+    fn add_edge(edges: &mut Interference, a: VarId, b: VarId) {
+        if a != b {
+            edges.entry(a).or_default().insert(b);
+            edges.entry(b).or_default().insert(a);
         }
     }
 
-    used
+    let clobbered = clobbered(program);
+    let mut edges = HashMap::default();
+    for (block, mut live) in program.blocks.iter().zip(live_out(program)) {
+        for ins in block.instructions.iter().rev() {
+            if let ir::Instruction::Assignment { id, value } = ins {
+                live.remove(id);
+                for var_id in &live {
+                    add_edge(&mut edges, *id, *var_id);
+                }
+                match value {
+                    ir::VarValue::Call { name, .. } => {
+                        for clobbered_var_id in clobbered.get(name.as_str()).into_iter().flatten() {
+                            for var_id in &live {
+                                add_edge(&mut edges, *var_id, *clobbered_var_id);
+                            }
+                        }
+                    }
+                    // `**` takes several instructions, which write the result before reading
+                    // all operands.
+                    ir::VarValue::BinaryOp {
+                        op: BinaryOpcode::Pow,
+                        ..
+                    } => {
+                        for operand in ins.reads() {
+                            add_edge(&mut edges, *id, operand);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            if !is_phi(ins) {
+                live.extend(ins.reads());
+            }
+        }
+    }
+    edges
 }
 
-fn add_edges(
-    graph: &mut Graph,
-    program: &ir::Program,
-    var_id: VarId,
-    var_to_node: &HashMap<VarId, i32>,
-) {
-    let mut start = find_var(program, var_id);
-    // Move to the next instruction after declaration.
-    start.1 += 1;
-    let mut visited = HashSet::default();
-    visited.insert(start.0);
-    tracing::trace!("add_edges({:?}), pos = {:?}", var_id, start);
-    graph.edges.entry(var_to_node[&var_id]).or_default();
-    add_edges_rec(graph, program, start, var_id, &mut visited, var_to_node);
+/// Returns the variables live at the end of each block, read later without being assigned
+/// again first. The operands of a PHI are read at the end of the blocks they flow from.
+fn live_out(program: &ir::Program) -> Vec<HashSet<VarId>> {
+    let mut live_in = vec![HashSet::default(); program.blocks.len()];
+    let mut live_out = vec![HashSet::default(); program.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (idx, block) in program.blocks.iter().enumerate().rev() {
+            let mut live: HashSet<VarId> = HashSet::default();
+            for next in &block.next {
+                let next_block = &program.blocks[next.0];
+                live.extend(live_in[next.0].iter().copied());
+                for (i, _) in next_block
+                    .prev
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, prev)| prev.0 == idx)
+                {
+                    for ins in &next_block.instructions {
+                        if let ir::Instruction::Assignment {
+                            value: ir::VarValue::Phi(phi),
+                            ..
+                        } = ins
+                        {
+                            live.insert(phi[i]);
+                        }
+                    }
+                }
+            }
+            let mut live_at_start = live.clone();
+            for ins in block.instructions.iter().rev() {
+                if let ir::Instruction::Assignment { id, .. } = ins {
+                    live_at_start.remove(id);
+                }
+                if !is_phi(ins) {
+                    live_at_start.extend(ins.reads());
+                }
+            }
+            if live != live_out[idx] || live_at_start != live_in[idx] {
+                changed = true;
+                live_out[idx] = live;
+                live_in[idx] = live_at_start;
+            }
+        }
+    }
+    live_out
 }
@@ -1,7 +1,9 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-use ayysee_parser::ast::BinaryOpcode;
+use ayysee_parser::ast::{BinaryOpcode, Span, UnaryOpcode};
 use ordered_float::OrderedFloat;
+use smallvec::SmallVec;
+use stationeers_mips::types::Device;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum VarOrConst {
@@ -46,25 +48,141 @@ impl From<VarId> for VarOrConst {
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct BlockId(pub usize);
 
+/// A function compiled once and called with `jal`, rather than inlined where it is called.
+/// The arguments and the return value are passed on the stack.
 pub struct Function {
     pub block_id: BlockId,
-    // TODO: figure out if we should remove those
+    /// The variables the arguments are popped into, in the order of the parameters.
     pub params: Vec<VarId>,
-    // TODO: remove, unused
-    pub ret: Option<VarId>,
 }
 
+/// The location of an instruction: its block and its index in the block.
+pub type Location = (BlockId, usize);
+
 #[derive(Default)]
 pub struct Program {
     pub blocks: Vec<Block>,
+    /// The functions called rather than inlined. The program itself starts at the first
+    /// block.
     pub functions: HashMap<String, Function>,
+    /// The values the variables of the source are assigned, for top-level code. A value
+    /// can be assigned to several variables, as in `let y = x;`.
+    pub names: Vec<(VarId, String)>,
+    /// The constants of the program bound to device pins.
+    pub devices: Vec<(String, Device)>,
+    /// The names given to device pins by `alias` statements.
+    pub aliases: Vec<(String, Device)>,
+    /// The statement of the source each variable is assigned in. Variables of the prelude
+    /// have the statement calling it.
+    pub spans: HashMap<VarId, Span>,
+    /// Where each variable is assigned.
+    defs: HashMap<VarId, Location>,
+    /// The instructions reading each variable, once per read.
+    uses: HashMap<VarId, Vec<Location>>,
+}
+
+impl Program {
+    /// Rebuilds the definitions and uses of variables. Passes editing `blocks` directly
+    /// call this afterwards, or keep them up to date with [`Program::set_value`].
+    pub fn reindex(&mut self) {
+        self.defs.clear();
+        self.uses.clear();
+        for (block_idx, block) in self.blocks.iter().enumerate() {
+            for (idx, ins) in block.instructions.iter().enumerate() {
+                let location = (BlockId(block_idx), idx);
+                if let Instruction::Assignment { id, .. } = ins {
+                    self.defs.insert(*id, location);
+                }
+                for read in ins.reads() {
+                    self.uses.entry(read).or_default().push(location);
+                }
+            }
+        }
+    }
+
+    /// Returns the blocks reachable from `start`, which are those of its function.
+    pub fn function_blocks(&self, start: BlockId) -> Vec<BlockId> {
+        let mut blocks = vec![start];
+        let mut seen = HashSet::from([start]);
+        let mut i = 0;
+        while let Some(block) = blocks.get(i) {
+            for next in self.blocks[block.0].next.clone() {
+                if seen.insert(next) {
+                    blocks.push(next);
+                }
+            }
+            i += 1;
+        }
+        blocks
+    }
+
+    /// Returns the functions of [`Program::functions`] called from the blocks.
+    pub fn callees(&self, blocks: &[BlockId]) -> BTreeSet<&str> {
+        let mut callees = BTreeSet::new();
+        for block in blocks {
+            for ins in &self.blocks[block.0].instructions {
+                if let Instruction::Assignment {
+                    value: VarValue::Call { name, .. },
+                    ..
+                } = ins
+                {
+                    if self.functions.contains_key(name) {
+                        callees.insert(name.as_str());
+                    }
+                }
+            }
+        }
+        callees
+    }
+
+    /// Returns the number of instructions in all blocks.
+    pub fn instruction_count(&self) -> usize {
+        self.blocks.iter().map(|b| b.instructions.len()).sum()
+    }
+
+    /// Returns where the variable is assigned.
+    pub fn definition(&self, id: VarId) -> Option<Location> {
+        self.defs.get(&id).copied()
+    }
+
+    /// Returns the value assigned to the variable.
+    pub fn value(&self, id: VarId) -> Option<&VarValue> {
+        let (block, idx) = self.definition(id)?;
+        match &self.blocks[block.0].instructions[idx] {
+            Instruction::Assignment { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the instructions reading the variable, once per read.
+    pub fn uses(&self, id: VarId) -> &[Location] {
+        self.uses.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Replaces the value assigned to a variable, keeping the uses up to date.
+    pub fn set_value(&mut self, id: VarId, value: VarValue) {
+        let location = self.definition(id).expect("the variable is defined");
+        let ins = &mut self.blocks[location.0 .0].instructions[location.1];
+        for read in ins.reads() {
+            if let Some(uses) = self.uses.get_mut(&read) {
+                if let Some(i) = uses.iter().position(|l| *l == location) {
+                    uses.swap_remove(i);
+                }
+            }
+        }
+        *ins = Instruction::Assignment { id, value };
+        for read in ins.reads() {
+            self.uses.entry(read).or_default().push(location);
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct Block {
     pub instructions: Vec<Instruction>,
-    pub prev: Vec<BlockId>,
-    pub next: Vec<BlockId>,
+    // Blocks have at most two predecessors and successors, except for loop heads.
+    pub prev: SmallVec<[BlockId; 2]>,
+    pub next: SmallVec<[BlockId; 2]>,
 }
 
 #[derive(Clone)]
@@ -82,7 +200,40 @@ pub enum Instruction {
         false_block: BlockId,
     },
     Yield,
-    Return(VarId),
+    /// Stops the IC for good, ending the block.
+    Halt,
+    /// Ends a function called with `jal`, returning the value, or the whole program at the
+    /// top level, where there is no value.
+    Return(Option<VarOrConst>),
+}
+
+impl Instruction {
+    /// Returns the variables the instruction reads, once per read.
+    pub fn reads(&self) -> SmallVec<[VarId; 2]> {
+        let var = |v: &VarOrConst| match v {
+            VarOrConst::Var(id) => Some(*id),
+            _ => None,
+        };
+        match self {
+            Instruction::Assignment { value, .. } => match value {
+                VarValue::Single(x) => var(x).into_iter().collect(),
+                VarValue::Phi(phi) => phi.iter().copied().collect(),
+                VarValue::BinaryOp { lhs, rhs, .. } => {
+                    var(lhs).into_iter().chain(var(rhs)).collect()
+                }
+                VarValue::UnaryOp { operand, .. } => var(operand).into_iter().collect(),
+                VarValue::Call { args, .. } => args.iter().filter_map(var).collect(),
+                VarValue::Get { address } => var(address).into_iter().collect(),
+                VarValue::Put { address, value } => {
+                    var(address).into_iter().chain(var(value)).collect()
+                }
+                VarValue::Param => SmallVec::new(),
+            },
+            Instruction::Branch { cond, .. } => var(cond).into_iter().collect(),
+            Instruction::Yield | Instruction::Halt => SmallVec::new(),
+            Instruction::Return(value) => value.iter().filter_map(var).collect(),
+        }
+    }
 }
 
 impl std::fmt::Debug for Instruction {
@@ -103,7 +254,9 @@ impl std::fmt::Debug for Instruction {
                 )
             }
             Instruction::Yield => write!(f, "yield"),
-            Instruction::Return(var_id) => write!(f, "return {:?}", var_id),
+            Instruction::Halt => write!(f, "halt"),
+            Instruction::Return(Some(value)) => write!(f, "return {:?}", value),
+            Instruction::Return(None) => write!(f, "return"),
         }
     }
 }
@@ -126,11 +279,7 @@ impl std::fmt::Debug for Program {
         }
 
         for (name, fun) in &self.functions {
-            writeln!(
-                f,
-                "fn {}({:?}) -> {:?} {{ {:?} }}",
-                name, fun.params, fun.ret, fun.block_id
-            )?;
+            writeln!(f, "fn {}({:?}) {{ {:?} }}", name, fun.params, fun.block_id)?;
         }
         Ok(())
     }
@@ -154,14 +303,43 @@ pub enum VarValue {
         op: BinaryOpcode,
         rhs: VarOrConst,
     },
+    UnaryOp {
+        op: UnaryOpcode,
+        operand: VarOrConst,
+    },
     Call {
         name: String,
         args: Vec<VarOrConst>,
     },
+    /// The value at an address of the stack of the IC, an element of an array.
+    Get {
+        address: VarOrConst,
+    },
+    /// Writes a value at an address of the stack of the IC. The variable assigned is never
+    /// read, like that of a call to `store`.
+    Put {
+        address: VarOrConst,
+        value: VarOrConst,
+    },
     Param,
 }
 
 impl VarValue {
+    /// Builds `op operand`, computed at compile time when the operand is a constant.
+    pub fn unary(op: UnaryOpcode, operand: VarOrConst) -> Self {
+        match operand {
+            VarOrConst::Const(x) => VarValue::Single(VarOrConst::Const(
+                match op {
+                    UnaryOpcode::Not => f64::from(x.0 == 0.0),
+                    UnaryOpcode::Neg => -x.0,
+                    UnaryOpcode::BitNot => !(x.0 as i64) as f64,
+                }
+                .into(),
+            )),
+            operand => VarValue::UnaryOp { op, operand },
+        }
+    }
+
     pub fn used_vars(&self) -> HashSet<VarId> {
         match self {
             VarValue::Single(x) => x.used_vars(),
@@ -172,6 +350,7 @@ impl VarValue {
                 ret.extend(rhs.used_vars());
                 ret
             }
+            VarValue::UnaryOp { operand, .. } => operand.used_vars(),
             VarValue::Call { name: _, args } => {
                 let mut ret = HashSet::default();
                 for arg in args {
@@ -179,6 +358,12 @@ impl VarValue {
                 }
                 ret
             }
+            VarValue::Get { address } => address.used_vars(),
+            VarValue::Put { address, value } => {
+                let mut ret = address.used_vars();
+                ret.extend(value.used_vars());
+                ret
+            }
             VarValue::Param => HashSet::default(),
         }
     }
@@ -211,6 +396,7 @@ impl std::fmt::Debug for VarValue {
                 )
             }
             VarValue::BinaryOp { lhs, op, rhs } => write!(f, "{lhs:?} {op:?} {rhs:?}"),
+            VarValue::UnaryOp { op, operand } => write!(f, "{op:?} {operand:?}"),
             VarValue::Call { name, args } => {
                 write!(
                     f,
@@ -221,6 +407,8 @@ impl std::fmt::Debug for VarValue {
                         .join(", ")
                 )
             }
+            VarValue::Get { address } => write!(f, "get({address:?})"),
+            VarValue::Put { address, value } => write!(f, "put({address:?}, {value:?})"),
             VarValue::Param => write!(f, "PARAM"),
         }
     }
@@ -1,7 +1,72 @@
+pub mod builtin;
+pub mod debug_info;
+pub mod game_data;
+pub mod import;
+pub mod incremental;
 pub mod ir;
+pub mod prelude;
+pub mod profile;
+pub mod resolve;
 pub mod simulator;
+pub mod target;
+
+pub use builtin::{Builtin, Builtins};
+pub use debug_info::DebugInfo;
+pub use game_data::GameData;
+pub use ir::OptLevel;
+pub use profile::Profile;
+pub use target::{InstructionSet, Target};
+
+use std::collections::BTreeMap;
+
+/// Options controlling how a program is compiled.
+#[derive(Clone, Default, Debug)]
+pub struct CompileOptions {
+    pub opt_level: OptLevel,
+    /// The chip to compile for.
+    pub target: Target,
+    /// The device pins and logic types programs can use.
+    pub game_data: GameData,
+    /// The functions provided by the compiler.
+    pub builtins: Builtins,
+    /// Name the device pins and registers after the constants and variables of the program
+    /// with `alias` lines, as long as they fit in the line limit of the target, so that the
+    /// program stays readable in the game's editor.
+    pub aliases: bool,
+    /// Constants given outside the program, e.g. on the command line. They replace the
+    /// value of the program's `const` of the same name, or are declared before it.
+    pub definitions: BTreeMap<String, f64>,
+    /// Where to record the time spent in each pass, if anywhere.
+    pub profile: Option<Profile>,
+    /// Accept device fields the game data does not list as logic types, such as those of
+    /// mods, rather than reporting them as errors.
+    pub unknown_logic_types: bool,
+}
+
+impl CompileOptions {
+    /// Records a pass in the profile, if any.
+    pub(crate) fn record(
+        &self,
+        name: &'static str,
+        start: std::time::Instant,
+        size_before: usize,
+        size_after: usize,
+    ) {
+        if let Some(profile) = &self.profile {
+            profile.record(name, start, size_before, size_after);
+        }
+    }
+}
 
 /// Generates the MIPS assemby based on ayysee language.
 pub fn generate_program(program: ayysee_parser::ast::Program) -> anyhow::Result<String> {
     Ok(crate::ir::generate_program(program)?.to_string())
 }
+
+/// Generates the MIPS assembly based on ayysee language, using the given options.
+pub fn generate_program_with(
+    program: ayysee_parser::ast::Program,
+    options: &CompileOptions,
+) -> anyhow::Result<String> {
+    Ok(crate::ir::generate_program_with(program, options)?.to_string())
+}
@@ -0,0 +1,83 @@
+//! Functions available to every program, written in ayysee and inlined where they are
+//! called like the functions of the program.
+
+use ayysee_parser::ast::Statement;
+use std::sync::OnceLock;
+
+/// The source of the prelude.
+pub const SOURCE: &str = include_str!("prelude.ayy");
+
+/// Returns the function definitions of the prelude.
+pub fn functions() -> &'static [Statement] {
+    static FUNCTIONS: OnceLock<Vec<Statement>> = OnceLock::new();
+    FUNCTIONS.get_or_init(|| {
+        ayysee_parser::parse(SOURCE)
+            .expect("the prelude is valid")
+            .statements
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulator::Simulator;
+    use ayysee_parser::ast::Program;
+    use stationeers_mips::types::{Device, DeviceVariable};
+
+    /// Runs a tick of `db.Setting = <expr>;` with d0 to d3 set to 1, 2, 3 and 4, returning
+    /// the setting.
+    fn eval(expr: &str) -> f64 {
+        let source = format!("db.Setting = {};", expr);
+        let program = ayysee_parser::parse(&source).unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        for (i, device) in [Device::D0, Device::D1, Device::D2, Device::D3]
+            .into_iter()
+            .enumerate()
+        {
+            simulator.write(device, DeviceVariable::Temperature, (i + 1) as f64);
+        }
+        simulator.tick();
+        simulator.read(Device::Db, DeviceVariable::Setting)
+    }
+
+    #[test]
+    fn test_prelude() {
+        let prelude = Program::new(super::functions().to_vec());
+        assert!(crate::resolve::check(&prelude).is_ok());
+
+        assert_eq!(eval("deadband(20, 25, 1, 0)"), 1.0);
+        assert_eq!(eval("deadband(25.5, 25, 1, 0)"), 0.0);
+        assert_eq!(eval("deadband(25.5, 25, 1, 1)"), 1.0);
+        assert_eq!(eval("deadband(30, 25, 1, 1)"), 0.0);
+        assert_eq!(eval("debounce(debounce(0, 1), 1)"), 2.0);
+        assert_eq!(eval("debounce(3, 5 < 1)"), 0.0);
+        assert_eq!(eval("scale(5, 0, 10, 100, 200)"), 150.0);
+        assert_eq!(eval("average2(Temperature, d0, d1)"), 1.5);
+        assert_eq!(eval("average3(Temperature, d0, d1, d2)"), 2.0);
+        assert_eq!(eval("average4(Temperature, d0, d1, d2, d3)"), 2.5);
+    }
+
+    #[test]
+    fn test_program_functions_hide_the_prelude() {
+        let program =
+            ayysee_parser::parse("fn debounce(x) { return x + 1; }\ndb.Setting = debounce(1);")
+                .unwrap();
+        let mips = crate::ir::generate_program(program).unwrap();
+        let mut simulator = Simulator::new(mips);
+        simulator.tick();
+        assert_eq!(simulator.read(Device::Db, DeviceVariable::Setting), 2.0);
+
+        let program = ayysee_parser::parse("db.Setting = scale(1, 0);").unwrap();
+        assert!(crate::resolve::check(&program).is_err());
+
+        let (table, _) =
+            crate::resolve::resolve(&ayysee_parser::parse("scale(1, 0, 2, 0, 1);").unwrap());
+        let scale = table.lookup("scale").next().unwrap();
+        assert!(table
+            .symbol(scale)
+            .doc
+            .as_ref()
+            .unwrap()
+            .starts_with("Maps"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Instrumentation of the compiler passes, to find the inputs that make them slow.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A run of a compiler pass.
+#[derive(Clone, Debug)]
+pub struct Pass {
+    pub name: &'static str,
+    pub duration: Duration,
+    /// The size of the program before the pass: the number of IR instructions, or of MIPS
+    /// lines once it is generated.
+    pub size_before: usize,
+    pub size_after: usize,
+}
+
+/// Collects the passes run while compiling, when set in
+/// [`CompileOptions::profile`](crate::CompileOptions::profile). Clones share the same
+/// passes, so the profile can be read once the options have been used.
+#[derive(Clone, Default)]
+pub struct Profile(Arc<Mutex<Vec<Pass>>>);
+
+impl Profile {
+    /// Records a pass that started at `start` and ends now.
+    pub fn record(
+        &self,
+        name: &'static str,
+        start: Instant,
+        size_before: usize,
+        size_after: usize,
+    ) {
+        self.0.lock().unwrap().push(Pass {
+            name,
+            duration: start.elapsed(),
+            size_before,
+            size_after,
+        });
+    }
+
+    /// Returns the passes in the order they ran. Passes run several times, as by `-O2`, are
+    /// listed once per run.
+    pub fn passes(&self) -> Vec<Pass> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Leaves out the passes, which differ from one compilation to the next.
+impl std::fmt::Debug for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Profile")
+    }
+}
+
+/// A table of the passes, with their total.
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let passes = self.passes();
+        writeln!(
+            f,
+            "{:<20} {:>10} {:>8} {:>8}",
+            "pass", "time", "before", "after"
+        )?;
+        for pass in &passes {
+            writeln!(
+                f,
+                "{:<20} {:>10.3?} {:>8} {:>8}",
+                pass.name, pass.duration, pass.size_before, pass.size_after
+            )?;
+        }
+        let total: Duration = passes.iter().map(|p| p.duration).sum();
+        write!(f, "{:<20} {:>10.3?}", "total", total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CompileOptions, OptLevel};
+
+    #[test]
+    fn test_profile() {
+        let options = CompileOptions {
+            opt_level: OptLevel::O2,
+            profile: Some(Default::default()),
+            ..Default::default()
+        };
+        let program = ayysee_parser::parse("let x = 1; let y = x + 2; db.Setting = y;").unwrap();
+        crate::generate_program_with(program, &options).unwrap();
+
+        let passes = options.profile.unwrap().passes();
+        let names = passes.iter().map(|p| p.name).collect::<Vec<_>>();
+        assert_eq!(names.first(), Some(&"resolve"));
+        assert!(names.contains(&"inline"));
+        assert_eq!(names.last(), Some(&"verify"));
+        let ir = passes.iter().find(|p| p.name == "generate ir").unwrap();
+        let optimized = passes
+            .iter()
+            .rev()
+            .find(|p| p.name == "remove unused")
+            .unwrap();
+        assert!(optimized.size_after < ir.size_after);
+    }
+}
@@ -0,0 +1,1521 @@
+//! Name resolution, run before generating IR so that mistakes in a program are reported
+//! as errors pointing at the source instead of failing deep in the compiler.
+
+use crate::builtin::Type;
+use crate::{Builtins, CompileOptions};
+use ayysee_parser::ast::{
+    BinaryOpcode, Block, Expr, ExprKind, Identifier, IfStatement, Program, Span, Statement,
+    StatementKind, UnaryOpcode,
+};
+use stationeers_mips::hash::hash;
+use stationeers_mips::types::Device;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// A mistake in a program found by [`check`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SemanticError {
+    pub message: String,
+    /// The part of the source the error points at.
+    pub span: Span,
+}
+
+/// All the errors found in a program.
+#[derive(Debug)]
+pub struct SemanticErrors(pub Vec<SemanticError>);
+
+impl std::fmt::Display for SemanticErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SemanticErrors {}
+
+/// Identifies a symbol of a [`SymbolTable`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SymbolId(pub usize);
+
+/// Identifies a scope of a [`SymbolTable`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ScopeId(pub usize);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Constant,
+    /// A variable declared with `let name = [value; length];`.
+    Array {
+        length: usize,
+    },
+    Function {
+        parameters: usize,
+    },
+    Builtin {
+        parameters: usize,
+    },
+    /// A device pin or logic type of the game.
+    External,
+}
+
+/// The value of a constant known at compile time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstValue {
+    Number(f64),
+    /// A device pin or logic type.
+    External(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The name in the declaration, `None` for builtins, externals and definitions.
+    pub declaration: Option<Span>,
+    pub scope: ScopeId,
+    /// The value of constants, when it can be computed.
+    pub value: Option<ConstValue>,
+    /// The doc comments of functions and constants.
+    pub doc: Option<String>,
+}
+
+/// The top level of the program, or the body of a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scope {
+    pub span: Span,
+    /// The function the scope is the body of.
+    pub function: Option<SymbolId>,
+}
+
+/// The names of a program and what they refer to.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    pub symbols: Vec<Symbol>,
+    /// The scope of the whole program comes first.
+    pub scopes: Vec<Scope>,
+    /// Every occurrence of a name that was resolved, declarations included.
+    pub references: Vec<(Span, SymbolId)>,
+}
+
+impl SymbolTable {
+    pub fn symbol(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id.0]
+    }
+
+    /// Returns the symbol whose name covers the byte `offset`, with the span of that name.
+    pub fn symbol_at(&self, offset: usize) -> Option<(Span, SymbolId)> {
+        self.references
+            .iter()
+            .find(|(span, _)| span.start <= offset && offset <= span.end)
+            .copied()
+    }
+
+    /// Returns the occurrences of a symbol, in source order.
+    pub fn references_to(&self, id: SymbolId) -> impl Iterator<Item = Span> + '_ {
+        self.references
+            .iter()
+            .filter(move |(_, symbol)| *symbol == id)
+            .map(|(span, _)| *span)
+    }
+
+    /// Returns the symbols named `name`, across all scopes.
+    pub fn lookup<'a>(&'a self, name: &'a str) -> impl Iterator<Item = SymbolId> + 'a {
+        self.symbols
+            .iter()
+            .enumerate()
+            .filter(move |(_, symbol)| symbol.name == name)
+            .map(|(i, _)| SymbolId(i))
+    }
+
+    /// Returns the device pin a symbol is bound to, through constants.
+    pub fn device(&self, id: SymbolId) -> Option<Device> {
+        let symbol = self.symbol(id);
+        match (&symbol.kind, &symbol.value) {
+            (SymbolKind::External, _) => symbol.name.parse().ok(),
+            (SymbolKind::Constant, Some(ConstValue::External(name))) => name.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that every name used in the program is defined, and that functions are called
+/// with the right number of arguments.
+///
+/// Variables are visible from their `let` (or parameter) to the end of the enclosing
+/// function, or of the program for top-level code. Constants are visible from their
+/// definition on, and functions everywhere.
+pub fn check(program: &Program) -> Result<(), SemanticErrors> {
+    check_with(program, &CompileOptions::default())
+}
+
+/// Like [`check`], with the game data and builtins of `options`.
+pub fn check_with(program: &Program, options: &CompileOptions) -> Result<(), SemanticErrors> {
+    let (_, errors) = resolve_with(program, options);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(SemanticErrors(errors))
+    }
+}
+
+/// Resolves every name of the program like [`check`], returning what they refer to along
+/// with the errors.
+pub fn resolve(program: &Program) -> (SymbolTable, Vec<SemanticError>) {
+    resolve_with(program, &CompileOptions::default())
+}
+
+/// Like [`resolve`], with the game data and builtins of `options`.
+pub fn resolve_with(
+    program: &Program,
+    options: &CompileOptions,
+) -> (SymbolTable, Vec<SemanticError>) {
+    let mut resolver = Resolver {
+        externals: options.game_data.externals().map(String::from).collect(),
+        logic_types: (options.game_data.logic_types.iter())
+            .chain(&options.game_data.slot_logic_types)
+            .cloned()
+            .collect(),
+        unknown_logic_types: options.unknown_logic_types,
+        builtins: options.builtins.clone(),
+        definitions: options.definitions.clone(),
+        ..Default::default()
+    };
+    let end = program.statements.last().map_or(0, |s| s.span.end);
+    resolver.table.scopes.push(Scope {
+        span: Span::new(0, end),
+        function: None,
+    });
+    resolver.collect_functions(&program.statements);
+    resolver.collect_prelude();
+    resolver.collect_constants(&program.statements);
+    resolver.collect_definitions();
+    resolver.statements(&program.statements);
+    resolver
+        .table
+        .references
+        .sort_by_key(|(span, _)| span.start);
+    (resolver.table, resolver.errors)
+}
+
+#[derive(Default)]
+struct Resolver {
+    table: SymbolTable,
+    /// The device pins and logic types.
+    externals: HashSet<String>,
+    /// The logic types and slot logic types, suggested for unknown device fields.
+    logic_types: Vec<String>,
+    /// Whether device fields can be logic types the game data does not list.
+    unknown_logic_types: bool,
+    builtins: Builtins,
+    /// The constants given outside the program.
+    definitions: BTreeMap<String, f64>,
+    scope: usize,
+    functions: HashMap<String, SymbolId>,
+    /// Builtins and externals, added when first used, and the functions of the prelude.
+    globals: HashMap<String, SymbolId>,
+    /// Every constant of the program, defined or not yet.
+    all_constants: HashSet<String>,
+    constants: HashMap<String, SymbolId>,
+    variables: HashMap<String, SymbolId>,
+    /// The variables declared by the `let`s of the innermost block, which another `let` of
+    /// the block defines again rather than shadows.
+    declared: HashSet<String>,
+    /// The labels of the loops around the statement, in the current function, innermost
+    /// last.
+    loops: Vec<Option<String>>,
+    /// The number of blocks around the statement.
+    depth: usize,
+    errors: Vec<SemanticError>,
+}
+
+impl Resolver {
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(SemanticError { message, span });
+    }
+
+    fn declare(&mut self, identifier: &Identifier, kind: SymbolKind) -> SymbolId {
+        let name: &str = identifier.as_ref();
+        if !matches!(kind, SymbolKind::Function { .. }) && self.externals.contains(name) {
+            self.reserved(identifier);
+        }
+        let id = SymbolId(self.table.symbols.len());
+        self.table.symbols.push(Symbol {
+            name: identifier.to_string(),
+            kind,
+            declaration: Some(identifier.span),
+            scope: ScopeId(self.scope),
+            value: None,
+            doc: None,
+        });
+        self.table.references.push((identifier.span, id));
+        id
+    }
+
+    /// Reports a declaration hiding a device pin or logic type.
+    fn reserved(&mut self, identifier: &Identifier) {
+        let name: &str = identifier.as_ref();
+        let (what, suggestion) = match name.parse::<Device>() {
+            Ok(_) => ("device pin", format!("{}_value", name)),
+            Err(_) => ("logic type", snake_case(name)),
+        };
+        self.error(
+            identifier.span,
+            format!(
+                "`{}` is a {} and cannot be declared, rename it (e.g. `{}`)",
+                name, what, suggestion
+            ),
+        );
+    }
+
+    fn reference(&mut self, identifier: &Identifier, id: SymbolId) {
+        self.table.references.push((identifier.span, id));
+    }
+
+    /// Returns the symbol of a builtin or external, creating it on first use.
+    fn global(&mut self, name: &str, kind: SymbolKind) -> SymbolId {
+        if let Some(id) = self.globals.get(name) {
+            return *id;
+        }
+        let id = SymbolId(self.table.symbols.len());
+        self.table.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            declaration: None,
+            scope: ScopeId(0),
+            value: None,
+            doc: None,
+        });
+        self.globals.insert(name.to_string(), id);
+        id
+    }
+
+    /// Adds the functions of the prelude the program does not define itself.
+    fn collect_prelude(&mut self) {
+        for statement in crate::prelude::functions() {
+            if let StatementKind::Function {
+                identifier,
+                parameters,
+                ..
+            } = &statement.kind
+            {
+                let name: &str = identifier.as_ref();
+                if self.functions.contains_key(name) || self.builtins.get(name).is_some() {
+                    continue;
+                }
+                let kind = SymbolKind::Function {
+                    parameters: parameters.len(),
+                };
+                let id = self.global(name, kind);
+                self.table.symbols[id.0].doc = statement.doc();
+                self.functions.insert(name.to_string(), id);
+            }
+        }
+    }
+
+    fn collect_functions(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            if let StatementKind::Function {
+                identifier,
+                parameters,
+                ..
+            } = &statement.kind
+            {
+                let name: &str = identifier.as_ref();
+                if self.builtins.get(name).is_some() {
+                    self.error(
+                        identifier.span,
+                        format!("function `{}` is already a builtin function", identifier),
+                    );
+                } else if self.functions.contains_key(name) {
+                    self.error(
+                        identifier.span,
+                        format!("function `{}` is defined more than once", identifier),
+                    );
+                } else {
+                    let kind = SymbolKind::Function {
+                        parameters: parameters.len(),
+                    };
+                    let id = self.declare(identifier, kind);
+                    self.table.symbols[id.0].doc = statement.doc();
+                    self.functions.insert(name.to_string(), id);
+                }
+            }
+            for block in blocks(statement) {
+                self.collect_functions(block.statements());
+            }
+        }
+    }
+
+    fn collect_constants(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Constant(identifier, _)
+                | StatementKind::Alias {
+                    alias: identifier, ..
+                } => {
+                    self.all_constants.insert(identifier.to_string());
+                }
+                _ => (),
+            }
+            for block in blocks(statement) {
+                self.collect_constants(block.statements());
+            }
+        }
+    }
+
+    /// Declares the definitions that no `const` of the program declares.
+    fn collect_definitions(&mut self) {
+        for (name, x) in &self.definitions {
+            if self.all_constants.contains(name) {
+                continue;
+            }
+            let id = SymbolId(self.table.symbols.len());
+            self.table.symbols.push(Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Constant,
+                declaration: None,
+                scope: ScopeId(0),
+                value: Some(ConstValue::Number(*x)),
+                doc: None,
+            });
+            self.constants.insert(name.clone(), id);
+            self.all_constants.insert(name.clone());
+        }
+    }
+
+    /// Returns the constant or external named `name`.
+    fn constant(&mut self, name: &str) -> Option<SymbolId> {
+        if let Some(id) = self.constants.get(name) {
+            Some(*id)
+        } else if self.externals.contains(name) {
+            Some(self.global(name, SymbolKind::External))
+        } else {
+            None
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Resolves the statements of a block, whose variables are not visible after it.
+    fn block(&mut self, block: &Block) {
+        let outer = self.variables.clone();
+        let outer_declared = std::mem::take(&mut self.declared);
+        self.depth += 1;
+        self.statements(block.statements());
+        self.depth -= 1;
+        self.variables = outer;
+        self.declared = outer_declared;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Assignment { lhs, rhs } => {
+                self.expr(rhs);
+                match &lhs.kind {
+                    ExprKind::Identifier(identifier) => self.assign(identifier),
+                    ExprKind::FieldExpr(..) | ExprKind::Index(..) => self.expr(lhs),
+                    _ => self.error(
+                        lhs.span,
+                        "only variables, array elements and device fields can be assigned to"
+                            .to_string(),
+                    ),
+                }
+            }
+            StatementKind::Definition {
+                identifier,
+                expression,
+            } => match &expression.kind {
+                ExprKind::Array(value, length) => {
+                    self.expr(value);
+                    self.expr(length);
+                    let length = self.length(length);
+                    let kind = SymbolKind::Array {
+                        length: length.unwrap_or_default(),
+                    };
+                    let id = self.declare(identifier, kind);
+                    self.variables.insert(identifier.to_string(), id);
+                }
+                _ => {
+                    self.expr(expression);
+                    self.define(identifier);
+                }
+            },
+            StatementKind::TupleDefinition {
+                identifiers,
+                expressions,
+            } => {
+                for expression in expressions {
+                    self.expr(expression);
+                }
+                if identifiers.len() != expressions.len() {
+                    self.error(
+                        statement.span,
+                        format!(
+                            "{} variables are defined from {} values",
+                            identifiers.len(),
+                            expressions.len()
+                        ),
+                    );
+                }
+                for identifier in identifiers {
+                    self.define(identifier);
+                }
+            }
+            StatementKind::Alias { identifier, alias } => {
+                let errors = self.errors.len();
+                self.identifier(identifier);
+                let target = Expr::new(ExprKind::Identifier(identifier.clone()), identifier.span);
+                let value = self.value(&target);
+                let device = matches!(&value, Some(ConstValue::External(name)) if name.parse::<Device>().is_ok());
+                if !device && self.errors.len() == errors {
+                    self.error(
+                        identifier.span,
+                        format!("`{}` is not a device pin", identifier),
+                    );
+                }
+                self.redefinition(alias);
+                let id = self.declare(alias, SymbolKind::Constant);
+                self.table.symbols[id.0].value = value;
+                self.table.symbols[id.0].doc = statement.doc();
+                self.constants.insert(alias.to_string(), id);
+            }
+            StatementKind::Constant(identifier, expression) => {
+                self.expr(expression);
+                self.redefinition(identifier);
+                let id = self.declare(identifier, SymbolKind::Constant);
+                self.table.symbols[id.0].value =
+                    match self.definitions.get::<str>(identifier.as_ref()) {
+                        Some(x) => Some(ConstValue::Number(*x)),
+                        None => self.value(expression),
+                    };
+                self.table.symbols[id.0].doc = statement.doc();
+                self.constants.insert(identifier.to_string(), id);
+            }
+            StatementKind::Function {
+                identifier,
+                parameters,
+                body,
+            } => {
+                let name: &str = identifier.as_ref();
+                if name == "main" && !parameters.is_empty() {
+                    self.error(
+                        identifier.span,
+                        "`main` runs when the program starts and cannot take parameters"
+                            .to_string(),
+                    );
+                }
+                let function = self.functions.get(name).copied();
+                let outer_scope = self.scope;
+                self.scope = self.table.scopes.len();
+                self.table.scopes.push(Scope {
+                    span: statement.span,
+                    function,
+                });
+                // Functions only see their parameters, not the variables around them.
+                let outer = std::mem::take(&mut self.variables);
+                let outer_loops = std::mem::take(&mut self.loops);
+                for (i, parameter) in parameters.iter().enumerate() {
+                    if parameters[..i].contains(parameter) {
+                        self.error(
+                            parameter.span,
+                            format!("parameter `{}` is defined more than once", parameter),
+                        );
+                    }
+                    let id = self.declare(parameter, SymbolKind::Parameter);
+                    self.variables.insert(parameter.to_string(), id);
+                }
+                self.block(body);
+                self.variables = outer;
+                self.loops = outer_loops;
+                self.scope = outer_scope;
+            }
+            StatementKind::FunctionCall {
+                identifier,
+                arguments,
+            } => self.call(identifier, arguments, statement.span),
+            StatementKind::Block(body) => self.block(body),
+            StatementKind::Loop { label, body } => self.loop_body(label, body),
+            StatementKind::LoopUntil {
+                label,
+                body,
+                condition,
+            } => {
+                self.loop_body(label, body);
+                self.expr(condition);
+            }
+            StatementKind::For {
+                label,
+                variable,
+                start,
+                end,
+                body,
+            } => {
+                self.expr(start);
+                self.expr(end);
+                self.define(variable);
+                self.loop_body(label, body);
+            }
+            StatementKind::IfStatement(IfStatement::If { condition, body }) => {
+                self.expr(condition);
+                self.block(body);
+            }
+            StatementKind::IfStatement(IfStatement::IfElse {
+                condition,
+                body,
+                else_body,
+            }) => {
+                self.expr(condition);
+                self.block(body);
+                self.block(else_body);
+            }
+            StatementKind::IfStatement(IfStatement::ElseIf {
+                branches,
+                else_body,
+            }) => {
+                for (condition, body) in branches {
+                    self.expr(condition);
+                    self.block(body);
+                }
+                if let Some(else_body) = else_body {
+                    self.block(else_body);
+                }
+            }
+            StatementKind::Match {
+                value,
+                arms,
+                default_arm,
+            } => {
+                self.expr(value);
+                let mut patterns: Vec<f64> = vec![];
+                for (pattern, body) in arms {
+                    self.expr(pattern);
+                    match self.value(pattern) {
+                        Some(ConstValue::Number(x)) if patterns.contains(&x) => self.error(
+                            pattern.span,
+                            format!("{} is already matched by an earlier arm", x),
+                        ),
+                        Some(ConstValue::Number(x)) => patterns.push(x),
+                        _ => self.error(
+                            pattern.span,
+                            "the pattern of a `match` arm must be a number known at compile time"
+                                .to_string(),
+                        ),
+                    }
+                    self.block(body);
+                }
+                if let Some(default_arm) = default_arm {
+                    self.block(default_arm);
+                }
+            }
+            StatementKind::Break(label) | StatementKind::Continue(label) => {
+                let keyword = match statement.kind {
+                    StatementKind::Break(_) => "break",
+                    _ => "continue",
+                };
+                match label {
+                    None if self.loops.is_empty() => self.error(
+                        statement.span,
+                        format!("`{}` can only be used inside a loop", keyword),
+                    ),
+                    Some(label) if !self.loops.contains(&Some(label.to_string())) => self.error(
+                        label.span,
+                        format!(
+                            "`{}` is not the label of a loop around this `{}`",
+                            label, keyword
+                        ),
+                    ),
+                    _ => {}
+                }
+            }
+            StatementKind::DeviceStatement(_)
+            | StatementKind::Yield
+            | StatementKind::Halt
+            | StatementKind::Error => {}
+            // The imports of the top level are replaced by what they import before compiling,
+            // see [`crate::import`].
+            StatementKind::Import(_) if self.depth > 0 => self.error(
+                statement.span,
+                "`import` can only be used at the top level of a file".to_string(),
+            ),
+            StatementKind::Import(_) => {}
+            StatementKind::Return(None) => {}
+            StatementKind::Return(Some(expr)) => {
+                if self.scope == 0 {
+                    self.error(
+                        statement.span,
+                        "`return` outside a function ends the program and cannot return a value"
+                            .to_string(),
+                    );
+                }
+                self.expr(expr)
+            }
+        }
+    }
+
+    /// Returns the length of an array, reporting it unless it is a positive whole number
+    /// known at compile time.
+    fn length(&mut self, expr: &Expr) -> Option<usize> {
+        match self.value(expr) {
+            Some(ConstValue::Number(x)) if x >= 1.0 && x.fract() == 0.0 => Some(x as usize),
+            _ => {
+                self.error(
+                    expr.span,
+                    "the length of an array must be a positive whole number known at compile time"
+                        .to_string(),
+                );
+                None
+            }
+        }
+    }
+
+    /// Resolves the body of a loop, which `break` and `continue` can refer to by its label.
+    fn loop_body(&mut self, label: &Option<Identifier>, body: &Block) {
+        self.loops.push(label.as_ref().map(Identifier::to_string));
+        self.block(body);
+        self.loops.pop();
+    }
+
+    /// Declares a variable with `let`, which only assigns to it if it already exists. An
+    /// array of the same name is hidden by a new variable.
+    fn define(&mut self, identifier: &Identifier) {
+        let name: &str = identifier.as_ref();
+        let array = |id: &SymbolId| matches!(self.table.symbol(*id).kind, SymbolKind::Array { .. });
+        let declared = self.declared.contains(name);
+        match self.variables.get(name).filter(|id| declared && !array(id)) {
+            Some(id) => self.reference(identifier, *id),
+            None => {
+                let id = self.declare(identifier, SymbolKind::Variable);
+                self.variables.insert(identifier.to_string(), id);
+                self.declared.insert(identifier.to_string());
+            }
+        }
+    }
+
+    /// Reports a constant or alias of the same name as one declared before it, which
+    /// constants cannot shadow.
+    fn redefinition(&mut self, identifier: &Identifier) {
+        let declared = self.constants.get::<str>(identifier.as_ref()).copied();
+        if declared.is_some_and(|id| self.table.symbol(id).declaration.is_some()) {
+            self.error(
+                identifier.span,
+                format!("constant `{}` is defined more than once", identifier),
+            );
+        }
+    }
+
+    fn assign(&mut self, identifier: &Identifier) {
+        let name: &str = identifier.as_ref();
+        if let Some(id) = self.variables.get(name).copied() {
+            self.reference(identifier, id);
+            if let SymbolKind::Array { .. } = self.table.symbol(id).kind {
+                self.error(
+                    identifier.span,
+                    format!(
+                        "cannot assign to array `{}`, assign to its elements as in `{}[0] = ...;`",
+                        identifier, identifier
+                    ),
+                );
+            }
+            return;
+        }
+        let message = if self.constant(name).is_some() || self.all_constants.contains(name) {
+            format!("cannot assign to constant `{}`", identifier)
+        } else {
+            format!(
+                "cannot assign to undefined variable `{}`, declare it with `let {} = ...;`",
+                identifier, identifier
+            )
+        };
+        self.error(identifier.span, message);
+    }
+
+    fn call(&mut self, identifier: &Identifier, arguments: &[Box<Expr>], span: Span) {
+        for argument in arguments {
+            self.expr(argument);
+        }
+        let name: &str = identifier.as_ref();
+        let builtin = self
+            .builtins
+            .get(name)
+            .map(|builtin| (builtin.arity(), builtin.parameters()));
+        let id = match &builtin {
+            Some((parameters, _)) => Some(self.global(
+                name,
+                SymbolKind::Builtin {
+                    parameters: *parameters,
+                },
+            )),
+            None => self.functions.get(name).copied(),
+        };
+        let arity = match id.map(|id| &self.table.symbol(id).kind) {
+            Some(SymbolKind::Function { parameters } | SymbolKind::Builtin { parameters }) => {
+                *parameters
+            }
+            _ => {
+                self.error(
+                    identifier.span,
+                    format!("cannot find function `{}`", identifier),
+                );
+                return;
+            }
+        };
+        self.reference(identifier, id.unwrap());
+        if arity != arguments.len() {
+            self.error(
+                span,
+                format!(
+                    "function `{}` takes {} argument{} but {} {} given",
+                    identifier,
+                    arity,
+                    if arity == 1 { "" } else { "s" },
+                    arguments.len(),
+                    if arguments.len() == 1 { "was" } else { "were" }
+                ),
+            );
+        } else if let Some((_, types)) = builtin {
+            for (argument, expected) in arguments.iter().zip(types) {
+                self.check_type(argument, expected);
+            }
+        }
+    }
+
+    /// Reports an argument or operand known to be of another type than `expected`. Variables
+    /// and parameters are not checked: functions are inlined, so they can hold device pins
+    /// and logic types.
+    fn check_type(&mut self, expr: &Expr, expected: Type) {
+        let found = match &expr.kind {
+            ExprKind::DeviceIndex(_) => Type::Device,
+            ExprKind::Identifier(_) => match self.value(expr) {
+                Some(ConstValue::External(name)) => Type::of_external(&name),
+                Some(ConstValue::Number(_)) => Type::Number,
+                None => return,
+            },
+            _ => Type::Number,
+        };
+        if expected.accepts(found) {
+            return;
+        }
+        let found = match &expr.kind {
+            ExprKind::Identifier(identifier) if found != Type::Number => {
+                format!("{} `{}`", found, identifier)
+            }
+            _ => format!("a {}", found),
+        };
+        self.error(
+            expr.span,
+            format!("expected a {}, found {}", expected, found),
+        );
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Constant(_) | ExprKind::Hash(_) => {}
+            ExprKind::Identifier(identifier) => self.identifier(identifier),
+            ExprKind::BinaryOp(lhs, _, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.check_type(lhs, Type::Number);
+                self.check_type(rhs, Type::Number);
+            }
+            ExprKind::UnaryOp(_, operand) => {
+                self.expr(operand);
+                self.check_type(operand, Type::Number);
+            }
+            ExprKind::FunctionCall(identifier, arguments) => {
+                self.call(identifier, arguments, expr.span)
+            }
+            ExprKind::FieldExpr(device, variable) => {
+                self.expr(device);
+                self.field(variable);
+            }
+            ExprKind::DeviceIndex(index) => self.expr(index),
+            ExprKind::Array(value, length) => {
+                self.expr(value);
+                self.expr(length);
+                self.error(
+                    expr.span,
+                    "arrays can only be declared with `let`, as in `let buf = [0; 8];`".to_string(),
+                );
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(index);
+                self.index(array, index);
+            }
+        }
+    }
+
+    /// Checks an element of an array, and that its index is in bounds when it is known at
+    /// compile time.
+    fn index(&mut self, array: &Identifier, index: &Expr) {
+        let name: &str = array.as_ref();
+        let Some(id) = self
+            .variables
+            .get(name)
+            .copied()
+            .or_else(|| self.constant(name))
+        else {
+            self.identifier(array);
+            return;
+        };
+        self.reference(array, id);
+        let SymbolKind::Array { length } = self.table.symbol(id).kind else {
+            self.error(array.span, format!("`{}` is not an array", array));
+            return;
+        };
+        if let Some(ConstValue::Number(x)) = self.value(index) {
+            if x < 0.0 || x >= length as f64 || x.fract() != 0.0 {
+                self.error(
+                    index.span,
+                    format!(
+                        "index {} is out of bounds for array `{}` of length {}",
+                        x, array, length
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Checks a name used as a value.
+    fn identifier(&mut self, identifier: &Identifier) {
+        let name: &str = identifier.as_ref();
+        if let Some(id) = self
+            .variables
+            .get(name)
+            .copied()
+            .or_else(|| self.constant(name))
+        {
+            self.reference(identifier, id);
+            if let SymbolKind::Array { .. } = self.table.symbol(id).kind {
+                self.error(
+                    identifier.span,
+                    format!(
+                        "array `{}` can only be used through its elements, as in `{}[0]`",
+                        identifier, identifier
+                    ),
+                );
+            }
+            return;
+        }
+        let message = if self.all_constants.contains(name) {
+            format!("constant `{}` is used before its definition", identifier)
+        } else {
+            format!("cannot find variable `{}`", identifier)
+        };
+        self.error(identifier.span, message);
+    }
+
+    /// Resolves the logic type of a device field, as in `d0.Setting`.
+    fn field(&mut self, identifier: &Identifier) {
+        let name: &str = identifier.as_ref();
+        let known = self.variables.contains_key(name)
+            || self.constants.contains_key(name)
+            || self.all_constants.contains(name)
+            || self.externals.contains(name);
+        if known {
+            return self.identifier(identifier);
+        }
+        if self.unknown_logic_types {
+            let id = self.global(name, SymbolKind::External);
+            return self.reference(identifier, id);
+        }
+        let closest = self
+            .logic_types
+            .iter()
+            .map(|logic_type| (edit_distance(name, logic_type), logic_type))
+            .min();
+        let message = match closest {
+            Some((distance, logic_type)) if distance <= (name.len() / 3).max(1) => format!(
+                "unknown logic type `{}`, did you mean `{}`?",
+                identifier, logic_type
+            ),
+            _ => format!("unknown logic type `{}`", identifier),
+        };
+        self.error(identifier.span, message);
+    }
+
+    /// Computes the value of a constant's expression, once its names are resolved.
+    fn value(&self, expr: &Expr) -> Option<ConstValue> {
+        let number = |expr| match self.value(expr)? {
+            ConstValue::Number(x) => Some(x),
+            ConstValue::External(_) => None,
+        };
+        match &expr.kind {
+            ExprKind::Constant(value) => Some(ConstValue::Number(value.into())),
+            ExprKind::Hash(name) => Some(ConstValue::Number(hash(name).into())),
+            ExprKind::Identifier(identifier) => {
+                let name: &str = identifier.as_ref();
+                match self.constants.get(name) {
+                    Some(id) => self.table.symbol(*id).value.clone(),
+                    None if self.externals.contains(name) => {
+                        Some(ConstValue::External(name.into()))
+                    }
+                    None => None,
+                }
+            }
+            ExprKind::BinaryOp(lhs, op, rhs) => {
+                let (a, b) = (number(lhs)?, number(rhs)?);
+                let x = match op {
+                    BinaryOpcode::Add => a + b,
+                    BinaryOpcode::Sub => a - b,
+                    BinaryOpcode::Mul => a * b,
+                    BinaryOpcode::Div => a / b,
+                    BinaryOpcode::Mod => crate::simulator::modulo(a, b),
+                    BinaryOpcode::Pow => a.powf(b),
+                    BinaryOpcode::BitAnd => ((a as i64) & (b as i64)) as f64,
+                    BinaryOpcode::BitOr => ((a as i64) | (b as i64)) as f64,
+                    BinaryOpcode::BitXor => ((a as i64) ^ (b as i64)) as f64,
+                    BinaryOpcode::ShiftLeft => crate::simulator::shift_left(a, b),
+                    BinaryOpcode::ShiftRight => crate::simulator::shift_right(a, b),
+                    BinaryOpcode::Conj => f64::from(a != 0.0 && b != 0.0),
+                    BinaryOpcode::Disj => f64::from(a != 0.0 || b != 0.0),
+                    BinaryOpcode::Equals => f64::from(a == b),
+                    BinaryOpcode::NotEquals => f64::from(a != b),
+                    BinaryOpcode::Greater => f64::from(a > b),
+                    BinaryOpcode::GreaterEquals => f64::from(a >= b),
+                    BinaryOpcode::Lower => f64::from(a < b),
+                    BinaryOpcode::LowerEquals => f64::from(a <= b),
+                };
+                Some(ConstValue::Number(x))
+            }
+            ExprKind::UnaryOp(UnaryOpcode::Not, operand) => {
+                Some(ConstValue::Number(f64::from(number(operand)? == 0.0)))
+            }
+            ExprKind::UnaryOp(UnaryOpcode::Neg, operand) => {
+                Some(ConstValue::Number(-number(operand)?))
+            }
+            ExprKind::UnaryOp(UnaryOpcode::BitNot, operand) => {
+                Some(ConstValue::Number(!(number(operand)? as i64) as f64))
+            }
+            ExprKind::FunctionCall(..)
+            | ExprKind::FieldExpr(..)
+            | ExprKind::DeviceIndex(..)
+            | ExprKind::Array(..)
+            | ExprKind::Index(..) => None,
+        }
+    }
+}
+
+/// Converts a `CamelCase` logic type name into a variable name.
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.char_indices() {
+        if c.is_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+/// Returns the blocks nested in the statement.
+pub(crate) fn blocks(statement: &Statement) -> Vec<&Block> {
+    match &statement.kind {
+        StatementKind::Function { body, .. }
+        | StatementKind::Loop { body, .. }
+        | StatementKind::LoopUntil { body, .. }
+        | StatementKind::For { body, .. }
+        | StatementKind::Block(body) => vec![body],
+        StatementKind::IfStatement(IfStatement::If { body, .. }) => vec![body],
+        StatementKind::IfStatement(IfStatement::IfElse {
+            body, else_body, ..
+        }) => vec![body, else_body],
+        StatementKind::Match {
+            arms, default_arm, ..
+        } => arms
+            .iter()
+            .map(|(_, body)| body)
+            .chain(default_arm)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The number of characters to insert, delete or replace to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(x != *y);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<(String, &str)> {
+        let program = ayysee_parser::parse(source).unwrap();
+        match check(&program) {
+            Ok(()) => vec![],
+            Err(SemanticErrors(errors)) => errors
+                .into_iter()
+                .map(|e| (e.message, &source[e.span.start..e.span.end]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let source = r"
+            const LIMIT = 10;
+            let x = 0;
+            fn double(a) { return a * 2; }
+            loop {
+                if d0.Setting > LIMIT { let y = 1; x = y; }
+                db.Setting = double(x) + load(d1, Temperature);
+                store(d2, On, x);
+                yield;
+            }
+        ";
+        assert_eq!(errors(source), vec![]);
+    }
+
+    #[test]
+    fn test_undefined_names() {
+        let source = "let x = y + 1;\nd0.Setting = f(x);\nd0.Bogus = 1;\nz = 1;\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                ("cannot find variable `y`".to_string(), "y"),
+                ("cannot find function `f`".to_string(), "f"),
+                ("unknown logic type `Bogus`".to_string(), "Bogus"),
+                (
+                    "cannot assign to undefined variable `z`, declare it with `let z = ...;`"
+                        .to_string(),
+                    "z"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_logic_types() {
+        let source = "d0.Setings = 1;\nlet x = d0.Temprature + d1.ModPower;\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "unknown logic type `Setings`, did you mean `Setting`?".to_string(),
+                    "Setings"
+                ),
+                (
+                    "unknown logic type `Temprature`, did you mean `Temperature`?".to_string(),
+                    "Temprature"
+                ),
+                ("unknown logic type `ModPower`".to_string(), "ModPower"),
+            ]
+        );
+        let options = CompileOptions {
+            unknown_logic_types: true,
+            ..Default::default()
+        };
+        let program = ayysee_parser::parse(source).unwrap();
+        assert!(check_with(&program, &options).is_ok());
+    }
+
+    #[test]
+    fn test_constants() {
+        let source = "let x = LIMIT;\nconst LIMIT = 1;\nLIMIT = 2;\nSetting = 3;\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "constant `LIMIT` is used before its definition".to_string(),
+                    "LIMIT"
+                ),
+                ("cannot assign to constant `LIMIT`".to_string(), "LIMIT"),
+                ("cannot assign to constant `Setting`".to_string(), "Setting"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_definitions() {
+        let source = "const A = 1;\nalias A = d0;\nconst B = 2;\nfn f(a, b, a) { return a + b; }\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                ("constant `A` is defined more than once".to_string(), "A"),
+                ("parameter `a` is defined more than once".to_string(), "a"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alias() {
+        let source = "alias sensor = d0;\nlet x = sensor.Temperature;\nalias other = Temperature;\nsensor = d1;\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "`Temperature` is not a device pin".to_string(),
+                    "Temperature"
+                ),
+                ("cannot assign to constant `sensor`".to_string(), "sensor"),
+            ]
+        );
+        let (table, _) = resolve(&ayysee_parser::parse(source).unwrap());
+        let sensor = table.symbol_at(source.find("sensor.").unwrap()).unwrap().1;
+        assert_eq!(table.device(sensor), Some(Device::D0));
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = r"let outer = 1;
+fn f(a) { return a + outer; }
+fn f(b) { return b; }
+fn load(a) { return a; }
+let x = f(1, 2);
+store(d0, Setting);
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                ("function `f` is defined more than once".to_string(), "f"),
+                (
+                    "function `load` is already a builtin function".to_string(),
+                    "load"
+                ),
+                ("cannot find variable `outer`".to_string(), "outer"),
+                (
+                    "function `f` takes 1 argument but 2 were given".to_string(),
+                    "f(1, 2)"
+                ),
+                (
+                    "function `store` takes 3 arguments but 2 were given".to_string(),
+                    "store(d0, Setting);"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_types() {
+        let source = r#"alias sensor = d0;
+store(Setting, sensor, 1);
+let x = load_batch(hash("Sensor"), Temperature, Sum) + -Setting;
+let y = load(devices[x], 3) * d1;
+fn read(device, logic_type) { return load(device, logic_type); }
+let z = read(d0, Temperature) + load_reagent(d0, Contents, 0);
+"#;
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "expected a device pin, found logic type `Setting`".to_string(),
+                    "Setting"
+                ),
+                (
+                    "expected a logic type, found device pin `sensor`".to_string(),
+                    "sensor"
+                ),
+                (
+                    "expected a number, found logic type `Setting`".to_string(),
+                    "Setting"
+                ),
+                ("expected a logic type, found a number".to_string(), "3"),
+                ("expected a number, found device pin `d1`".to_string(), "d1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let source = r"break;
+loop {
+    if d0.On { break; }
+    continue;
+}
+fn f() { continue; }
+loop { f(); }
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "`break` can only be used inside a loop".to_string(),
+                    "break;"
+                ),
+                (
+                    "`continue` can only be used inside a loop".to_string(),
+                    "continue;"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_scopes() {
+        let source = r"let x = 1;
+if x > 0 {
+    x = 2;
+    let x = x + 1;
+    let x = x * 2;
+    let y = x;
+}
+db.Setting = x + y;
+";
+        assert_eq!(
+            errors(source),
+            vec![("cannot find variable `y`".to_string(), "y")]
+        );
+        let (table, _) = resolve(&ayysee_parser::parse(source).unwrap());
+        let at = |needle: &str, nth: usize| {
+            let offset = source.match_indices(needle).nth(nth).unwrap().0;
+            table.symbol_at(offset).unwrap().1
+        };
+        // Assigning writes the outer `x`, `let` shadows it until the end of the block.
+        let outer = at("x", 0);
+        assert_eq!(at("x", 2), outer);
+        assert_eq!(at("x", 4), outer);
+        let inner = at("x", 3);
+        assert_ne!(inner, outer);
+        assert_eq!(at("x", 5), inner);
+        assert_eq!(at("x", 6), inner);
+        assert_eq!(at("x", 7), inner);
+        assert_eq!(at("x", 8), outer);
+    }
+
+    #[test]
+    fn test_tuple_definition() {
+        let source = r"let (p, t) = (d0.Pressure, d0.Temperature);
+let (a, b) = (p, t, 1);
+let (c, d) = ([0; 4], p);
+db.Setting = p + t + a + b + c + d + e;
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "2 variables are defined from 3 values".to_string(),
+                    "let (a, b) = (p, t, 1);"
+                ),
+                (
+                    "arrays can only be declared with `let`, as in `let buf = [0; 8];`".to_string(),
+                    "[0; 4]"
+                ),
+                ("cannot find variable `e`".to_string(), "e"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        let source = r"outer: loop {
+    inner: for i in 0..3 {
+        if i == 1 { continue outer; }
+        break inner;
+    }
+    break other;
+}
+fn f() { break outer; }
+outer: loop { f(); }
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "`other` is not the label of a loop around this `break`".to_string(),
+                    "other"
+                ),
+                (
+                    "`outer` is not the label of a loop around this `break`".to_string(),
+                    "outer"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_import() {
+        let source = "import \"lib.ayy\";\nfn f() { import \"lib.ayy\"; }\n";
+        assert_eq!(
+            errors(source),
+            vec![(
+                "`import` can only be used at the top level of a file".to_string(),
+                "import \"lib.ayy\";"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_return_outside_function() {
+        let source = r"fn f(a) { if a { return 1; } return 2; }
+if d0.On { return f(1); }
+if d0.Mode { return; }
+fn main(a) { }
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "`return` outside a function ends the program and cannot return a value"
+                        .to_string(),
+                    "return f(1);"
+                ),
+                (
+                    "`main` runs when the program starts and cannot take parameters".to_string(),
+                    "main"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let source = r"const SENSOR = d0;
+/// The limit.
+const LIMIT = 10 * 2;
+fn f(a) { let x = a; return x; }
+let x = SENSOR.Temperature;
+x = f(LIMIT);
+";
+        let (table, errors) = resolve(&ayysee_parser::parse(source).unwrap());
+        assert_eq!(errors, vec![]);
+        let at = |needle: &str, nth: usize| {
+            let offset = source.match_indices(needle).nth(nth).unwrap().0;
+            table.symbol_at(offset).unwrap().1
+        };
+
+        let sensor = at("SENSOR", 1);
+        assert_eq!(sensor, at("SENSOR", 0));
+        assert_eq!(table.symbol(sensor).kind, SymbolKind::Constant);
+        assert_eq!(table.device(sensor), Some(Device::D0));
+        assert_eq!(
+            table.symbol(at("LIMIT", 1)).value,
+            Some(ConstValue::Number(20.0))
+        );
+        assert_eq!(
+            table.symbol(at("LIMIT", 1)).doc.as_deref(),
+            Some("The limit.")
+        );
+        assert_eq!(
+            table.symbol(at("Temperature", 0)).kind,
+            SymbolKind::External
+        );
+
+        // The `x` of the function is not the one of the program.
+        let x = at("x", 2);
+        assert_ne!(x, at("x", 0));
+        assert_eq!(table.references_to(x).count(), 2);
+        assert_eq!(table.symbol(x).scope, ScopeId(0));
+        assert_eq!(table.symbol(at("a", 1)).kind, SymbolKind::Parameter);
+        assert_eq!(table.symbol(at("x", 0)).scope, ScopeId(1));
+        assert_eq!(table.scopes[1].function, Some(at("f", 1)));
+        assert_eq!(
+            table.symbol(at("f(", 1)).kind,
+            SymbolKind::Function { parameters: 1 }
+        );
+        assert_eq!(table.lookup("x").count(), 2);
+    }
+
+    #[test]
+    fn test_arrays() {
+        let source = r"let buf = [0; 4];
+let n = 2;
+let bad = [0; n];
+buf[3] = buf[n] + buf[4];
+let x = buf;
+buf = 1;
+n[0] = 1;
+d0.Setting = [1; 2];
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "the length of an array must be a positive whole number known at compile time"
+                        .to_string(),
+                    "n"
+                ),
+                (
+                    "index 4 is out of bounds for array `buf` of length 4".to_string(),
+                    "4"
+                ),
+                (
+                    "array `buf` can only be used through its elements, as in `buf[0]`".to_string(),
+                    "buf"
+                ),
+                (
+                    "cannot assign to array `buf`, assign to its elements as in `buf[0] = ...;`"
+                        .to_string(),
+                    "buf"
+                ),
+                ("`n` is not an array".to_string(), "n"),
+                (
+                    "arrays can only be declared with `let`, as in `let buf = [0; 8];`".to_string(),
+                    "[1; 2]"
+                ),
+            ]
+        );
+        let (table, _) = resolve(&ayysee_parser::parse(source).unwrap());
+        let buf = table.symbol_at(source.find("buf[n]").unwrap()).unwrap().1;
+        assert_eq!(table.symbol(buf).kind, SymbolKind::Array { length: 4 });
+    }
+
+    #[test]
+    fn test_match_patterns() {
+        let source = r"const IDLE = 0;
+let mode = d0.Mode;
+match mode {
+    IDLE => { d1.On = 0; }
+    1 => { d1.On = 1; }
+    0 => { d1.On = 2; }
+    mode => { d1.On = 3; }
+}
+";
+        assert_eq!(
+            errors(source),
+            vec![
+                ("0 is already matched by an earlier arm".to_string(), "0"),
+                (
+                    "the pattern of a `match` arm must be a number known at compile time"
+                        .to_string(),
+                    "mode"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        let source = "let Setting = 1;\nconst d0 = 2;\nfn f(RatioOxygen) { return RatioOxygen; }\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                (
+                    "`Setting` is a logic type and cannot be declared, rename it (e.g. `setting`)"
+                        .to_string(),
+                    "Setting"
+                ),
+                (
+                    "`d0` is a device pin and cannot be declared, rename it (e.g. `d0_value`)"
+                        .to_string(),
+                    "d0"
+                ),
+                (
+                    "`RatioOxygen` is a logic type and cannot be declared, rename it (e.g. `ratio_oxygen`)"
+                        .to_string(),
+                    "RatioOxygen"
+                ),
+            ]
+        );
+    }
+}
@@ -1,19 +1,59 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
 
 use stationeers_mips::instructions::{
-    Arithmetic, DeviceIo, FlowControl, Instruction, Logic, Misc, VariableSelection,
+    Arithmetic, DeviceIo, FlowControl, Instruction, Logic, Misc, Stack, VariableSelection,
+};
+use stationeers_mips::types::{
+    BatchMode, Device, DeviceVariable, JumpDest, ReagentMode, Register, RegisterOrNumber, Slot,
+    TypeHash,
 };
-use stationeers_mips::types::{Device, DeviceVariable, JumpDest, Register, RegisterOrNumber};
 use stationeers_mips::Program;
 
+/// Maximum number of instructions the IC executes in a single tick.
+const INSTRUCTIONS_PER_TICK: usize = 127;
+/// Number of values the stack of the IC holds.
+pub(crate) const STACK_SIZE: usize = 512;
+
 pub struct Simulator {
     instructions: Vec<Instruction>,
     state: State,
+    /// Lines before which [`Simulator::tick`] stops.
+    breakpoints: BTreeSet<usize>,
 }
 
 struct State {
+    /// The line of the next instruction to execute.
+    pc: i32,
     registers: HashMap<Register, f64>,
+    stack: Vec<f64>,
+    /// The stacks of the devices on the pins, which `get` and `put` reach like that of the
+    /// IC through `db`.
+    memories: HashMap<Device, Vec<f64>>,
     devices: HashMap<Device, HashMap<DeviceVariable, f64>>,
+    /// The pins with no device plugged in. All are set until [`Simulator::set_connected`]
+    /// unplugs them.
+    disconnected: HashSet<Device>,
+    /// The variables of the items in the slots of devices, by device and slot index.
+    slots: HashMap<(Device, usize), HashMap<DeviceVariable, f64>>,
+    /// The amounts of reagents of devices, by device, reagent mode and reagent hash.
+    reagents: HashMap<(Device, ReagentMode, i64), f64>,
+    /// The devices on the data network of the IC, which batch instructions reach by prefab
+    /// hash.
+    network: Vec<NetworkDevice>,
+    executed: usize,
+    /// The state of the generator of the numbers `rand` returns.
+    rng: u64,
+    /// Instructions executed in the current tick.
+    executed_in_tick: usize,
+    fault: Option<Fault>,
+}
+
+struct NetworkDevice {
+    type_hash: f64,
+    /// The hash of the name given with a labeller, if any.
+    name_hash: Option<f64>,
+    variables: HashMap<DeviceVariable, f64>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -21,21 +61,141 @@ pub enum TickResult {
     Yield,
     LimitHit,
     End,
+    /// The tick was interrupted before a line with a breakpoint. The next call to
+    /// [`Simulator::tick`] or [`Simulator::step`] resumes it.
+    Breakpoint,
+    /// The program stopped with an error, as the IC does in game. It stays stopped, every
+    /// later tick reports the same fault.
+    Fault(Fault),
+    /// The program ran `hcf`. It stays stopped at that line, every later tick halts again.
+    Halted,
+}
+
+/// An error stopping the program. [`DebugInfo::statement`](crate::DebugInfo::statement)
+/// finds the statement of the source it was compiled from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fault {
+    /// The line of the instruction that failed.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn unsupported(ins: &impl std::fmt::Display) -> String {
+    format!("`{}` is not supported by the simulator", ins)
+}
+
+/// `a mod b` as the game computes it: unlike `%`, the result of a negative `a` is not
+/// negative when `b` is positive.
+pub(crate) fn modulo(a: f64, b: f64) -> f64 {
+    let x = a % b;
+    if x < 0.0 {
+        x + b
+    } else {
+        x
+    }
+}
+
+/// `f(a, b)`, or NaN when either operand is NaN.
+fn nan_or(a: f64, b: f64, f: fn(f64, f64) -> f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else {
+        f(a, b)
+    }
+}
+
+/// `a << b` on the integer part of `a`, 0 once every bit is shifted out.
+pub(crate) fn shift_left(a: f64, b: f64) -> f64 {
+    (a as i64).checked_shl(b as u32).unwrap_or(0) as f64
+}
+
+/// `a >> b` on the integer part of `a`, shifting zeros in like `srl`.
+pub(crate) fn shift_right(a: f64, b: f64) -> f64 {
+    (a as i64 as u64).checked_shr(b as u32).unwrap_or(0) as f64
 }
 
 impl Simulator {
+    /// Returns a simulator of the program, where `rand` returns different numbers at each
+    /// run, like in the game.
     pub fn new(program: Program) -> Self {
+        let seed = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        Self::with_seed(program, seed)
+    }
+
+    /// Returns a simulator of the program, where `rand` returns the same numbers for the
+    /// same seed, so that runs can be reproduced.
+    pub fn with_seed(program: Program, seed: u64) -> Self {
         Simulator {
             instructions: program.instructions,
             state: State {
+                pc: 0,
                 registers: HashMap::default(),
+                stack: vec![0.0; STACK_SIZE],
+                memories: HashMap::default(),
                 devices: HashMap::default(),
+                disconnected: HashSet::default(),
+                slots: HashMap::default(),
+                reagents: HashMap::default(),
+                network: Vec::new(),
+                executed: 0,
+                rng: seed,
+                executed_in_tick: 0,
+                fault: None,
             },
+            breakpoints: BTreeSet::new(),
         }
     }
 
+    /// Runs the program until the end of the tick, or until it reaches a breakpoint. The
+    /// line the tick resumes from never triggers a breakpoint.
     pub fn tick(&mut self) -> TickResult {
-        self.state.tick(&self.instructions)
+        loop {
+            if let Some(result) = self.step() {
+                return result;
+            }
+            if self.breakpoints.contains(&self.line()) {
+                return TickResult::Breakpoint;
+            }
+        }
+    }
+
+    /// Executes a single instruction, returning how the tick ended if it did.
+    pub fn step(&mut self) -> Option<TickResult> {
+        self.state.step(&self.instructions)
+    }
+
+    /// Returns the line of the next instruction to execute.
+    pub fn line(&self) -> usize {
+        self.state.pc.max(0) as usize
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn register(&self, r: Register) -> f64 {
+        self.state.read(&r.into())
+    }
+
+    /// Sets or removes the breakpoint at the line, returning whether it is now set.
+    pub fn toggle_breakpoint(&mut self, line: usize) -> bool {
+        if self.breakpoints.remove(&line) {
+            false
+        } else {
+            self.breakpoints.insert(line)
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
     }
 
     pub fn read(&self, d: Device, logic_type: DeviceVariable) -> f64 {
@@ -51,43 +211,155 @@ impl Simulator {
             .or_default()
             .insert(logic_type, v);
     }
+
+    /// Plugs a device into the pin or unplugs it. Accessing an unplugged pin is a fault,
+    /// while `sdse` and the `bdse` family test for it.
+    pub fn set_connected(&mut self, d: Device, connected: bool) {
+        if connected {
+            self.state.disconnected.remove(&d);
+        } else {
+            self.state.disconnected.insert(d);
+        }
+    }
+
+    pub fn read_slot(&self, d: Device, slot: usize, logic_type: DeviceVariable) -> f64 {
+        self.state
+            .slots
+            .get(&(d, slot))
+            .and_then(|x| x.get(&logic_type))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    pub fn write_slot(&mut self, d: Device, slot: usize, logic_type: DeviceVariable, v: f64) {
+        self.state
+            .slots
+            .entry((d, slot))
+            .or_default()
+            .insert(logic_type, v);
+    }
+
+    /// Sets the amount of the reagent with the hash `lr` reads from the device in the mode.
+    pub fn write_reagent(&mut self, d: Device, mode: ReagentMode, hash: f64, v: f64) {
+        self.state.reagents.insert((d, mode, hash as i64), v);
+    }
+
+    /// Adds a device of the prefab to the network, returning the index it is read and written
+    /// with.
+    pub fn connect(&mut self, type_hash: f64) -> usize {
+        self.connect_device(type_hash, None)
+    }
+
+    /// Adds a device of the prefab named with a labeller, which named batch instructions
+    /// reach by the hash of its name.
+    pub fn connect_named(&mut self, type_hash: f64, name_hash: f64) -> usize {
+        self.connect_device(type_hash, Some(name_hash))
+    }
+
+    fn connect_device(&mut self, type_hash: f64, name_hash: Option<f64>) -> usize {
+        self.state.network.push(NetworkDevice {
+            type_hash,
+            name_hash,
+            variables: HashMap::default(),
+        });
+        self.state.network.len() - 1
+    }
+
+    pub fn read_network(&self, index: usize, logic_type: DeviceVariable) -> f64 {
+        self.state.network[index]
+            .variables
+            .get(&logic_type)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    pub fn write_network(&mut self, index: usize, logic_type: DeviceVariable, v: f64) {
+        self.state.network[index].variables.insert(logic_type, v);
+    }
+
+    /// Returns the fault the program stopped with, if any.
+    pub fn fault(&self) -> Option<&Fault> {
+        self.state.fault.as_ref()
+    }
+
+    /// Returns the number of instructions executed since the simulation started.
+    pub fn executed(&self) -> usize {
+        self.state.executed
+    }
+
+    /// Returns the values on the stack, from the bottom up to the stack pointer.
+    pub fn stack(&self) -> &[f64] {
+        let sp = self
+            .register(Register::Sp)
+            .round()
+            .clamp(0.0, STACK_SIZE as f64);
+        &self.state.stack[..sp as usize]
+    }
+
+    /// Returns all devices the program or the caller interacted with, with their variables.
+    pub fn devices(&self) -> impl Iterator<Item = (Device, &HashMap<DeviceVariable, f64>)> {
+        self.state.devices.iter().map(|(d, vars)| (*d, vars))
+    }
 }
 
 impl State {
-    fn tick(&mut self, instructions: &[Instruction]) -> TickResult {
-        for _ in 0..127 {
-            let ins = match instructions.get(self.sp() as usize) {
-                Some(x) => x,
-                None => return TickResult::End,
-            };
-            println!("Executing `{}`", ins);
-            match ins {
-                Instruction::Arithmetic(x) => self.execute_arithmetic(&x),
-                Instruction::DeviceIo(x) => self.execute_deviceio(&x),
-                Instruction::Misc(Misc::Yield) => {
-                    self.set_sp(self.sp() + 1);
-                    return TickResult::Yield;
-                }
-                Instruction::Misc(x) => self.execute_misc(&x),
-                Instruction::VariableSelection(x) => self.execute_select(&x),
-                Instruction::FlowControl(x) => self.execute_flow(&x),
-                Instruction::Logic(x) => self.execute_logic(&x),
-                _ => todo!("{}", ins),
+    fn step(&mut self, instructions: &[Instruction]) -> Option<TickResult> {
+        let result = self.execute(instructions);
+        if result.is_none() && self.executed_in_tick < INSTRUCTIONS_PER_TICK {
+            return None;
+        }
+        self.executed_in_tick = 0;
+        Some(result.unwrap_or(TickResult::LimitHit))
+    }
+
+    /// Executes the instruction at the current line, returning how the tick ended if it did.
+    fn execute(&mut self, instructions: &[Instruction]) -> Option<TickResult> {
+        if let Some(fault) = &self.fault {
+            return Some(TickResult::Fault(fault.clone()));
+        }
+        let line = self.pc as usize;
+        let ins = match instructions.get(line) {
+            Some(x) => x,
+            None => return Some(TickResult::End),
+        };
+        tracing::trace!("Executing `{}`", ins);
+        self.executed += 1;
+        self.executed_in_tick += 1;
+        let result = match ins {
+            Instruction::Arithmetic(x) => self.execute_arithmetic(x),
+            Instruction::DeviceIo(x) => self.execute_deviceio(x),
+            Instruction::Misc(Misc::Yield) => {
+                self.pc += 1;
+                return Some(TickResult::Yield);
             }
-            self.set_sp(self.sp() + 1);
+            Instruction::Misc(Misc::Halt) => return Some(TickResult::Halted),
+            Instruction::Misc(x) => self.execute_misc(x),
+            Instruction::VariableSelection(x) => self.execute_select(x),
+            Instruction::FlowControl(x) => self.execute_flow(x),
+            Instruction::Logic(x) => self.execute_logic(x),
+            Instruction::Stack(x) => self.execute_stack(x),
+        };
+        if let Err(message) = result {
+            return Some(self.raise(line, message));
+        }
+        self.pc += 1;
+        if self.pc < 0 {
+            return Some(self.raise(line, format!("jump to line {}", self.pc)));
         }
-        return TickResult::LimitHit;
+        None
     }
 
-    fn sp(&self) -> i32 {
-        self.registers
-            .get(&Register::Sp)
-            .copied()
-            .unwrap_or(0.0)
-            .round() as i32
+    /// Stops the program at the line, which stays at the fault until it is reset.
+    fn raise(&mut self, line: usize, message: String) -> TickResult {
+        let fault = Fault { line, message };
+        self.fault = Some(fault.clone());
+        self.pc = line as i32;
+        TickResult::Fault(fault)
     }
-    fn set_sp(&mut self, sp: i32) {
-        self.registers.insert(Register::Sp, sp as f64);
+
+    /// Moves to the line, which is executed next.
+    fn jump(&mut self, line: f64) {
+        self.pc = line.round() as i32 - 1;
     }
 
     fn read(&self, r: &RegisterOrNumber) -> f64 {
@@ -97,25 +369,111 @@ impl State {
         }
     }
 
-    fn read_bool(&self, v: &RegisterOrNumber) -> bool {
-        self.read(v) != 0.0
+    /// Returns the variables of the item in the slot of the device.
+    fn slot(
+        &mut self,
+        device: Device,
+        slot: &Slot,
+    ) -> Result<&mut HashMap<DeviceVariable, f64>, String> {
+        let device = self.pin(device)?;
+        let index = self.read(slot.index());
+        if index < 0.0 || index.fract() != 0.0 {
+            return Err(format!("slot index {} out of range", index));
+        }
+        Ok(self.slots.entry((device, index as usize)).or_default())
+    }
+
+    /// Reads the operand of a hash, a number or a register.
+    fn read_hash(&self, hash: &TypeHash) -> Result<f64, String> {
+        hash.to_string()
+            .parse()
+            .map(|hash| self.read(&hash))
+            .map_err(|_| format!("`{}` is not a hash", hash))
     }
 
-    fn execute_logic(&mut self, ins: &Logic) {
+    /// Returns the devices on the network with the prefab hash, and the name hash if any.
+    fn batch<'a>(
+        &'a mut self,
+        type_hash: &TypeHash,
+        name_hash: Option<&TypeHash>,
+    ) -> Result<impl Iterator<Item = &'a mut NetworkDevice>, String> {
+        let type_hash = self.read_hash(type_hash)?;
+        let name_hash = name_hash.map(|hash| self.read_hash(hash)).transpose()?;
+        Ok(self.network.iter_mut().filter(move |device| {
+            device.type_hash == type_hash && name_hash.is_none_or(|h| device.name_hash == Some(h))
+        }))
+    }
+
+    /// Loads a variable of the devices of a batch, combined by the mode.
+    fn load_batch(
+        &mut self,
+        register: Register,
+        type_hash: &TypeHash,
+        name_hash: Option<&TypeHash>,
+        variable: &DeviceVariable,
+        batch_mode: &BatchMode,
+    ) -> Result<(), String> {
+        let values = self
+            .batch(type_hash, name_hash)?
+            .map(|device| device.variables.get(variable).copied().unwrap_or_default())
+            .collect::<Vec<_>>();
+        // Like in the game, no device reads as 0 whatever the mode.
+        let value = match batch_mode {
+            _ if values.is_empty() => 0.0,
+            BatchMode::Average => values.iter().sum::<f64>() / values.len() as f64,
+            BatchMode::Sum => values.iter().sum(),
+            BatchMode::Minimum => values.iter().copied().fold(f64::INFINITY, f64::min),
+            BatchMode::Maximum => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        };
+        self.registers.insert(register, value);
+        Ok(())
+    }
+
+    fn store_batch(
+        &mut self,
+        type_hash: &TypeHash,
+        name_hash: Option<&TypeHash>,
+        variable: &DeviceVariable,
+        register: &RegisterOrNumber,
+    ) -> Result<(), String> {
+        let value = self.read(register);
+        for device in self.batch(type_hash, name_hash)? {
+            device.variables.insert(variable.clone(), value);
+        }
+        Ok(())
+    }
+
+    fn execute_logic(&mut self, ins: &Logic) -> Result<(), String> {
         match &ins {
             Logic::And { register, a, b } => {
-                self.registers
-                    .insert(*register, (self.read_bool(a) && self.read_bool(b)).into());
+                let x = (self.read(a) as i64) & (self.read(b) as i64);
+                self.registers.insert(*register, x as f64);
             }
             Logic::Or { register, a, b } => {
-                self.registers
-                    .insert(*register, (self.read_bool(a) || self.read_bool(b)).into());
+                let x = (self.read(a) as i64) | (self.read(b) as i64);
+                self.registers.insert(*register, x as f64);
+            }
+            Logic::Xor { register, a, b } => {
+                let x = (self.read(a) as i64) ^ (self.read(b) as i64);
+                self.registers.insert(*register, x as f64);
+            }
+            Logic::Nor { register, a, b } => {
+                let x = !((self.read(a) as i64) | (self.read(b) as i64));
+                self.registers.insert(*register, x as f64);
+            }
+            Logic::ShiftLeftLogical { register, a, b } => {
+                let x = shift_left(self.read(a), self.read(b));
+                self.registers.insert(*register, x);
+            }
+            Logic::ShiftRightLogical { register, a, b } => {
+                let x = shift_right(self.read(a), self.read(b));
+                self.registers.insert(*register, x);
             }
-            _ => todo!(),
         }
+        Ok(())
     }
 
-    fn execute_arithmetic(&mut self, ins: &Arithmetic) {
+    fn execute_arithmetic(&mut self, ins: &Arithmetic) -> Result<(), String> {
         match &ins {
             Arithmetic::Add { register, a, b } => {
                 self.registers
@@ -133,19 +491,161 @@ impl State {
                 self.registers
                     .insert(*register, self.read(a) - self.read(b));
             }
-            _ => todo!(),
+            // Unlike `f64::max`, the game's `Math.Max` is NaN when either operand is.
+            Arithmetic::Maximum { register, a, b } => {
+                self.registers
+                    .insert(*register, nan_or(self.read(a), self.read(b), f64::max));
+            }
+            Arithmetic::Minimum { register, a, b } => {
+                self.registers
+                    .insert(*register, nan_or(self.read(a), self.read(b), f64::min));
+            }
+            Arithmetic::AbsoluteValue { register, a } => {
+                self.registers.insert(*register, self.read(a).abs());
+            }
+            Arithmetic::SquareRoot { register, a } => {
+                self.registers.insert(*register, self.read(a).sqrt());
+            }
+            Arithmetic::Floor { register, a } => {
+                self.registers.insert(*register, self.read(a).floor());
+            }
+            Arithmetic::Ceiling { register, a } => {
+                self.registers.insert(*register, self.read(a).ceil());
+            }
+            // The game rounds halfway values to even, as C#'s `Math.Round` does.
+            Arithmetic::Round { register, a } => {
+                self.registers
+                    .insert(*register, self.read(a).round_ties_even());
+            }
+            Arithmetic::Truncate { register, a } => {
+                self.registers.insert(*register, self.read(a).trunc());
+            }
+            Arithmetic::Sine { register, a } => {
+                self.registers.insert(*register, self.read(a).sin());
+            }
+            Arithmetic::Cosine { register, a } => {
+                self.registers.insert(*register, self.read(a).cos());
+            }
+            Arithmetic::Tangent { register, a } => {
+                self.registers.insert(*register, self.read(a).tan());
+            }
+            Arithmetic::ArcSine { register, a } => {
+                self.registers.insert(*register, self.read(a).asin());
+            }
+            Arithmetic::ArcCosine { register, a } => {
+                self.registers.insert(*register, self.read(a).acos());
+            }
+            Arithmetic::ArcTangent { register, a } => {
+                self.registers.insert(*register, self.read(a).atan());
+            }
+            Arithmetic::ArcTangent2 { register, a, b } => {
+                self.registers
+                    .insert(*register, self.read(a).atan2(self.read(b)));
+            }
+            Arithmetic::Mod { register, a, b } => {
+                self.registers
+                    .insert(*register, modulo(self.read(a), self.read(b)));
+            }
+            Arithmetic::Exponent { register, a } => {
+                self.registers.insert(*register, self.read(a).exp());
+            }
+            Arithmetic::Logarithm { register, a } => {
+                self.registers.insert(*register, self.read(a).ln());
+            }
+            Arithmetic::Random { register } => {
+                let x = self.random();
+                self.registers.insert(*register, x);
+            }
         }
+        Ok(())
+    }
+    /// Returns the next number of the generator, from 0 up to 1 excluded, with SplitMix64.
+    fn random(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn is_set(&self, d: Device) -> bool {
+        !self.disconnected.contains(&d)
     }
-    fn execute_deviceio(&mut self, ins: &DeviceIo) {
+
+    /// Returns the pin a device operand refers to, reading the number of `dr?` from its
+    /// register.
+    fn pin(&self, device: Device) -> Result<Device, String> {
+        match device {
+            Device::Indirect(register) => {
+                let index = self.read(&register.into());
+                Device::PINS
+                    .get(index as usize)
+                    .filter(|_| index >= 0.0 && index.fract() == 0.0)
+                    .copied()
+                    .ok_or_else(|| format!("device index {} out of range", index))
+            }
+            device => Ok(device),
+        }
+    }
+
+    fn execute_deviceio(&mut self, ins: &DeviceIo) -> Result<(), String> {
+        let accessed = match ins {
+            DeviceIo::LoadDeviceVariable { device, .. }
+            | DeviceIo::StoreDeviceVariable { device, .. }
+            | DeviceIo::LoadSlot { device, .. }
+            | DeviceIo::StoreSlot { device, .. }
+            | DeviceIo::LoadReagent { device, .. } => Some(*device),
+            _ => None,
+        };
+        if let Some(device) = accessed {
+            let device = self.pin(device)?;
+            if !self.is_set(device) {
+                return Err(format!("device {} is not set", device));
+            }
+        }
         match &ins {
+            DeviceIo::BranchDeviceSet { device, line } => {
+                if self.is_set(self.pin(*device)?) {
+                    self.jump(self.read(line));
+                }
+            }
+            DeviceIo::BranchDeviceNotSet { device, line } => {
+                if !self.is_set(self.pin(*device)?) {
+                    self.jump(self.read(line));
+                }
+            }
+            DeviceIo::BranchDeviceSetAndLink { device, line } => {
+                if self.is_set(self.pin(*device)?) {
+                    self.registers.insert(Register::Ra, (self.pc + 1) as f64);
+                    self.jump(self.read(line));
+                }
+            }
+            DeviceIo::BranchDeviceNotSetAndLink { device, line } => {
+                if !self.is_set(self.pin(*device)?) {
+                    self.registers.insert(Register::Ra, (self.pc + 1) as f64);
+                    self.jump(self.read(line));
+                }
+            }
+            DeviceIo::BranchRelativeDeviceSet { device, line } => {
+                if self.is_set(self.pin(*device)?) {
+                    self.jump(self.pc as f64 + self.read(line));
+                }
+            }
+            DeviceIo::BranchRelativeDeviceNotSet { device, line } => {
+                if !self.is_set(self.pin(*device)?) {
+                    self.jump(self.pc as f64 + self.read(line));
+                }
+            }
             DeviceIo::StoreDeviceVariable {
                 device,
                 variable,
                 register,
             } => {
                 let value: f64 = self.read(register);
+                let device = self.pin(*device)?;
                 self.devices
-                    .entry(device.clone())
+                    .entry(device)
                     .or_default()
                     .insert(variable.clone(), value);
             }
@@ -154,30 +654,97 @@ impl State {
                 device,
                 variable,
             } => {
+                let device = self.pin(*device)?;
                 let value = self
                     .devices
-                    .entry(device.clone())
+                    .entry(device)
                     .or_default()
-                    .get(&variable)
+                    .get(variable)
                     .copied()
                     .unwrap_or_default();
-                self.registers.insert(register.clone(), value);
+                self.registers.insert(*register, value);
             }
-            _ => todo!(),
+            DeviceIo::LoadSlot {
+                register,
+                device,
+                slot,
+                variable,
+            } => {
+                let value = self
+                    .slot(*device, slot)?
+                    .get(variable)
+                    .copied()
+                    .unwrap_or_default();
+                self.registers.insert(*register, value);
+            }
+            DeviceIo::StoreSlot {
+                device,
+                slot,
+                variable,
+                register,
+            } => {
+                let value = self.read(register);
+                self.slot(*device, slot)?.insert(variable.clone(), value);
+            }
+            DeviceIo::LoadReagent {
+                register,
+                device,
+                reagent_mode,
+                reagent,
+            } => {
+                let hash = self.read(reagent.hash()) as i64;
+                let device = self.pin(*device)?;
+                let value = self
+                    .reagents
+                    .get(&(device, *reagent_mode, hash))
+                    .copied()
+                    .unwrap_or_default();
+                self.registers.insert(*register, value);
+            }
+            DeviceIo::LoadBatch {
+                register,
+                type_hash,
+                variable,
+                batch_mode,
+            } => self.load_batch(*register, type_hash, None, variable, batch_mode)?,
+            DeviceIo::LoadBatchNamed {
+                register,
+                type_hash,
+                name_hash,
+                variable,
+                batch_mode,
+            } => self.load_batch(*register, type_hash, Some(name_hash), variable, batch_mode)?,
+            DeviceIo::StoreBatch {
+                type_hash,
+                variable,
+                register,
+            } => self.store_batch(type_hash, None, variable, register)?,
+            DeviceIo::StoreBatchNamed {
+                type_hash,
+                name_hash,
+                variable,
+                register,
+            } => self.store_batch(type_hash, Some(name_hash), variable, register)?,
         }
+        Ok(())
     }
-    fn execute_misc(&mut self, ins: &Misc) {
+    fn execute_misc(&mut self, ins: &Misc) -> Result<(), String> {
         match &ins {
             Misc::Move { register, a } => match a {
                 stationeers_mips::types::RegisterOrNumber::Number(x) => {
                     self.registers.insert(*register, *x);
                 }
-                _ => todo!(),
+                stationeers_mips::types::RegisterOrNumber::Register(_) => {
+                    self.registers.insert(*register, self.read(a));
+                }
             },
-            _ => todo!(),
+            // Operands are registers and pins already, the names are for people.
+            Misc::Alias { .. } | Misc::Comment { .. } => (),
+            _ => return Err(unsupported(ins)),
         }
+        Ok(())
     }
-    fn execute_select(&mut self, ins: &VariableSelection) {
+    fn execute_select(&mut self, ins: &VariableSelection) -> Result<(), String> {
         match ins {
             VariableSelection::SelectApproximatelyEqual { register, a, b, c } => {
                 self.registers.insert(
@@ -269,6 +836,14 @@ impl State {
                     },
                 );
             }
+            VariableSelection::SelectNaN { register, a } => {
+                self.registers
+                    .insert(*register, self.read(a).is_nan() as i32 as f64);
+            }
+            VariableSelection::SelectNotNaN { register, a } => {
+                self.registers
+                    .insert(*register, !self.read(a).is_nan() as i32 as f64);
+            }
             VariableSelection::SelectNotEqual { register, a, b } => {
                 self.registers
                     .insert(*register, (self.read(a) != self.read(b)) as i32 as f64);
@@ -277,30 +852,324 @@ impl State {
                 self.registers
                     .insert(*register, (self.read(a) != 0.0) as i32 as f64);
             }
-            _ => todo!(),
+            VariableSelection::SelectDeviceSet { register, d } => {
+                let set = self.is_set(self.pin(*d)?);
+                self.registers.insert(*register, set.into());
+            }
+            VariableSelection::SelectDeviceNotSet { register, d } => {
+                let set = self.is_set(self.pin(*d)?);
+                self.registers.insert(*register, (!set).into());
+            }
         }
+        Ok(())
     }
-    fn execute_flow(&mut self, ins: &FlowControl) {
+    fn execute_stack(&mut self, ins: &Stack) -> Result<(), String> {
+        let sp = self.read(&Register::Sp.into()).round() as i64;
+        match ins {
+            Stack::Push { a } => {
+                let slot = usize::try_from(sp)
+                    .ok()
+                    .filter(|sp| *sp < STACK_SIZE)
+                    .ok_or("stack overflow")?;
+                self.stack[slot] = self.read(a);
+                self.registers.insert(Register::Sp, (sp + 1) as f64);
+            }
+            Stack::Pop { register } | Stack::Peek { register } => {
+                let slot = usize::try_from(sp - 1)
+                    .ok()
+                    .filter(|sp| *sp < STACK_SIZE)
+                    .ok_or("stack underflow")?;
+                self.registers.insert(*register, self.stack[slot]);
+                if let Stack::Pop { .. } = ins {
+                    self.registers.insert(Register::Sp, (sp - 1) as f64);
+                }
+            }
+            Stack::Get {
+                register,
+                device,
+                address,
+            } => {
+                let address = self.read(address);
+                let value = *self.memory(*device, address)?;
+                self.registers.insert(*register, value);
+            }
+            Stack::Put {
+                device,
+                address,
+                value,
+            } => {
+                let value = self.read(value);
+                let address = self.read(address);
+                *self.memory(*device, address)? = value;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value at an address of the stack of a device, the stack of the IC for
+    /// `db`.
+    fn memory(&mut self, device: Device, address: f64) -> Result<&mut f64, String> {
+        let device = self.pin(device)?;
+        if !self.is_set(device) {
+            return Err(format!("device {} is not set", device));
+        }
+        let address = address.round();
+        if !(0.0..STACK_SIZE as f64).contains(&address) {
+            return Err(format!("stack address {} out of range", address));
+        }
+        let stack = match device {
+            Device::Db => &mut self.stack,
+            device => self
+                .memories
+                .entry(device)
+                .or_insert_with(|| vec![0.0; STACK_SIZE]),
+        };
+        Ok(&mut stack[address as usize])
+    }
+
+    fn execute_flow(&mut self, ins: &FlowControl) -> Result<(), String> {
         match ins {
             FlowControl::BranchEqualZero { a, b } => {
                 if self.read(a) == 0.0 {
-                    let idx = self.read(b) as i32;
-                    self.registers.insert(Register::Sp, (idx - 1) as f64);
+                    self.jump(self.read(b));
                 }
             }
+            FlowControl::BranchEqual { a, b, c } => self.branch_if(c, |a, b| a == b, a, b),
+            FlowControl::BranchNotEqual { a, b, c } => self.branch_if(c, |a, b| a != b, a, b),
+            FlowControl::BranchGreaterThan { a, b, c } => self.branch_if(c, |a, b| a > b, a, b),
+            FlowControl::BranchGreaterOrEqual { a, b, c } => self.branch_if(c, |a, b| a >= b, a, b),
+            FlowControl::BranchLessThan { a, b, c } => self.branch_if(c, |a, b| a < b, a, b),
+            FlowControl::BranchLessOrEqual { a, b, c } => self.branch_if(c, |a, b| a <= b, a, b),
             FlowControl::Jump { a } => {
                 match a {
-                    JumpDest::Label(_) => unimplemented!(),
-                    JumpDest::Register(r) => {
-                        self.registers
-                            .insert(Register::Sp, self.read(&(r.clone().into())) - 1.0);
-                    }
-                    JumpDest::Number(a) => {
-                        self.registers.insert(Register::Sp, a - 1.0);
-                    }
+                    JumpDest::Label(_) => return Err(unsupported(ins)),
+                    JumpDest::Register(r) => self.jump(self.read(&(*r).into())),
+                    JumpDest::Number(a) => self.jump(*a),
                 };
             }
-            _ => todo!(),
+            FlowControl::JumpAndLink { a } => {
+                self.registers.insert(Register::Ra, (self.pc + 1) as f64);
+                self.jump(*a as f64);
+            }
+            _ => return Err(unsupported(ins)),
+        }
+        Ok(())
+    }
+
+    fn branch_if(
+        &mut self,
+        target: &RegisterOrNumber,
+        cond: impl Fn(f64, f64) -> bool,
+        a: &RegisterOrNumber,
+        b: &RegisterOrNumber,
+    ) {
+        if cond(self.read(a), self.read(b)) {
+            self.jump(self.read(target));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulator(source: &str) -> Simulator {
+        Simulator::new(source.parse().unwrap())
+    }
+
+    #[test]
+    fn test_step() {
+        let mut simulator = simulator("move r0 1\nadd r0 r0 1\nyield\n");
+        assert_eq!(simulator.step(), None);
+        assert_eq!(simulator.register(Register::R0), 1.0);
+        assert_eq!(simulator.line(), 1);
+        assert_eq!(simulator.step(), None);
+        assert_eq!(simulator.step(), Some(TickResult::Yield));
+        assert_eq!(simulator.register(Register::R0), 2.0);
+        assert_eq!(simulator.step(), Some(TickResult::End));
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut simulator = simulator("move r0 1\nmove r0 2\nyield\nj 0\n");
+        assert!(simulator.toggle_breakpoint(1));
+        assert_eq!(simulator.tick(), TickResult::Breakpoint);
+        assert_eq!(simulator.register(Register::R0), 1.0);
+        // Resuming executes the line with the breakpoint.
+        assert_eq!(simulator.tick(), TickResult::Yield);
+        assert_eq!(simulator.register(Register::R0), 2.0);
+        assert!(!simulator.toggle_breakpoint(1));
+        assert_eq!(simulator.tick(), TickResult::Yield);
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut sleep = simulator("move r0 4\nsleep 1\nyield\n");
+        let fault = Fault {
+            line: 1,
+            message: "`sleep 1` is not supported by the simulator".to_string(),
+        };
+        assert_eq!(sleep.tick(), TickResult::Fault(fault.clone()));
+        assert_eq!(sleep.line(), 1);
+        // The program stays stopped.
+        assert_eq!(sleep.tick(), TickResult::Fault(fault.clone()));
+        assert_eq!(sleep.fault(), Some(&fault));
+
+        let mut jump = simulator("j -1\n");
+        assert_eq!(
+            jump.tick(),
+            TickResult::Fault(Fault {
+                line: 0,
+                message: "jump to line -1".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_halt() {
+        let mut simulator = simulator("move r0 1\nhcf\nmove r0 2\n");
+        assert_eq!(simulator.tick(), TickResult::Halted);
+        assert_eq!(simulator.line(), 1);
+        assert_eq!(simulator.tick(), TickResult::Halted);
+        assert_eq!(simulator.register(Register::R0), 1.0);
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut simulator = simulator(
+            "lb r0 -42 Pressure Average\nlb r1 -42 Pressure Sum\nlb r2 -42 Pressure Minimum\n\
+             lb r3 -42 Pressure Maximum\nlb r4 7 Pressure Maximum\nmove r5 -42\nsb r5 On 1\n",
+        );
+        let a = simulator.connect(-42.0);
+        let b = simulator.connect(-42.0);
+        let other = simulator.connect(7.0);
+        simulator.write_network(a, DeviceVariable::Pressure, 100.0);
+        simulator.write_network(b, DeviceVariable::Pressure, 300.0);
+        simulator.write_network(other, DeviceVariable::Pressure, 1000.0);
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.register(Register::R0), 200.0);
+        assert_eq!(simulator.register(Register::R1), 400.0);
+        assert_eq!(simulator.register(Register::R2), 100.0);
+        assert_eq!(simulator.register(Register::R3), 300.0);
+        assert_eq!(simulator.register(Register::R4), 1000.0);
+        assert_eq!(simulator.read_network(a, DeviceVariable::On), 1.0);
+        assert_eq!(simulator.read_network(b, DeviceVariable::On), 1.0);
+        assert_eq!(simulator.read_network(other, DeviceVariable::On), 0.0);
+
+        // No device of the prefab reads as 0.
+        let mut empty = self::simulator("lb r0 5 Pressure Minimum\n");
+        empty.tick();
+        assert_eq!(empty.register(Register::R0), 0.0);
+    }
+
+    #[test]
+    fn test_slots() {
+        let mut simulator =
+            simulator("ls r0 d0 r1 Quantity\nss d0 1 Lock r0\nmove r1 -1\nls r2 d0 r1 Quantity\n");
+        simulator.write_slot(Device::D0, 0, DeviceVariable::Quantity, 5.0);
+        assert!(matches!(simulator.tick(), TickResult::Fault(_)));
+        assert_eq!(simulator.register(Register::R0), 5.0);
+        assert_eq!(
+            simulator.read_slot(Device::D0, 1, DeviceVariable::Lock),
+            5.0
+        );
+        assert_eq!(simulator.fault().unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_reagents() {
+        let mut simulator =
+            simulator("lr r0 d0 Contents -654790771\nmove r1 7\nlr r1 d0 Recipe r1\n");
+        simulator.write_reagent(Device::D0, ReagentMode::Contents, -654790771.0, 3.0);
+        simulator.write_reagent(Device::D0, ReagentMode::Recipe, 7.0, 2.0);
+        simulator.write_reagent(Device::D1, ReagentMode::Recipe, 7.0, 5.0);
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.register(Register::R0), 3.0);
+        assert_eq!(simulator.register(Register::R1), 2.0);
+    }
+
+    #[test]
+    fn test_device_presence() {
+        let mut simulator = simulator(
+            "sdse r0 d0\nsdns r1 d0\nbrdns d0 2\nmove r2 1\nbdse d1 6\nmove r3 1\nmove r4 1\n\
+             l r5 d0 On\n",
+        );
+        simulator.set_connected(Device::D0, false);
+        let fault = match simulator.tick() {
+            TickResult::Fault(fault) => fault,
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(fault.line, 7);
+        assert_eq!(fault.message, "device d0 is not set");
+        assert_eq!(simulator.register(Register::R0), 0.0);
+        assert_eq!(simulator.register(Register::R1), 1.0);
+        // Both branches jumped over the `move` after them.
+        assert_eq!(simulator.register(Register::R2), 0.0);
+        assert_eq!(simulator.register(Register::R3), 0.0);
+        assert_eq!(simulator.register(Register::R4), 1.0);
+    }
+
+    #[test]
+    fn test_batch_named() {
+        let mut simulator = simulator(
+            "lbn r0 -42 5 Pressure Sum\nlbn r1 -42 6 Pressure Sum\nsbn -42 6 On 1\nsb -42 Lock 1\n",
+        );
+        let first = simulator.connect_named(-42.0, 5.0);
+        let second = simulator.connect_named(-42.0, 6.0);
+        let unnamed = simulator.connect(-42.0);
+        for (device, pressure) in [(first, 100.0), (second, 200.0), (unnamed, 400.0)] {
+            simulator.write_network(device, DeviceVariable::Pressure, pressure);
         }
+        assert_eq!(simulator.tick(), TickResult::End);
+        assert_eq!(simulator.register(Register::R0), 100.0);
+        assert_eq!(simulator.register(Register::R1), 200.0);
+        assert_eq!(simulator.read_network(first, DeviceVariable::On), 0.0);
+        assert_eq!(simulator.read_network(second, DeviceVariable::On), 1.0);
+        assert_eq!(simulator.read_network(unnamed, DeviceVariable::On), 0.0);
+        // Unnamed batch instructions reach named devices too.
+        assert_eq!(simulator.read_network(first, DeviceVariable::Lock), 1.0);
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut call =
+            simulator("push 3\njal 5\npop r0\nyield\nj 9\npop r1\nmul r1 r1 2\npush r1\nj ra\n");
+        assert_eq!(call.tick(), TickResult::Yield);
+        assert_eq!(call.register(Register::R0), 6.0);
+        assert_eq!(call.register(Register::Ra), 2.0);
+        assert_eq!(call.register(Register::Sp), 0.0);
+
+        let mut underflow = simulator("pop r0\n");
+        assert_eq!(
+            underflow.tick(),
+            TickResult::Fault(Fault {
+                line: 0,
+                message: "stack underflow".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_addresses() {
+        let mut simulator =
+            simulator("push 4\nput db 511 7\nget r0 db 0\nget r1 db 511\nput d0 0 2\nget r2 d0 0\nget r3 db 512\n");
+        let fault = match simulator.tick() {
+            TickResult::Fault(fault) => fault,
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(fault.line, 6);
+        assert_eq!(fault.message, "stack address 512 out of range");
+        assert_eq!(simulator.register(Register::R0), 4.0);
+        assert_eq!(simulator.register(Register::R1), 7.0);
+        // The stack of the device is not that of the IC.
+        assert_eq!(simulator.register(Register::R2), 2.0);
+        assert_eq!(simulator.stack(), [4.0]);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut simulator = simulator("add r0 r0 1\nj 0\n");
+        assert_eq!(simulator.tick(), TickResult::LimitHit);
+        assert_eq!(simulator.executed(), INSTRUCTIONS_PER_TICK);
     }
 }
@@ -0,0 +1,70 @@
+use stationeers_mips as mips;
+
+/// Version of the instruction set understood by a chip.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum InstructionSet {
+    /// Only the basic instructions, without the two operand compare-and-branch family
+    /// (`beq`, `bgt`, ...). Used by older game versions and some modded chips.
+    Legacy,
+    /// The instruction set of the current game version.
+    #[default]
+    Current,
+}
+
+/// The limits of the chip a program is compiled for.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Target {
+    pub name: String,
+    /// Maximum number of lines the chip executes.
+    pub max_lines: usize,
+    /// Number of general purpose registers, starting at `r0`.
+    pub registers: usize,
+    pub instruction_set: InstructionSet,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self::ic10()
+    }
+}
+
+impl Target {
+    /// Names of the built-in targets, accepted by [`Target::builtin`].
+    pub const BUILTIN: &'static [&'static str] = &["ic10", "ic10-legacy"];
+
+    /// The IC10 chip of the current game version.
+    pub fn ic10() -> Self {
+        Self {
+            name: "ic10".to_string(),
+            max_lines: 128,
+            registers: 16,
+            instruction_set: InstructionSet::Current,
+        }
+    }
+
+    /// Returns the built-in target with the given name.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "ic10" => Some(Self::ic10()),
+            "ic10-legacy" => Some(Self {
+                name: name.to_string(),
+                instruction_set: InstructionSet::Legacy,
+                ..Self::ic10()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Checks that the compiled program fits on the chip.
+    pub fn verify(&self, program: &mips::Program) -> anyhow::Result<()> {
+        let lines = program.instructions.len();
+        anyhow::ensure!(
+            lines <= self.max_lines,
+            "the program has {} lines, but {} runs at most {}",
+            lines,
+            self.name,
+            self.max_lines
+        );
+        Ok(())
+    }
+}
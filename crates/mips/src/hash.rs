@@ -0,0 +1,40 @@
+//! The hashes the game identifies prefabs and device names by.
+
+/// Returns the hash of a prefab or device name, the CRC32 of the name read as a signed
+/// number, as the game shows it and `HASH("...")` computes it.
+pub fn hash(name: &str) -> i32 {
+    crc32(name.as_bytes()) as i32
+}
+
+/// Returns the CRC32 (IEEE) checksum of the bytes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(hash("StructureGasSensor"), -1252983604);
+        assert_eq!(hash("StructureVendingMachine"), -443130773);
+    }
+}
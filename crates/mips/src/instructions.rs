@@ -16,6 +16,17 @@ pub use variable::VariableSelection;
 
 use crate::error::Error;
 
+/// Parses the next operand of the instruction `s`.
+fn operand<'a, T: std::str::FromStr>(
+    parts: &mut impl Iterator<Item = &'a str>,
+    s: &str,
+) -> Result<T, Error> {
+    parts
+        .next()
+        .and_then(|part| part.parse().ok())
+        .ok_or_else(|| Error::ParseError(s.to_string()))
+}
+
 #[derive(Default, Clone)]
 pub struct Program {
     pub instructions: Vec<Instruction>,
@@ -126,12 +137,65 @@ impl std::str::FromStr for Instruction {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Ok(device_io) = s.parse::<DeviceIo>() {
             Ok(Instruction::DeviceIo(device_io))
-        } else if let Ok(misc) = s.parse::<Misc>() {
-            Ok(Instruction::Misc(misc))
+        } else if let Ok(flow_control) = s.parse::<FlowControl>() {
+            Ok(Instruction::FlowControl(flow_control))
+        } else if let Ok(variable_selection) = s.parse::<VariableSelection>() {
+            Ok(Instruction::VariableSelection(variable_selection))
         } else if let Ok(a) = s.parse::<Arithmetic>() {
             Ok(Instruction::Arithmetic(a))
+        } else if let Ok(logic) = s.parse::<Logic>() {
+            Ok(Instruction::Logic(logic))
+        } else if let Ok(stack) = s.parse::<Stack>() {
+            Ok(Instruction::Stack(stack))
+        } else if let Ok(misc) = s.parse::<Misc>() {
+            Ok(Instruction::Misc(misc))
         } else {
             Err(Error::ParseError(s.to_string()))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_roundtrip() {
+        let lines = [
+            "l r0 d0 Temperature",
+            "s db Setting r1",
+            "beq r0 5 12",
+            "bgtzal r2 3",
+            "j loop",
+            "j ra",
+            "jal 4",
+            "sgt r0 r1 300",
+            "select r3 r0 1 0",
+            "add r0 r0 1",
+            "rand r5",
+            "xor r0 r1 r2",
+            "push r0",
+            "pop r1",
+            "get r2 db r0",
+            "put d1 511 r3",
+            "alias sensor d0",
+            "define limit 300",
+            "sleep 2",
+            "hcf",
+            "yield",
+            "loop:",
+            "# check the heater",
+        ];
+        for line in lines {
+            let instruction: Instruction = line.parse().unwrap();
+            assert_eq!(instruction.to_string(), line);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid() {
+        for line in ["foo r0", "add r0 r1", "l r0 d9 Temperature", "move r99 1"] {
+            assert!(line.parse::<Instruction>().is_err(), "{}", line);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use super::operand;
+use crate::error::Error;
 use crate::types::{JumpDest, RegisterOrNumber};
 
 /// Instructions for flow control, branching, and jumping
@@ -462,3 +464,256 @@ impl std::fmt::Display for FlowControl {
         }
     }
 }
+
+impl std::str::FromStr for FlowControl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+
+        let command = parts
+            .next()
+            .ok_or_else(|| Error::ParseError(s.to_string()))?;
+
+        match command {
+            "bap" => Ok(FlowControl::BranchAbsoluteLessThan {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "bapal" => Ok(FlowControl::BranchAbsoluteLessThanAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "bapz" => Ok(FlowControl::BranchAbsoluteZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bapzal" => Ok(FlowControl::BranchAbsoluteZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "beq" => Ok(FlowControl::BranchEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "beqal" => Ok(FlowControl::BranchEqualAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "beqz" => Ok(FlowControl::BranchEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "beqzal" => Ok(FlowControl::BranchEqualZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "bge" => Ok(FlowControl::BranchGreaterOrEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bgeal" => Ok(FlowControl::BranchGreaterOrEqualAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bgez" => Ok(FlowControl::BranchGreaterOrEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "bgezal" => Ok(FlowControl::BranchGreaterOrEqualZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "bgt" => Ok(FlowControl::BranchGreaterThan {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bgtal" => Ok(FlowControl::BranchGreaterThanAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bgtz" => Ok(FlowControl::BranchGreaterThanZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "bgtzal" => Ok(FlowControl::BranchGreaterThanZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "ble" => Ok(FlowControl::BranchLessOrEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bleal" => Ok(FlowControl::BranchLessOrEqualAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "blez" => Ok(FlowControl::BranchLessOrEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "blezal" => Ok(FlowControl::BranchLessOrEqualZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "blt" => Ok(FlowControl::BranchLessThan {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bltal" => Ok(FlowControl::BranchLessThanAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bltz" => Ok(FlowControl::BranchLessThanZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "bltzal" => Ok(FlowControl::BranchLessThanZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "bna" => Ok(FlowControl::BranchNotApproximatelyEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "bnaal" => Ok(FlowControl::BranchNotApproximatelyEqualAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "bnaz" => Ok(FlowControl::BranchNotApproximatelyZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bnazal" => Ok(FlowControl::BranchNotApproximatelyZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bne" => Ok(FlowControl::BranchNotEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bneal" => Ok(FlowControl::BranchNotEqualAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "bnez" => Ok(FlowControl::BranchNotEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "bnezal" => Ok(FlowControl::BranchNotEqualZeroAndLink {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "brap" => Ok(FlowControl::RelativeBranchApproximatelyEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "brapz" => Ok(FlowControl::RelativeBranchApproximatelyZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "breq" => Ok(FlowControl::RelativeBranchEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "breqz" => Ok(FlowControl::RelativeBranchEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "brge" => Ok(FlowControl::RelativeBranchGreaterOrEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "brgez" => Ok(FlowControl::RelativeBranchGreaterOrEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "brgt" => Ok(FlowControl::RelativeBranchGreaterThan {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "brgtz" => Ok(FlowControl::RelativeBranchGreaterThanZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "brle" => Ok(FlowControl::RelativeBranchLessOrEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "brlez" => Ok(FlowControl::RelativeBranchLessOrEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "brlt" => Ok(FlowControl::RelativeBranchLessThan {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "brltz" => Ok(FlowControl::RelativeBranchLessThanZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "brna" => Ok(FlowControl::RelativeBranchNotApproximatelyEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "brnaz" => Ok(FlowControl::RelativeBranchNotApproximatelyZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "brne" => Ok(FlowControl::RelativeBranchNotEqual {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "brnez" => Ok(FlowControl::RelativeBranchNotEqualZero {
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "j" => Ok(FlowControl::Jump {
+                a: operand(&mut parts, s)?,
+            }),
+            "jal" => Ok(FlowControl::JumpAndLink {
+                a: operand(&mut parts, s)?,
+            }),
+            "jr" => Ok(FlowControl::JumpRelative {
+                a: operand(&mut parts, s)?,
+            }),
+            _ => Err(Error::ParseError(s.to_string())),
+        }
+    }
+}
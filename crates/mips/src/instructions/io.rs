@@ -1,3 +1,4 @@
+use super::operand;
 use crate::{
     error::Error,
     types::{
@@ -70,6 +71,17 @@ pub enum DeviceIo {
         variable: DeviceVariable,
         batch_mode: BatchMode,
     },
+    /// Loads var from all output network devices with the provided type hash and name hash
+    /// using the provided batch mode.
+    ///
+    /// lbn r? type name var batchMode
+    LoadBatchNamed {
+        register: Register,
+        type_hash: TypeHash,
+        name_hash: TypeHash,
+        variable: DeviceVariable,
+        batch_mode: BatchMode,
+    },
     /// Loads reagent lof device's reagentMode to register. Contents(0), Required(1), Recipe(2).
     /// Can use either the word, or the number.
     ///
@@ -97,6 +109,15 @@ pub enum DeviceIo {
         variable: DeviceVariable,
         register: RegisterOrNumber,
     },
+    /// Stores register value to var on the item in the slot of the device
+    ///
+    /// ss d? slotIndex logicSlotType r?
+    StoreSlot {
+        device: Device,
+        slot: Slot,
+        variable: DeviceVariable,
+        register: RegisterOrNumber,
+    },
     /// Stores register value to var on all output network devices with the provided type hash.
     ///
     /// sb type var a(r?|num)
@@ -105,6 +126,16 @@ pub enum DeviceIo {
         variable: DeviceVariable,
         register: RegisterOrNumber,
     },
+    /// Stores register value to var on all output network devices with the provided type hash
+    /// and name hash.
+    ///
+    /// sbn type name var a(r?|num)
+    StoreBatchNamed {
+        type_hash: TypeHash,
+        name_hash: TypeHash,
+        variable: DeviceVariable,
+        register: RegisterOrNumber,
+    },
 }
 
 impl std::fmt::Display for DeviceIo {
@@ -141,6 +172,17 @@ impl std::fmt::Display for DeviceIo {
                 "lb {} {} {} {}",
                 register, type_hash, variable, batch_mode
             ),
+            DeviceIo::LoadBatchNamed {
+                register,
+                type_hash,
+                name_hash,
+                variable,
+                batch_mode,
+            } => write!(
+                f,
+                "lbn {} {} {} {} {}",
+                register, type_hash, name_hash, variable, batch_mode
+            ),
             DeviceIo::LoadReagent {
                 register,
                 device,
@@ -158,11 +200,120 @@ impl std::fmt::Display for DeviceIo {
                 variable,
                 register,
             } => write!(f, "s {} {} {}", device, variable, register),
+            DeviceIo::StoreSlot {
+                device,
+                slot,
+                variable,
+                register,
+            } => write!(f, "ss {} {} {} {}", device, slot, variable, register),
             DeviceIo::StoreBatch {
                 type_hash,
                 variable,
                 register,
             } => write!(f, "sb {} {} {}", type_hash, variable, register),
+            DeviceIo::StoreBatchNamed {
+                type_hash,
+                name_hash,
+                variable,
+                register,
+            } => write!(
+                f,
+                "sbn {} {} {} {}",
+                type_hash, name_hash, variable, register
+            ),
+        }
+    }
+}
+
+// DeviceIo
+impl std::str::FromStr for DeviceIo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+
+        let command = parts
+            .next()
+            .ok_or_else(|| Error::ParseError(s.to_string()))?;
+
+        match command {
+            "bdns" => Ok(DeviceIo::BranchDeviceNotSet {
+                device: operand(&mut parts, s)?,
+                line: operand(&mut parts, s)?,
+            }),
+            "bdnsal" => Ok(DeviceIo::BranchDeviceNotSetAndLink {
+                device: operand(&mut parts, s)?,
+                line: operand(&mut parts, s)?,
+            }),
+            "bdse" => Ok(DeviceIo::BranchDeviceSet {
+                device: operand(&mut parts, s)?,
+                line: operand(&mut parts, s)?,
+            }),
+            "bdseal" => Ok(DeviceIo::BranchDeviceSetAndLink {
+                device: operand(&mut parts, s)?,
+                line: operand(&mut parts, s)?,
+            }),
+            "brdns" => Ok(DeviceIo::BranchRelativeDeviceNotSet {
+                device: operand(&mut parts, s)?,
+                line: operand(&mut parts, s)?,
+            }),
+            "brdse" => Ok(DeviceIo::BranchRelativeDeviceSet {
+                device: operand(&mut parts, s)?,
+                line: operand(&mut parts, s)?,
+            }),
+            "l" => Ok(DeviceIo::LoadDeviceVariable {
+                register: operand(&mut parts, s)?,
+                device: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+            }),
+            "lb" => Ok(DeviceIo::LoadBatch {
+                register: operand(&mut parts, s)?,
+                type_hash: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+                batch_mode: operand(&mut parts, s)?,
+            }),
+            "lbn" => Ok(DeviceIo::LoadBatchNamed {
+                register: operand(&mut parts, s)?,
+                type_hash: operand(&mut parts, s)?,
+                name_hash: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+                batch_mode: operand(&mut parts, s)?,
+            }),
+            "lr" => Ok(DeviceIo::LoadReagent {
+                register: operand(&mut parts, s)?,
+                device: operand(&mut parts, s)?,
+                reagent_mode: operand(&mut parts, s)?,
+                reagent: operand(&mut parts, s)?,
+            }),
+            "ls" => Ok(DeviceIo::LoadSlot {
+                register: operand(&mut parts, s)?,
+                device: operand(&mut parts, s)?,
+                slot: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+            }),
+            "s" => Ok(DeviceIo::StoreDeviceVariable {
+                device: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+                register: operand(&mut parts, s)?,
+            }),
+            "ss" => Ok(DeviceIo::StoreSlot {
+                device: operand(&mut parts, s)?,
+                slot: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+                register: operand(&mut parts, s)?,
+            }),
+            "sb" => Ok(DeviceIo::StoreBatch {
+                type_hash: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+                register: operand(&mut parts, s)?,
+            }),
+            "sbn" => Ok(DeviceIo::StoreBatchNamed {
+                type_hash: operand(&mut parts, s)?,
+                name_hash: operand(&mut parts, s)?,
+                variable: operand(&mut parts, s)?,
+                register: operand(&mut parts, s)?,
+            }),
+            _ => Err(Error::ParseError(s.to_string())),
         }
     }
 }
@@ -189,133 +340,47 @@ mod tests {
             "Instruction string does not match expected"
         );
     }
-}
-
-// DeviceIo
-impl std::str::FromStr for DeviceIo {
-    type Err = Error;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split_whitespace();
-
-        let command = parts
-            .next()
-            .ok_or_else(|| Error::ParseError(s.to_string()))?;
-
-        match command {
-            "bdns" => {
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let line = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-
-                Ok(DeviceIo::BranchDeviceNotSet { device, line })
-            }
-            "bdnsal" => {
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let line = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-
-                Ok(DeviceIo::BranchDeviceNotSetAndLink { device, line })
-            }
-            "bdse" => {
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let line = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-
-                Ok(DeviceIo::BranchDeviceSet { device, line })
-            }
-            "bdseal" => {
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let line = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-
-                Ok(DeviceIo::BranchDeviceSetAndLink { device, line })
-            }
-            "brdns" => {
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let line = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-
-                Ok(DeviceIo::BranchRelativeDeviceNotSet { device, line })
-            }
-            "brdse" => {
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let line = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
+    #[test]
+    fn serde_device_io_slot() {
+        for source in [
+            "ls r0 d0 2 Occupied",
+            "ls r0 d0 r1 Quantity",
+            "ss d1 0 On r2",
+        ] {
+            let instruction: Instruction = source.parse().unwrap();
+            assert_eq!(instruction.to_string(), source);
+        }
+        assert!("ls r0 d0 1.5 Occupied".parse::<Instruction>().is_err());
+    }
 
-                Ok(DeviceIo::BranchRelativeDeviceSet { device, line })
-            }
-            "l" => {
-                let register = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let variable = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
+    #[test]
+    fn serde_device_io_reagent() {
+        for source in ["lr r0 d0 Contents -654790771", "lr r0 d1 Required r3"] {
+            let instruction: Instruction = source.parse().unwrap();
+            assert_eq!(instruction.to_string(), source);
+        }
+        assert_eq!(
+            "lr r0 d0 2 r1".parse::<Instruction>().unwrap().to_string(),
+            "lr r0 d0 Recipe r1"
+        );
+        assert!("lr r0 d0 Contents Iron".parse::<Instruction>().is_err());
+    }
 
-                Ok(DeviceIo::LoadDeviceVariable {
-                    register,
-                    device,
-                    variable,
-                })
-            }
-            "s" => {
-                let device = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let variable = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let register = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
+    #[test]
+    fn serde_device_io_indirect() {
+        for source in ["l r0 dr1 Temperature", "s dr15 On r2", "bdns dr3 5"] {
+            let instruction: Instruction = source.parse().unwrap();
+            assert_eq!(instruction.to_string(), source);
+        }
+        assert!("l r0 dr16 Temperature".parse::<Instruction>().is_err());
+    }
 
-                Ok(DeviceIo::StoreDeviceVariable {
-                    device,
-                    variable,
-                    register,
-                })
-            }
-            _ => Err(Error::ParseError(s.to_string())),
+    #[test]
+    fn serde_device_io_batch_named() {
+        for source in ["lbn r0 -42 r1 Pressure Maximum", "sbn -42 17 On r2"] {
+            let instruction: Instruction = source.parse().unwrap();
+            assert_eq!(instruction.to_string(), source);
         }
     }
 }
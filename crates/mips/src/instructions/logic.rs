@@ -1,9 +1,11 @@
+use super::operand;
+use crate::error::Error;
 use crate::types::{Register, RegisterOrNumber};
 
-/// Boolean logic instructions.
+/// Bitwise logic instructions, on the 64-bit integer parts of their operands.
 #[derive(Clone)]
 pub enum Logic {
-    /// Register = 1 if a != 0 and b != 0 else 0
+    /// Register = a & b
     ///
     /// and r? a(r?|num) b(r?|num)
     And {
@@ -11,7 +13,7 @@ pub enum Logic {
         a: RegisterOrNumber,
         b: RegisterOrNumber,
     },
-    /// Register = 1 if a == 0 and b == 0 else 0
+    /// Register = ~(a | b)
     ///
     /// nor r? a(r?|num) b(r?|num)
     Nor {
@@ -19,7 +21,7 @@ pub enum Logic {
         a: RegisterOrNumber,
         b: RegisterOrNumber,
     },
-    /// Register = 1 if a and/or b != 0 else 0
+    /// Register = a | b
     ///
     /// or r? a(r?|num) b(r?|num)
     Or {
@@ -27,7 +29,7 @@ pub enum Logic {
         a: RegisterOrNumber,
         b: RegisterOrNumber,
     },
-    /// Register = 1 if either a or b is nonzero, otherwise 0
+    /// Register = a ^ b
     ///
     /// xor r? a(r?|num) b(r?|num)
     Xor {
@@ -35,6 +37,22 @@ pub enum Logic {
         a: RegisterOrNumber,
         b: RegisterOrNumber,
     },
+    /// Register = a << b
+    ///
+    /// sll r? a(r?|num) b(r?|num)
+    ShiftLeftLogical {
+        register: Register,
+        a: RegisterOrNumber,
+        b: RegisterOrNumber,
+    },
+    /// Register = a >> b, shifting zeros in
+    ///
+    /// srl r? a(r?|num) b(r?|num)
+    ShiftRightLogical {
+        register: Register,
+        a: RegisterOrNumber,
+        b: RegisterOrNumber,
+    },
 }
 
 impl std::fmt::Display for Logic {
@@ -44,6 +62,67 @@ impl std::fmt::Display for Logic {
             Logic::Nor { register, a, b } => write!(f, "nor {register} {a} {b}"),
             Logic::Or { register, a, b } => write!(f, "or {register} {a} {b}"),
             Logic::Xor { register, a, b } => write!(f, "xor {register} {a} {b}"),
+            Logic::ShiftLeftLogical { register, a, b } => write!(f, "sll {register} {a} {b}"),
+            Logic::ShiftRightLogical { register, a, b } => write!(f, "srl {register} {a} {b}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Logic {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+
+        let command = parts
+            .next()
+            .ok_or_else(|| Error::ParseError(s.to_string()))?;
+
+        match command {
+            "and" => Ok(Logic::And {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "nor" => Ok(Logic::Nor {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "or" => Ok(Logic::Or {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "xor" => Ok(Logic::Xor {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "sll" => Ok(Logic::ShiftLeftLogical {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "srl" => Ok(Logic::ShiftRightLogical {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            _ => Err(Error::ParseError(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instructions::Instruction;
+
+    #[test]
+    fn serde_logic_shift() {
+        for source in ["sll r0 r1 4", "srl r0 255 r2", "nor r0 r1 0"] {
+            let instruction: Instruction = source.parse().unwrap();
+            assert_eq!(instruction.to_string(), source);
         }
     }
 }
@@ -1,3 +1,4 @@
+use super::operand;
 use crate::error::Error;
 use crate::types::{Register, RegisterOrNumber};
 
@@ -47,6 +48,15 @@ pub enum Arithmetic {
         register: Register,
         a: RegisterOrNumber,
     },
+    /// Register = atan2(a, b), the angle of the point (b, a)
+    ///
+    /// atan2 r? a(r?|num) b(r?|num)
+    ArcTangent2 {
+        /// the register to store the result in
+        register: Register,
+        a: RegisterOrNumber,
+        b: RegisterOrNumber,
+    },
     /// Register = ceil(a)
     ///
     /// ceil r? a(r?|num)
@@ -198,6 +208,9 @@ impl std::fmt::Display for Arithmetic {
             Arithmetic::Add { register, a, b } => write!(f, "add {} {} {}", register, a, b),
             Arithmetic::ArcSine { register, a } => write!(f, "asin {} {}", register, a),
             Arithmetic::ArcTangent { register, a } => write!(f, "atan {} {}", register, a),
+            Arithmetic::ArcTangent2 { register, a, b } => {
+                write!(f, "atan2 {} {} {}", register, a, b)
+            }
             Arithmetic::Ceiling { register, a } => write!(f, "ceil {} {}", register, a),
             Arithmetic::Cosine { register, a } => write!(f, "cos {} {}", register, a),
             Arithmetic::Divide { register, a, b } => write!(f, "div {} {} {}", register, a, b),
@@ -230,23 +243,106 @@ impl std::str::FromStr for Arithmetic {
             .ok_or_else(|| Error::ParseError(s.to_string()))?;
 
         match command {
-            "add" => {
-                let register = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let a = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let b = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-
-                Ok(Arithmetic::Add { register, a, b })
-            }
-            _ => todo!(),
+            "abs" => Ok(Arithmetic::AbsoluteValue {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "acos" => Ok(Arithmetic::ArcCosine {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "add" => Ok(Arithmetic::Add {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "asin" => Ok(Arithmetic::ArcSine {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "atan" => Ok(Arithmetic::ArcTangent {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "atan2" => Ok(Arithmetic::ArcTangent2 {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "ceil" => Ok(Arithmetic::Ceiling {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "cos" => Ok(Arithmetic::Cosine {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "div" => Ok(Arithmetic::Divide {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "exp" => Ok(Arithmetic::Exponent {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "floor" => Ok(Arithmetic::Floor {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "log" => Ok(Arithmetic::Logarithm {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "max" => Ok(Arithmetic::Maximum {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "min" => Ok(Arithmetic::Minimum {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "mod" => Ok(Arithmetic::Mod {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "mul" => Ok(Arithmetic::Multiply {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "rand" => Ok(Arithmetic::Random {
+                register: operand(&mut parts, s)?,
+            }),
+            "round" => Ok(Arithmetic::Round {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sin" => Ok(Arithmetic::Sine {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sqrt" => Ok(Arithmetic::SquareRoot {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sub" => Ok(Arithmetic::Subtract {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "tan" => Ok(Arithmetic::Tangent {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "trunc" => Ok(Arithmetic::Truncate {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            _ => Err(Error::ParseError(s.to_string())),
         }
     }
 }
@@ -1,3 +1,4 @@
+use super::operand;
 use crate::error::Error;
 use crate::types::{Register, RegisterOrNumber};
 
@@ -67,6 +68,12 @@ impl std::str::FromStr for Misc {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(comment) = s.trim_start().strip_prefix('#') {
+            return Ok(Misc::Comment {
+                comment: comment.trim().to_string(),
+            });
+        }
+
         let mut parts = s.split_whitespace();
 
         let command = parts
@@ -74,18 +81,26 @@ impl std::str::FromStr for Misc {
             .ok_or_else(|| Error::ParseError(s.to_string()))?;
 
         match command {
+            "alias" => Ok(Misc::Alias {
+                name: operand(&mut parts, s)?,
+                target: operand(&mut parts, s)?,
+            }),
+            "define" => Ok(Misc::Define {
+                name: operand(&mut parts, s)?,
+                value: operand(&mut parts, s)?,
+            }),
+            "hcf" => Ok(Misc::Halt),
+            "move" => Ok(Misc::Move {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sleep" => Ok(Misc::Sleep {
+                a: operand(&mut parts, s)?,
+            }),
             "yield" => Ok(Misc::Yield),
-            "move" => {
-                let register = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                let value = parts
-                    .next()
-                    .ok_or_else(|| Error::ParseError(s.to_string()))?
-                    .parse()?;
-                Ok(Misc::Move { register, a: value })
-            }
+            label if label.ends_with(':') && parts.next().is_none() => Ok(Misc::Label {
+                name: label.trim_end_matches(':').to_string(),
+            }),
             _ => Err(Error::ParseError(s.to_string())),
         }
     }
@@ -1,4 +1,6 @@
-use crate::types::{Register, RegisterOrNumber};
+use super::operand;
+use crate::error::Error;
+use crate::types::{Device, Register, RegisterOrNumber};
 
 /// Instructions for operating on the stack
 #[derive(Clone)]
@@ -15,6 +17,24 @@ pub enum Stack {
     ///
     /// push a(r?|num)
     Push { a: RegisterOrNumber },
+    /// Register = the value at an address of the stack of the device, `db` for the stack of
+    /// the IC itself. The stack pointer is left as is.
+    ///
+    /// get r? d? address(r?|num)
+    Get {
+        register: Register,
+        device: Device,
+        address: RegisterOrNumber,
+    },
+    /// Stores a value at an address of the stack of the device, `db` for the stack of the IC
+    /// itself. The stack pointer is left as is.
+    ///
+    /// put d? address(r?|num) value(r?|num)
+    Put {
+        device: Device,
+        address: RegisterOrNumber,
+        value: RegisterOrNumber,
+    },
 }
 
 impl std::fmt::Display for Stack {
@@ -23,6 +43,51 @@ impl std::fmt::Display for Stack {
             Stack::Peek { register } => write!(f, "peek {register}"),
             Stack::Pop { register } => write!(f, "pop {register}"),
             Stack::Push { a } => write!(f, "push {a}"),
+            Stack::Get {
+                register,
+                device,
+                address,
+            } => write!(f, "get {register} {device} {address}"),
+            Stack::Put {
+                device,
+                address,
+                value,
+            } => write!(f, "put {device} {address} {value}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Stack {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+
+        let command = parts
+            .next()
+            .ok_or_else(|| Error::ParseError(s.to_string()))?;
+
+        match command {
+            "peek" => Ok(Stack::Peek {
+                register: operand(&mut parts, s)?,
+            }),
+            "pop" => Ok(Stack::Pop {
+                register: operand(&mut parts, s)?,
+            }),
+            "push" => Ok(Stack::Push {
+                a: operand(&mut parts, s)?,
+            }),
+            "get" => Ok(Stack::Get {
+                register: operand(&mut parts, s)?,
+                device: operand(&mut parts, s)?,
+                address: operand(&mut parts, s)?,
+            }),
+            "put" => Ok(Stack::Put {
+                device: operand(&mut parts, s)?,
+                address: operand(&mut parts, s)?,
+                value: operand(&mut parts, s)?,
+            }),
+            _ => Err(Error::ParseError(s.to_string())),
         }
     }
 }
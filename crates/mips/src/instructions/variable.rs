@@ -1,4 +1,6 @@
-use crate::types::{Register, RegisterOrNumber};
+use super::operand;
+use crate::error::Error;
+use crate::types::{Device, Register, RegisterOrNumber};
 
 /// Instructions for variable selection
 #[derive(Clone)]
@@ -23,17 +25,11 @@ pub enum VariableSelection {
     /// Register = 1 if device is not set else 0
     ///
     /// sdns r? d?
-    SelectDeviceNotSet {
-        register: Register,
-        d: RegisterOrNumber,
-    },
+    SelectDeviceNotSet { register: Register, d: Device },
     /// Register = 1 if device is set else 0
     ///
     /// sdse r? d?
-    SelectDeviceSet {
-        register: Register,
-        d: RegisterOrNumber,
-    },
+    SelectDeviceSet { register: Register, d: Device },
     /// Register = b if a != 0 else c
     ///
     /// select r? a(r?|num) b(r?|num) c(r?|num)
@@ -135,6 +131,20 @@ pub enum VariableSelection {
         a: RegisterOrNumber,
         b: RegisterOrNumber,
     },
+    /// Register = 1 if a is NaN else 0
+    ///
+    /// snan r? a(r?|num)
+    SelectNaN {
+        register: Register,
+        a: RegisterOrNumber,
+    },
+    /// Register = 0 if a is NaN else 1
+    ///
+    /// snanz r? a(r?|num)
+    SelectNotNaN {
+        register: Register,
+        a: RegisterOrNumber,
+    },
     /// Register = 1 if a != b else 0
     ///
     /// sne r? a(r?|num) b(r?|num)
@@ -206,6 +216,12 @@ impl std::fmt::Display for VariableSelection {
             VariableSelection::SelectNotApproximatelyZero { register, a, b } => {
                 write!(f, "snaz {register} {a} {b}")
             }
+            VariableSelection::SelectNaN { register, a } => {
+                write!(f, "snan {register} {a}")
+            }
+            VariableSelection::SelectNotNaN { register, a } => {
+                write!(f, "snanz {register} {a}")
+            }
             VariableSelection::SelectNotEqual { register, a, b } => {
                 write!(f, "sne {register} {a} {b}")
             }
@@ -215,3 +231,117 @@ impl std::fmt::Display for VariableSelection {
         }
     }
 }
+
+impl std::str::FromStr for VariableSelection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+
+        let command = parts
+            .next()
+            .ok_or_else(|| Error::ParseError(s.to_string()))?;
+
+        match command {
+            "sap" => Ok(VariableSelection::SelectApproximatelyEqual {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "sapz" => Ok(VariableSelection::SelectApproximatelyZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "sdns" => Ok(VariableSelection::SelectDeviceNotSet {
+                register: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "sdse" => Ok(VariableSelection::SelectDeviceSet {
+                register: operand(&mut parts, s)?,
+                d: operand(&mut parts, s)?,
+            }),
+            "select" => Ok(VariableSelection::Select {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "seq" => Ok(VariableSelection::SelectEqual {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "seqz" => Ok(VariableSelection::SelectEqualZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sge" => Ok(VariableSelection::SelectGreaterOrEqual {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "sgez" => Ok(VariableSelection::SelectGreaterOrEqualZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sgt" => Ok(VariableSelection::SelectGreaterThan {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "sgtz" => Ok(VariableSelection::SelectGreaterThanZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sle" => Ok(VariableSelection::SelectLessOrEqual {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "slez" => Ok(VariableSelection::SelectLessOrEqualZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "slt" => Ok(VariableSelection::SelectLessThan {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "sltz" => Ok(VariableSelection::SelectLessThanZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sna" => Ok(VariableSelection::SelectNotApproximatelyEqual {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+                c: operand(&mut parts, s)?,
+            }),
+            "snaz" => Ok(VariableSelection::SelectNotApproximatelyZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "snan" => Ok(VariableSelection::SelectNaN {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "snanz" => Ok(VariableSelection::SelectNotNaN {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            "sne" => Ok(VariableSelection::SelectNotEqual {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+                b: operand(&mut parts, s)?,
+            }),
+            "snez" => Ok(VariableSelection::SelectNotEqualZero {
+                register: operand(&mut parts, s)?,
+                a: operand(&mut parts, s)?,
+            }),
+            _ => Err(Error::ParseError(s.to_string())),
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use crate::error::Error;
 
-#[repr(u8)]
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Device {
     D0,
@@ -10,6 +9,20 @@ pub enum Device {
     D4,
     D5,
     Db,
+    /// `dr?`, the pin whose number is in the register.
+    Indirect(Register),
+}
+
+impl Device {
+    /// The pins `d0` to `d5`, in the order of their numbers.
+    pub const PINS: [Device; 6] = [
+        Device::D0,
+        Device::D1,
+        Device::D2,
+        Device::D3,
+        Device::D4,
+        Device::D5,
+    ];
 }
 
 impl std::fmt::Display for Device {
@@ -22,6 +35,7 @@ impl std::fmt::Display for Device {
             Device::D4 => write!(f, "d4"),
             Device::D5 => write!(f, "d5"),
             Device::Db => write!(f, "db"),
+            Device::Indirect(register) => write!(f, "d{}", register),
         }
     }
 }
@@ -38,7 +52,11 @@ impl std::str::FromStr for Device {
             "d4" => Ok(Device::D4),
             "d5" => Ok(Device::D5),
             "db" => Ok(Device::Db),
-            _ => Err(Error::ParseError(s.to_string())),
+            _ => s
+                .strip_prefix('d')
+                .and_then(|register| register.parse().ok())
+                .map(Device::Indirect)
+                .ok_or_else(|| Error::ParseError(s.to_string())),
         }
     }
 }
@@ -245,6 +263,25 @@ pub enum DeviceVariable {
     Vertical,
     VerticalRatio,
     Volume,
+    // The logic types of the items in device slots, read with `ls` and written with `ss`.
+    ChargeRatio,
+    Class,
+    Damage,
+    Efficiency,
+    Growth,
+    Health,
+    Mature,
+    MaxQuantity,
+    OccupantHash,
+    Occupied,
+    PrefabHash,
+    PressureAir,
+    PressureWaste,
+    Seeding,
+    SortingClass,
+    /// A logic type added to the game after this list was written. Never produced by
+    /// parsing, which only accepts the known logic types.
+    Other(String),
 }
 
 impl std::str::FromStr for DeviceVariable {
@@ -309,6 +346,21 @@ impl std::str::FromStr for DeviceVariable {
             "Vertical" => Ok(DeviceVariable::Vertical),
             "VerticalRatio" => Ok(DeviceVariable::VerticalRatio),
             "Volume" => Ok(DeviceVariable::Volume),
+            "ChargeRatio" => Ok(DeviceVariable::ChargeRatio),
+            "Class" => Ok(DeviceVariable::Class),
+            "Damage" => Ok(DeviceVariable::Damage),
+            "Efficiency" => Ok(DeviceVariable::Efficiency),
+            "Growth" => Ok(DeviceVariable::Growth),
+            "Health" => Ok(DeviceVariable::Health),
+            "Mature" => Ok(DeviceVariable::Mature),
+            "MaxQuantity" => Ok(DeviceVariable::MaxQuantity),
+            "OccupantHash" => Ok(DeviceVariable::OccupantHash),
+            "Occupied" => Ok(DeviceVariable::Occupied),
+            "PrefabHash" => Ok(DeviceVariable::PrefabHash),
+            "PressureAir" => Ok(DeviceVariable::PressureAir),
+            "PressureWaste" => Ok(DeviceVariable::PressureWaste),
+            "Seeding" => Ok(DeviceVariable::Seeding),
+            "SortingClass" => Ok(DeviceVariable::SortingClass),
             _ => Err(Error::ParseError(s.to_string())),
         }
     }
@@ -374,6 +426,22 @@ impl std::fmt::Display for DeviceVariable {
             DeviceVariable::Vertical => write!(f, "Vertical"),
             DeviceVariable::VerticalRatio => write!(f, "VerticalRatio"),
             DeviceVariable::Volume => write!(f, "Volume"),
+            DeviceVariable::ChargeRatio => write!(f, "ChargeRatio"),
+            DeviceVariable::Class => write!(f, "Class"),
+            DeviceVariable::Damage => write!(f, "Damage"),
+            DeviceVariable::Efficiency => write!(f, "Efficiency"),
+            DeviceVariable::Growth => write!(f, "Growth"),
+            DeviceVariable::Health => write!(f, "Health"),
+            DeviceVariable::Mature => write!(f, "Mature"),
+            DeviceVariable::MaxQuantity => write!(f, "MaxQuantity"),
+            DeviceVariable::OccupantHash => write!(f, "OccupantHash"),
+            DeviceVariable::Occupied => write!(f, "Occupied"),
+            DeviceVariable::PrefabHash => write!(f, "PrefabHash"),
+            DeviceVariable::PressureAir => write!(f, "PressureAir"),
+            DeviceVariable::PressureWaste => write!(f, "PressureWaste"),
+            DeviceVariable::Seeding => write!(f, "Seeding"),
+            DeviceVariable::SortingClass => write!(f, "SortingClass"),
+            DeviceVariable::Other(name) => write!(f, "{}", name),
         }
     }
 }
@@ -430,7 +498,7 @@ impl std::str::FromStr for BatchMode {
 }
 
 #[repr(u8)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum ReagentMode {
     Contents = 0,
     Required = 1,
@@ -460,8 +528,21 @@ impl std::str::FromStr for ReagentMode {
     }
 }
 
+/// The hash of a reagent, a number or a register.
 #[derive(Clone, Debug)]
-pub struct Reagent(String);
+pub struct Reagent(RegisterOrNumber);
+
+impl Reagent {
+    pub fn hash(&self) -> &RegisterOrNumber {
+        &self.0
+    }
+}
+
+impl From<RegisterOrNumber> for Reagent {
+    fn from(hash: RegisterOrNumber) -> Self {
+        Reagent(hash)
+    }
+}
 
 impl std::fmt::Display for Reagent {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -473,12 +554,25 @@ impl std::str::FromStr for Reagent {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Reagent(s.to_string()))
+        Ok(Reagent(s.parse()?))
     }
 }
 
+/// The index of a slot of a device, a number or a register.
 #[derive(Clone, Debug)]
-pub struct Slot(u8);
+pub struct Slot(RegisterOrNumber);
+
+impl Slot {
+    pub fn index(&self) -> &RegisterOrNumber {
+        &self.0
+    }
+}
+
+impl From<RegisterOrNumber> for Slot {
+    fn from(index: RegisterOrNumber) -> Self {
+        Slot(index)
+    }
+}
 
 impl std::fmt::Display for Slot {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -490,7 +584,10 @@ impl std::str::FromStr for Slot {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.parse::<u8>() {
+        match s.parse::<RegisterOrNumber>() {
+            Ok(RegisterOrNumber::Number(v)) if v < 0.0 || v.fract() != 0.0 => {
+                Err(Error::ParseError(s.to_string()))
+            }
             Ok(v) => Ok(Slot(v)),
             Err(_) => Err(Error::ParseError(s.to_string())),
         }
@@ -516,6 +613,22 @@ impl From<Register> for JumpDest {
     }
 }
 
+impl std::str::FromStr for JumpDest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(register) = s.parse() {
+            Ok(JumpDest::Register(register))
+        } else if let Ok(number) = s.parse() {
+            Ok(JumpDest::Number(number))
+        } else if !s.is_empty() {
+            Ok(JumpDest::Label(s.to_string()))
+        } else {
+            Err(Error::ParseError(s.to_string()))
+        }
+    }
+}
+
 impl std::fmt::Display for JumpDest {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
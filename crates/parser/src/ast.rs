@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
@@ -9,17 +11,126 @@ impl Program {
     }
 }
 
-impl std::fmt::Display for Program {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for stmt in &self.statements {
-            writeln!(f, "{}", stmt)?;
+/// A byte range in the source a node was parsed from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the smallest span covering both spans.
+    pub fn to(self, other: Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl From<Span> for std::ops::Range<usize> {
+    fn from(span: Span) -> Self {
+        span.start..span.end
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+    /// Comments around the statement, attached by [`crate::parse`].
+    pub trivia: Trivia,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            trivia: Trivia::default(),
         }
-        Ok(())
+    }
+
+    /// Returns the blocks nested in the statement.
+    pub fn blocks_mut(&mut self) -> Vec<&mut Block> {
+        match &mut self.kind {
+            StatementKind::Function { body, .. }
+            | StatementKind::Loop { body, .. }
+            | StatementKind::LoopUntil { body, .. }
+            | StatementKind::For { body, .. } => vec![body],
+            StatementKind::Block(block) => vec![block],
+            StatementKind::IfStatement(IfStatement::If { body, .. }) => vec![body],
+            StatementKind::IfStatement(IfStatement::IfElse {
+                body, else_body, ..
+            }) => vec![body, else_body],
+            StatementKind::IfStatement(IfStatement::ElseIf {
+                branches,
+                else_body,
+            }) => branches
+                .iter_mut()
+                .map(|(_, body)| body)
+                .chain(else_body)
+                .collect(),
+            StatementKind::Match {
+                arms, default_arm, ..
+            } => arms
+                .iter_mut()
+                .map(|(_, body)| body)
+                .chain(default_arm)
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the text of the doc comments of the statement, without the slashes.
+    pub fn doc(&self) -> Option<String> {
+        if self.trivia.doc.is_empty() {
+            return None;
+        }
+        let lines = self.trivia.doc.iter().map(|c| {
+            let text = &c.text["///".len()..];
+            text.strip_prefix(' ').unwrap_or(text)
+        });
+        Some(lines.collect::<Vec<_>>().join("\n"))
+    }
+}
+
+/// A `//` comment.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comment {
+    /// The text of the comment, including the slashes.
+    pub text: String,
+    pub span: Span,
+}
+
+impl Comment {
+    /// Returns whether this is a `///` doc comment.
+    pub fn is_doc(&self) -> bool {
+        self.text.starts_with("///") && !self.text.starts_with("////")
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum Statement {
+/// The comments attached to a statement.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Trivia {
+    /// Comments on the lines before the statement.
+    pub leading: Vec<Comment>,
+    /// The `///` comments right before a function or constant, which document it.
+    pub doc: Vec<Comment>,
+    /// A comment at the end of the statement's last line.
+    pub trailing: Option<Comment>,
+    /// Comments after the last statement of a block or of the program.
+    pub after: Vec<Comment>,
+    /// Whether an empty line separates the statement from the comments after it.
+    pub blank_line_after: bool,
+    /// Whether an empty line separates the statement and its leading comments from the
+    /// previous statement.
+    pub blank_line: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StatementKind {
     // lhs = rhs;
     Assignment {
         lhs: Box<Expr>,
@@ -29,6 +140,12 @@ pub enum Statement {
         identifier: Identifier,
         expression: Box<Expr>,
     },
+    /// `let (a, b) = (x, y);`, defining each variable to the value in the same position.
+    /// All the values are computed before any variable is defined.
+    TupleDefinition {
+        identifiers: Vec<Identifier>,
+        expressions: Vec<Box<Expr>>,
+    },
     Alias {
         /// The identifier to alias to
         identifier: Identifier,
@@ -47,16 +164,55 @@ pub enum Statement {
         arguments: Vec<Box<Expr>>,
     },
     Block(Block),
+    /// `label: loop { body }`, running the body forever. The label is optional.
     Loop {
+        label: Option<Identifier>,
+        body: Block,
+    },
+    /// `loop { body } until condition;`, running the body until the condition holds. The
+    /// condition is tested after each iteration, so the body runs at least once.
+    LoopUntil {
+        label: Option<Identifier>,
+        body: Block,
+        condition: Box<Expr>,
+    },
+    /// `for variable in start..end { body }`, running the body with the variable set to each
+    /// whole number from `start` up to `end`, excluded.
+    For {
+        label: Option<Identifier>,
+        variable: Identifier,
+        start: Box<Expr>,
+        end: Box<Expr>,
         body: Block,
     },
     IfStatement(IfStatement),
+    /// `match value { 0 => { ... } 1 => { ... } _ => { ... } }`, running the body of the
+    /// first arm whose pattern, a number known at compile time, equals the value, or else
+    /// the body of the `_` arm.
+    Match {
+        value: Box<Expr>,
+        arms: Vec<(Box<Expr>, Block)>,
+        default_arm: Option<Block>,
+    },
     DeviceStatement(DeviceStatement),
     Yield,
-    Return(Box<Expr>),
+    /// Leaves the innermost loop, or the loop with the given label.
+    Break(Option<Identifier>),
+    /// Starts the next iteration of the innermost loop, or of the loop with the given label.
+    Continue(Option<Identifier>),
+    /// Stops the IC for good, with `hcf`.
+    Halt,
+    /// `return value;`, leaving the function with the value, or with 0 when there is none.
+    /// At the top level, `return;` ends the program, as running past its end does.
+    Return(Option<Box<Expr>>),
+    /// `import "path";`, bringing the functions and constants of another file, named
+    /// relative to this one, into the program.
+    Import(String),
+    /// A statement that failed to parse, only found in programs with syntax errors.
+    Error,
 }
 
-impl Statement {
+impl StatementKind {
     pub fn new_assignment(lhs: Box<Expr>, rhs: Box<Expr>) -> Self {
         Self::Assignment { lhs, rhs }
     }
@@ -68,6 +224,13 @@ impl Statement {
         }
     }
 
+    pub fn new_tuple_definition(identifiers: Vec<Identifier>, expressions: Vec<Box<Expr>>) -> Self {
+        Self::TupleDefinition {
+            identifiers,
+            expressions,
+        }
+    }
+
     pub fn new_alias(identifier: Identifier, alias: Identifier) -> Self {
         Self::Alias { identifier, alias }
     }
@@ -95,14 +258,50 @@ impl Statement {
         Self::Block(block)
     }
 
-    pub fn new_loop(body: Block) -> Self {
-        Self::Loop { body }
+    pub fn new_loop(label: Option<Identifier>, body: Block) -> Self {
+        Self::Loop { label, body }
+    }
+
+    pub fn new_loop_until(label: Option<Identifier>, body: Block, condition: Box<Expr>) -> Self {
+        Self::LoopUntil {
+            label,
+            body,
+            condition,
+        }
+    }
+
+    pub fn new_for(
+        label: Option<Identifier>,
+        variable: Identifier,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        body: Block,
+    ) -> Self {
+        Self::For {
+            label,
+            variable,
+            start,
+            end,
+            body,
+        }
     }
 
     pub fn new_if(if_statement: IfStatement) -> Self {
         Self::IfStatement(if_statement)
     }
 
+    pub fn new_match(
+        value: Box<Expr>,
+        arms: Vec<(Box<Expr>, Block)>,
+        default_arm: Option<Block>,
+    ) -> Self {
+        Self::Match {
+            value,
+            arms,
+            default_arm,
+        }
+    }
+
     pub fn new_device(statement: DeviceStatement) -> Self {
         Self::DeviceStatement(statement)
     }
@@ -111,33 +310,91 @@ impl Statement {
         Self::Yield
     }
 
-    pub fn new_return(expr: Box<Expr>) -> Self {
+    pub fn new_break(label: Option<Identifier>) -> Self {
+        Self::Break(label)
+    }
+
+    pub fn new_halt() -> Self {
+        Self::Halt
+    }
+
+    pub fn new_continue(label: Option<Identifier>) -> Self {
+        Self::Continue(label)
+    }
+
+    pub fn new_return(expr: Option<Box<Expr>>) -> Self {
         Self::Return(expr)
     }
+
+    pub fn new_import(path: String) -> Self {
+        Self::Import(path)
+    }
 }
 
-impl std::fmt::Display for Statement {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Builds a binary operation spanning both operands.
+    pub fn new_binary(lhs: Box<Expr>, op: BinaryOpcode, rhs: Box<Expr>) -> Box<Self> {
+        let span = lhs.span.to(rhs.span);
+        Box::new(Self::new(ExprKind::BinaryOp(lhs, op, rhs), span))
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum Expr {
+impl From<Identifier> for Expr {
+    fn from(identifier: Identifier) -> Self {
+        let span = identifier.span;
+        Self::new(ExprKind::Identifier(identifier), span)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ExprKind {
     Constant(Value),
     Identifier(Identifier),
     BinaryOp(Box<Expr>, BinaryOpcode, Box<Expr>),
     UnaryOp(UnaryOpcode, Box<Expr>),
     FunctionCall(Identifier, Vec<Box<Expr>>),
-    FieldExpr(Identifier, Identifier),
+    /// `device.LogicType`, where the device is a name or a [`ExprKind::DeviceIndex`].
+    FieldExpr(Box<Expr>, Identifier),
+    /// `devices[i]`, the device on pin `i` of the housing, which can be computed at run time.
+    DeviceIndex(Box<Expr>),
+    /// `[value; length]`, an array of `length` copies of `value`, only found in a `let`.
+    Array(Box<Expr>, Box<Expr>),
+    /// `name[i]`, an element of an array.
+    Index(Identifier, Box<Expr>),
+    /// `hash("name")`, the hash of a prefab or device name, known at compile time.
+    Hash(String),
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BinaryOpcode {
     Add,
     Sub,
     Mul,
     Div,
+    /// `a % b`, with the sign of `b` like the `mod` instruction.
+    Mod,
+    /// `a ** b`.
+    Pow,
+    /// `a & b`, on the integer parts of the operands like the bitwise instructions.
+    BitAnd,
+    /// `a | b`.
+    BitOr,
+    /// `a ^ b`.
+    BitXor,
+    /// `a << b`.
+    ShiftLeft,
+    /// `a >> b`, shifting zeros in.
+    ShiftRight,
     Conj,
     Disj,
     Equals,
@@ -148,6 +405,21 @@ pub enum BinaryOpcode {
     LowerEquals,
 }
 
+impl BinaryOpcode {
+    /// Returns true for operators comparing their operands.
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOpcode::Equals
+                | BinaryOpcode::NotEquals
+                | BinaryOpcode::Greater
+                | BinaryOpcode::GreaterEquals
+                | BinaryOpcode::Lower
+                | BinaryOpcode::LowerEquals
+        )
+    }
+}
+
 impl std::fmt::Debug for BinaryOpcode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -155,6 +427,13 @@ impl std::fmt::Debug for BinaryOpcode {
             BinaryOpcode::Sub => write!(f, "-"),
             BinaryOpcode::Mul => write!(f, "*"),
             BinaryOpcode::Div => write!(f, "/"),
+            BinaryOpcode::Mod => write!(f, "%"),
+            BinaryOpcode::Pow => write!(f, "**"),
+            BinaryOpcode::BitAnd => write!(f, "&"),
+            BinaryOpcode::BitOr => write!(f, "|"),
+            BinaryOpcode::BitXor => write!(f, "^"),
+            BinaryOpcode::ShiftLeft => write!(f, "<<"),
+            BinaryOpcode::ShiftRight => write!(f, ">>"),
             BinaryOpcode::Conj => write!(f, "&&"),
             BinaryOpcode::Disj => write!(f, "||"),
             BinaryOpcode::Equals => write!(f, "=="),
@@ -167,21 +446,44 @@ impl std::fmt::Debug for BinaryOpcode {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum UnaryOpcode {
+    /// `!x`, 1 when `x` is zero and 0 otherwise.
     Not,
+    /// `-x`.
+    Neg,
+    /// `~x`, flipping every bit of the integer part of `x`.
+    BitNot,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Value {
     Integer(i64),
     Float(f64),
     Boolean(bool),
 }
 
-impl Into<f64> for &Value {
-    fn into(self) -> f64 {
-        match self {
+impl From<i64> for Value {
+    fn from(x: i64) -> Self {
+        Value::Integer(x)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(x: bool) -> Self {
+        Value::Boolean(x)
+    }
+}
+
+impl From<&Value> for f64 {
+    fn from(val: &Value) -> Self {
+        match val {
             Value::Integer(x) => *x as f64,
             Value::Float(x) => *x,
             Value::Boolean(x) => (*x as i32) as f64,
@@ -189,67 +491,98 @@ impl Into<f64> for &Value {
     }
 }
 
-#[derive(Debug, Eq, Hash, PartialEq, Clone)]
-pub struct Identifier(String);
+/// A name, compared and hashed without its span.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identifier {
+    name: String,
+    pub span: Span,
+}
+
+impl Identifier {
+    pub fn new(name: impl Into<String>, span: Span) -> Self {
+        Self {
+            name: name.into(),
+            span,
+        }
+    }
+}
+
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Identifier {}
+
+impl std::hash::Hash for Identifier {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
+}
 
 impl From<String> for Identifier {
     fn from(s: String) -> Self {
-        Self(s)
+        Self::new(s, Span::default())
     }
 }
 
 impl From<&str> for Identifier {
     fn from(s: &str) -> Self {
-        Self(s.to_owned())
+        Self::new(s, Span::default())
     }
 }
 
 impl From<Identifier> for String {
     fn from(id: Identifier) -> Self {
-        id.0
+        id.name
     }
 }
 
-impl ToString for Identifier {
-    fn to_string(&self) -> String {
-        self.0.clone()
+impl std::fmt::Display for Identifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
     }
 }
 
 impl AsRef<String> for Identifier {
     fn as_ref(&self) -> &String {
-        &self.0
+        &self.name
     }
 }
 
 impl AsRef<str> for Identifier {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.name
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum Block {
-    Statements(Vec<Statement>),
+/// Statements between braces.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    /// The span from the opening to the closing brace.
+    pub span: Span,
 }
 
 impl Block {
-    pub fn new_statements(statements: Option<Vec<Statement>>) -> Self {
-        // Self::Statements(statements)
-        match statements {
-            Some(statements) => Self::Statements(statements),
-            None => Self::Statements(vec![]),
+    pub fn new_statements(statements: Option<Vec<Statement>>, span: Span) -> Self {
+        Self {
+            statements: statements.unwrap_or_default(),
+            span,
         }
     }
 
     pub fn statements(&self) -> &[Statement] {
-        match self {
-            Block::Statements(x) => x,
-        }
+        &self.statements
+    }
+
+    pub fn statements_mut(&mut self) -> &mut [Statement] {
+        &mut self.statements
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum IfStatement {
     If {
         condition: Box<Expr>,
@@ -260,6 +593,12 @@ pub enum IfStatement {
         body: Block,
         else_body: Block,
     },
+    /// An `if` followed by `else if` branches, and optionally an `else`.
+    ElseIf {
+        /// The condition and body of the `if`, then of each `else if`, in order.
+        branches: Vec<(Box<Expr>, Block)>,
+        else_body: Option<Block>,
+    },
 }
 
 impl IfStatement {
@@ -274,10 +613,17 @@ impl IfStatement {
             else_body,
         }
     }
+
+    pub fn new_else_if(branches: Vec<(Box<Expr>, Block)>, else_body: Option<Block>) -> Self {
+        Self::ElseIf {
+            branches,
+            else_body,
+        }
+    }
 }
 
 /// A statement that interacts with a device
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum DeviceStatement {
     Read {
         /// The device to read from
@@ -314,3 +660,44 @@ impl DeviceStatement {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans() {
+        let source = "let a = 1;\nd0.Setting = a + 2;\n";
+        let program = crate::parse(source).unwrap();
+
+        let span = |s: Span| &source[s.start..s.end];
+        assert_eq!(span(program.statements[0].span), "let a = 1;");
+        let stmt = &program.statements[1];
+        assert_eq!(span(stmt.span), "d0.Setting = a + 2;");
+        match &stmt.kind {
+            StatementKind::Assignment { lhs, rhs } => {
+                assert_eq!(span(lhs.span), "d0.Setting");
+                assert_eq!(span(rhs.span), "a + 2");
+                match &rhs.kind {
+                    ExprKind::BinaryOp(a, _, _) => assert_eq!(span(a.span), "a"),
+                    kind => panic!("unexpected expression {:?}", kind),
+                }
+            }
+            kind => panic!("unexpected statement {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_identifier_ignores_span() {
+        assert_eq!(Identifier::new("a", Span::new(0, 1)), Identifier::from("a"));
+    }
+
+    #[test]
+    fn test_serde() {
+        let source = "// heater\nloop { if !d0.On { d1.Setting = f(1.5, true); } }\n";
+        let program = crate::parse(source).unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        let parsed: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.to_string(), program.to_string());
+    }
+}
@@ -0,0 +1,326 @@
+//! Construction of ASTs from code, for tests and code generators.
+//!
+//! ```
+//! use ayysee_parser::builder::{field, ident, lit, ProgramBuilder};
+//!
+//! let program = ProgramBuilder::new()
+//!     .let_("x", lit(1))
+//!     .loop_(|body| {
+//!         body.if_(field("d0", "On"), |then| then.assign("x", ident("x") + lit(1)))
+//!             .set("db", "Setting", ident("x"))
+//!             .yield_()
+//!     })
+//!     .build();
+//! assert_eq!(
+//!     program.to_string(),
+//!     "let x = 1;\nloop {\n    if d0.On {\n        x = x + 1;\n    }\n    db.Setting = x;\n    yield;\n}\n"
+//! );
+//! ```
+//!
+//! Nodes built this way have empty spans.
+
+use crate::ast::{
+    BinaryOpcode, Block, Expr, ExprKind, Identifier, IfStatement, Program, Span, Statement,
+    StatementKind, UnaryOpcode, Value,
+};
+
+/// A literal number or boolean.
+pub fn lit(value: impl Into<Value>) -> Box<Expr> {
+    expr(ExprKind::Constant(value.into()))
+}
+
+/// A variable, constant, device pin or logic type.
+pub fn ident(name: &str) -> Box<Expr> {
+    expr(ExprKind::Identifier(name.into()))
+}
+
+/// A device field, as in `d0.Setting`.
+pub fn field(device: &str, variable: &str) -> Box<Expr> {
+    expr(ExprKind::FieldExpr(ident(device), variable.into()))
+}
+
+/// A field of a device given by an expression, as in `devices[i].Setting`.
+pub fn field_of(device: Box<Expr>, variable: &str) -> Box<Expr> {
+    expr(ExprKind::FieldExpr(device, variable.into()))
+}
+
+/// The device on a pin computed at run time, as in `devices[i]`.
+pub fn device_index(index: Box<Expr>) -> Box<Expr> {
+    expr(ExprKind::DeviceIndex(index))
+}
+
+/// An array of `length` copies of `value`, as in `[0; 8]`.
+pub fn array(value: Box<Expr>, length: Box<Expr>) -> Box<Expr> {
+    expr(ExprKind::Array(value, length))
+}
+
+/// An element of an array, as in `buf[i]`.
+pub fn index(array: &str, index: Box<Expr>) -> Box<Expr> {
+    expr(ExprKind::Index(array.into(), index))
+}
+
+/// The hash of a prefab or device name, as in `hash("StructureGasSensor")`.
+pub fn hash(name: &str) -> Box<Expr> {
+    expr(ExprKind::Hash(name.to_string()))
+}
+
+pub fn call(name: &str, arguments: impl IntoIterator<Item = Box<Expr>>) -> Box<Expr> {
+    expr(ExprKind::FunctionCall(
+        name.into(),
+        arguments.into_iter().collect(),
+    ))
+}
+
+pub fn binary(lhs: Box<Expr>, op: BinaryOpcode, rhs: Box<Expr>) -> Box<Expr> {
+    Expr::new_binary(lhs, op, rhs)
+}
+
+pub fn not(operand: Box<Expr>) -> Box<Expr> {
+    expr(ExprKind::UnaryOp(UnaryOpcode::Not, operand))
+}
+
+pub fn neg(operand: Box<Expr>) -> Box<Expr> {
+    expr(ExprKind::UnaryOp(UnaryOpcode::Neg, operand))
+}
+
+pub fn bit_not(operand: Box<Expr>) -> Box<Expr> {
+    expr(ExprKind::UnaryOp(UnaryOpcode::BitNot, operand))
+}
+
+fn expr(kind: ExprKind) -> Box<Expr> {
+    Box::new(Expr::new(kind, Span::default()))
+}
+
+macro_rules! operator {
+    ($trait:ident, $method:ident, $op:ident) => {
+        impl std::ops::$trait for Box<Expr> {
+            type Output = Box<Expr>;
+
+            fn $method(self, rhs: Box<Expr>) -> Box<Expr> {
+                binary(self, BinaryOpcode::$op, rhs)
+            }
+        }
+    };
+}
+
+operator!(Add, add, Add);
+operator!(Sub, sub, Sub);
+operator!(Mul, mul, Mul);
+operator!(Div, div, Div);
+operator!(Rem, rem, Mod);
+operator!(BitAnd, bitand, BitAnd);
+operator!(BitOr, bitor, BitOr);
+operator!(BitXor, bitxor, BitXor);
+operator!(Shl, shl, ShiftLeft);
+operator!(Shr, shr, ShiftRight);
+
+/// Builds a program, or the body of a block, one statement at a time.
+///
+/// Methods named after keywords end with `_`. Nested blocks are built by closures
+/// receiving an empty builder.
+#[derive(Default)]
+pub struct ProgramBuilder {
+    statements: Vec<Statement>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn statement(mut self, kind: StatementKind) -> Self {
+        self.statements.push(Statement::new(kind, Span::default()));
+        self
+    }
+
+    pub fn let_(self, name: &str, value: Box<Expr>) -> Self {
+        self.statement(StatementKind::new_definition(name.into(), value))
+    }
+
+    pub fn const_(self, name: &str, value: Box<Expr>) -> Self {
+        self.statement(StatementKind::new_constant(name.into(), value))
+    }
+
+    /// `alias alias = name;` for a device pin.
+    pub fn alias(self, name: &str, alias: &str) -> Self {
+        self.statement(StatementKind::new_alias(name.into(), alias.into()))
+    }
+
+    pub fn assign(self, name: &str, value: Box<Expr>) -> Self {
+        self.statement(StatementKind::new_assignment(ident(name), value))
+    }
+
+    /// Writes a device field, as in `d0.Setting = value;`.
+    pub fn set(self, device: &str, variable: &str, value: Box<Expr>) -> Self {
+        self.statement(StatementKind::new_assignment(
+            field(device, variable),
+            value,
+        ))
+    }
+
+    pub fn call(self, name: &str, arguments: impl IntoIterator<Item = Box<Expr>>) -> Self {
+        self.statement(StatementKind::new_function_call(
+            name.into(),
+            arguments.into_iter().collect(),
+        ))
+    }
+
+    pub fn fn_(self, name: &str, parameters: &[&str], body: impl FnOnce(Self) -> Self) -> Self {
+        let parameters = parameters.iter().map(|&p| Identifier::from(p)).collect();
+        self.statement(StatementKind::new_function(
+            name.into(),
+            parameters,
+            Self::block_of(body),
+        ))
+    }
+
+    pub fn if_(self, condition: Box<Expr>, body: impl FnOnce(Self) -> Self) -> Self {
+        self.statement(StatementKind::new_if(IfStatement::new_if(
+            condition,
+            Self::block_of(body),
+        )))
+    }
+
+    pub fn if_else(
+        self,
+        condition: Box<Expr>,
+        body: impl FnOnce(Self) -> Self,
+        else_body: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        self.statement(StatementKind::new_if(IfStatement::new_if_else(
+            condition,
+            Self::block_of(body),
+            Self::block_of(else_body),
+        )))
+    }
+
+    pub fn loop_(self, body: impl FnOnce(Self) -> Self) -> Self {
+        self.statement(StatementKind::new_loop(None, Self::block_of(body)))
+    }
+
+    /// `loop { body } until condition;`.
+    pub fn loop_until(self, body: impl FnOnce(Self) -> Self, condition: Box<Expr>) -> Self {
+        self.statement(StatementKind::new_loop_until(
+            None,
+            Self::block_of(body),
+            condition,
+        ))
+    }
+
+    /// `for variable in start..end { body }`.
+    pub fn for_(
+        self,
+        variable: &str,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        body: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        self.statement(StatementKind::new_for(
+            None,
+            variable.into(),
+            start,
+            end,
+            Self::block_of(body),
+        ))
+    }
+
+    pub fn block(self, body: impl FnOnce(Self) -> Self) -> Self {
+        self.statement(StatementKind::new_block(Self::block_of(body)))
+    }
+
+    pub fn yield_(self) -> Self {
+        self.statement(StatementKind::new_yield())
+    }
+
+    pub fn break_(self) -> Self {
+        self.statement(StatementKind::new_break(None))
+    }
+
+    pub fn continue_(self) -> Self {
+        self.statement(StatementKind::new_continue(None))
+    }
+
+    pub fn halt(self) -> Self {
+        self.statement(StatementKind::new_halt())
+    }
+
+    pub fn return_(self, value: Box<Expr>) -> Self {
+        self.statement(StatementKind::new_return(Some(value)))
+    }
+
+    pub fn import(self, path: &str) -> Self {
+        self.statement(StatementKind::new_import(path.to_string()))
+    }
+
+    pub fn build(self) -> Program {
+        Program::new(self.statements)
+    }
+
+    pub fn build_block(self) -> Block {
+        Block::new_statements(Some(self.statements), Span::default())
+    }
+
+    fn block_of(body: impl FnOnce(Self) -> Self) -> Block {
+        body(Self::new()).build_block()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_matches_parser() {
+        let source = r"const LIMIT = 10;
+alias sensor = d0;
+
+fn scale(a, b) {
+    return a * b / 2;
+}
+
+loop {
+    let t = sensor.Temperature;
+    if t > LIMIT && !d1.On {
+        store(db, Setting, scale(t, 2.5));
+    } else {
+        db.Setting = 0;
+    }
+    yield;
+}
+";
+        let program = ProgramBuilder::new()
+            .const_("LIMIT", lit(10))
+            .alias("d0", "sensor")
+            .fn_("scale", &["a", "b"], |body| {
+                body.return_(ident("a") * ident("b") / lit(2))
+            })
+            .loop_(|body| {
+                body.let_("t", field("sensor", "Temperature"))
+                    .if_else(
+                        binary(
+                            binary(ident("t"), BinaryOpcode::Greater, ident("LIMIT")),
+                            BinaryOpcode::Conj,
+                            not(field("d1", "On")),
+                        ),
+                        |then| {
+                            then.call(
+                                "store",
+                                [
+                                    ident("db"),
+                                    ident("Setting"),
+                                    call("scale", [ident("t"), lit(2.5)]),
+                                ],
+                            )
+                        },
+                        |otherwise| otherwise.set("db", "Setting", lit(0)),
+                    )
+                    .yield_()
+            })
+            .build();
+        assert_eq!(program.to_string(), source);
+        assert_eq!(
+            crate::parse(source).unwrap().to_string(),
+            program.to_string()
+        );
+    }
+}
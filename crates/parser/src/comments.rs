@@ -0,0 +1,199 @@
+use crate::ast::{Comment, Program, Span, Statement, StatementKind};
+use std::collections::VecDeque;
+
+/// Returns the `//` comments of the source, in order.
+///
+/// The grammar skips comments, so they are found by a separate scan. The language has no
+/// string literals, hence every `//` starts a comment.
+pub fn lex(source: &str) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        if let Some(start) = line.find("//") {
+            let text = line[start..].trim_end();
+            comments.push(Comment {
+                text: text.to_string(),
+                span: Span::new(offset + start, offset + start + text.len()),
+            });
+        }
+        offset += line.len();
+    }
+    comments
+}
+
+/// Attaches the comments of the source to the statements of the program.
+///
+/// Comments before a statement lead it, except for the `///` comments right before a
+/// function or constant, which document it. A comment on the line a statement ends on trails
+/// it, and comments after the last statement of a block are kept after it. Comments in
+/// places that hold no statement, such as an empty block or the middle of an expression,
+/// lead the enclosing statement.
+pub fn attach(program: &mut Program, source: &str) {
+    let comments = lex(source).into_iter().collect();
+    attach_block(&mut program.statements, comments, source);
+}
+
+/// Attaches the comments to the statements, returning them if there are no statements.
+fn attach_block(
+    statements: &mut [Statement],
+    mut comments: VecDeque<Comment>,
+    source: &str,
+) -> VecDeque<Comment> {
+    let count = statements.len();
+    for (i, statement) in statements.iter_mut().enumerate() {
+        while let Some(comment) = pop_if(&mut comments, |c| c.span.start < statement.span.start) {
+            statement.trivia.leading.push(comment);
+        }
+        let start = statement
+            .trivia
+            .leading
+            .first()
+            .map_or(statement.span.start, |c| c.span.start);
+        statement.trivia.blank_line = i > 0 && blank_line_before(source, start);
+        if matches!(
+            statement.kind,
+            StatementKind::Function { .. } | StatementKind::Constant(..)
+        ) {
+            let leading = &mut statement.trivia.leading;
+            let docs = leading.iter().rev().take_while(|c| c.is_doc()).count();
+            statement.trivia.doc = leading.split_off(leading.len() - docs);
+        }
+
+        let mut inside = VecDeque::new();
+        while let Some(comment) = pop_if(&mut comments, |c| c.span.start < statement.span.end) {
+            inside.push_back(comment);
+        }
+        if !inside.is_empty() {
+            let left = attach_nested(statement, inside, source);
+            statement.trivia.leading.extend(left);
+        }
+
+        let end = statement.span.end;
+        statement.trivia.trailing =
+            pop_if(&mut comments, |c| !source[end..c.span.start].contains('\n'));
+
+        if i + 1 == count {
+            statement.trivia.blank_line_after = comments
+                .front()
+                .is_some_and(|c| blank_line_before(source, c.span.start));
+            statement.trivia.after.extend(comments.drain(..));
+        }
+    }
+    comments
+}
+
+/// Distributes the comments among the blocks of the statement, returning those that fit
+/// in none of them.
+fn attach_nested(
+    statement: &mut Statement,
+    mut comments: VecDeque<Comment>,
+    source: &str,
+) -> VecDeque<Comment> {
+    let mut left = VecDeque::new();
+    for block in statement.blocks_mut() {
+        while let Some(comment) = pop_if(&mut comments, |c| c.span.start < block.span.start) {
+            left.push_back(comment);
+        }
+        let mut own = VecDeque::new();
+        while let Some(comment) = pop_if(&mut comments, |c| c.span.start < block.span.end) {
+            own.push_back(comment);
+        }
+        left.extend(attach_block(block.statements_mut(), own, source));
+    }
+    left.extend(comments);
+    left
+}
+
+/// Returns whether an empty line precedes the text at `offset`.
+fn blank_line_before(source: &str, offset: usize) -> bool {
+    let before = &source[..offset];
+    let whitespace = &before[before.trim_end().len()..];
+    whitespace.matches('\n').count() > 1
+}
+
+fn pop_if(comments: &mut VecDeque<Comment>, f: impl Fn(&Comment) -> bool) -> Option<Comment> {
+    if comments.front().is_some_and(f) {
+        comments.pop_front()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{IfStatement, StatementKind};
+
+    fn texts(comments: &[crate::ast::Comment]) -> Vec<&str> {
+        comments.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_attach() {
+        let source = r"// header
+let a = 1; // one
+loop {
+    // inside
+    yield;
+    // end of loop
+}
+if a {
+    a = 2;
+} else {
+    // else
+    a = 3;
+}
+// end of file
+";
+        let program = crate::parse(source).unwrap();
+        let [def, lp, cond] = &program.statements[..] else {
+            panic!("unexpected statements {:?}", program.statements)
+        };
+        assert_eq!(texts(&def.trivia.leading), vec!["// header"]);
+        assert_eq!(def.trivia.trailing.as_ref().unwrap().text, "// one");
+
+        let StatementKind::Loop { body, .. } = &lp.kind else {
+            panic!("expected a loop")
+        };
+        let yield_ = &body.statements()[0];
+        assert_eq!(texts(&yield_.trivia.leading), vec!["// inside"]);
+        assert_eq!(texts(&yield_.trivia.after), vec!["// end of loop"]);
+
+        let StatementKind::IfStatement(IfStatement::IfElse { else_body, .. }) = &cond.kind else {
+            panic!("expected an if statement")
+        };
+        assert_eq!(
+            texts(&else_body.statements()[0].trivia.leading),
+            vec!["// else"]
+        );
+        assert_eq!(texts(&cond.trivia.after), vec!["// end of file"]);
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let source = r"// Settings.
+/// The highest temperature.
+///
+/// In kelvin.
+const LIMIT = 300;
+/// Not documentation.
+let a = 1;
+
+//// Neither.
+fn f() {
+    return 1;
+}
+";
+        let program = crate::parse(source).unwrap();
+        let [limit, a, f] = &program.statements[..] else {
+            panic!("unexpected statements {:?}", program.statements)
+        };
+        assert_eq!(texts(&limit.trivia.leading), vec!["// Settings."]);
+        assert_eq!(
+            limit.doc().as_deref(),
+            Some("The highest temperature.\n\nIn kelvin.")
+        );
+        assert_eq!(a.doc(), None);
+        assert_eq!(f.doc(), None);
+        assert_eq!(program.to_string(), source);
+    }
+}
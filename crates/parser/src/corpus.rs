@@ -0,0 +1,135 @@
+//! Snapshot tests over a directory of programs.
+//!
+//! Every `name.ayy` of the directory is parsed and formatted. The syntax tree (or the
+//! syntax errors) is compared against `name.ast`, as JSON, and the formatted program,
+//! which must parse back to the same syntax tree, against `name.fmt`. The crate runs this
+//! over its own `corpus/` directory, so that changes to the grammar show up as snapshot
+//! differences; [`run`] can be pointed at any other directory of programs as well.
+
+use crate::format::{format, FormatConfig};
+use std::path::{Path, PathBuf};
+
+/// A snapshot that does not match what the current parser produces.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub snapshot: PathBuf,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} does not match:", self.snapshot.display())?;
+        let expected = self.expected.lines().collect::<Vec<_>>();
+        let actual = self.actual.lines().collect::<Vec<_>>();
+        // Show the first differing line, whole snapshots are too long to compare by eye.
+        let line = expected
+            .iter()
+            .zip(&actual)
+            .position(|(e, a)| e != a)
+            .unwrap_or(expected.len().min(actual.len()));
+        writeln!(f, "  line {}:", line + 1)?;
+        writeln!(f, "  - {}", expected.get(line).unwrap_or(&"<end of file>"))?;
+        write!(f, "  + {}", actual.get(line).unwrap_or(&"<end of file>"))
+    }
+}
+
+/// Checks every program of `dir` against its snapshots, returning those that differ.
+///
+/// With `bless`, differing and missing snapshots are written instead.
+pub fn run(dir: impl AsRef<Path>, bless: bool) -> anyhow::Result<Vec<Mismatch>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|p| p.extension().is_some_and(|e| e == "ayy"));
+    paths.sort();
+
+    let mut mismatches = vec![];
+    for path in paths {
+        let source = std::fs::read_to_string(&path)?;
+        for (extension, actual) in snapshots(&source)? {
+            let snapshot = path.with_extension(extension);
+            let expected = std::fs::read_to_string(&snapshot).unwrap_or_default();
+            if expected == actual {
+                continue;
+            }
+            if bless {
+                std::fs::write(&snapshot, actual)?;
+            } else {
+                mismatches.push(Mismatch {
+                    snapshot,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Returns the contents of the snapshots of a program, by extension.
+fn snapshots(source: &str) -> anyhow::Result<Vec<(&'static str, String)>> {
+    let program = match crate::parse(source) {
+        Ok(program) => program,
+        Err(errors) => {
+            let errors = errors.iter().map(|e| format!("{}\n", e)).collect();
+            return Ok(vec![("ast", errors)]);
+        }
+    };
+    anyhow::ensure!(
+        crate::cst::build(&program, source).to_string() == source,
+        "the concrete syntax tree is not lossless"
+    );
+    let ast = serde_json::to_string_pretty(&program)? + "\n";
+    let tree = shape(&program)?;
+    let formatted = format(program, source, &FormatConfig::default())?;
+    // Formatting must not change the meaning of the program, nor the formatted program.
+    let reformatted = crate::parse(&formatted)
+        .map_err(|_| anyhow::anyhow!("the formatted program does not parse"))?;
+    anyhow::ensure!(
+        shape(&reformatted)? == tree,
+        "the formatted program does not parse to the same syntax tree"
+    );
+    anyhow::ensure!(
+        format(reformatted, &formatted, &FormatConfig::default())? == formatted,
+        "formatting is not idempotent"
+    );
+    Ok(vec![("ast", ast), ("fmt", formatted)])
+}
+
+/// Returns the syntax tree as JSON without the spans and comments, which formatting moves.
+fn shape(program: &crate::ast::Program) -> anyhow::Result<serde_json::Value> {
+    fn strip(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.remove("span");
+                map.remove("trivia");
+                map.values_mut().for_each(strip);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(strip),
+            _ => {}
+        }
+    }
+    let mut value = serde_json::to_value(program)?;
+    strip(&mut value);
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    /// Run with `GALVANIC_BLESS=1` to update the snapshots after changing the grammar.
+    #[test]
+    fn test_corpus() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/corpus");
+        let bless = std::env::var_os("GALVANIC_BLESS").is_some();
+        let mismatches = super::run(dir, bless).unwrap();
+        for mismatch in &mismatches {
+            eprintln!("{}", mismatch);
+        }
+        assert!(
+            mismatches.is_empty(),
+            "{} snapshots differ",
+            mismatches.len()
+        );
+    }
+}
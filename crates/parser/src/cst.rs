@@ -0,0 +1,205 @@
+//! A lossless syntax tree.
+//!
+//! The AST drops whitespace, comments and punctuation. The concrete syntax tree keeps
+//! every byte of the source as a token, grouped in nodes following the statements, blocks
+//! and expressions of the AST, so that tools can edit a program precisely without
+//! reformatting it. The text of the tree is always exactly the source it was built from.
+
+use crate::ast::{Block, Expr, Program, Span, Statement};
+use crate::lexer::Lexer;
+use crate::visit::{walk_block, walk_expr, walk_statement, Visitor};
+use serde::{Deserialize, Serialize};
+use std::iter::Peekable;
+
+pub use crate::lexer::{lex, Token, TokenKind};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Program,
+    Statement,
+    Block,
+    Expr,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Element {
+    Node(Node),
+    Token(Token),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+    pub children: Vec<Element>,
+}
+
+impl Node {
+    /// Returns the tokens of the node, in order.
+    pub fn tokens(&self) -> Vec<&Token> {
+        let mut tokens = vec![];
+        for child in &self.children {
+            match child {
+                Element::Node(node) => tokens.extend(node.tokens()),
+                Element::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    /// Returns the innermost node containing the byte `offset`.
+    pub fn node_at(&self, offset: usize) -> &Node {
+        self.children
+            .iter()
+            .find_map(|child| match child {
+                Element::Node(node) if node.span.start <= offset && offset < node.span.end => {
+                    Some(node.node_at(offset))
+                }
+                _ => None,
+            })
+            .unwrap_or(self)
+    }
+
+    /// Returns the token containing the byte `offset`.
+    pub fn token_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens()
+            .into_iter()
+            .find(|t| t.span.start <= offset && offset < t.span.end)
+    }
+
+    /// Writes the tree with one element per line, indented by depth.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(&mut out, 0);
+        out
+    }
+
+    fn dump_into(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        out.push_str(&format!(
+            "{}{:?}@{}..{}\n",
+            indent, self.kind, self.span.start, self.span.end
+        ));
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.dump_into(out, depth + 1),
+                Element::Token(token) => out.push_str(&format!(
+                    "{}  {:?}@{}..{} {:?}\n",
+                    indent, token.kind, token.span.start, token.span.end, token.text
+                )),
+            }
+        }
+    }
+}
+
+/// Writes the source text of the node.
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for token in self.tokens() {
+            f.write_str(&token.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a program into its concrete syntax tree.
+pub fn parse(source: &str) -> Result<Node, Vec<crate::ParseError<'_>>> {
+    let program = crate::parse(source)?;
+    Ok(build(&program, source))
+}
+
+/// Builds the concrete syntax tree of a program parsed from `source`.
+pub fn build(program: &Program, source: &str) -> Node {
+    let mut spans = Spans(vec![]);
+    spans.visit_program(program);
+    let mut nodes = spans.0.into_iter().peekable();
+    let mut tokens = Lexer::new(source).peekable();
+    node(
+        NodeKind::Program,
+        Span::new(0, source.len()),
+        &mut nodes,
+        &mut tokens,
+    )
+}
+
+/// Collects the spans of the nodes, parents before their children.
+struct Spans(Vec<(NodeKind, Span)>);
+
+impl Visitor for Spans {
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.0.push((NodeKind::Statement, statement.span));
+        walk_statement(self, statement)
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.0.push((NodeKind::Block, block.span));
+        walk_block(self, block)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.0.push((NodeKind::Expr, expr.span));
+        walk_expr(self, expr)
+    }
+}
+
+fn node(
+    kind: NodeKind,
+    span: Span,
+    nodes: &mut Peekable<impl Iterator<Item = (NodeKind, Span)>>,
+    tokens: &mut Peekable<impl Iterator<Item = Token>>,
+) -> Node {
+    let mut children = vec![];
+    while let Some(token) = tokens.peek().filter(|t| t.span.start < span.end) {
+        let start = token.span.start;
+        match nodes.next_if(|(_, child)| child.start <= start) {
+            Some((kind, child)) => {
+                children.push(Element::Node(node(kind, child, nodes, tokens)));
+            }
+            None => children.push(Element::Token(tokens.next().unwrap())),
+        }
+    }
+    Node {
+        kind,
+        span,
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lossless() {
+        let source = "// header\nlet  a = -1.5 ;\n\nloop {\n\td0.Setting = a+f(2) ; // end\n}\n";
+        let tree = parse(source).unwrap();
+        assert_eq!(tree.to_string(), source);
+
+        let token = tree.token_at(source.find("Setting").unwrap()).unwrap();
+        assert_eq!(token.kind, TokenKind::Identifier);
+        assert_eq!(token.text, "Setting");
+        let number = tree.token_at(source.find("1.5").unwrap()).unwrap();
+        assert_eq!(number.text, "1.5");
+
+        let node = tree.node_at(source.find("f(2)").unwrap());
+        assert_eq!(node.kind, NodeKind::Expr);
+        assert_eq!(node.to_string(), "f(2)");
+        assert_eq!(
+            tree.node_at(source.find(';').unwrap()).to_string(),
+            "let  a = -1.5 ;"
+        );
+    }
+
+    #[test]
+    fn test_dump() {
+        assert_eq!(
+            parse("yield;\n").unwrap().dump(),
+            r#"Program@0..7
+  Statement@0..6
+    Keyword@0..5 "yield"
+    Punctuation@5..6 ";"
+  Whitespace@6..7 "\n"
+"#
+        );
+    }
+}
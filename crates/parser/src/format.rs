@@ -1,7 +1,749 @@
-use crate::ast::Program;
+use crate::ast::{
+    BinaryOpcode, Block, Comment, DeviceStatement, Expr, ExprKind, Identifier, IfStatement,
+    Program, Statement, StatementKind, UnaryOpcode, Value,
+};
+use std::fmt::Write;
 
-pub fn format(program: Program) -> anyhow::Result<String> {
-    Ok(program.to_string())
+/// Settings of the formatter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormatConfig {
+    /// Number of spaces blocks are indented by.
+    pub indent_width: usize,
+    /// Conditions making a line longer than this are split before their `&&` and `||`.
+    pub max_line_length: usize,
+    /// Whether binary operators are surrounded by spaces, as in `a + b` rather than `a+b`.
+    pub spaces_around_operators: bool,
+    /// Whether the last statement of a block keeps its `;`.
+    pub trailing_semicolons: bool,
+    /// Where the opening brace of a block goes.
+    pub brace_style: BraceStyle,
 }
 
-pub trait Formatter {}
+/// Placement of the opening brace of function, loop and `if` bodies.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BraceStyle {
+    /// At the end of the line, as in `loop {`.
+    #[default]
+    SameLine,
+    /// On a line of its own, with `else` after the closing brace of the previous branch on
+    /// the next line too.
+    NextLine,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            max_line_length: 100,
+            spaces_around_operators: true,
+            trailing_semicolons: true,
+            brace_style: BraceStyle::SameLine,
+        }
+    }
+}
+
+/// Formats the program, one statement per line, keeping its comments. `source` is the text
+/// the program was parsed from, whose integer literals are kept as written, such as
+/// `0xFF_FF`.
+pub fn format(program: Program, source: &str, config: &FormatConfig) -> anyhow::Result<String> {
+    let mut out = String::new();
+    Printer::new(&mut out, source, config).statements(&program.statements)?;
+    Ok(out)
+}
+
+/// Writes statements and expressions as source code.
+///
+/// Expressions are parenthesized only where needed to keep their meaning, as the parser
+/// does not keep the parentheses of the source.
+struct Printer<'a> {
+    out: &'a mut dyn Write,
+    /// The source the spans point into, empty when unknown.
+    source: &'a str,
+    config: &'a FormatConfig,
+    depth: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(out: &'a mut dyn Write, source: &'a str, config: &'a FormatConfig) -> Self {
+        Self {
+            out,
+            source,
+            config,
+            depth: 0,
+        }
+    }
+
+    fn indent(&mut self) -> std::fmt::Result {
+        let width = self.depth * self.config.indent_width;
+        write!(self.out, "{:width$}", "", width = width)
+    }
+
+    /// Writes the statements on their own lines with their comments, separating functions
+    /// from their neighbours by a blank line and keeping single blank lines of the source.
+    fn statements(&mut self, statements: &[Statement]) -> std::fmt::Result {
+        for (i, statement) in statements.iter().enumerate() {
+            let function = matches!(statement.kind, StatementKind::Function { .. });
+            let after_function =
+                i > 0 && matches!(statements[i - 1].kind, StatementKind::Function { .. });
+            if i > 0 && (function || after_function || statement.trivia.blank_line) {
+                self.out.write_char('\n')?;
+            }
+            for comment in statement.trivia.leading.iter().chain(&statement.trivia.doc) {
+                self.comment(comment)?;
+            }
+            self.indent()?;
+            if self.depth > 0 && !self.config.trailing_semicolons && i + 1 == statements.len() {
+                let mut line = String::new();
+                Printer {
+                    out: &mut line,
+                    source: self.source,
+                    config: self.config,
+                    depth: self.depth,
+                }
+                .statement(statement)?;
+                self.out
+                    .write_str(line.strip_suffix(';').unwrap_or(&line))?;
+            } else {
+                self.statement(statement)?;
+            }
+            if let Some(comment) = &statement.trivia.trailing {
+                write!(self.out, " {}", comment.text)?;
+            }
+            self.out.write_char('\n')?;
+            if statement.trivia.blank_line_after {
+                self.out.write_char('\n')?;
+            }
+            for comment in &statement.trivia.after {
+                self.comment(comment)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a comment on its own line.
+    fn comment(&mut self, comment: &Comment) -> std::fmt::Result {
+        self.indent()?;
+        writeln!(self.out, "{}", comment.text)
+    }
+
+    /// Writes a single statement, without indentation or a trailing newline.
+    fn statement(&mut self, statement: &Statement) -> std::fmt::Result {
+        match &statement.kind {
+            StatementKind::Assignment { lhs, rhs } => {
+                self.expr(lhs)?;
+                self.out.write_str(" = ")?;
+                self.expr(rhs)?;
+                self.out.write_char(';')
+            }
+            StatementKind::Definition {
+                identifier,
+                expression,
+            } => {
+                write!(self.out, "let {} = ", identifier)?;
+                self.expr(expression)?;
+                self.out.write_char(';')
+            }
+            StatementKind::TupleDefinition {
+                identifiers,
+                expressions,
+            } => {
+                self.out.write_str("let (")?;
+                for (i, identifier) in identifiers.iter().enumerate() {
+                    if i > 0 {
+                        self.out.write_str(", ")?;
+                    }
+                    write!(self.out, "{}", identifier)?;
+                }
+                self.out.write_str(") = (")?;
+                self.arguments(expressions)?;
+                self.out.write_str(");")
+            }
+            StatementKind::Alias { identifier, alias } => {
+                write!(self.out, "alias {} = {};", alias, identifier)
+            }
+            StatementKind::Constant(identifier, expression) => {
+                write!(self.out, "const {} = ", identifier)?;
+                self.expr(expression)?;
+                self.out.write_char(';')
+            }
+            StatementKind::Function {
+                identifier,
+                parameters,
+                body,
+            } => {
+                write!(self.out, "fn {}(", identifier)?;
+                for (i, parameter) in parameters.iter().enumerate() {
+                    if i > 0 {
+                        self.out.write_str(", ")?;
+                    }
+                    write!(self.out, "{}", parameter)?;
+                }
+                self.out.write_char(')')?;
+                self.separator()?;
+                self.block(body)
+            }
+            StatementKind::FunctionCall {
+                identifier,
+                arguments,
+            } => {
+                write!(self.out, "{}(", identifier)?;
+                self.arguments(arguments)?;
+                self.out.write_str(");")
+            }
+            StatementKind::Block(block) => self.block(block),
+            StatementKind::Loop { label, body } => {
+                self.label(label)?;
+                self.out.write_str("loop")?;
+                self.separator()?;
+                self.block(body)
+            }
+            StatementKind::LoopUntil {
+                label,
+                body,
+                condition,
+            } => {
+                self.label(label)?;
+                self.out.write_str("loop")?;
+                self.separator()?;
+                self.block(body)?;
+                self.out.write_str(" until ")?;
+                self.expr(condition)?;
+                self.out.write_char(';')
+            }
+            StatementKind::For {
+                label,
+                variable,
+                start,
+                end,
+                body,
+            } => {
+                self.label(label)?;
+                write!(self.out, "for {} in ", variable)?;
+                self.expr(start)?;
+                self.out.write_str("..")?;
+                self.expr(end)?;
+                self.separator()?;
+                self.block(body)
+            }
+            StatementKind::IfStatement(IfStatement::If { condition, body }) => {
+                self.condition("if", condition)?;
+                self.block(body)
+            }
+            StatementKind::IfStatement(IfStatement::IfElse {
+                condition,
+                body,
+                else_body,
+            }) => {
+                self.condition("if", condition)?;
+                self.block(body)?;
+                self.separator()?;
+                self.out.write_str("else")?;
+                self.separator()?;
+                self.block(else_body)
+            }
+            StatementKind::IfStatement(IfStatement::ElseIf {
+                branches,
+                else_body,
+            }) => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    if i > 0 {
+                        self.separator()?;
+                    }
+                    self.condition(if i == 0 { "if" } else { "else if" }, condition)?;
+                    self.block(body)?;
+                }
+                if let Some(else_body) = else_body {
+                    self.separator()?;
+                    self.out.write_str("else")?;
+                    self.separator()?;
+                    self.block(else_body)?;
+                }
+                Ok(())
+            }
+            StatementKind::Match {
+                value,
+                arms,
+                default_arm,
+            } => {
+                self.out.write_str("match ")?;
+                self.expr(value)?;
+                self.out.write_str(" {\n")?;
+                self.depth += 1;
+                for (pattern, body) in arms {
+                    self.indent()?;
+                    self.expr(pattern)?;
+                    self.out.write_str(" => ")?;
+                    self.block(body)?;
+                    self.out.write_char('\n')?;
+                }
+                if let Some(default_arm) = default_arm {
+                    self.indent()?;
+                    self.out.write_str("_ => ")?;
+                    self.block(default_arm)?;
+                    self.out.write_char('\n')?;
+                }
+                self.depth -= 1;
+                self.indent()?;
+                self.out.write_char('}')
+            }
+            StatementKind::DeviceStatement(DeviceStatement::Read {
+                device,
+                device_variable,
+                local,
+            }) => write!(self.out, "let {} = {}.{};", local, device, device_variable),
+            StatementKind::DeviceStatement(DeviceStatement::Write {
+                value,
+                device,
+                device_variable,
+            }) => {
+                write!(self.out, "{}.{} = ", device, device_variable)?;
+                self.expr(value)?;
+                self.out.write_char(';')
+            }
+            StatementKind::Yield => self.out.write_str("yield;"),
+            StatementKind::Break(label) => self.jump("break", label),
+            StatementKind::Continue(label) => self.jump("continue", label),
+            StatementKind::Halt => self.out.write_str("halt;"),
+            StatementKind::Return(None) => self.out.write_str("return;"),
+            StatementKind::Return(Some(expr)) => {
+                self.out.write_str("return ")?;
+                self.expr(expr)?;
+                self.out.write_char(';')
+            }
+            StatementKind::Import(path) => write!(self.out, "import \"{}\";", path),
+            StatementKind::Error => self.out.write_str("// syntax error"),
+        }
+    }
+
+    /// Writes `if`, or `else if` after the closing brace of the previous branch, and its
+    /// condition, up to the opening brace of the body. Conditions too long for a line are
+    /// split before each operand of their outermost `&&` or `||`, with the brace on its own
+    /// line.
+    fn condition(&mut self, keyword: &str, condition: &Expr) -> std::fmt::Result {
+        let mut flat = String::new();
+        Printer::new(&mut flat, self.source, self.config).expr(condition)?;
+        let before = if keyword == "if" { 0 } else { "} ".len() };
+        let width = self.depth * self.config.indent_width
+            + before
+            + keyword.len()
+            + " ".len()
+            + flat.len()
+            + " {".len();
+        let operands = match &condition.kind {
+            ExprKind::BinaryOp(_, op @ (BinaryOpcode::Conj | BinaryOpcode::Disj), _)
+                if width > self.config.max_line_length =>
+            {
+                chain(condition, *op)
+            }
+            _ => {
+                write!(self.out, "{} {}", keyword, flat)?;
+                return self.separator();
+            }
+        };
+        write!(self.out, "{} ", keyword)?;
+        for (i, (op, operand)) in operands.into_iter().enumerate() {
+            if i > 0 {
+                self.out.write_char('\n')?;
+                self.depth += 1;
+                self.indent()?;
+                self.depth -= 1;
+                write!(self.out, "{:?} ", op)?;
+            }
+            let parens = precedence(operand) <= precedence(condition);
+            self.operand(operand, parens)?;
+        }
+        self.out.write_char('\n')?;
+        self.indent()
+    }
+
+    /// Writes what separates a function, loop or `if` header from the opening brace of its
+    /// body, and a closing brace from the `else` after it.
+    fn separator(&mut self) -> std::fmt::Result {
+        match self.config.brace_style {
+            BraceStyle::SameLine => self.out.write_char(' '),
+            BraceStyle::NextLine => {
+                self.out.write_char('\n')?;
+                self.indent()
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) -> std::fmt::Result {
+        if block.statements().is_empty() {
+            return self.out.write_str("{}");
+        }
+        self.out.write_str("{\n")?;
+        self.depth += 1;
+        self.statements(block.statements())?;
+        self.depth -= 1;
+        self.indent()?;
+        self.out.write_char('}')
+    }
+
+    /// Writes the label of a loop, if any.
+    fn label(&mut self, label: &Option<Identifier>) -> std::fmt::Result {
+        match label {
+            Some(label) => write!(self.out, "{}: ", label),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes `break;` or `continue;`, with the label of the loop, if any.
+    fn jump(&mut self, keyword: &str, label: &Option<Identifier>) -> std::fmt::Result {
+        match label {
+            Some(label) => write!(self.out, "{} {};", keyword, label),
+            None => write!(self.out, "{};", keyword),
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Box<Expr>]) -> std::fmt::Result {
+        for (i, argument) in arguments.iter().enumerate() {
+            if i > 0 {
+                self.out.write_str(", ")?;
+            }
+            self.expr(argument)?;
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> std::fmt::Result {
+        match &expr.kind {
+            ExprKind::Constant(value) => self.value(value, expr),
+            ExprKind::Identifier(identifier) => write!(self.out, "{}", identifier),
+            ExprKind::BinaryOp(lhs, op, rhs) => {
+                let (lhs_parens, rhs_parens) = parentheses(expr);
+                self.operand(lhs, lhs_parens)?;
+                // `a--b` would read as a decrement.
+                let minus = *op == BinaryOpcode::Sub && !rhs_parens && starts_with_minus(rhs);
+                if self.config.spaces_around_operators || minus {
+                    write!(self.out, " {:?} ", op)?;
+                } else {
+                    write!(self.out, "{:?}", op)?;
+                }
+                self.operand(rhs, rhs_parens)
+            }
+            ExprKind::UnaryOp(op, operand) => {
+                match op {
+                    UnaryOpcode::Not => self.out.write_char('!')?,
+                    UnaryOpcode::Neg => self.out.write_char('-')?,
+                    UnaryOpcode::BitNot => self.out.write_char('~')?,
+                }
+                // `--a` would read as a decrement.
+                let minus = matches!(op, UnaryOpcode::Neg) && starts_with_minus(operand);
+                self.operand(operand, minus || precedence(operand) < precedence(expr))
+            }
+            ExprKind::FunctionCall(identifier, arguments) => {
+                write!(self.out, "{}(", identifier)?;
+                self.arguments(arguments)?;
+                self.out.write_char(')')
+            }
+            ExprKind::FieldExpr(device, variable) => {
+                self.expr(device)?;
+                write!(self.out, ".{}", variable)
+            }
+            ExprKind::DeviceIndex(index) => {
+                self.out.write_str("devices[")?;
+                self.expr(index)?;
+                self.out.write_char(']')
+            }
+            ExprKind::Array(value, length) => {
+                self.out.write_char('[')?;
+                self.expr(value)?;
+                self.out.write_str("; ")?;
+                self.expr(length)?;
+                self.out.write_char(']')
+            }
+            ExprKind::Index(array, index) => {
+                write!(self.out, "{}[", array)?;
+                self.expr(index)?;
+                self.out.write_char(']')
+            }
+            ExprKind::Hash(name) => write!(self.out, "hash(\"{}\")", name),
+        }
+    }
+
+    fn operand(&mut self, expr: &Expr, parens: bool) -> std::fmt::Result {
+        if parens {
+            self.out.write_char('(')?;
+            self.expr(expr)?;
+            self.out.write_char(')')
+        } else {
+            self.expr(expr)
+        }
+    }
+
+    fn value(&mut self, value: &Value, expr: &Expr) -> std::fmt::Result {
+        match value {
+            // Bit masks are easier to read in the radix and with the separators they were
+            // written with.
+            Value::Integer(x) => match self.source.get(expr.span.start..expr.span.end) {
+                Some(literal) if integer_literal(literal) == Some(*x) => {
+                    self.out.write_str(literal)
+                }
+                _ => write!(self.out, "{}", x),
+            },
+            // Floats need a fractional part to be parsed back as floats.
+            Value::Float(x) if x.fract() == 0.0 => write!(self.out, "{}.0", x),
+            Value::Float(x) => write!(self.out, "{}", x),
+            Value::Boolean(x) => write!(self.out, "{}", x),
+        }
+    }
+}
+
+/// Returns the value of an integer literal as the grammar reads it, if `literal` is one.
+fn integer_literal(literal: &str) -> Option<i64> {
+    let (digits, radix) = match (literal.strip_prefix("0x"), literal.strip_prefix("0b")) {
+        (Some(digits), _) => (digits, 16),
+        (_, Some(digits)) => (digits, 2),
+        _ => (literal, 10),
+    };
+    // Only hexadecimal and binary literals have separators, after their first digit.
+    if !digits.starts_with(|c: char| c.is_digit(radix)) || (radix == 10 && digits.contains('_')) {
+        return None;
+    }
+    i64::from_str_radix(&digits.replace('_', ""), radix).ok()
+}
+
+/// Returns how tightly the expression binds, following the levels of the grammar.
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::BinaryOp(_, op, _) => match op {
+            BinaryOpcode::Disj => 1,
+            BinaryOpcode::Conj => 2,
+            BinaryOpcode::Equals
+            | BinaryOpcode::NotEquals
+            | BinaryOpcode::Greater
+            | BinaryOpcode::GreaterEquals
+            | BinaryOpcode::Lower
+            | BinaryOpcode::LowerEquals => 3,
+            BinaryOpcode::BitOr => 4,
+            BinaryOpcode::BitXor => 5,
+            BinaryOpcode::BitAnd => 6,
+            BinaryOpcode::ShiftLeft | BinaryOpcode::ShiftRight => 7,
+            BinaryOpcode::Add | BinaryOpcode::Sub => 8,
+            BinaryOpcode::Mul | BinaryOpcode::Div | BinaryOpcode::Mod => 9,
+            BinaryOpcode::Pow => 11,
+        },
+        ExprKind::UnaryOp(..) => 10,
+        _ => 12,
+    }
+}
+
+/// Returns whether the operands of a binary operation need parentheses, on the left and on
+/// the right.
+fn parentheses(expr: &Expr) -> (bool, bool) {
+    let ExprKind::BinaryOp(lhs, op, rhs) = &expr.kind else {
+        return (false, false);
+    };
+    let level = precedence(expr);
+    // Operators are left associative, except comparisons which do not chain and `**`, which
+    // is right associative and takes a unary operand on its right.
+    match op {
+        BinaryOpcode::Pow => (precedence(lhs) <= level, precedence(rhs) < level - 1),
+        _ => (
+            precedence(lhs) < level || (op.is_comparison() && precedence(lhs) == level),
+            precedence(rhs) <= level,
+        ),
+    }
+}
+
+/// Returns whether the expression is printed starting with a minus.
+fn starts_with_minus(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::UnaryOp(UnaryOpcode::Neg, _) => true,
+        ExprKind::Constant(value) => f64::from(value) < 0.0,
+        ExprKind::BinaryOp(lhs, _, _) => !parentheses(expr).0 && starts_with_minus(lhs),
+        _ => false,
+    }
+}
+
+/// Splits a chain of `op` operations, such as `a && b && c`, into its operands, each with
+/// the operator before it.
+fn chain(expr: &Expr, op: BinaryOpcode) -> Vec<(BinaryOpcode, &Expr)> {
+    match &expr.kind {
+        ExprKind::BinaryOp(lhs, lhs_op, rhs) if *lhs_op == op => {
+            let mut operands = chain(lhs, op);
+            operands.push((op, rhs));
+            operands
+        }
+        _ => vec![(op, expr)],
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Printer::new(f, "", &FormatConfig::default()).statements(&self.statements)
+    }
+}
+
+impl std::fmt::Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Printer::new(f, "", &FormatConfig::default()).statement(self)
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Printer::new(f, "", &FormatConfig::default()).expr(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        fmt_with(source, &FormatConfig::default())
+    }
+
+    fn fmt_with(source: &str, config: &FormatConfig) -> String {
+        format(crate::parse(source).unwrap(), source, config).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        let source = r"
+            const   LIMIT=  20.0;
+            fn   heat(t){if t<LIMIT{return 1;}else{return 0;}}
+            loop{let t=d0.Temperature;d1.On=heat(t);
+            if !(t > 1 && t < 2) || t == 3 {} yield;}
+        ";
+        assert_eq!(
+            fmt(source),
+            "const LIMIT = 20.0;
+
+fn heat(t) {
+    if t < LIMIT {
+        return 1;
+    } else {
+        return 0;
+    }
+}
+
+loop {
+    let t = d0.Temperature;
+    d1.On = heat(t);
+    if !(t > 1 && t < 2) || t == 3 {}
+    yield;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_needed_parentheses() {
+        let source = "let a = (1 - (2 - 3)) * (4 + 5) / (6 / 7);\nlet b = (a < 1) == true;\n";
+        assert_eq!(
+            fmt(source),
+            "let a = (1 - (2 - 3)) * (4 + 5) / (6 / 7);\nlet b = (a < 1) == true;\n"
+        );
+        assert_eq!(fmt("let c = ((1 * 2) + 3);\n"), "let c = 1 * 2 + 3;\n");
+        assert_eq!(
+            fmt("let d = -(-a) * (-a) + !(!a);\n"),
+            "let d = -(-a) * -a + !!a;\n"
+        );
+        assert_eq!(fmt("let e = -(-1);\n"), "let e = -(-1);\n");
+        assert_eq!(
+            fmt("let f = (a ** b) ** (c ** d) + (-a) ** (-b) + -(a ** b);\n"),
+            "let f = (a ** b) ** c ** d + (-a) ** -b + -a ** b;\n"
+        );
+    }
+
+    #[test]
+    fn test_format_is_idempotent() {
+        let source = "fn f(a, b) { return a + b; }\nlet x = f(1, 2.5);\n{ d0.Setting = x; }\n";
+        let formatted = fmt(source);
+        assert_eq!(fmt(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_format_config() {
+        let config = FormatConfig {
+            indent_width: 2,
+            max_line_length: 20,
+            spaces_around_operators: false,
+            ..FormatConfig::default()
+        };
+        let source = "loop { if a > 1 && b < 2 || c == 3 && d { yield; } d0.On = a + 1; }";
+        assert_eq!(
+            fmt_with(source, &config),
+            "loop {
+  if a>1&&b<2
+    || c==3&&d
+  {
+    yield;
+  }
+  d0.On = a+1;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_format_without_spaces_parses_back() {
+        let config = FormatConfig {
+            spaces_around_operators: false,
+            ..FormatConfig::default()
+        };
+        let source = "let a = x - 1 - -1 - -x ** 2 - (-1) * 2 + -(x - 1) - 0x1 - 1.5;\n";
+        let formatted = fmt_with(source, &config);
+        assert_eq!(
+            formatted,
+            "let a = x-1 - -1 - -x**2 - -1*2+-(x-1)-0x1-1.5;\n"
+        );
+        assert_eq!(fmt(&formatted), fmt(source));
+    }
+
+    #[test]
+    fn test_format_brace_style() {
+        let config = FormatConfig {
+            trailing_semicolons: false,
+            brace_style: BraceStyle::NextLine,
+            ..FormatConfig::default()
+        };
+        let source = "fn f(a) { if a { return 1; } else if a > 2 { yield; } else { return 2; } }\nlet x = f(1);\n";
+        let formatted = fmt_with(source, &config);
+        assert_eq!(
+            formatted,
+            "fn f(a)
+{
+    if a
+    {
+        return 1
+    }
+    else if a > 2
+    {
+        yield
+    }
+    else
+    {
+        return 2
+    }
+}
+
+let x = f(1);
+"
+        );
+        assert_eq!(fmt_with(&formatted, &config), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_comments() {
+        let source = "//! test ticks\n// heat\nfn heat(t) { return t<1; } // below\nloop {\n  yield;\n    // done\n}\n";
+        let formatted = fmt(source);
+        assert_eq!(
+            formatted,
+            "//! test ticks\n// heat\nfn heat(t) {\n    return t < 1;\n} // below\n\nloop {\n    yield;\n    // done\n}\n"
+        );
+        assert_eq!(fmt(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_blank_lines() {
+        let source = "let a = 1;\n\n\n// b\nlet b = 2;\nlet c = 3;\n\n// end\n";
+        assert_eq!(
+            fmt(source),
+            "let a = 1;\n\n// b\nlet b = 2;\nlet c = 3;\n\n// end\n"
+        );
+    }
+}
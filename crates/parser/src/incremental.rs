@@ -0,0 +1,195 @@
+//! Reparsing after an edit.
+//!
+//! Top-level statements are parsed independently of each other, so after an edit only the
+//! statements it touches need to be parsed again. The others are kept, with their spans
+//! moved by the change in length, and the comments of the whole program are attached
+//! again, which is cheap compared to parsing.
+
+use crate::ast::{Block, Expr, Identifier, Program, Span, Statement, Trivia};
+use crate::visit::{walk_block_mut, walk_expr_mut, walk_statement_mut, VisitorMut};
+use crate::ParseError;
+
+/// A replacement of part of the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    /// The replaced part of the previous source.
+    pub span: Span,
+    pub text: String,
+}
+
+impl Edit {
+    /// Applies the edit to the source it was made on.
+    pub fn apply(&self, source: &str) -> String {
+        let mut source = source.to_string();
+        source.replace_range(self.span.start..self.span.end, &self.text);
+        source
+    }
+}
+
+/// Parses `source`, which is the source `previous` was parsed from with `edit` applied.
+///
+/// Returns the same program and errors as [`crate::parse`] on the whole source.
+pub fn reparse<'input>(
+    previous: Program,
+    edit: &Edit,
+    source: &'input str,
+) -> Result<Program, Vec<ParseError<'input>>> {
+    let delta = edit.text.len() as isize - (edit.span.end - edit.span.start) as isize;
+    let mut statements = previous.statements;
+
+    // The statements touching the edit, or the gap between statements it is in. The region
+    // reparsed starts after the statement before them rather than at the edit, which may be
+    // in a comment or a string.
+    let first = statements.partition_point(|s| s.span.end < edit.span.start);
+    let last = statements.partition_point(|s| s.span.start <= edit.span.end);
+    let start = first.checked_sub(1).map_or(0, |i| statements[i].span.end);
+    let end = match statements.get(last) {
+        Some(s) => s.span.start,
+        None => (source.len() as isize - delta) as usize,
+    };
+    let new_end = (end as isize + delta) as usize;
+
+    let text = &source[start..new_end];
+    let mut recovered = vec![];
+    let region = match crate::grammar::ProgramParser::new().parse(&mut recovered, text) {
+        Ok(region) if recovered.is_empty() => region,
+        // The grammar wants at least one statement.
+        _ if text
+            .lines()
+            .all(|l| l.trim().is_empty() || l.trim().starts_with("//")) =>
+        {
+            Program::new(vec![])
+        }
+        // Leave the reporting of errors to a full parse.
+        _ => return crate::parse(source),
+    };
+
+    let after = statements.split_off(last);
+    statements.truncate(first);
+    let mut new = region.statements;
+    let mut shift = Shift(start as isize);
+    for statement in &mut new {
+        shift.visit_statement_mut(statement);
+    }
+    statements.extend(new);
+    let mut shift = Shift(delta);
+    for mut statement in after {
+        shift.visit_statement_mut(&mut statement);
+        statements.push(statement);
+    }
+
+    let mut program = Program::new(statements);
+    ClearTrivia.visit_program_mut(&mut program);
+    crate::comments::attach(&mut program, source);
+    Ok(program)
+}
+
+/// Moves every span by a number of bytes.
+struct Shift(isize);
+
+impl Shift {
+    fn span(&self, span: &mut Span) {
+        span.start = (span.start as isize + self.0) as usize;
+        span.end = (span.end as isize + self.0) as usize;
+    }
+}
+
+impl VisitorMut for Shift {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        self.span(&mut statement.span);
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        self.span(&mut block.span);
+        walk_block_mut(self, block)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        self.span(&mut expr.span);
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+        self.span(&mut identifier.span);
+    }
+}
+
+struct ClearTrivia;
+
+impl VisitorMut for ClearTrivia {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        statement.trivia = Trivia::default();
+        walk_statement_mut(self, statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that reparsing after the edit gives the same program as a full parse.
+    fn check(source: &str, edit: Edit) {
+        let previous = crate::parse(source).unwrap();
+        let new_source = edit.apply(source);
+        let reparsed = reparse(previous, &edit, &new_source).map_err(|e| e.len());
+        let parsed = crate::parse(&new_source).map_err(|e| e.len());
+        assert_eq!(
+            serde_json::to_value(reparsed.as_ref().ok()).unwrap(),
+            serde_json::to_value(parsed.as_ref().ok()).unwrap(),
+            "after editing into {:?}",
+            new_source
+        );
+        assert_eq!(reparsed.err(), parsed.err());
+    }
+
+    fn edit(source: &str, old: &str, text: &str) -> Edit {
+        let start = source.find(old).unwrap();
+        Edit {
+            span: Span::new(start, start + old.len()),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_reparse() {
+        let source =
+            "// a\nlet a = 1;\n\nfn f(x) {\n    return x; // x\n}\nloop {\n    yield;\n}\n";
+        check(source, edit(source, "1", "100 + 2"));
+        check(
+            source,
+            edit(source, "return x;", "let y = x;\n    return y;"),
+        );
+        check(source, edit(source, "\n\n", "\nlet b = 2;\n"));
+        check(source, edit(source, "// a\n", ""));
+        check(source, edit(source, "loop {\n    yield;\n}\n", ""));
+        check(source, edit(source, "}\nloop", "}\nlet c = a;\nloop"));
+        check(
+            source,
+            Edit {
+                span: Span::new(source.len(), source.len()),
+                text: "yield;".into(),
+            },
+        );
+        check(
+            source,
+            Edit {
+                span: Span::new(0, 0),
+                text: "const B = 1;".into(),
+            },
+        );
+        // Edits breaking or merging statements.
+        check(source, edit(source, ";\n\nfn", "\n\nfn"));
+        check(source, edit(source, "}\nloop", "loop"));
+        check(source, edit(source, "let a", "le t a"));
+    }
+
+    #[test]
+    fn test_reparse_from_comment_or_string() {
+        let source = "let a = 1;\n// x = 1;\nlet b = 2;\n";
+        check(source, edit(source, "x = 1;\nlet b", "x = 1;\nlet c"));
+        check(source, edit(source, "1;\nlet b", "1;\nlet b = 3;\nlet d"));
+        let source = "let a = 1;\nlet h = hash(\"x; let y = 2;\");\nlet b = 2;\n";
+        check(source, edit(source, "2;\");\nlet b", "3;\");\nlet c"));
+    }
+}
@@ -0,0 +1,221 @@
+//! Tokens of the source.
+//!
+//! The grammar has its own lexer, which skips whitespace and comments. This one keeps every
+//! byte of the source, for tools that work on the text rather than the syntax tree, such as
+//! highlighters and the [concrete syntax tree](crate::cst).
+
+use crate::ast::Span;
+use serde::{Deserialize, Serialize};
+
+pub(crate) const KEYWORDS: &[&str] = &[
+    "let", "const", "fn", "return", "loop", "yield", "break", "continue", "halt", "if", "else",
+    "true", "false", "hash", "alias", "devices", "for", "in", "until", "match", "import",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenKind {
+    Whitespace,
+    Comment,
+    Keyword,
+    Identifier,
+    Number,
+    /// A quoted name, such as the argument of `hash`.
+    String,
+    Punctuation,
+    /// A character the language does not use.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+}
+
+impl Token {
+    /// Whether the token is whitespace or a comment, which the grammar skips.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.kind, TokenKind::Whitespace | TokenKind::Comment)
+    }
+}
+
+/// Splits the source into tokens, whitespace and comments included.
+pub fn lex(source: &str) -> Vec<Token> {
+    Lexer::new(source).collect()
+}
+
+/// Yields the tokens of a source one at a time, in order.
+///
+/// The lexer never fails: characters the language does not use are
+/// [`TokenKind::Unknown`] tokens, and the texts of the tokens always add up to the source.
+pub struct Lexer<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self { source, offset: 0 }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let rest = &self.source[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let start = self.offset;
+        let (kind, len) = token(rest);
+        self.offset += len;
+        Some(Token {
+            kind,
+            text: rest[..len].to_string(),
+            span: Span::new(start, start + len),
+        })
+    }
+}
+
+/// Returns the kind and length of the token at the start of `text`.
+fn token(text: &str) -> (TokenKind, usize) {
+    let len_while = |f: fn(char) -> bool| text.find(|c| !f(c)).unwrap_or(text.len());
+    let first = text.chars().next().unwrap();
+    let digits = |text: &str| {
+        text.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len())
+    };
+    if first.is_whitespace() {
+        (TokenKind::Whitespace, len_while(char::is_whitespace))
+    } else if text.starts_with("//") {
+        (
+            TokenKind::Comment,
+            text.find(['\r', '\n']).unwrap_or(text.len()),
+        )
+    } else if first.is_ascii_alphabetic() {
+        let len = len_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        let kind = if KEYWORDS.contains(&&text[..len]) {
+            TokenKind::Keyword
+        } else {
+            TokenKind::Identifier
+        };
+        (kind, len)
+    } else if first.is_ascii_digit() {
+        // A minus before a number is an operator, so that `x-1` is a subtraction.
+        if let Some(len) = radix_len(text) {
+            return (TokenKind::Number, len);
+        }
+        let mut len = digits(text);
+        if text[len..].starts_with('.') && digits(&text[len + 1..]) > 0 {
+            len += 1 + digits(&text[len + 1..]);
+        }
+        (TokenKind::Number, len)
+    } else if first == '"' {
+        // Up to the closing quote, or the end of the line if there is none.
+        let len = text[1..].find(['"', '\r', '\n']).map_or(text.len(), |i| {
+            1 + i + usize::from(text[1 + i..].starts_with('"'))
+        });
+        (TokenKind::String, len)
+    } else if [
+        "==", "!=", "<=", ">=", "&&", "||", "**", "<<", ">>", "..", "=>",
+    ]
+    .iter()
+    .any(|op| text.starts_with(op))
+    {
+        (TokenKind::Punctuation, 2)
+    } else if "=;:,.(){}[]+-*/%<>!&|^~_".contains(first) {
+        (TokenKind::Punctuation, 1)
+    } else {
+        (TokenKind::Unknown, first.len_utf8())
+    }
+}
+
+/// Returns the length of the hexadecimal or binary literal at the start of `text`, if any.
+fn radix_len(text: &str) -> Option<usize> {
+    let radix_digits = |prefix: &str, f: fn(char) -> bool| {
+        let digits = text.strip_prefix(prefix)?;
+        let len = digits
+            .find(|c: char| !f(c) && c != '_')
+            .unwrap_or(digits.len());
+        digits.starts_with(f).then_some(prefix.len() + len)
+    };
+    radix_digits("0x", |c| c.is_ascii_hexdigit())
+        .or_else(|| radix_digits("0b", |c| c == '0' || c == '1'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lexer() {
+        let tokens = Lexer::new("let a=1; // one\n")
+            .filter(|token| !token.is_trivia())
+            .map(|token| (token.kind, token.text))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                (TokenKind::Keyword, "let".to_string()),
+                (TokenKind::Identifier, "a".to_string()),
+                (TokenKind::Punctuation, "=".to_string()),
+                (TokenKind::Number, "1".to_string()),
+                (TokenKind::Punctuation, ";".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        let numbers = |source| {
+            lex(source)
+                .into_iter()
+                .filter(|token| token.kind == TokenKind::Number)
+                .map(|token| token.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            numbers("0xFF_FF+-0b1_0 - 0x 1.5-1"),
+            ["0xFF_FF", "0b1_0", "0", "1.5", "1"]
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        let punctuation = |source| {
+            lex(source)
+                .into_iter()
+                .filter(|token| token.kind == TokenKind::Punctuation)
+                .map(|token| token.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            punctuation("~a&b && c|d || e^f<<1>>2<=3"),
+            ["~", "&", "&&", "|", "||", "^", "<<", ">>", "<="]
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let kinds = |source| {
+            lex(source)
+                .into_iter()
+                .map(|token| (token.kind, token.text))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            kinds("hash(\"Gas Sensor\")")[2],
+            (TokenKind::String, "\"Gas Sensor\"".to_string())
+        );
+        // An unterminated string stops at the end of the line.
+        assert_eq!(
+            kinds("\"abc\nx")[..2],
+            [
+                (TokenKind::String, "\"abc".to_string()),
+                (TokenKind::Whitespace, "\n".to_string())
+            ]
+        );
+    }
+}
@@ -1,11 +1,189 @@
 use lalrpop_util::lalrpop_mod;
 
 pub mod ast;
+pub mod builder;
+pub mod comments;
+pub mod corpus;
+pub mod cst;
 pub mod error;
 pub mod format;
+pub mod incremental;
+pub mod lexer;
+pub mod tree_sitter;
 pub mod utils;
+pub mod visit;
 
 lalrpop_mod!(
     #[allow(clippy::all)]
     pub grammar
 );
+
+/// A syntax error, located by byte offsets into the source.
+pub type ParseError<'input> =
+    lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, LiteralError>;
+
+/// A literal the grammar accepts but that has no value, such as an integer too large for 64
+/// bits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LiteralError {
+    pub message: &'static str,
+    pub span: ast::Span,
+}
+
+impl std::fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+/// Parses a program, attaching its comments to the statements.
+///
+/// Syntax errors skip to the end of the statement they occur in, so that all the errors of
+/// the program are returned, in source order.
+pub fn parse(source: &str) -> Result<ast::Program, Vec<ParseError<'_>>> {
+    match parse_with_errors(source) {
+        (Some(program), errors) if errors.is_empty() => Ok(program),
+        (_, errors) => Err(errors),
+    }
+}
+
+/// Like [`parse`], also returning the program when it has syntax errors, with a
+/// [`StatementKind::Error`](ast::StatementKind::Error) in place of each statement that failed
+/// to parse, so that tools can still work with the rest. The program is `None` when the
+/// parser could not recover from an error.
+pub fn parse_with_errors(source: &str) -> (Option<ast::Program>, Vec<ParseError<'_>>) {
+    let mut recovered = vec![];
+    let result = grammar::ProgramParser::new().parse(&mut recovered, source);
+    let mut errors = recovered.into_iter().map(|e| e.error).collect::<Vec<_>>();
+    match result {
+        Ok(mut program) => {
+            comments::attach(&mut program, source);
+            (Some(program), errors)
+        }
+        Err(e) => {
+            errors.push(e);
+            (None, errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_reports_all_errors() {
+        let errors = super::parse("let a = = 1;\nlet b = 2;\nd0.Setting = b +;\n").unwrap_err();
+        let locations = errors
+            .iter()
+            .map(|e| match e {
+                super::ParseError::UnrecognizedToken { token, .. } => token.0,
+                e => panic!("unexpected error {:?}", e),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(locations, vec![8, 40]);
+    }
+
+    #[test]
+    fn test_error_placeholders() {
+        use crate::ast::StatementKind;
+
+        let source = "let a = 1;\nd0.Setting = ;\nloop {\n    yield;\n    d1.On = a +\n}\nlet b = ";
+        let (program, errors) = super::parse_with_errors(source);
+        assert_eq!(errors.len(), 3);
+        let program = program.unwrap();
+        let statements = &program.statements;
+        assert_eq!(statements.len(), 4);
+        assert!(matches!(statements[1].kind, StatementKind::Error));
+        assert_eq!(
+            &source[statements[1].span.start..statements[1].span.end],
+            "d0.Setting = ;"
+        );
+        let StatementKind::Loop { body, .. } = &statements[2].kind else {
+            panic!("expected a loop, found {:?}", statements[2].kind);
+        };
+        assert!(matches!(body.statements()[0].kind, StatementKind::Yield));
+        assert!(matches!(body.statements()[1].kind, StatementKind::Error));
+        assert!(matches!(statements[3].kind, StatementKind::Error));
+        assert_eq!(
+            program.to_string(),
+            "let a = 1;\n// syntax error\nloop {\n    yield;\n    // syntax error\n}\n// syntax error\n"
+        );
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        use crate::ast::{BinaryOpcode, ExprKind, StatementKind, UnaryOpcode};
+
+        let value = |source| {
+            let program = super::parse(source).unwrap();
+            let StatementKind::Definition { expression, .. } = &program.statements[0].kind else {
+                panic!(
+                    "expected a definition, found {:?}",
+                    program.statements[0].kind
+                );
+            };
+            expression.kind.clone()
+        };
+        // A minus is an operator, subtracting without spaces too.
+        assert!(matches!(
+            value("let a = x-1;"),
+            ExprKind::BinaryOp(_, BinaryOpcode::Sub, _)
+        ));
+        assert!(matches!(
+            value("let a = x-0x1;"),
+            ExprKind::BinaryOp(_, BinaryOpcode::Sub, _)
+        ));
+        // `**` binds tighter than the minus, as for any other operand.
+        let ExprKind::UnaryOp(UnaryOpcode::Neg, operand) = value("let a = -2 ** 2;") else {
+            panic!("expected a negation");
+        };
+        assert!(matches!(
+            operand.kind,
+            ExprKind::BinaryOp(_, BinaryOpcode::Pow, _)
+        ));
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        let program = super::parse("let a = 0xFF_FF + -0x1f * 0b1010 - -0b1_1;").unwrap();
+        assert_eq!(program.to_string(), "let a = 65535 + -31 * 10 - -3;\n");
+        for source in ["let a = 0x;", "let a = 0x_1;", "let a = 0b2;"] {
+            assert!(super::parse(source).is_err(), "{}", source);
+        }
+        for source in [
+            "let a = 0xFFFF_FFFF_FFFF_FFFF;",
+            "let a = 99999999999999999999;",
+        ] {
+            let errors = super::parse(source).unwrap_err();
+            let [super::ParseError::User { error }] = errors[..] else {
+                panic!("unexpected errors {:?}", errors);
+            };
+            assert_eq!(error.message, "integer literal is too large");
+            assert_eq!(error.span, crate::ast::Span::new(8, source.len() - 1));
+        }
+    }
+
+    #[test]
+    fn test_bitwise_precedence() {
+        let program =
+            super::parse("let a = ((x & 1) == 0) && ((y << 1) | 1) == (~y ^ (z + 1));").unwrap();
+        assert_eq!(
+            program.to_string(),
+            "let a = x & 1 == 0 && y << 1 | 1 == ~y ^ z + 1;\n"
+        );
+        let program = super::parse("let a = (x | y) & z >> (1 << 2);").unwrap();
+        assert_eq!(program.to_string(), "let a = (x | y) & z >> (1 << 2);\n");
+    }
+
+    #[test]
+    fn test_optional_separators() {
+        let program = super::parse(
+            "fn f(a, b,) { return a + b }\nloop { store(db, Setting, f(1, 2,),); yield }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.to_string(),
+            "fn f(a, b) {\n    return a + b;\n}\n\nloop {\n    store(db, Setting, f(1, 2));\n    yield;\n}\n"
+        );
+        assert!(super::parse("loop { yield yield }").is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! A tree-sitter grammar for ayysee, for the syntax highlighting of editors.
+//!
+//! The grammar is maintained by hand alongside the LALRPOP grammar in `tree-sitter/`, and
+//! written out by `cli grammar export`. Run `tree-sitter generate` in the exported
+//! directory to build the parser.
+
+/// The files of the grammar, relative to the root of a tree-sitter grammar directory.
+pub const FILES: &[(&str, &str)] = &[
+    ("grammar.js", include_str!("../tree-sitter/grammar.js")),
+    (
+        "queries/highlights.scm",
+        include_str!("../tree-sitter/queries/highlights.scm"),
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::FILES;
+    use std::collections::BTreeSet;
+
+    /// Returns the words quoted in a grammar file, such as `'let'` or `"let"`, except field
+    /// names.
+    fn quoted_words(file: &str) -> BTreeSet<&str> {
+        let mut words = BTreeSet::new();
+        let mut rest = file;
+        while let Some(start) = rest.find(['\'', '"']) {
+            let quote = rest[start..].chars().next().unwrap();
+            let Some(len) = rest[start + 1..].find(quote) else {
+                break;
+            };
+            let word = &rest[start + 1..start + 1 + len];
+            if !rest[..start].ends_with("field(")
+                && !word.is_empty()
+                && word.chars().all(|c| c.is_ascii_lowercase())
+            {
+                words.insert(word);
+            }
+            rest = &rest[start + len + 2..];
+        }
+        words
+    }
+
+    #[test]
+    fn test_same_keywords() {
+        let keywords = crate::lexer::KEYWORDS
+            .iter()
+            .copied()
+            .collect::<BTreeSet<_>>();
+        for (name, content) in FILES {
+            let mut words = quoted_words(content);
+            // The name of the language.
+            words.remove("ayysee");
+            assert_eq!(words, keywords, "in {}", name);
+        }
+    }
+}
@@ -1,4 +1,23 @@
+use crate::ast::Span;
+use crate::LiteralError;
+
 pub fn append<T>(mut accum: Vec<T>, item: T) -> Vec<T> {
     accum.push(item);
     accum
 }
+
+/// Parses the digits of an integer literal at `span`, ignoring the underscores separating
+/// them.
+pub fn parse_int<T>(
+    digits: &str,
+    radix: u32,
+    span: Span,
+) -> Result<i64, lalrpop_util::ParseError<usize, T, LiteralError>> {
+    let digits = digits.replace('_', "");
+    i64::from_str_radix(&digits, radix).map_err(|_| lalrpop_util::ParseError::User {
+        error: LiteralError {
+            message: "integer literal is too large",
+            span,
+        },
+    })
+}
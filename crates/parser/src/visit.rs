@@ -0,0 +1,730 @@
+//! Traversal of the AST.
+//!
+//! Implement [`Visitor`] (or [`VisitorMut`] to modify the tree, or [`Folder`] to rebuild
+//! it) and override the methods for the nodes of interest. The default methods call the
+//! matching `walk_*` function, which visits the children of the node; overriding methods
+//! call it themselves to keep descending.
+
+use crate::ast::{
+    Block, DeviceStatement, Expr, ExprKind, Identifier, IfStatement, Program, Statement,
+    StatementKind,
+};
+
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_identifier(&mut self, _identifier: &Identifier) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &Block) {
+    for statement in block.statements() {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match &statement.kind {
+        StatementKind::Assignment { lhs, rhs } => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        StatementKind::Definition {
+            identifier,
+            expression,
+        }
+        | StatementKind::Constant(identifier, expression) => {
+            visitor.visit_identifier(identifier);
+            visitor.visit_expr(expression);
+        }
+        StatementKind::TupleDefinition {
+            identifiers,
+            expressions,
+        } => {
+            for identifier in identifiers {
+                visitor.visit_identifier(identifier);
+            }
+            for expression in expressions {
+                visitor.visit_expr(expression);
+            }
+        }
+        StatementKind::Alias { identifier, alias } => {
+            visitor.visit_identifier(identifier);
+            visitor.visit_identifier(alias);
+        }
+        StatementKind::Function {
+            identifier,
+            parameters,
+            body,
+        } => {
+            visitor.visit_identifier(identifier);
+            for parameter in parameters {
+                visitor.visit_identifier(parameter);
+            }
+            visitor.visit_block(body);
+        }
+        StatementKind::FunctionCall {
+            identifier,
+            arguments,
+        } => {
+            visitor.visit_identifier(identifier);
+            for argument in arguments {
+                visitor.visit_expr(argument);
+            }
+        }
+        StatementKind::Block(body) | StatementKind::Loop { body, .. } => visitor.visit_block(body),
+        StatementKind::LoopUntil {
+            body, condition, ..
+        } => {
+            visitor.visit_block(body);
+            visitor.visit_expr(condition);
+        }
+        StatementKind::For {
+            variable,
+            start,
+            end,
+            body,
+            ..
+        } => {
+            visitor.visit_identifier(variable);
+            visitor.visit_expr(start);
+            visitor.visit_expr(end);
+            visitor.visit_block(body);
+        }
+        StatementKind::IfStatement(IfStatement::If { condition, body }) => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(body);
+        }
+        StatementKind::IfStatement(IfStatement::IfElse {
+            condition,
+            body,
+            else_body,
+        }) => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(body);
+            visitor.visit_block(else_body);
+        }
+        StatementKind::IfStatement(IfStatement::ElseIf {
+            branches,
+            else_body,
+        }) => {
+            for (condition, body) in branches {
+                visitor.visit_expr(condition);
+                visitor.visit_block(body);
+            }
+            if let Some(else_body) = else_body {
+                visitor.visit_block(else_body);
+            }
+        }
+        StatementKind::Match {
+            value,
+            arms,
+            default_arm,
+        } => {
+            visitor.visit_expr(value);
+            for (pattern, body) in arms {
+                visitor.visit_expr(pattern);
+                visitor.visit_block(body);
+            }
+            if let Some(default_arm) = default_arm {
+                visitor.visit_block(default_arm);
+            }
+        }
+        StatementKind::DeviceStatement(DeviceStatement::Read {
+            device,
+            device_variable,
+            local,
+        }) => {
+            visitor.visit_identifier(device);
+            visitor.visit_identifier(device_variable);
+            visitor.visit_identifier(local);
+        }
+        StatementKind::DeviceStatement(DeviceStatement::Write {
+            value,
+            device,
+            device_variable,
+        }) => {
+            visitor.visit_expr(value);
+            visitor.visit_identifier(device);
+            visitor.visit_identifier(device_variable);
+        }
+        StatementKind::Return(Some(expr)) => visitor.visit_expr(expr),
+        StatementKind::Yield
+        | StatementKind::Break(_)
+        | StatementKind::Continue(_)
+        | StatementKind::Halt
+        | StatementKind::Return(None)
+        | StatementKind::Import(_)
+        | StatementKind::Error => {}
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Constant(_) | ExprKind::Hash(_) => {}
+        ExprKind::Identifier(identifier) => visitor.visit_identifier(identifier),
+        ExprKind::BinaryOp(lhs, _, rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        ExprKind::UnaryOp(_, operand) => visitor.visit_expr(operand),
+        ExprKind::FunctionCall(identifier, arguments) => {
+            visitor.visit_identifier(identifier);
+            for argument in arguments {
+                visitor.visit_expr(argument);
+            }
+        }
+        ExprKind::FieldExpr(device, variable) => {
+            visitor.visit_expr(device);
+            visitor.visit_identifier(variable);
+        }
+        ExprKind::DeviceIndex(index) => visitor.visit_expr(index),
+        ExprKind::Array(value, length) => {
+            visitor.visit_expr(value);
+            visitor.visit_expr(length);
+        }
+        ExprKind::Index(array, index) => {
+            visitor.visit_identifier(array);
+            visitor.visit_expr(index);
+        }
+    }
+}
+
+/// Like [`Visitor`], with mutable access to the nodes.
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_identifier_mut(&mut self, _identifier: &mut Identifier) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for statement in &mut program.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for statement in block.statements_mut() {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match &mut statement.kind {
+        StatementKind::Assignment { lhs, rhs } => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        StatementKind::Definition {
+            identifier,
+            expression,
+        }
+        | StatementKind::Constant(identifier, expression) => {
+            visitor.visit_identifier_mut(identifier);
+            visitor.visit_expr_mut(expression);
+        }
+        StatementKind::TupleDefinition {
+            identifiers,
+            expressions,
+        } => {
+            for identifier in identifiers {
+                visitor.visit_identifier_mut(identifier);
+            }
+            for expression in expressions {
+                visitor.visit_expr_mut(expression);
+            }
+        }
+        StatementKind::Alias { identifier, alias } => {
+            visitor.visit_identifier_mut(identifier);
+            visitor.visit_identifier_mut(alias);
+        }
+        StatementKind::Function {
+            identifier,
+            parameters,
+            body,
+        } => {
+            visitor.visit_identifier_mut(identifier);
+            for parameter in parameters {
+                visitor.visit_identifier_mut(parameter);
+            }
+            visitor.visit_block_mut(body);
+        }
+        StatementKind::FunctionCall {
+            identifier,
+            arguments,
+        } => {
+            visitor.visit_identifier_mut(identifier);
+            for argument in arguments {
+                visitor.visit_expr_mut(argument);
+            }
+        }
+        StatementKind::Block(body) | StatementKind::Loop { body, .. } => {
+            visitor.visit_block_mut(body)
+        }
+        StatementKind::LoopUntil {
+            body, condition, ..
+        } => {
+            visitor.visit_block_mut(body);
+            visitor.visit_expr_mut(condition);
+        }
+        StatementKind::For {
+            variable,
+            start,
+            end,
+            body,
+            ..
+        } => {
+            visitor.visit_identifier_mut(variable);
+            visitor.visit_expr_mut(start);
+            visitor.visit_expr_mut(end);
+            visitor.visit_block_mut(body);
+        }
+        StatementKind::IfStatement(IfStatement::If { condition, body }) => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_block_mut(body);
+        }
+        StatementKind::IfStatement(IfStatement::IfElse {
+            condition,
+            body,
+            else_body,
+        }) => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_block_mut(body);
+            visitor.visit_block_mut(else_body);
+        }
+        StatementKind::IfStatement(IfStatement::ElseIf {
+            branches,
+            else_body,
+        }) => {
+            for (condition, body) in branches {
+                visitor.visit_expr_mut(condition);
+                visitor.visit_block_mut(body);
+            }
+            if let Some(else_body) = else_body {
+                visitor.visit_block_mut(else_body);
+            }
+        }
+        StatementKind::Match {
+            value,
+            arms,
+            default_arm,
+        } => {
+            visitor.visit_expr_mut(value);
+            for (pattern, body) in arms {
+                visitor.visit_expr_mut(pattern);
+                visitor.visit_block_mut(body);
+            }
+            if let Some(default_arm) = default_arm {
+                visitor.visit_block_mut(default_arm);
+            }
+        }
+        StatementKind::DeviceStatement(DeviceStatement::Read {
+            device,
+            device_variable,
+            local,
+        }) => {
+            visitor.visit_identifier_mut(device);
+            visitor.visit_identifier_mut(device_variable);
+            visitor.visit_identifier_mut(local);
+        }
+        StatementKind::DeviceStatement(DeviceStatement::Write {
+            value,
+            device,
+            device_variable,
+        }) => {
+            visitor.visit_expr_mut(value);
+            visitor.visit_identifier_mut(device);
+            visitor.visit_identifier_mut(device_variable);
+        }
+        StatementKind::Return(Some(expr)) => visitor.visit_expr_mut(expr),
+        StatementKind::Yield
+        | StatementKind::Break(_)
+        | StatementKind::Continue(_)
+        | StatementKind::Halt
+        | StatementKind::Return(None)
+        | StatementKind::Import(_)
+        | StatementKind::Error => {}
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Constant(_) | ExprKind::Hash(_) => {}
+        ExprKind::Identifier(identifier) => visitor.visit_identifier_mut(identifier),
+        ExprKind::BinaryOp(lhs, _, rhs) => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        ExprKind::UnaryOp(_, operand) => visitor.visit_expr_mut(operand),
+        ExprKind::FunctionCall(identifier, arguments) => {
+            visitor.visit_identifier_mut(identifier);
+            for argument in arguments {
+                visitor.visit_expr_mut(argument);
+            }
+        }
+        ExprKind::FieldExpr(device, variable) => {
+            visitor.visit_expr_mut(device);
+            visitor.visit_identifier_mut(variable);
+        }
+        ExprKind::DeviceIndex(index) => visitor.visit_expr_mut(index),
+        ExprKind::Array(value, length) => {
+            visitor.visit_expr_mut(value);
+            visitor.visit_expr_mut(length);
+        }
+        ExprKind::Index(array, index) => {
+            visitor.visit_identifier_mut(array);
+            visitor.visit_expr_mut(index);
+        }
+    }
+}
+
+/// Like [`Visitor`], taking the nodes by value and returning their replacement, to rebuild
+/// the tree with other kinds of nodes.
+pub trait Folder {
+    fn fold_program(&mut self, program: Program) -> Program {
+        walk_program_fold(self, program)
+    }
+
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        walk_statement_fold(self, statement)
+    }
+
+    fn fold_block(&mut self, block: Block) -> Block {
+        walk_block_fold(self, block)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        walk_expr_fold(self, expr)
+    }
+
+    fn fold_identifier(&mut self, identifier: Identifier) -> Identifier {
+        identifier
+    }
+}
+
+pub fn walk_program_fold<F: Folder + ?Sized>(folder: &mut F, program: Program) -> Program {
+    Program::new(fold_statements(folder, program.statements))
+}
+
+pub fn walk_block_fold<F: Folder + ?Sized>(folder: &mut F, block: Block) -> Block {
+    Block {
+        statements: fold_statements(folder, block.statements),
+        span: block.span,
+    }
+}
+
+fn fold_statements<F: Folder + ?Sized>(
+    folder: &mut F,
+    statements: Vec<Statement>,
+) -> Vec<Statement> {
+    statements
+        .into_iter()
+        .map(|statement| folder.fold_statement(statement))
+        .collect()
+}
+
+pub fn walk_statement_fold<F: Folder + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+    let kind = match statement.kind {
+        StatementKind::Assignment { lhs, rhs } => StatementKind::Assignment {
+            lhs: Box::new(folder.fold_expr(*lhs)),
+            rhs: Box::new(folder.fold_expr(*rhs)),
+        },
+        StatementKind::Definition {
+            identifier,
+            expression,
+        } => StatementKind::Definition {
+            identifier: folder.fold_identifier(identifier),
+            expression: Box::new(folder.fold_expr(*expression)),
+        },
+        StatementKind::Constant(identifier, expression) => StatementKind::Constant(
+            folder.fold_identifier(identifier),
+            Box::new(folder.fold_expr(*expression)),
+        ),
+        StatementKind::TupleDefinition {
+            identifiers,
+            expressions,
+        } => StatementKind::TupleDefinition {
+            identifiers: identifiers
+                .into_iter()
+                .map(|identifier| folder.fold_identifier(identifier))
+                .collect(),
+            expressions: expressions
+                .into_iter()
+                .map(|expr| Box::new(folder.fold_expr(*expr)))
+                .collect(),
+        },
+        StatementKind::Alias { identifier, alias } => StatementKind::Alias {
+            identifier: folder.fold_identifier(identifier),
+            alias: folder.fold_identifier(alias),
+        },
+        StatementKind::Function {
+            identifier,
+            parameters,
+            body,
+        } => StatementKind::Function {
+            identifier: folder.fold_identifier(identifier),
+            parameters: parameters
+                .into_iter()
+                .map(|parameter| folder.fold_identifier(parameter))
+                .collect(),
+            body: folder.fold_block(body),
+        },
+        StatementKind::FunctionCall {
+            identifier,
+            arguments,
+        } => StatementKind::FunctionCall {
+            identifier: folder.fold_identifier(identifier),
+            arguments: arguments
+                .into_iter()
+                .map(|expr| Box::new(folder.fold_expr(*expr)))
+                .collect(),
+        },
+        StatementKind::Block(body) => StatementKind::Block(folder.fold_block(body)),
+        StatementKind::Loop { label, body } => StatementKind::Loop {
+            label,
+            body: folder.fold_block(body),
+        },
+        StatementKind::LoopUntil {
+            label,
+            body,
+            condition,
+        } => StatementKind::LoopUntil {
+            label,
+            body: folder.fold_block(body),
+            condition: Box::new(folder.fold_expr(*condition)),
+        },
+        StatementKind::For {
+            label,
+            variable,
+            start,
+            end,
+            body,
+        } => StatementKind::For {
+            label,
+            variable: folder.fold_identifier(variable),
+            start: Box::new(folder.fold_expr(*start)),
+            end: Box::new(folder.fold_expr(*end)),
+            body: folder.fold_block(body),
+        },
+        StatementKind::IfStatement(IfStatement::If { condition, body }) => {
+            StatementKind::IfStatement(IfStatement::If {
+                condition: Box::new(folder.fold_expr(*condition)),
+                body: folder.fold_block(body),
+            })
+        }
+        StatementKind::IfStatement(IfStatement::IfElse {
+            condition,
+            body,
+            else_body,
+        }) => StatementKind::IfStatement(IfStatement::IfElse {
+            condition: Box::new(folder.fold_expr(*condition)),
+            body: folder.fold_block(body),
+            else_body: folder.fold_block(else_body),
+        }),
+        StatementKind::IfStatement(IfStatement::ElseIf {
+            branches,
+            else_body,
+        }) => StatementKind::IfStatement(IfStatement::ElseIf {
+            branches: branches
+                .into_iter()
+                .map(|(condition, body)| {
+                    (
+                        Box::new(folder.fold_expr(*condition)),
+                        folder.fold_block(body),
+                    )
+                })
+                .collect(),
+            else_body: else_body.map(|else_body| folder.fold_block(else_body)),
+        }),
+        StatementKind::Match {
+            value,
+            arms,
+            default_arm,
+        } => StatementKind::Match {
+            value: Box::new(folder.fold_expr(*value)),
+            arms: arms
+                .into_iter()
+                .map(|(pattern, body)| {
+                    (
+                        Box::new(folder.fold_expr(*pattern)),
+                        folder.fold_block(body),
+                    )
+                })
+                .collect(),
+            default_arm: default_arm.map(|default_arm| folder.fold_block(default_arm)),
+        },
+        StatementKind::DeviceStatement(DeviceStatement::Read {
+            device,
+            device_variable,
+            local,
+        }) => StatementKind::DeviceStatement(DeviceStatement::Read {
+            device: folder.fold_identifier(device),
+            device_variable: folder.fold_identifier(device_variable),
+            local: folder.fold_identifier(local),
+        }),
+        StatementKind::DeviceStatement(DeviceStatement::Write {
+            value,
+            device,
+            device_variable,
+        }) => StatementKind::DeviceStatement(DeviceStatement::Write {
+            value: Box::new(folder.fold_expr(*value)),
+            device: folder.fold_identifier(device),
+            device_variable: folder.fold_identifier(device_variable),
+        }),
+        StatementKind::Return(Some(expr)) => {
+            StatementKind::Return(Some(Box::new(folder.fold_expr(*expr))))
+        }
+        kind @ (StatementKind::Yield
+        | StatementKind::Break(_)
+        | StatementKind::Continue(_)
+        | StatementKind::Halt
+        | StatementKind::Return(None)
+        | StatementKind::Import(_)
+        | StatementKind::Error) => kind,
+    };
+    Statement { kind, ..statement }
+}
+
+pub fn walk_expr_fold<F: Folder + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    let kind = match expr.kind {
+        kind @ (ExprKind::Constant(_) | ExprKind::Hash(_)) => kind,
+        ExprKind::Identifier(identifier) => {
+            ExprKind::Identifier(folder.fold_identifier(identifier))
+        }
+        ExprKind::BinaryOp(lhs, op, rhs) => ExprKind::BinaryOp(
+            Box::new(folder.fold_expr(*lhs)),
+            op,
+            Box::new(folder.fold_expr(*rhs)),
+        ),
+        ExprKind::UnaryOp(op, operand) => {
+            ExprKind::UnaryOp(op, Box::new(folder.fold_expr(*operand)))
+        }
+        ExprKind::FunctionCall(identifier, arguments) => ExprKind::FunctionCall(
+            folder.fold_identifier(identifier),
+            arguments
+                .into_iter()
+                .map(|expr| Box::new(folder.fold_expr(*expr)))
+                .collect(),
+        ),
+        ExprKind::FieldExpr(device, variable) => ExprKind::FieldExpr(
+            Box::new(folder.fold_expr(*device)),
+            folder.fold_identifier(variable),
+        ),
+        ExprKind::DeviceIndex(index) => ExprKind::DeviceIndex(Box::new(folder.fold_expr(*index))),
+        ExprKind::Array(value, length) => ExprKind::Array(
+            Box::new(folder.fold_expr(*value)),
+            Box::new(folder.fold_expr(*length)),
+        ),
+        ExprKind::Index(array, index) => ExprKind::Index(
+            folder.fold_identifier(array),
+            Box::new(folder.fold_expr(*index)),
+        ),
+    };
+    Expr { kind, ..expr }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BinaryOpcode, Value};
+
+    #[test]
+    fn test_visitor() {
+        struct Identifiers(Vec<String>);
+
+        impl Visitor for Identifiers {
+            fn visit_identifier(&mut self, identifier: &Identifier) {
+                self.0.push(identifier.to_string());
+            }
+        }
+
+        let program =
+            crate::parse("fn f(a) { return a + 1; }\nloop { if d0.On { db.Setting = f(2); } }\n")
+                .unwrap();
+        let mut identifiers = Identifiers(vec![]);
+        identifiers.visit_program(&program);
+        assert_eq!(
+            identifiers.0,
+            vec!["f", "a", "a", "d0", "On", "db", "Setting", "f"]
+        );
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        /// Replaces every integer by its double.
+        struct Double;
+
+        impl VisitorMut for Double {
+            fn visit_expr_mut(&mut self, expr: &mut Expr) {
+                if let ExprKind::Constant(Value::Integer(x)) = &mut expr.kind {
+                    *x *= 2;
+                }
+                walk_expr_mut(self, expr)
+            }
+        }
+
+        let mut program = crate::parse("let a = 1 + f(2);\n").unwrap();
+        Double.visit_program_mut(&mut program);
+        assert_eq!(program.to_string(), "let a = 2 + f(4);\n");
+    }
+
+    #[test]
+    fn test_folder() {
+        /// Replaces additions of two integers by their sum.
+        struct Sums;
+
+        impl Folder for Sums {
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                let expr = walk_expr_fold(self, expr);
+                match &expr.kind {
+                    ExprKind::BinaryOp(lhs, BinaryOpcode::Add, rhs) => match (&lhs.kind, &rhs.kind)
+                    {
+                        (
+                            ExprKind::Constant(Value::Integer(a)),
+                            ExprKind::Constant(Value::Integer(b)),
+                        ) => Expr::new(ExprKind::Constant(Value::Integer(a + b)), expr.span),
+                        _ => expr,
+                    },
+                    _ => expr,
+                }
+            }
+        }
+
+        let program = crate::parse("loop { d0.On = 1 + 2 + x; }\n").unwrap();
+        let program = Sums.fold_program(program);
+        assert_eq!(program.to_string(), "loop {\n    d0.On = 3 + x;\n}\n");
+    }
+}
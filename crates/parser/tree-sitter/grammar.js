@@ -0,0 +1,223 @@
+// Tree-sitter grammar for ayysee, maintained alongside `src/grammar.lalrpop`.
+//
+// Exported by `cli grammar export`. Keep the two grammars in sync: the tests of
+// `src/tree_sitter.rs` check that both know the same keywords.
+
+const PREC = {
+  or: 1,
+  and: 2,
+  compare: 3,
+  bit_or: 4,
+  bit_xor: 5,
+  bit_and: 6,
+  shift: 7,
+  add: 8,
+  multiply: 9,
+  unary: 10,
+  power: 11,
+};
+
+module.exports = grammar({
+  name: 'ayysee',
+
+  extras: $ => [/\s/, $.comment],
+
+  word: $ => $.identifier,
+
+  rules: {
+    program: $ => repeat1($._statement),
+
+    _statement: $ => choice(
+      seq($._simple_statement, ';'),
+      $.block,
+      $.function_definition,
+      $.loop_statement,
+      $.for_statement,
+      $.if_statement,
+      $.match_statement,
+    ),
+
+    // Statements ending with a semicolon, which is optional for the last one of a block.
+    _simple_statement: $ => choice(
+      $.let_statement,
+      $.loop_until_statement,
+      $.const_statement,
+      $.alias_statement,
+      $.assignment,
+      $.call_expression,
+      $.yield_statement,
+      $.break_statement,
+      $.continue_statement,
+      $.halt_statement,
+      $.return_statement,
+      $.import_statement,
+    ),
+
+    let_statement: $ => choice(
+      seq('let', field('name', $.identifier), '=', field('value', $._expression)),
+      // `let (a, b) = (x, y)`, defining each variable to the value in the same position.
+      seq(
+        'let',
+        '(', commaSep(field('name', $.identifier)), ')',
+        '=',
+        '(', commaSep(field('value', $._expression)), ')',
+      ),
+    ),
+
+    const_statement: $ => seq('const', field('name', $.identifier), '=', field('value', $._expression)),
+
+    alias_statement: $ => seq('alias', field('name', $.identifier), '=', field('device', $.identifier)),
+
+    assignment: $ => seq(field('left', $._expression), '=', field('right', $._expression)),
+
+    yield_statement: _ => 'yield',
+
+    break_statement: $ => seq('break', optional(field('label', $.identifier))),
+
+    continue_statement: $ => seq('continue', optional(field('label', $.identifier))),
+
+    halt_statement: _ => 'halt',
+
+    return_statement: $ => seq('return', optional($._expression)),
+
+    // Brings the functions and constants of another file into the program.
+    import_statement: $ => seq('import', field('path', $.string)),
+
+    function_definition: $ => seq(
+      'fn',
+      field('name', $.identifier),
+      field('parameters', $.parameters),
+      field('body', $.block),
+    ),
+
+    parameters: $ => seq('(', commaSep($.identifier), ')'),
+
+    // `outer:` before a loop, which `break outer` and `continue outer` refer to.
+    label: $ => seq(field('name', $.identifier), ':'),
+
+    loop_statement: $ => seq(optional($.label), 'loop', field('body', $.block)),
+
+    // Runs the body until the condition, tested after each iteration, holds.
+    loop_until_statement: $ => seq(
+      optional($.label),
+      'loop',
+      field('body', $.block),
+      'until',
+      field('condition', $._expression),
+    ),
+
+    // Runs the body for each whole number from `start` up to `end`, excluded.
+    for_statement: $ => seq(
+      optional($.label),
+      'for',
+      field('variable', $.identifier),
+      'in',
+      field('start', $._expression),
+      '..',
+      field('end', $._expression),
+      field('body', $.block),
+    ),
+
+    if_statement: $ => seq(
+      'if',
+      field('condition', $._expression),
+      field('consequence', $.block),
+      optional(seq('else', field('alternative', choice($.block, $.if_statement)))),
+    ),
+
+    // Runs the body of the first arm whose pattern equals the value, or else the `_` arm.
+    match_statement: $ => seq(
+      'match',
+      field('value', $._expression),
+      '{',
+      repeat($.match_arm),
+      optional($.default_arm),
+      '}',
+    ),
+
+    match_arm: $ => seq(field('pattern', $._expression), '=>', field('body', $.block), optional(',')),
+
+    default_arm: $ => seq('_', '=>', field('body', $.block), optional(',')),
+
+    block: $ => seq('{', repeat($._statement), optional($._simple_statement), '}'),
+
+    _expression: $ => choice(
+      $.binary_expression,
+      $.unary_expression,
+      $.parenthesized_expression,
+      $.call_expression,
+      $.field_expression,
+      $.device_index,
+      $.array_expression,
+      $.index_expression,
+      $.hash_expression,
+      $.identifier,
+      $.number,
+      $.boolean,
+    ),
+
+    binary_expression: $ => choice(
+      prec.left(PREC.or, seq(field('left', $._expression), field('operator', '||'), field('right', $._expression))),
+      prec.left(PREC.and, seq(field('left', $._expression), field('operator', '&&'), field('right', $._expression))),
+      // Comparisons do not chain.
+      ...['==', '!=', '<', '>', '<=', '>='].map(operator =>
+        prec(PREC.compare, seq(field('left', $._expression), field('operator', operator), field('right', $._expression))),
+      ),
+      prec.left(PREC.bit_or, seq(field('left', $._expression), field('operator', '|'), field('right', $._expression))),
+      prec.left(PREC.bit_xor, seq(field('left', $._expression), field('operator', '^'), field('right', $._expression))),
+      prec.left(PREC.bit_and, seq(field('left', $._expression), field('operator', '&'), field('right', $._expression))),
+      ...['<<', '>>'].map(operator =>
+        prec.left(PREC.shift, seq(field('left', $._expression), field('operator', operator), field('right', $._expression))),
+      ),
+      ...['+', '-'].map(operator =>
+        prec.left(PREC.add, seq(field('left', $._expression), field('operator', operator), field('right', $._expression))),
+      ),
+      ...['*', '/', '%'].map(operator =>
+        prec.left(PREC.multiply, seq(field('left', $._expression), field('operator', operator), field('right', $._expression))),
+      ),
+      prec.right(PREC.power, seq(field('left', $._expression), field('operator', '**'), field('right', $._expression))),
+    ),
+
+    unary_expression: $ => prec(PREC.unary, seq(field('operator', choice('!', '-', '~')), field('argument', $._expression))),
+
+    parenthesized_expression: $ => seq('(', $._expression, ')'),
+
+    call_expression: $ => seq(field('function', $.identifier), field('arguments', $.arguments)),
+
+    arguments: $ => seq('(', commaSep($._expression), ')'),
+
+    // A device variable, such as `d0.Temperature`.
+    field_expression: $ => seq(
+      field('device', choice($.identifier, $.device_index)),
+      '.',
+      field('field', $.identifier),
+    ),
+
+    // The device on a pin computed at run time.
+    device_index: $ => seq('devices', '[', field('index', $._expression), ']'),
+
+    // An array of copies of a value, such as `[0; 8]`.
+    array_expression: $ => seq('[', field('value', $._expression), ';', field('length', $._expression), ']'),
+
+    // An element of an array, such as `buf[i]`.
+    index_expression: $ => seq(field('array', $.identifier), '[', field('index', $._expression), ']'),
+
+    // The hash of a prefab or device name, computed by the compiler.
+    hash_expression: $ => seq('hash', '(', field('name', $.string), ')'),
+
+    identifier: _ => /[a-zA-Z][a-zA-Z0-9_]*/,
+
+    number: _ => /[0-9]+(\.[0-9]+)?|0x[0-9a-fA-F][0-9a-fA-F_]*|0b[01][01_]*/,
+
+    boolean: _ => choice('true', 'false'),
+
+    string: _ => token(seq('"', /[^\x22\n\r]*/, '"')),
+
+    comment: _ => token(seq('//', /[^\n\r]*/)),
+  },
+});
+
+// Zero or more comma separated items, with an optional trailing comma.
+function commaSep(rule) {
+  return optional(seq(rule, repeat(seq(',', rule)), optional(',')));
+}
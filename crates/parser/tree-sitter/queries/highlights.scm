@@ -0,0 +1,79 @@
+; Syntax highlighting for ayysee, see grammar.js.
+
+(comment) @comment
+
+[
+  "let"
+  "const"
+  "alias"
+  "fn"
+  "return"
+  "import"
+  "loop"
+  "until"
+  "for"
+  "in"
+  "yield"
+  "break"
+  "continue"
+  "halt"
+  "if"
+  "else"
+  "match"
+] @keyword
+
+["true" "false"] @constant.builtin
+(number) @number
+(string) @string
+"hash" @function.builtin
+
+(function_definition name: (identifier) @function)
+(call_expression function: (identifier) @function.call)
+(parameters (identifier) @variable.parameter)
+(const_statement name: (identifier) @constant)
+(alias_statement name: (identifier) @constant)
+(alias_statement device: (identifier) @variable.builtin)
+(label name: (identifier) @label)
+(break_statement label: (identifier) @label)
+(continue_statement label: (identifier) @label)
+
+(field_expression device: (identifier) @variable.builtin)
+"devices" @variable.builtin
+(field_expression field: (identifier) @property)
+
+((identifier) @variable.builtin
+  (#match? @variable.builtin "^(d[0-5]|db)$"))
+
+((identifier) @constant
+  (#match? @constant "^[A-Z][A-Z0-9_]*$"))
+
+(identifier) @variable
+
+[
+  "="
+  "=="
+  "!="
+  "<"
+  ">"
+  "<="
+  ">="
+  "+"
+  "-"
+  "*"
+  "/"
+  "%"
+  "**"
+  "&"
+  "|"
+  "^"
+  "<<"
+  ">>"
+  "~"
+  "&&"
+  "||"
+  "!"
+] @operator
+
+["(" ")" "[" "]" "{" "}"] @punctuation.bracket
+["," ";" ":" "." ".." "=>"] @punctuation.delimiter
+"_" @variable.builtin
@@ -0,0 +1,189 @@
+//! The `galvanic` Python module, for generating and testing IC programs from Python.
+//!
+//! ```python
+//! import galvanic
+//!
+//! mips = galvanic.compile("loop { db.Setting = d0.Temperature; yield; }")
+//! chip = galvanic.Simulator(mips)
+//! chip.write("d0", "Temperature", 300.0)
+//! chip.tick()
+//! assert chip.read("db", "Setting") == 300.0
+//! ```
+
+use ayysee_compiler::resolve::SemanticErrors;
+use ayysee_compiler::simulator::TickResult;
+use ayysee_compiler::{CompileOptions, OptLevel, Target};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use stationeers_mips::types::{Device, DeviceVariable};
+use std::collections::HashMap;
+
+create_exception!(
+    galvanic,
+    CompileError,
+    PyException,
+    "The program has errors, one per line of the message."
+);
+
+/// The state of devices, as `{"d0": {"Temperature": 300.0}}`.
+type Devices = HashMap<String, HashMap<String, f64>>;
+
+fn options(opt_level: Option<&str>, target: Option<&str>) -> PyResult<CompileOptions> {
+    let opt_level = opt_level
+        .map(str::parse::<OptLevel>)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?
+        .unwrap_or_default();
+    let target = match target {
+        None => Target::default(),
+        Some(name) => Target::builtin(name)
+            .ok_or_else(|| PyValueError::new_err(format!("unknown target `{}`", name)))?,
+    };
+    Ok(CompileOptions {
+        opt_level,
+        target,
+        ..Default::default()
+    })
+}
+
+fn compile_program(source: &str, options: &CompileOptions) -> PyResult<stationeers_mips::Program> {
+    let location = |offset: usize| {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        format!("{}:{}", line, column)
+    };
+    let program = ayysee_parser::parse(source).map_err(|errors| {
+        let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        CompileError::new_err(messages.join("\n"))
+    })?;
+    ayysee_compiler::ir::generate_program_with(program, options).map_err(|e| {
+        let message = match e.downcast_ref::<SemanticErrors>() {
+            Some(errors) => {
+                let messages = errors
+                    .0
+                    .iter()
+                    .map(|e| format!("{}: {}", location(e.span.start), e.message))
+                    .collect::<Vec<_>>();
+                messages.join("\n")
+            }
+            None => e.to_string(),
+        };
+        CompileError::new_err(message)
+    })
+}
+
+/// Compiles an ayysee program into MIPS code.
+///
+/// `opt_level` is one of `"0"`, `"1"`, `"2"` (the default) or `"s"`, and `target` one of
+/// `"ic10"` (the default) or `"ic10-legacy"`. Raises `CompileError` if the program has
+/// errors.
+#[pyfunction]
+#[pyo3(signature = (source, opt_level=None, target=None))]
+fn compile(source: &str, opt_level: Option<&str>, target: Option<&str>) -> PyResult<String> {
+    Ok(compile_program(source, &options(opt_level, target)?)?.to_string())
+}
+
+/// Compiles a program and runs it for a number of ticks, starting from the given device
+/// states. Returns the device states at the end.
+#[pyfunction]
+#[pyo3(signature = (source, devices=None, ticks=1, opt_level=None, target=None))]
+fn simulate(
+    source: &str,
+    devices: Option<Devices>,
+    ticks: usize,
+    opt_level: Option<&str>,
+    target: Option<&str>,
+) -> PyResult<Devices> {
+    let program = compile_program(source, &options(opt_level, target)?)?;
+    let mut simulator = Simulator(ayysee_compiler::simulator::Simulator::new(program));
+    for (device, variables) in devices.unwrap_or_default() {
+        for (logic_type, value) in variables {
+            simulator.write(&device, &logic_type, value)?;
+        }
+    }
+    for _ in 0..ticks {
+        if matches!(
+            simulator.0.tick(),
+            TickResult::End | TickResult::Fault(_) | TickResult::Halted
+        ) {
+            break;
+        }
+    }
+    Ok(simulator.devices())
+}
+
+/// A chip running MIPS code, with the devices it is connected to.
+#[pyclass]
+struct Simulator(ayysee_compiler::simulator::Simulator);
+
+#[pymethods]
+impl Simulator {
+    #[new]
+    fn new(mips: &str) -> PyResult<Self> {
+        let program = mips
+            .trim_end()
+            .parse()
+            .map_err(|e| PyValueError::new_err(format!("{}", e)))?;
+        Ok(Self(ayysee_compiler::simulator::Simulator::new(program)))
+    }
+
+    /// Runs the program until the end of the tick. Returns how the tick ended: `"yield"`,
+    /// `"limit"` (too many instructions in the tick), `"end"`, `"fault"` or `"halted"`.
+    fn tick(&mut self) -> &'static str {
+        match self.0.tick() {
+            TickResult::Yield => "yield",
+            TickResult::LimitHit => "limit",
+            TickResult::End => "end",
+            TickResult::Breakpoint => "breakpoint",
+            TickResult::Fault(_) => "fault",
+            TickResult::Halted => "halted",
+        }
+    }
+
+    fn read(&self, device: &str, logic_type: &str) -> PyResult<f64> {
+        Ok(self
+            .0
+            .read(parse_device(device)?, parse_logic_type(logic_type)))
+    }
+
+    fn write(&mut self, device: &str, logic_type: &str, value: f64) -> PyResult<()> {
+        self.0
+            .write(parse_device(device)?, parse_logic_type(logic_type), value);
+        Ok(())
+    }
+
+    /// Returns the devices the program or the caller used, with their variables.
+    fn devices(&self) -> Devices {
+        self.0
+            .devices()
+            .map(|(device, variables)| {
+                let variables = variables
+                    .iter()
+                    .map(|(logic_type, value)| (logic_type.to_string(), *value))
+                    .collect();
+                (device.to_string(), variables)
+            })
+            .collect()
+    }
+}
+
+fn parse_device(name: &str) -> PyResult<Device> {
+    name.parse()
+        .map_err(|_| PyValueError::new_err(format!("unknown device `{}`", name)))
+}
+
+fn parse_logic_type(name: &str) -> DeviceVariable {
+    name.parse()
+        .unwrap_or_else(|_| DeviceVariable::Other(name.to_string()))
+}
+
+#[pymodule]
+fn galvanic(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(simulate, m)?)?;
+    m.add_class::<Simulator>()?;
+    m.add("CompileError", m.py().get_type_bound::<CompileError>())?;
+    Ok(())
+}
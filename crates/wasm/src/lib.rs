@@ -1,7 +1,13 @@
+//! Bindings for running the compiler and the simulator in a browser, for the web
+//! playground.
+
 use wasm_bindgen::prelude::*;
 
-use ayysee_compiler::generate_program;
-use ayysee_parser::grammar::ProgramParser;
+use ayysee_compiler::resolve::SemanticErrors;
+use ayysee_compiler::simulator::TickResult;
+use ayysee_compiler::{generate_program, CompileOptions, OptLevel, Target};
+use ayysee_parser::ParseError;
+use stationeers_mips::types::{Device, DeviceVariable, Register};
 
 #[wasm_bindgen(start)]
 fn init_wasm() -> Result<(), JsValue> {
@@ -10,11 +16,207 @@ fn init_wasm() -> Result<(), JsValue> {
 
 #[wasm_bindgen]
 pub fn compile_code(code: String) -> Result<String, JsValue> {
-    let parser = ProgramParser::new();
-    let parsed = parser
-        .parse(&code)
-        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let parsed = ayysee_parser::parse(&code).map_err(|errors| {
+        let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        JsValue::from_str(&messages.join("\n"))
+    })?;
 
     let compiled = generate_program(parsed).map_err(|e| JsValue::from_str(&e.to_string()))?;
     Ok(compiled)
 }
+
+/// Options of [`compile`]. Unset options keep their default.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Default)]
+pub struct Options {
+    /// `0`, `1`, `2` or `s`.
+    pub opt_level: Option<String>,
+    /// `ic10` or `ic10-legacy`.
+    pub target: Option<String>,
+}
+
+#[wasm_bindgen]
+impl Options {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TryFrom<Options> for CompileOptions {
+    type Error = JsValue;
+
+    fn try_from(options: Options) -> Result<Self, JsValue> {
+        let opt_level = match options.opt_level.as_deref() {
+            None => OptLevel::default(),
+            Some(level) => level.parse().map_err(|e| format!("{}", e))?,
+        };
+        let target = match options.target.as_deref() {
+            None => Target::default(),
+            Some(name) => {
+                Target::builtin(name).ok_or_else(|| format!("unknown target `{}`", name))?
+            }
+        };
+        Ok(CompileOptions {
+            opt_level,
+            target,
+            ..Default::default()
+        })
+    }
+}
+
+/// An error in the source, located by byte offsets.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Diagnostic {
+    fn new(message: String, span: std::ops::Range<usize>) -> Self {
+        Self {
+            message,
+            start: span.start,
+            end: span.end,
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub lines: usize,
+    pub max_lines: usize,
+    /// Number of registers the program uses.
+    pub registers: usize,
+}
+
+/// The result of [`compile`]: the MIPS code and its stats, or the diagnostics.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Compiled {
+    pub mips: Option<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub stats: Option<Stats>,
+}
+
+/// Compiles a program. Mistakes in the program are returned as diagnostics, invalid
+/// options as an error.
+#[wasm_bindgen]
+pub fn compile(source: &str, options: Option<Options>) -> Result<Compiled, JsValue> {
+    let options = CompileOptions::try_from(options.unwrap_or_default())?;
+    let failed = |diagnostics| Compiled {
+        mips: None,
+        diagnostics,
+        stats: None,
+    };
+    let program = match ayysee_parser::parse(source) {
+        Ok(program) => program,
+        Err(errors) => {
+            let diagnostics = errors
+                .iter()
+                .map(|e| Diagnostic::new(e.to_string(), parse_error_span(e)))
+                .collect();
+            return Ok(failed(diagnostics));
+        }
+    };
+    let mips = match ayysee_compiler::ir::generate_program_with(program, &options) {
+        Ok(mips) => mips,
+        Err(e) => {
+            let diagnostics = match e.downcast_ref::<SemanticErrors>() {
+                Some(errors) => errors
+                    .0
+                    .iter()
+                    .map(|e| Diagnostic::new(e.message.clone(), e.span.into()))
+                    .collect(),
+                None => vec![Diagnostic::new(e.to_string(), 0..0)],
+            };
+            return Ok(failed(diagnostics));
+        }
+    };
+    let mut registers = std::collections::HashSet::new();
+    for ins in &mips.instructions {
+        for operand in ins.to_string().split_whitespace().skip(1) {
+            if let Ok(register) = operand.parse::<Register>() {
+                if !matches!(register, Register::Ra | Register::Sp) {
+                    registers.insert(register);
+                }
+            }
+        }
+    }
+    Ok(Compiled {
+        stats: Some(Stats {
+            lines: mips.instructions.len(),
+            max_lines: options.target.max_lines,
+            registers: registers.len(),
+        }),
+        mips: Some(mips.to_string()),
+        diagnostics: vec![],
+    })
+}
+
+fn parse_error_span(error: &ParseError) -> std::ops::Range<usize> {
+    match error {
+        ParseError::InvalidToken { location } => *location..*location + 1,
+        ParseError::UnrecognizedEOF { location, .. } => *location..*location,
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            ..
+        }
+        | ParseError::ExtraToken {
+            token: (start, _, end),
+        } => *start..*end,
+        ParseError::User { error } => error.span.start..error.span.end,
+    }
+}
+
+/// A chip running a compiled program, with its devices.
+#[wasm_bindgen]
+pub struct Simulator(ayysee_compiler::simulator::Simulator);
+
+#[wasm_bindgen]
+impl Simulator {
+    /// Loads MIPS code, such as the `mips` of [`compile`].
+    #[wasm_bindgen(constructor)]
+    pub fn new(mips: &str) -> Result<Simulator, JsValue> {
+        let program = mips.trim_end().parse().map_err(|e| format!("{}", e))?;
+        Ok(Self(ayysee_compiler::simulator::Simulator::new(program)))
+    }
+
+    /// Runs the program until the end of the tick, returning how it ended: `yield`,
+    /// `limit` (too many instructions in the tick), `end`, `breakpoint`, `fault` or `halted`.
+    pub fn tick(&mut self) -> String {
+        match self.0.tick() {
+            TickResult::Yield => "yield",
+            TickResult::LimitHit => "limit",
+            TickResult::End => "end",
+            TickResult::Breakpoint => "breakpoint",
+            TickResult::Fault(_) => "fault",
+            TickResult::Halted => "halted",
+        }
+        .to_string()
+    }
+
+    pub fn read(&self, device: &str, logic_type: &str) -> Result<f64, JsValue> {
+        Ok(self
+            .0
+            .read(parse_device(device)?, parse_logic_type(logic_type)))
+    }
+
+    pub fn write(&mut self, device: &str, logic_type: &str, value: f64) -> Result<(), JsValue> {
+        self.0
+            .write(parse_device(device)?, parse_logic_type(logic_type), value);
+        Ok(())
+    }
+}
+
+fn parse_device(name: &str) -> Result<Device, JsValue> {
+    name.parse()
+        .map_err(|_| format!("unknown device `{}`", name).into())
+}
+
+fn parse_logic_type(name: &str) -> DeviceVariable {
+    name.parse()
+        .unwrap_or_else(|_| DeviceVariable::Other(name.to_string()))
+}
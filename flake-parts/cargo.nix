@@ -34,6 +34,22 @@
 
       deps-only = craneLib.buildDepsOnly ({ } // common-build-args);
 
+      # must be the same version as the `wasm-bindgen` pinned in Cargo.toml
+      wasm-bindgen-cli = pkgs.buildWasmBindgenCli rec {
+        src = pkgs.fetchCrate {
+          pname = "wasm-bindgen-cli";
+          version = "0.2.129";
+          # TODO: replace with the hash reported by the first `nix build .#wasm-bindgen-cli`
+          hash = pkgs.lib.fakeHash;
+        };
+        cargoDeps = pkgs.rustPlatform.fetchCargoVendor {
+          inherit src;
+          inherit (src) pname version;
+          # TODO: replace with the hash reported by the second `nix build .#wasm-bindgen-cli`
+          hash = pkgs.lib.fakeHash;
+        };
+      };
+
       packages =
         let
           buildWasmPackage =
@@ -57,7 +73,7 @@
                   cargoBuildLog=$(mktemp cargoBuildLogXXXX.json)
                   cargoWithProfile build -p ${name} --target wasm32-unknown-unknown --message-format json-render-diagnostics > $cargoBuildLog
 
-                  ${pkgs.wasm-bindgen-cli}/bin/wasm-bindgen \
+                  ${wasm-bindgen-cli}/bin/wasm-bindgen \
                     target/wasm32-unknown-unknown/release/${underscore_name}.wasm \
                     --out-dir $out \
                     --target ${wasm-bindgen-target} \
@@ -72,6 +88,8 @@
             );
         in
         {
+          inherit wasm-bindgen-cli;
+
           default = packages.cli;
           cli = craneLib.buildPackage (
             {
@@ -19,7 +19,7 @@
         pkgs.cargo-audit
         pkgs.cargo-udeps
         pkgs.bacon
-        pkgs.wasm-bindgen-cli
+        self'.packages.wasm-bindgen-cli
         # formatting
         self'.packages.treefmt
         # misc